@@ -0,0 +1,177 @@
+//! The DS9097 / DS9097U style passive serial adapters (or any UART with TX and RX wired onto the bus
+//! through a diode).
+//!
+//! Each time slot is generated by sending a single character: the start bit pulls the bus low, and
+//! the data bits either release it (0xFF, a write-one / read slot) or keep it low (0x00, a write-zero
+//! slot). The adapter reads back its own transmission, so a device holding the bus low shows up in
+//! the echoed character. Resets are done the same way at a slower baud rate.
+
+extern crate std;
+
+use crate::offload::WaveformTransport;
+use std::io::{self, Read, Write};
+
+/// Used for reset pulses. Sending 0xF0 holds the bus low for ~520 µs
+const RESET_BAUD_RATE: u32 = 9600;
+
+/// Used for time slots. Each bit is ~8.7 µs, so the bus is sampled ~17 µs after the slot starts
+const SLOT_BAUD_RATE: u32 = 115_200;
+
+/// The parts of a serial port the adapter needs. This is implemented for all `serialport` ports
+/// (use `open_native` to get a sized port type)
+pub trait SerialLine: Read + Write {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()>;
+}
+
+impl<P: serialport::SerialPort> SerialLine for P {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        serialport::SerialPort::set_baud_rate(self, baud_rate).map_err(io::Error::from)
+    }
+}
+
+pub struct Ds9097<P> {
+    port: P,
+    baud_rate: u32,
+}
+
+impl<P: SerialLine> Ds9097<P> {
+    /// The port should be configured for 8 data bits, no parity, and 1 stop bit
+    pub fn new(port: P) -> Ds9097<P> {
+        Ds9097 { port, baud_rate: 0 }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        if self.baud_rate != baud_rate {
+            self.port.set_baud_rate(baud_rate)?;
+            self.baud_rate = baud_rate;
+        }
+        Ok(())
+    }
+}
+
+impl<P: SerialLine> WaveformTransport for Ds9097<P> {
+    type Error = io::Error;
+
+    fn generate_reset(&mut self) -> io::Result<bool> {
+        self.set_baud_rate(RESET_BAUD_RATE)?;
+        self.port.write_all(&[0xF0])?;
+        let mut echo = [0];
+        self.port.read_exact(&mut echo)?;
+        // a presence pulse pulls some of the high bits low
+        Ok(echo[0] != 0xF0)
+    }
+
+    fn exchange_bits(&mut self, bits: &mut [u8], bit_count: usize) -> io::Result<()> {
+        self.set_baud_rate(SLOT_BAUD_RATE)?;
+        let mut slots = [0; 64];
+        for start in (0..bit_count).step_by(slots.len()) {
+            let slots = &mut slots[..(bit_count - start).min(64)];
+            for (offset, slot) in slots.iter_mut().enumerate() {
+                let index = start + offset;
+                *slot = if bits[index / 8] & (1 << (index % 8)) != 0 {
+                    0xFF
+                } else {
+                    0x00
+                };
+            }
+            self.port.write_all(slots)?;
+            self.port.read_exact(slots)?;
+            for (offset, slot) in slots.iter().enumerate() {
+                let index = start + offset;
+                // the first data bit is sampled as the bus value
+                if *slot & 0x01 == 0 {
+                    bits[index / 8] &= !(1 << (index % 8));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{Ds9097, SerialLine, RESET_BAUD_RATE};
+    use crate::mock::NoDelay;
+    use crate::offload::OffloadedBus;
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWire, OneWireBus};
+    use std::collections::VecDeque;
+    use std::io;
+
+    /// Generates the echo of each character with a simulated bus
+    struct Adapter<'a> {
+        one_wire: OneWire<SimulatedPin<'a>>,
+        delay: SimulatedDelay<'a>,
+        baud_rate: u32,
+        echo: VecDeque<u8>,
+    }
+
+    impl<'a> io::Write for Adapter<'a> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for byte in buf {
+                let echo = if self.baud_rate == RESET_BAUD_RATE {
+                    match self.one_wire.reset(&mut self.delay).unwrap() {
+                        true => 0xE0,
+                        false => 0xF0,
+                    }
+                } else if *byte == 0xFF {
+                    self.one_wire.read_bit(&mut self.delay).unwrap() as u8 * 0xFF
+                } else {
+                    self.one_wire.write_bit(false, &mut self.delay).unwrap();
+                    0x00
+                };
+                self.echo.push_back(echo);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> io::Read for Adapter<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let count = buf.len().min(self.echo.len());
+            for byte in &mut buf[..count] {
+                *byte = self.echo.pop_front().unwrap();
+            }
+            Ok(count)
+        }
+    }
+
+    impl<'a> SerialLine for Adapter<'a> {
+        fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+            self.baud_rate = baud_rate;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ds9097() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x5A; 10];
+        simulator.add_device(device);
+        let mut bus = OffloadedBus::new(Ds9097::new(Adapter {
+            one_wire: OneWire::new(simulator.pin()).unwrap(),
+            delay: simulator.delay(),
+            baud_rate: 0,
+            echo: VecDeque::new(),
+        }));
+
+        bus.send_command(commands::READ_SCRATCHPAD, None, &mut NoDelay)
+            .unwrap();
+        let mut output = [0; 10];
+        bus.read_bytes(&mut output, &mut NoDelay).unwrap();
+        assert_eq!(output, [0x5A; 10]);
+        simulator.remove_device(&Address(0x28));
+        assert!(!bus.reset(&mut NoDelay).unwrap());
+    }
+}
@@ -0,0 +1,345 @@
+//! The DS9490R / DS9490B USB adapters, which are built around the DS2490 USB to 1-Wire bridge.
+//!
+//! The DS2490 generates all of the timing itself. Commands are sent as vendor control requests, data
+//! is exchanged over the bulk endpoints, and the interrupt endpoint reports when a command has
+//! finished (with any errors, such as no presence pulse).
+
+extern crate std;
+
+use crate::delay::Delay;
+use crate::{OneWireBus, OneWireError, OneWireResult};
+use std::time::Duration;
+
+pub const VENDOR_ID: u16 = 0x04FA;
+pub const PRODUCT_ID: u16 = 0x2490;
+
+// control requests
+const COMM_CMD: u8 = 0x01;
+
+// communication commands, and their flags
+const COMM_BIT_IO: u16 = 0x0020;
+const COMM_1_WIRE_RESET: u16 = 0x0042;
+const COMM_BYTE_IO: u16 = 0x0052;
+const COMM_BLOCK_IO: u16 = 0x0074;
+const COMM_IM: u16 = 0x0001;
+const COMM_D: u16 = 0x0008;
+const COMM_ICP: u16 = 0x0200;
+
+// endpoints
+const EP_STATUS: u8 = 0x81;
+const EP_DATA_OUT: u8 = 0x02;
+const EP_DATA_IN: u8 = 0x83;
+
+// the status packet is 16 bytes of registers, followed by result codes
+const STATUS_FLAGS: usize = 8;
+const STATUS_RESULTS: usize = 16;
+const ST_IDLE: u8 = 0x20;
+const RR_DETECT: u8 = 0xA5;
+const RR_NRS: u8 = 0x01;
+const RR_SH: u8 = 0x02;
+
+/// How many status packets to wait for a command to finish
+const MAX_STATUS_READS: u32 = 10;
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The USB transfers used to talk to a DS2490. This is implemented for `rusb::DeviceHandle`
+pub trait UsbLink {
+    type Error;
+
+    /// Sends a vendor control request with no data
+    fn control(&mut self, request: u8, value: u16, index: u16) -> Result<(), Self::Error>;
+
+    /// Writes to the bulk data out endpoint
+    fn write_data(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Reads from the bulk data in endpoint
+    fn read_data(&mut self, data: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Reads a status packet from the interrupt endpoint
+    fn read_status(&mut self, status: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl<C: rusb::UsbContext> UsbLink for rusb::DeviceHandle<C> {
+    type Error = rusb::Error;
+
+    fn control(&mut self, request: u8, value: u16, index: u16) -> Result<(), rusb::Error> {
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            rusb::RequestType::Vendor,
+            rusb::Recipient::Device,
+        );
+        self.write_control(request_type, request, value, index, &[], TIMEOUT)?;
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<(), rusb::Error> {
+        self.write_bulk(EP_DATA_OUT, data, TIMEOUT)?;
+        Ok(())
+    }
+
+    fn read_data(&mut self, data: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.read_bulk(EP_DATA_IN, data, TIMEOUT)
+    }
+
+    fn read_status(&mut self, status: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.read_interrupt(EP_STATUS, status, TIMEOUT)
+    }
+}
+
+/// A DS2490 based adapter. The delays passed to it are never used, since the adapter does the
+/// timing itself
+pub struct Ds9490<L> {
+    link: L,
+}
+
+impl Ds9490<rusb::DeviceHandle<rusb::GlobalContext>> {
+    /// Opens the first adapter that's plugged in
+    pub fn open() -> Result<Self, rusb::Error> {
+        let handle =
+            rusb::open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID).ok_or(rusb::Error::NoDevice)?;
+        handle.claim_interface(0)?;
+        // the alternate setting with the largest bulk endpoints
+        handle.set_alternate_setting(0, 3)?;
+        Ok(Ds9490::new(handle))
+    }
+}
+
+impl<L: UsbLink> Ds9490<L> {
+    /// `link` must already have the interface claimed
+    pub fn new(link: L) -> Ds9490<L> {
+        Ds9490 { link }
+    }
+
+    pub fn into_inner(self) -> L {
+        self.link
+    }
+
+    fn command(&mut self, command: u16, parameter: u16) -> OneWireResult<(), L::Error> {
+        Ok(self.link.control(COMM_CMD, command, parameter)?)
+    }
+
+    /// Waits for the current command to finish, and returns its result codes combined
+    fn wait_until_idle(&mut self) -> OneWireResult<u8, L::Error> {
+        let mut results = 0;
+        for _ in 0..MAX_STATUS_READS {
+            let mut status = [0; 32];
+            let len = self.link.read_status(&mut status)?;
+            if len > STATUS_RESULTS {
+                for result in &status[STATUS_RESULTS..len] {
+                    if *result != RR_DETECT {
+                        results |= result;
+                    }
+                }
+            }
+            if len > STATUS_FLAGS && status[STATUS_FLAGS] & ST_IDLE != 0 {
+                return Ok(results);
+            }
+        }
+        Err(OneWireError::Timeout(
+            MAX_STATUS_READS * TIMEOUT.as_micros() as u32,
+        ))
+    }
+
+    fn read_data(&mut self, data: &mut [u8]) -> OneWireResult<(), L::Error> {
+        let len = self.link.read_data(data)?;
+        if len != data.len() {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    /// Sends `data`, replacing it with the bytes read back (the same as `touch_byte` on each byte)
+    fn block_io(&mut self, data: &mut [u8]) -> OneWireResult<(), L::Error> {
+        for chunk in data.chunks_mut(64) {
+            self.link.write_data(chunk)?;
+            self.command(COMM_BLOCK_IO | COMM_IM, chunk.len() as u16)?;
+            self.wait_until_idle()?;
+            self.read_data(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L: UsbLink> OneWireBus for Ds9490<L> {
+    type Error = L::Error;
+
+    fn reset(&mut self, _delay: &mut impl Delay) -> OneWireResult<bool, L::Error> {
+        // the parameter is the bus speed, 0 is the regular speed
+        self.command(COMM_1_WIRE_RESET | COMM_IM, 0)?;
+        let results = self.wait_until_idle()?;
+        if results & RR_SH != 0 {
+            return Err(OneWireError::BusNotHigh);
+        }
+        Ok(results & RR_NRS == 0)
+    }
+
+    fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, L::Error> {
+        self.touch_bit(true, delay)
+    }
+
+    fn write_bit(&mut self, value: bool, _delay: &mut impl Delay) -> OneWireResult<(), L::Error> {
+        let data = if value { COMM_D } else { 0 };
+        self.command(COMM_BIT_IO | COMM_IM | COMM_ICP | data, 0)?;
+        self.wait_until_idle()?;
+        Ok(())
+    }
+
+    fn touch_bit(&mut self, value: bool, _delay: &mut impl Delay) -> OneWireResult<bool, L::Error> {
+        let data = if value { COMM_D } else { 0 };
+        self.command(COMM_BIT_IO | COMM_IM | data, 0)?;
+        self.wait_until_idle()?;
+        let mut bit = [0];
+        self.read_data(&mut bit)?;
+        Ok(bit[0] & 0x01 == 0x01)
+    }
+
+    fn touch_byte(&mut self, value: u8, _delay: &mut impl Delay) -> OneWireResult<u8, L::Error> {
+        self.command(COMM_BYTE_IO | COMM_IM, value as u16)?;
+        self.wait_until_idle()?;
+        let mut byte = [0];
+        self.read_data(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, L::Error> {
+        self.touch_byte(0xFF, delay)
+    }
+
+    fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        _delay: &mut impl Delay,
+    ) -> OneWireResult<(), L::Error> {
+        output.fill(0xFF);
+        self.block_io(output)
+    }
+
+    fn write_byte(&mut self, value: u8, _delay: &mut impl Delay) -> OneWireResult<(), L::Error> {
+        self.command(COMM_BYTE_IO | COMM_IM | COMM_ICP, value as u16)?;
+        self.wait_until_idle()?;
+        Ok(())
+    }
+
+    fn write_bytes(
+        &mut self,
+        bytes: &[u8],
+        _delay: &mut impl Delay,
+    ) -> OneWireResult<(), L::Error> {
+        // the echo has to be read back, so the data is copied into a buffer first
+        let mut buffer = [0; 64];
+        for chunk in bytes.chunks(buffer.len()) {
+            let buffer = &mut buffer[..chunk.len()];
+            buffer.copy_from_slice(chunk);
+            self.block_io(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::NoDelay;
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWire};
+    use core::convert::Infallible;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// Runs the adapter's commands on a simulated bus
+    struct Adapter<'a> {
+        one_wire: OneWire<SimulatedPin<'a>>,
+        delay: SimulatedDelay<'a>,
+        data_out: VecDeque<u8>,
+        data_in: VecDeque<u8>,
+        results: Vec<u8>,
+    }
+
+    impl<'a> UsbLink for Adapter<'a> {
+        type Error = Infallible;
+
+        fn control(&mut self, request: u8, value: u16, index: u16) -> Result<(), Infallible> {
+            assert_eq!(request, COMM_CMD);
+            let respond = value & COMM_ICP == 0;
+            let delay = &mut self.delay;
+            match value & 0xF0 {
+                0x40 => {
+                    if !self.one_wire.reset(delay).unwrap() {
+                        self.results.push(RR_NRS);
+                    }
+                }
+                0x20 => {
+                    let bit = self.one_wire.touch_bit(value & COMM_D != 0, delay);
+                    if respond {
+                        self.data_in.push_back(bit.unwrap() as u8);
+                    }
+                }
+                0x50 => {
+                    let byte = self.one_wire.touch_byte(index as u8, delay);
+                    if respond {
+                        self.data_in.push_back(byte.unwrap());
+                    }
+                }
+                0x70 => {
+                    for _ in 0..index {
+                        let byte = self.data_out.pop_front().unwrap();
+                        let byte = self.one_wire.touch_byte(byte, delay).unwrap();
+                        self.data_in.push_back(byte);
+                    }
+                }
+                _ => panic!("unknown command {:04X}", value),
+            }
+            Ok(())
+        }
+
+        fn write_data(&mut self, data: &[u8]) -> Result<(), Infallible> {
+            self.data_out.extend(data);
+            Ok(())
+        }
+
+        fn read_data(&mut self, data: &mut [u8]) -> Result<usize, Infallible> {
+            let len = data.len().min(self.data_in.len());
+            for byte in &mut data[..len] {
+                *byte = self.data_in.pop_front().unwrap();
+            }
+            Ok(len)
+        }
+
+        fn read_status(&mut self, status: &mut [u8]) -> Result<usize, Infallible> {
+            status[..STATUS_RESULTS].fill(0);
+            status[STATUS_FLAGS] = ST_IDLE;
+            let len = STATUS_RESULTS + self.results.len();
+            status[STATUS_RESULTS..len].copy_from_slice(&self.results);
+            self.results.clear();
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_ds9490() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x5A, 0x01, 0x02];
+        simulator.add_device(device);
+        let mut bus = Ds9490::new(Adapter {
+            one_wire: OneWire::new(simulator.pin()).unwrap(),
+            delay: simulator.delay(),
+            data_out: VecDeque::new(),
+            data_in: VecDeque::new(),
+            results: Vec::new(),
+        });
+
+        bus.send_command(commands::READ_SCRATCHPAD, None, &mut NoDelay)
+            .unwrap();
+        assert_eq!(bus.read_bit(&mut NoDelay), Ok(false));
+        assert_eq!(bus.read_bits(7, &mut NoDelay), Ok(0x2D));
+        let mut output = [0; 2];
+        bus.read_bytes(&mut output, &mut NoDelay).unwrap();
+        assert_eq!(output, [0x01, 0x02]);
+
+        simulator.remove_device(&Address(0x28));
+        assert_eq!(bus.reset(&mut NoDelay), Ok(false));
+    }
+}
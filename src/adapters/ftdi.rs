@@ -0,0 +1,197 @@
+//! FTDI chips (FT232R, FT2232, FT232H, ...) in synchronous bit-bang mode, for bench setups with a
+//! logic-level USB adapter instead of a dedicated 1-Wire bridge.
+//!
+//! One pin drives the bus low through an open-drain buffer (or a diode, with the cathode on the pin),
+//! and another pin reads the bus. In synchronous bit-bang mode each byte written sets the pins for
+//! one sample period, and the pins are read back at the same time, so a reset or a group of time
+//! slots is sent as a single buffer of samples. The MPSSE capable chips also support this mode, so
+//! MPSSE isn't used.
+
+extern crate std;
+
+use crate::offload::WaveformTransport;
+use libftd2xx::{BitMode, FtdiCommon, TimeoutError};
+use std::vec::Vec;
+
+/// The bit-bang clock is 16 times the baud rate, so each sample is 1 µs
+const BAUD_RATE: u32 = 62_500;
+
+// Maxim recommended timings, in samples
+const RESET_LOW: usize = 480;
+const PRESENCE_SAMPLE: usize = 70;
+const RESET_RECOVERY: usize = 410;
+const WRITE_ONE_LOW: usize = 6;
+const WRITE_ZERO_LOW: usize = 60;
+const SLOT_SAMPLE: usize = 15;
+const SLOT_LENGTH: usize = 70;
+
+/// The transfers needed in synchronous bit-bang mode. This is implemented for all `libftd2xx`
+/// devices
+pub trait BitBangPort {
+    type Error;
+
+    /// Writes each byte of `output` to the pins for one sample period, and reads the pins into
+    /// `input` at the same time. Both are the same length
+    fn exchange(&mut self, output: &[u8], input: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+impl<T: FtdiCommon> BitBangPort for T {
+    type Error = TimeoutError;
+
+    fn exchange(&mut self, output: &[u8], input: &mut [u8]) -> Result<(), TimeoutError> {
+        self.write_all(output)?;
+        self.read_all(input)
+    }
+}
+
+pub struct FtdiBitBang<P> {
+    port: P,
+    drive_mask: u8,
+    sense_mask: u8,
+    output: Vec<u8>,
+    input: Vec<u8>,
+}
+
+impl<P: FtdiCommon> FtdiBitBang<P> {
+    /// Puts the chip into synchronous bit-bang mode. `drive_pin` and `sense_pin` are the bit
+    /// numbers of the pins (0 - 7)
+    pub fn open(mut port: P, drive_pin: u8, sense_pin: u8) -> Result<Self, TimeoutError> {
+        port.reset()?;
+        port.set_bit_mode(1 << drive_pin, BitMode::SyncBitbang)?;
+        port.set_baud_rate(BAUD_RATE)?;
+        port.purge_all()?;
+        let mut bus = FtdiBitBang::new(port, drive_pin, sense_pin);
+        // release the bus, which may have been driven low when the mode changed
+        bus.push_slot(0, 1);
+        bus.run()?;
+        Ok(bus)
+    }
+}
+
+impl<P: BitBangPort> FtdiBitBang<P> {
+    /// `port` must already be in synchronous bit-bang mode with 1 µs samples, and `drive_pin` set as
+    /// an output
+    pub fn new(port: P, drive_pin: u8, sense_pin: u8) -> FtdiBitBang<P> {
+        FtdiBitBang {
+            port,
+            drive_mask: 1 << drive_pin,
+            sense_mask: 1 << sense_pin,
+            output: Vec::new(),
+            input: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Adds the samples for the bus being held low for `low` samples, then released until `length`
+    fn push_slot(&mut self, low: usize, length: usize) {
+        let drive_mask = self.drive_mask;
+        self.output
+            .extend((0..length).map(|i| if i < low { 0x00 } else { drive_mask }));
+    }
+
+    fn run(&mut self) -> Result<(), P::Error> {
+        self.input.clear();
+        self.input.resize(self.output.len(), 0);
+        let result = self.port.exchange(&self.output, &mut self.input);
+        self.output.clear();
+        result
+    }
+
+    fn is_low(&self, sample: usize) -> bool {
+        self.input[sample] & self.sense_mask == 0
+    }
+}
+
+impl<P: BitBangPort> WaveformTransport for FtdiBitBang<P> {
+    type Error = P::Error;
+
+    fn generate_reset(&mut self) -> Result<bool, P::Error> {
+        self.push_slot(RESET_LOW, RESET_LOW + PRESENCE_SAMPLE + RESET_RECOVERY);
+        self.run()?;
+        Ok(self.is_low(RESET_LOW + PRESENCE_SAMPLE))
+    }
+
+    fn exchange_bits(&mut self, bits: &mut [u8], bit_count: usize) -> Result<(), P::Error> {
+        // one byte of slots at a time, to keep the transfers within the chip's buffers
+        for start in (0..bit_count).step_by(8) {
+            let count = (bit_count - start).min(8);
+            for index in start..start + count {
+                match bits[index / 8] & (1 << (index % 8)) != 0 {
+                    true => self.push_slot(WRITE_ONE_LOW, SLOT_LENGTH),
+                    false => self.push_slot(WRITE_ZERO_LOW, SLOT_LENGTH),
+                }
+            }
+            self.run()?;
+            for (offset, index) in (start..start + count).enumerate() {
+                if self.is_low(offset * SLOT_LENGTH + SLOT_SAMPLE) {
+                    bits[index / 8] &= !(1 << (index % 8));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{BitBangPort, FtdiBitBang};
+    use crate::delay::Delay;
+    use crate::mock::NoDelay;
+    use crate::offload::OffloadedBus;
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWireBus};
+    use core::convert::Infallible;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+    const DRIVE_PIN: u8 = 0;
+    const SENSE_PIN: u8 = 1;
+
+    /// Plays the samples on a simulated bus
+    struct Adapter<'a> {
+        pin: SimulatedPin<'a>,
+        delay: SimulatedDelay<'a>,
+    }
+
+    impl<'a> BitBangPort for Adapter<'a> {
+        type Error = Infallible;
+
+        fn exchange(&mut self, output: &[u8], input: &mut [u8]) -> Result<(), Infallible> {
+            for (output, input) in output.iter().zip(input.iter_mut()) {
+                if output & (1 << DRIVE_PIN) != 0 {
+                    self.pin.set_high()?;
+                } else {
+                    self.pin.set_low()?;
+                }
+                *input = (self.pin.is_high()? as u8) << SENSE_PIN;
+                self.delay.delay_us(1);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ftdi() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x5A, 0x01];
+        simulator.add_device(device);
+        let adapter = Adapter {
+            pin: simulator.pin(),
+            delay: simulator.delay(),
+        };
+        let mut bus = OffloadedBus::new(FtdiBitBang::new(adapter, DRIVE_PIN, SENSE_PIN));
+
+        bus.send_command(commands::READ_SCRATCHPAD, None, &mut NoDelay)
+            .unwrap();
+        let mut output = [0; 2];
+        bus.read_bytes(&mut output, &mut NoDelay).unwrap();
+        assert_eq!(output, [0x5A, 0x01]);
+        simulator.remove_device(&Address(0x28));
+        assert_eq!(bus.reset(&mut NoDelay), Ok(false));
+    }
+}
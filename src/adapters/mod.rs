@@ -0,0 +1,11 @@
+//! Bus adapters for desktop / std platforms. Each adapter is enabled with a feature of the same
+//! name, and implements `WaveformTransport` (use it with `OffloadedBus`) or `OneWireBus` directly
+
+#[cfg(feature = "ds9097")]
+pub mod ds9097;
+
+#[cfg(feature = "ds9490")]
+pub mod ds9490;
+
+#[cfg(feature = "ftdi")]
+pub mod ftdi;
@@ -1,18 +1,367 @@
-use core::fmt::{Error, Formatter};
+use crate::crc::crc8;
+use crate::{FamilyCode, OneWireError, OneWireResult};
+use core::fmt::{Debug, Display, Error, Formatter};
+use core::marker::PhantomData;
+use core::str::FromStr;
 
 /// A 64-bit address of a device. These are globally unique, and used to single out a single device on
-/// a potentially crowded bus
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// a potentially crowded bus.
+///
+/// Addresses are ordered by their numeric value, which is not the order a device search finds them in
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Address(pub u64);
 
 impl Address {
+    /// Creates an address, checking that the crc (the most significant byte) matches the rest of
+    /// the address
+    pub fn new(address: u64) -> Result<Address, InvalidAddress> {
+        if crc8(&address.to_le_bytes()) != 0 {
+            return Err(InvalidAddress);
+        }
+        Ok(Address(address))
+    }
+
+    /// Creates an address without checking the crc, which is the same as `Address(address)`
+    pub const fn new_unchecked(address: u64) -> Address {
+        Address(address)
+    }
+
+    /// Creates an address from a family code and a 48-bit serial number, with the correct crc.
+    /// This is meant for creating realistic addresses in tests and simulations (the higher bits of
+    /// `serial_number` are ignored)
+    pub fn for_test(family_code: u8, serial_number: u64) -> Address {
+        Address::from_family_and_serial(family_code, serial_number)
+    }
+
+    /// Creates an address from a family code and a 48-bit serial number (the higher bits are
+    /// ignored), computing the crc
+    fn from_family_and_serial(family_code: u8, serial_number: u64) -> Address {
+        let address = family_code as u64 | ((serial_number & 0xFFFF_FFFF_FFFF) << 8);
+        let crc = crc8(&address.to_le_bytes()[..7]);
+        Address(address | ((crc as u64) << 56))
+    }
+
     pub fn family_code(&self) -> u8 {
-        self.0.to_le_bytes()[0]
+        self.to_bytes()[0]
+    }
+
+    /// The family of the device, from the family code
+    pub fn family(&self) -> FamilyCode {
+        FamilyCode::from(self.family_code())
+    }
+
+    /// The address in the order it's sent on the bus: the family code first, then the serial
+    /// number (least significant byte first), then the crc
+    pub fn to_bytes(&self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    /// Creates an address from bytes in the order they're sent on the bus (the same as `to_bytes`).
+    /// The crc is not checked
+    pub fn from_bytes(bytes: [u8; 8]) -> Address {
+        Address(u64::from_le_bytes(bytes))
+    }
+
+    /// The 48-bit serial number, in the order it's sent on the bus (least significant byte first)
+    pub fn serial_number(&self) -> [u8; 6] {
+        let mut serial_number = [0; 6];
+        serial_number.copy_from_slice(&self.to_bytes()[1..7]);
+        serial_number
+    }
+
+    /// The crc of the family code and serial number
+    pub fn crc(&self) -> u8 {
+        self.to_bytes()[7]
+    }
+
+    /// The address as an EUI-64, in the order the ROM ID is printed on Maxim datasheets and labels:
+    /// the family code first, then the serial number (most significant byte first), then the crc.
+    ///
+    /// The universal / local and group bits of the EUI-64 are the lowest bits of the family code,
+    /// so they may need to be adjusted for protocols that check them (a 6LoWPAN interface
+    /// identifier inverts the universal / local bit).
+    pub fn to_eui64(&self) -> [u8; 8] {
+        let bytes = self.to_bytes();
+        [
+            bytes[0], bytes[6], bytes[5], bytes[4], bytes[3], bytes[2], bytes[1], bytes[7],
+        ]
+    }
+
+    /// Creates an address from an EUI-64 in the order used by `to_eui64`. The crc is not checked
+    pub fn from_eui64(eui64: [u8; 8]) -> Address {
+        Address::from_bytes([
+            eui64[0], eui64[6], eui64[5], eui64[4], eui64[3], eui64[2], eui64[1], eui64[7],
+        ])
+    }
+}
+
+/// The address as a number, with the family code in the least significant byte. The crc is not
+/// checked
+impl From<u64> for Address {
+    fn from(address: u64) -> Address {
+        Address(address)
     }
 }
 
-impl core::fmt::Debug for Address {
+impl From<Address> for u64 {
+    fn from(address: Address) -> u64 {
+        address.0
+    }
+}
+
+impl Debug for Address {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "{:016X?}", self.0)
     }
 }
+
+/// Formats the address as the family code and serial number, separated by a dash (eg: `28-0000053B2F1C`).
+/// This is the same format used by the Linux w1 subsystem and most Maxim tools. The crc is not included
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "{:02X}-{:012X}",
+            self.family_code(),
+            (self.0 >> 8) & 0xFFFF_FFFF_FFFF
+        )
+    }
+}
+
+/// A type of device, identified by its family code. This is implemented by the device drivers, so
+/// a `TypedAddress` can be used to only accept addresses of that device
+pub trait Family {
+    const FAMILY_CODE: u8;
+}
+
+/// An address that has been checked to have the family code of `F`
+pub struct TypedAddress<F> {
+    address: Address,
+    family: PhantomData<F>,
+}
+
+impl<F: Family> TypedAddress<F> {
+    /// Checks that the given address contains the family code of `F`
+    pub fn new<E>(address: Address) -> OneWireResult<TypedAddress<F>, E> {
+        if address.family_code() == F::FAMILY_CODE {
+            Ok(TypedAddress {
+                address,
+                family: PhantomData,
+            })
+        } else {
+            Err(OneWireError::FamilyCodeMismatch)
+        }
+    }
+}
+
+impl<F> TypedAddress<F> {
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+}
+
+impl<F> From<TypedAddress<F>> for Address {
+    fn from(address: TypedAddress<F>) -> Address {
+        address.address
+    }
+}
+
+// these aren't derived, since that would require `F` to implement them as well
+impl<F> Clone for TypedAddress<F> {
+    fn clone(&self) -> TypedAddress<F> {
+        *self
+    }
+}
+
+impl<F> Copy for TypedAddress<F> {}
+
+impl<F> PartialEq for TypedAddress<F> {
+    fn eq(&self, other: &TypedAddress<F>) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<F> Eq for TypedAddress<F> {}
+
+impl<F> Debug for TypedAddress<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        Debug::fmt(&self.address, f)
+    }
+}
+
+/// The error returned by `Address::new` when the crc doesn't match the rest of the address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidAddress;
+
+impl Display for InvalidAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "address crc mismatch")
+    }
+}
+
+/// The error returned when parsing an `Address` from a string fails
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseAddressError {
+    /// The string was not in either of the supported address formats
+    InvalidFormat,
+
+    /// The crc included in the string does not match the rest of the address
+    CrcMismatch,
+}
+
+impl Display for ParseAddressError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            ParseAddressError::InvalidFormat => write!(f, "invalid address format"),
+            ParseAddressError::CrcMismatch => write!(f, "address crc mismatch"),
+        }
+    }
+}
+
+/// Parses either the family code / serial number format used by `Display` (eg: `28-0000053B2F1C`),
+/// in which case the crc is calculated, or the full 16 hex digit format used by `Debug`
+/// (eg: `E800000B1FCD1028`), in which case the crc is verified.
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<Address, ParseAddressError> {
+        if let Some((family_code, serial_number)) = s.split_once('-') {
+            Ok(Address::from_family_and_serial(
+                parse_hex(family_code, 2)? as u8,
+                parse_hex(serial_number, 12)?,
+            ))
+        } else {
+            let address = parse_hex(s, 16)?;
+            if crc8(&address.to_le_bytes()) != 0 {
+                return Err(ParseAddressError::CrcMismatch);
+            }
+            Ok(Address(address))
+        }
+    }
+}
+
+fn parse_hex(s: &str, digits: usize) -> Result<u64, ParseAddressError> {
+    // `from_str_radix` allows a leading sign, so the digits are checked first
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseAddressError::InvalidFormat);
+    }
+    u64::from_str_radix(s, 16).map_err(|_| ParseAddressError::InvalidFormat)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::address::{Address, Family, InvalidAddress, ParseAddressError, TypedAddress};
+    use crate::OneWireError;
+    use std::string::ToString;
+
+    #[test]
+    fn test_new() {
+        assert_eq!(
+            Address::new(0xE800000B1FCD1028),
+            Ok(Address(0xE800000B1FCD1028))
+        );
+        assert_eq!(Address::new(0xE900000B1FCD1028), Err(InvalidAddress));
+        assert_eq!(
+            Address::new_unchecked(0xE900000B1FCD1028),
+            Address(0xE900000B1FCD1028)
+        );
+    }
+
+    #[test]
+    fn test_for_test() {
+        assert_eq!(
+            Address::for_test(0x28, 0x0B1FCD10),
+            Address(0xE800000B1FCD1028)
+        );
+        assert_eq!(
+            Address::for_test(0x28, 0xFF00_0000_0B1F_CD10),
+            Address(0xE800000B1FCD1028)
+        );
+        assert!(Address::new(Address::for_test(0x10, 0x0B20687E).0).is_ok());
+    }
+
+    #[test]
+    fn test_typed_address() {
+        struct Ds18b20;
+
+        impl Family for Ds18b20 {
+            const FAMILY_CODE: u8 = 0x28;
+        }
+
+        let address = Address(0xE800000B1FCD1028);
+        let typed = TypedAddress::<Ds18b20>::new::<()>(address).unwrap();
+        assert_eq!(typed.address(), &address);
+        assert_eq!(Address::from(typed), address);
+        assert_eq!(
+            TypedAddress::<Ds18b20>::new::<()>(Address::for_test(0x10, 1)),
+            Err(OneWireError::FamilyCodeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_components() {
+        let address = Address(0xE800000B1FCD1028);
+        assert_eq!(address.family_code(), 0x28);
+        assert_eq!(
+            address.serial_number(),
+            [0x10, 0xCD, 0x1F, 0x0B, 0x00, 0x00]
+        );
+        assert_eq!(address.crc(), 0xE8);
+    }
+
+    #[test]
+    fn test_bytes() {
+        let address = Address(0xE800000B1FCD1028);
+        let bytes = [0x28, 0x10, 0xCD, 0x1F, 0x0B, 0x00, 0x00, 0xE8];
+        assert_eq!(address.to_bytes(), bytes);
+        assert_eq!(Address::from_bytes(bytes), address);
+        assert_eq!(Address::from(0xE800000B1FCD1028), address);
+        assert_eq!(u64::from(address), 0xE800000B1FCD1028);
+    }
+
+    #[test]
+    fn test_eui64() {
+        let address = Address(0xE800000B1FCD1028);
+        let eui64 = [0x28, 0x00, 0x00, 0x0B, 0x1F, 0xCD, 0x10, 0xE8];
+        assert_eq!(address.to_eui64(), eui64);
+        assert_eq!(Address::from_eui64(eui64), address);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Address(0xE800000B1FCD1028).to_string(), "28-00000B1FCD10");
+        assert_eq!(Address(0x5700000B2015FF28).to_string(), "28-00000B2015FF");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("28-00000B1FCD10".parse(), Ok(Address(0xE800000B1FCD1028)));
+        assert_eq!("28-00000b2015ff".parse(), Ok(Address(0x5700000B2015FF28)));
+        assert_eq!("E800000B1FCD1028".parse(), Ok(Address(0xE800000B1FCD1028)));
+
+        assert_eq!(
+            "E900000B1FCD1028".parse::<Address>(),
+            Err(ParseAddressError::CrcMismatch)
+        );
+        assert_eq!(
+            "28-00000B1FCD1".parse::<Address>(),
+            Err(ParseAddressError::InvalidFormat)
+        );
+        assert_eq!(
+            "28-+0000B1FCD10".parse::<Address>(),
+            Err(ParseAddressError::InvalidFormat)
+        );
+        assert_eq!(
+            "28_00000B1FCD10".parse::<Address>(),
+            Err(ParseAddressError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let address = Address(0x70000008AC851628);
+        assert_eq!(address.to_string().parse(), Ok(address));
+    }
+}
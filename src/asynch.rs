@@ -0,0 +1,486 @@
+//! An async version of the bus, enabled with the `async` feature. This uses the embedded-hal 1.0
+//! traits, and the pin's `Wait` implementation to wait for the bus to change instead of using fixed
+//! delays where possible (waiting for the pull-up, and detecting the presence pulse), so it copes
+//! better with slow-rising buses.
+
+use crate::search::{triplet_bit, RomSearch, SearchOrder};
+use crate::timing::{SlotTimes, Timings};
+use crate::{commands, Address, OneWireError, OneWireResult, SearchState, Speed};
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+use embedded_hal_1::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+
+/// How long to wait for the pull-up resistor to bring the bus high
+const BUS_HIGH_TIMEOUT_MICROS: u32 = 250;
+
+/// Devices wait at least this long after a reset before starting the presence pulse, so the bus
+/// has to have risen by then. Otherwise a bus that's still rising would look like a presence pulse
+const PRESENCE_WAIT_MICROS: u32 = 15;
+
+/// `PRESENCE_WAIT_MICROS` at overdrive speed
+const OVERDRIVE_PRESENCE_WAIT_MICROS: u32 = 2;
+
+/// Runs `future`, giving up after `micros`. Returns `None` if it timed out
+async fn with_timeout<F: Future>(
+    future: F,
+    delay: &mut impl DelayNs,
+    micros: u32,
+) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut timeout = pin!(delay.delay_us(micros));
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        if timeout.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Runs `future` while waiting for `micros`, returning its output if it finished in that time.
+/// Unlike `with_timeout`, this always waits for the whole time
+async fn during<F: Future>(future: F, delay: &mut impl DelayNs, micros: u32) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut timeout = pin!(delay.delay_us(micros));
+    let mut output = None;
+    poll_fn(|cx| {
+        if output.is_none() {
+            if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                output = Some(result);
+            }
+        }
+        timeout.as_mut().poll(cx).map(|()| output.take())
+    })
+    .await
+}
+
+/// An async 1-Wire bus. The pin must be configured the same way as for `OneWire`. The delay is
+/// stored in the bus, since it can't be shared between futures anyway. The timings are the same
+/// `Timings` as `OneWire` uses
+pub struct AsyncOneWire<T, D> {
+    pin: T,
+    delay: D,
+    times: SlotTimes,
+    /// The configured timings while switched to overdrive
+    standard_times: Option<SlotTimes>,
+}
+
+impl<T, D> AsyncOneWire<T, D>
+where
+    T: InputPin + OutputPin + Wait,
+    D: DelayNs,
+{
+    pub fn new(pin: T, delay: D) -> OneWireResult<AsyncOneWire<T, D>, T::Error> {
+        let mut one_wire = AsyncOneWire {
+            pin,
+            delay,
+            times: SlotTimes::from(&Timings::STANDARD),
+            standard_times: None,
+        };
+        // Pin should be high during idle.
+        one_wire.release_bus()?;
+        Ok(one_wire)
+    }
+
+    pub fn into_inner(self) -> (T, D) {
+        (self.pin, self.delay)
+    }
+
+    /// Sets the timings of the reset pulse and time slots. The default is `Timings::STANDARD`
+    pub fn set_timings(&mut self, timings: Timings) {
+        self.times = SlotTimes::from(&timings);
+        self.standard_times = None;
+    }
+
+    pub fn timings(&self) -> Timings {
+        Timings::from(&self.times)
+    }
+
+    /// Switches between the configured timings and `Timings::OVERDRIVE`. See `OneWire::set_speed`
+    pub fn set_speed(&mut self, speed: Speed) {
+        match speed {
+            Speed::Standard => {
+                if let Some(times) = self.standard_times.take() {
+                    self.times = times;
+                }
+            }
+            Speed::Overdrive => {
+                if self.standard_times.is_none() {
+                    self.standard_times = Some(self.times);
+                    self.times = SlotTimes::from(&Timings::OVERDRIVE);
+                }
+            }
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        match self.standard_times {
+            Some(_) => Speed::Overdrive,
+            None => Speed::Standard,
+        }
+    }
+
+    /// Disconnects the bus, letting another device (or the pull-up resistor) set the bus value
+    pub fn release_bus(&mut self) -> OneWireResult<(), T::Error> {
+        Ok(self.pin.set_high()?)
+    }
+
+    /// Drives the bus low
+    pub fn set_bus_low(&mut self) -> OneWireResult<(), T::Error> {
+        Ok(self.pin.set_low()?)
+    }
+
+    pub fn is_bus_high(&mut self) -> OneWireResult<bool, T::Error> {
+        Ok(self.pin.is_high()?)
+    }
+
+    /// Waits for the extra recovery time, which is usually 0
+    async fn recover(&mut self, micros: u16) {
+        if micros > 0 {
+            self.delay.delay_us(micros as u32).await;
+        }
+    }
+
+    async fn wait_for_high(&mut self) -> OneWireResult<(), T::Error> {
+        match with_timeout(
+            self.pin.wait_for_high(),
+            &mut self.delay,
+            BUS_HIGH_TIMEOUT_MICROS,
+        )
+        .await
+        {
+            Some(result) => Ok(result?),
+            None => Err(OneWireError::BusNotHigh),
+        }
+    }
+
+    /// Sends a reset pulse, then returns true if a device is present
+    pub async fn reset(&mut self) -> OneWireResult<bool, T::Error> {
+        self.wait_for_high().await?;
+
+        self.set_bus_low()?;
+        self.delay.delay_us(self.times.reset_low as u32).await;
+
+        self.release_bus()?;
+        let presence_wait = match self.standard_times {
+            Some(_) => OVERDRIVE_PRESENCE_WAIT_MICROS,
+            None => PRESENCE_WAIT_MICROS,
+        };
+        match with_timeout(self.pin.wait_for_high(), &mut self.delay, presence_wait).await {
+            Some(result) => result?,
+            None => return Err(OneWireError::BusNotHigh),
+        }
+        // the presence pulse has to have started by the presence sample time. The rest of the
+        // window is waited out even after the pulse starts, so the recovery time is measured from
+        // the sample point as in `OneWire::reset`
+        let presence_sample = self.times.presence_sample as u32;
+        let device_present =
+            match during(self.pin.wait_for_low(), &mut self.delay, presence_sample).await {
+                Some(result) => {
+                    result?;
+                    true
+                }
+                None => false,
+            };
+
+        self.delay.delay_us(self.times.reset_recovery as u32).await;
+        self.wait_for_high().await?;
+        Ok(device_present)
+    }
+
+    pub async fn read_bit(&mut self) -> OneWireResult<bool, T::Error> {
+        self.set_bus_low()?;
+        self.delay.delay_us(self.times.write_one_low as u32).await;
+
+        self.release_bus()?;
+        self.delay.delay_us(self.times.read_sample as u32).await;
+
+        let bit_value = self.is_bus_high()?;
+        self.delay.delay_us(self.times.read_recovery as u32).await;
+        self.wait_for_high().await?;
+        self.recover(self.times.slot_recovery).await;
+        Ok(bit_value)
+    }
+
+    pub async fn read_byte(&mut self) -> OneWireResult<u8, T::Error> {
+        let mut output: u8 = 0;
+        for _ in 0..8 {
+            output >>= 1;
+            if self.read_bit().await? {
+                output |= 0x80;
+            }
+        }
+        self.recover(self.times.byte_recovery).await;
+        Ok(output)
+    }
+
+    pub async fn read_bytes(&mut self, output: &mut [u8]) -> OneWireResult<(), T::Error> {
+        for byte in output.iter_mut() {
+            *byte = self.read_byte().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn write_bit(&mut self, value: bool) -> OneWireResult<(), T::Error> {
+        let (low, recovery) = match value {
+            true => (self.times.write_one_low, self.times.write_one_recovery),
+            false => (self.times.write_zero_low, self.times.write_zero_recovery),
+        };
+        self.set_bus_low()?;
+        self.delay.delay_us(low as u32).await;
+        self.release_bus()?;
+        self.delay.delay_us(recovery as u32).await;
+        self.wait_for_high().await?;
+        self.recover(self.times.slot_recovery).await;
+        Ok(())
+    }
+
+    pub async fn write_byte(&mut self, mut value: u8) -> OneWireResult<(), T::Error> {
+        for _ in 0..8 {
+            self.write_bit(value & 0x01 == 0x01).await?;
+            value >>= 1;
+        }
+        self.recover(self.times.byte_recovery).await;
+        Ok(())
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> OneWireResult<(), T::Error> {
+        for byte in bytes {
+            self.write_byte(*byte).await?;
+        }
+        Ok(())
+    }
+
+    /// Address a specific device. All others will wait for a reset pulse.
+    /// This should only be called after a reset, and should be immediately followed by another command
+    pub async fn match_address(&mut self, address: &Address) -> OneWireResult<(), T::Error> {
+        self.write_byte(commands::MATCH_ROM).await?;
+        self.write_bytes(&address.to_bytes()).await
+    }
+
+    /// Address all devices on the bus simultaneously.
+    /// This should only be called after a reset, and should be immediately followed by another command
+    pub async fn skip_address(&mut self) -> OneWireResult<(), T::Error> {
+        self.write_byte(commands::SKIP_ROM).await
+    }
+
+    /// Sends a reset, followed with either a SKIP_ROM or MATCH_ROM (with an address), and then the supplied command
+    /// This should be followed by any reading/writing, if needed by the command used
+    pub async fn send_command(
+        &mut self,
+        command: u8,
+        address: Option<&Address>,
+    ) -> OneWireResult<(), T::Error> {
+        self.reset().await?;
+        if let Some(address) = address {
+            self.match_address(address).await?;
+        } else {
+            self.skip_address().await?;
+        }
+        self.write_byte(command).await
+    }
+
+    /// One step of a device search. See `OneWireBus::triplet`
+    pub async fn triplet(
+        &mut self,
+        direction: bool,
+    ) -> OneWireResult<(bool, bool, bool), T::Error> {
+        let id_bit = self.read_bit().await?;
+        let cmp_bit = self.read_bit().await?;
+        let taken = triplet_bit(id_bit, cmp_bit, direction);
+        self.write_bit(taken).await?;
+        Ok((id_bit, cmp_bit, taken))
+    }
+
+    /// Search for device addresses on the bus. This is the same as `OneWire::device_search`
+    pub async fn device_search(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+    ) -> OneWireResult<Option<(Address, SearchState)>, T::Error> {
+        let mut search = match RomSearch::next_pass(search_state, SearchOrder::Ascending) {
+            Some(search) => search,
+            None => return Ok(None),
+        };
+
+        if !self.reset().await? {
+            return Ok(None);
+        }
+        if only_alarming {
+            self.write_byte(commands::SEARCH_ALARM).await?;
+        } else {
+            self.write_byte(commands::SEARCH_NORMAL).await?;
+        }
+
+        while !search.is_complete() {
+            search.take_triplet(self.triplet(search.direction()).await?)?;
+        }
+        Ok(Some(search.finish()?))
+    }
+
+    /// Returns a search of the devices on the bus, where each address is found with
+    /// `AsyncDeviceSearch::next().await`. The search can be stopped at any point (including by
+    /// dropping a `next` future part way through), and the bus used for other things
+    pub fn devices(&mut self, only_alarming: bool) -> AsyncDeviceSearch<'_, T, D> {
+        AsyncDeviceSearch {
+            onewire: self,
+            state: None,
+            finished: false,
+            only_alarming,
+        }
+    }
+}
+
+/// An async search of the devices on the bus, created by `AsyncOneWire::devices`
+pub struct AsyncDeviceSearch<'a, T, D> {
+    onewire: &'a mut AsyncOneWire<T, D>,
+    state: Option<SearchState>,
+    finished: bool,
+    only_alarming: bool,
+}
+
+impl<'a, T, D> AsyncDeviceSearch<'a, T, D>
+where
+    T: InputPin + OutputPin + Wait,
+    D: DelayNs,
+{
+    /// Finds the next device. Returns `None` once every device has been found, or after an error
+    pub async fn next(&mut self) -> Option<OneWireResult<Address, T::Error>> {
+        if self.finished {
+            return None;
+        }
+        let result = self
+            .onewire
+            .device_search(self.state.as_ref(), self.only_alarming)
+            .await;
+        match result {
+            Ok(Some((address, search_state))) => {
+                self.state = Some(search_state);
+                Some(Ok(address))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Stops the search, returning its state. See `DeviceSearch::into_state`
+    pub fn into_state(self) -> Option<SearchState> {
+        self.state
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    extern crate std;
+
+    use crate::asynch::AsyncOneWire;
+    use crate::simulator::{block_on, Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    use crate::{commands, Address, OneWireError, Speed};
+    use core::time::Duration;
+
+    #[test]
+    fn test_async() {
+        let simulator = Simulator::new();
+        let mut one_wire = AsyncOneWire::new(simulator.pin(), simulator.delay()).unwrap();
+        assert_eq!(block_on(one_wire.reset()), Ok(false));
+
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x12, 0x34];
+        simulator.add_device(device);
+        let mut scratchpad = [0; 2];
+        block_on(async {
+            one_wire
+                .send_command(commands::READ_SCRATCHPAD, None)
+                .await?;
+            one_wire.read_bytes(&mut scratchpad).await
+        })
+        .unwrap();
+        assert_eq!(scratchpad, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_async_slow_rise() {
+        let simulator = Simulator::new();
+        simulator.set_rise_micros(10);
+        let mut one_wire = AsyncOneWire::new(simulator.pin(), simulator.delay()).unwrap();
+        // the bus is still low for a while after the reset pulse, which isn't a presence pulse
+        assert_eq!(block_on(one_wire.reset()), Ok(false));
+
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        assert_eq!(block_on(one_wire.reset()), Ok(true));
+
+        simulator.set_rise_micros(20);
+        assert_eq!(block_on(one_wire.reset()), Err(OneWireError::BusNotHigh));
+    }
+
+    #[test]
+    fn test_async_search() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x7000_0000_0000_0228)));
+        simulator.add_device(VirtualDevice::new(Address(0x2900_0000_0000_0128)));
+        let mut one_wire = AsyncOneWire::new(simulator.pin(), simulator.delay()).unwrap();
+
+        let found = block_on(async {
+            let mut found = std::vec::Vec::new();
+            let mut devices = one_wire.devices(false);
+            while let Some(address) = devices.next().await {
+                found.push(address.unwrap());
+            }
+            found
+        });
+        assert_eq!(
+            found,
+            [
+                Address(0x7000_0000_0000_0228),
+                Address(0x2900_0000_0000_0128)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_async_timings() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.overdrive_capable = true;
+        simulator.add_device(device);
+        let mut one_wire = AsyncOneWire::new(simulator.pin(), simulator.delay()).unwrap();
+        one_wire.set_timings(Timings {
+            reset_low: Duration::from_micros(600),
+            read_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+
+        let start = simulator.time_micros();
+        assert_eq!(block_on(one_wire.reset()), Ok(true));
+        // the recovery time starts at the presence sample point, not when the presence pulse starts
+        assert!(simulator.time_micros() - start >= 600 + 70 + 410);
+
+        let start = simulator.time_micros();
+        assert_eq!(block_on(one_wire.read_bit()), Ok(true));
+        assert!(simulator.time_micros() - start >= 6 + 9 + 100);
+
+        assert_eq!(block_on(one_wire.reset()), Ok(true));
+        block_on(one_wire.write_byte(commands::OVERDRIVE_SKIP_ROM)).unwrap();
+        one_wire.set_speed(Speed::Overdrive);
+        assert_eq!(one_wire.speed(), Speed::Overdrive);
+        let start = simulator.time_micros();
+        assert_eq!(block_on(one_wire.reset()), Ok(true));
+        assert!(simulator.time_micros() - start < 200);
+
+        one_wire.set_speed(Speed::Standard);
+        assert_eq!(one_wire.timings().reset_low, Duration::from_micros(600));
+    }
+}
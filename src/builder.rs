@@ -0,0 +1,232 @@
+use crate::pullup::{NoStrongPullup, StrongPullupPin};
+use crate::timing::Timings;
+#[cfg(feature = "trace")]
+use crate::BusEvent;
+use crate::{OneWire, OneWireResult};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// Configures a bus before it's created, with `OneWire::builder`. Every option defaults to the same
+/// as `OneWire::new`
+pub struct OneWireBuilder<T, P = NoStrongPullup> {
+    pin: T,
+    strong_pullup: P,
+    inverted_output: bool,
+    timings: Timings,
+    majority_reads: bool,
+    overdrive_fallback: bool,
+    retries: u8,
+    bus_high_timeout_micros: u16,
+    idle_hook: Option<fn()>,
+    #[cfg(feature = "trace")]
+    tracer: Option<fn(BusEvent)>,
+}
+
+impl<T, E> OneWire<T>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+{
+    pub fn builder(pin: T) -> OneWireBuilder<T> {
+        OneWireBuilder {
+            pin,
+            strong_pullup: NoStrongPullup,
+            inverted_output: false,
+            timings: Timings::STANDARD,
+            majority_reads: false,
+            overdrive_fallback: false,
+            retries: 0,
+            bus_high_timeout_micros: 250,
+            idle_hook: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+        }
+    }
+}
+
+impl<T, E, P> OneWireBuilder<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    /// Sets a strong pull-up for the bus to use when one isn't passed to an operation that needs
+    /// it, such as `OneWireBus::convert_all` or `memory::copy_scratchpad` (see the `pullup` module).
+    /// It's set low when the bus is built
+    pub fn strong_pullup_pin<Q>(self, strong_pullup: Q) -> OneWireBuilder<T, Q>
+    where
+        Q: OutputPin<Error = E>,
+    {
+        OneWireBuilder {
+            pin: self.pin,
+            strong_pullup,
+            inverted_output: self.inverted_output,
+            timings: self.timings,
+            majority_reads: self.majority_reads,
+            overdrive_fallback: self.overdrive_fallback,
+            retries: self.retries,
+            bus_high_timeout_micros: self.bus_high_timeout_micros,
+            idle_hook: self.idle_hook,
+            #[cfg(feature = "trace")]
+            tracer: self.tracer,
+        }
+    }
+
+    /// See `OneWire::new_inverted`
+    pub fn inverted(mut self, inverted: bool) -> Self {
+        self.inverted_output = inverted;
+        self
+    }
+
+    /// See `OneWire::set_timings`
+    pub fn timings(mut self, timings: Timings) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// See `OneWire::set_majority_reads`
+    pub fn majority_reads(mut self, enabled: bool) -> Self {
+        self.majority_reads = enabled;
+        self
+    }
+
+    /// See `OneWire::set_overdrive_fallback`
+    pub fn overdrive_fallback(mut self, enabled: bool) -> Self {
+        self.overdrive_fallback = enabled;
+        self
+    }
+
+    /// See `OneWire::set_retries`
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// See `OneWire::set_bus_high_timeout`
+    pub fn bus_high_timeout(mut self, micros: u16) -> Self {
+        self.bus_high_timeout_micros = micros;
+        self
+    }
+
+    /// See `OneWire::set_idle_hook`
+    pub fn idle_hook(mut self, hook: fn()) -> Self {
+        self.idle_hook = Some(hook);
+        self
+    }
+
+    /// See `OneWire::set_tracer`
+    #[cfg(feature = "trace")]
+    pub fn tracer(mut self, tracer: fn(BusEvent)) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Creates the bus, and releases the pin
+    pub fn build(self) -> OneWireResult<OneWire<T, P>, E> {
+        let mut one_wire =
+            OneWire::with_polarity(self.pin, self.inverted_output, self.strong_pullup)?;
+        one_wire.set_strong_pullup(false)?;
+        one_wire.set_timings(self.timings);
+        one_wire.set_majority_reads(self.majority_reads);
+        one_wire.set_overdrive_fallback(self.overdrive_fallback);
+        one_wire.set_retries(self.retries);
+        one_wire.set_bus_high_timeout(self.bus_high_timeout_micros);
+        one_wire.set_idle_hook(self.idle_hook);
+        #[cfg(feature = "trace")]
+        one_wire.set_tracer(self.tracer);
+        Ok(one_wire)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mock::Pullup;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    #[cfg(feature = "trace")]
+    use crate::BusEvent;
+    use crate::{Address, ConversionWait, OneWire, OneWireBus};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::time::Duration;
+
+    static IDLE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn idle() {
+        IDLE_CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "trace")]
+    static EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[cfg(feature = "trace")]
+    fn trace(_event: BusEvent) {
+        EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_builder() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let timings = Timings {
+            read_sample: Duration::from_micros(10),
+            ..Timings::STANDARD
+        };
+        let builder = OneWire::builder(simulator.pin())
+            .timings(timings)
+            .majority_reads(true)
+            .overdrive_fallback(true)
+            .retries(3)
+            .bus_high_timeout(500)
+            .idle_hook(idle);
+        #[cfg(feature = "trace")]
+        let builder = builder.tracer(trace);
+        let mut one_wire = builder.build().unwrap();
+        assert_eq!(one_wire.timings(), timings);
+        assert!(one_wire.majority_reads());
+        assert!(one_wire.overdrive_fallback());
+        assert_eq!(one_wire.retries(), 3);
+        assert_eq!(one_wire.bus_high_timeout(), 500);
+        assert!(!one_wire.is_inverted());
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        // the reset calls the idle hook, and is traced
+        assert_eq!(IDLE_CALLS.load(Ordering::Relaxed), 1);
+        #[cfg(feature = "trace")]
+        assert_eq!(EVENTS.load(Ordering::Relaxed), 1);
+
+        let one_wire = OneWire::builder(simulator.pin())
+            .inverted(true)
+            .build()
+            .unwrap();
+        assert!(one_wire.is_inverted());
+        assert!(!one_wire.overdrive_fallback());
+    }
+
+    #[test]
+    fn test_strong_pullup_pin() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::builder(simulator.pin())
+            .strong_pullup_pin(Pullup::new())
+            .build()
+            .unwrap();
+        assert_eq!(one_wire.strong_pullup_pin().states, [false]);
+
+        let wait = ConversionWait::Fixed {
+            micros: 750_000,
+            strong_pullup: None,
+        };
+        assert_eq!(one_wire.convert_all(0x44, wait, &mut delay), Ok(750_000));
+        assert_eq!(one_wire.strong_pullup_pin().states, [false, true, false]);
+
+        // a strong pull-up passed to the operation is used instead
+        let mut pullup = Pullup::new();
+        let wait = ConversionWait::Fixed {
+            micros: 750_000,
+            strong_pullup: Some(&mut pullup),
+        };
+        one_wire.convert_all(0x44, wait, &mut delay).unwrap();
+        assert_eq!(pullup.states, [true, false]);
+        assert_eq!(one_wire.strong_pullup_pin().states, [false, true, false]);
+    }
+}
@@ -0,0 +1,860 @@
+use crate::commands::{self, Addressing, RomCommand};
+use crate::deadline::{Deadline, NoDeadline};
+use crate::delay::Delay;
+use crate::pullup::{bus_strong_pullup_delay, StrongPullupPin};
+use crate::scan;
+#[cfg(feature = "alloc")]
+use crate::scan::ScanDiff;
+use crate::search::{triplet_bit, Decision, RomSearch, SearchOrder};
+use crate::{
+    crc, Address, OneWire, OneWireError, OneWireResult, Operation, SearchState, Speed,
+    READ_SLOT_DURATION_MICROS,
+};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// How `OneWireBus::convert_all` waits for the devices to finish
+pub enum ConversionWait<'a, E> {
+    /// Polls with read slots, which return 0 until every device has finished, giving up with
+    /// `OneWireError::Timeout` after `max_micros`. Polling doesn't work with parasite powered
+    /// devices, which need the bus held high while they convert
+    Poll { max_micros: u32 },
+
+    /// Waits for `micros` (the longest conversion time of the devices), with the strong pull-up
+    /// (or the bus's own, if this is `None`) enabled for the whole time if there is one
+    Fixed {
+        micros: u32,
+        strong_pullup: Option<&'a mut dyn OutputPin<Error = E>>,
+    },
+}
+
+/// The operations device drivers need from a 1-Wire bus.
+///
+/// This is implemented by `OneWire`. Device drivers can be generic over this trait, so they can also
+/// be used with other implementations, such as the `mock` module for unit testing.
+///
+/// Only `reset`, `read_bit`, and `write_bit` are required. Everything else is built on top of them,
+/// but can be overridden if an implementation can do it more efficiently.
+pub trait OneWireBus {
+    /// The error of the underlying pin (or other transport)
+    type Error;
+
+    /// Sends a reset pulse, then returns true if a device is present
+    fn reset(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, Self::Error>;
+
+    fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, Self::Error>;
+
+    fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), Self::Error>;
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, Self::Error> {
+        let mut output: u8 = 0;
+        for _ in 0..8 {
+            output >>= 1;
+            if self.read_bit(delay)? {
+                output |= 0x80;
+            }
+        }
+        Ok(output)
+    }
+
+    fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        for byte in output.iter_mut() {
+            *byte = self.read_byte(delay)?;
+        }
+        Ok(())
+    }
+
+    /// Reads `output.len()` bytes, followed by a crc8 of them, which is checked
+    fn read_bytes_crc8(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.read_bytes(output, delay)?;
+        let crc = self.read_byte(delay)?;
+        let computed = crc::crc8(output);
+        if computed != crc {
+            return crc_checked(
+                self,
+                Operation::Read,
+                Err(OneWireError::CrcMismatch {
+                    computed: computed as u16,
+                    received: crc as u16,
+                    len: output.len(),
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads `count` bits (at most 8), least significant bit first
+    fn read_bits(&mut self, count: u8, delay: &mut impl Delay) -> OneWireResult<u8, Self::Error> {
+        assert!(count <= 8, "at most 8 bits can be read at once");
+        let mut output: u8 = 0;
+        for bit_index in 0..count {
+            if self.read_bit(delay)? {
+                output |= 1 << bit_index;
+            }
+        }
+        Ok(output)
+    }
+
+    fn write_byte(
+        &mut self,
+        mut value: u8,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        for _ in 0..8 {
+            self.write_bit(value & 0x01 == 0x01, delay)?;
+            value >>= 1;
+        }
+        Ok(())
+    }
+
+    /// Writes `bytes`, then reads the same number of bytes back and checks that they match, for
+    /// devices that echo data back after it's written. Returns `VerificationFailed` if they don't
+    fn write_bytes_verified(
+        &mut self,
+        bytes: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.write_bytes(bytes, delay)?;
+        for byte in bytes {
+            if self.read_byte(delay)? != *byte {
+                return noted(
+                    self,
+                    Operation::Write,
+                    Err(OneWireError::VerificationFailed),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the lowest `count` bits (at most 8) of `value`, least significant bit first
+    fn write_bits(
+        &mut self,
+        value: u8,
+        count: u8,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        assert!(count <= 8, "at most 8 bits can be written at once");
+        for bit_index in 0..count {
+            self.write_bit(value & (1 << bit_index) != 0, delay)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a bit, and returns the value of the bus during the slot. Sending a 1 is the same as a
+    /// read slot, so this returns what the devices sent. Sending a 0 always returns false
+    fn touch_bit(
+        &mut self,
+        value: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<bool, Self::Error> {
+        if value {
+            self.read_bit(delay)
+        } else {
+            self.write_bit(false, delay)?;
+            Ok(false)
+        }
+    }
+
+    /// Sends a byte using `touch_bit`, and returns the bits that were read. Sending 0xFF is the same
+    /// as `read_byte`
+    fn touch_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<u8, Self::Error> {
+        let mut output: u8 = 0;
+        for bit_index in 0..8 {
+            if self.touch_bit(value & (1 << bit_index) != 0, delay)? {
+                output |= 1 << bit_index;
+            }
+        }
+        Ok(output)
+    }
+
+    fn write_bytes(
+        &mut self,
+        bytes: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        for byte in bytes {
+            self.write_byte(*byte, delay)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a ROM command. This should only be called after a reset
+    fn write_rom_command(
+        &mut self,
+        command: RomCommand,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.write_byte(command.into(), delay)
+    }
+
+    /// Address a specific device. All others will wait for a reset pulse.
+    /// This should only be called after a reset, and should be immediately followed by another command
+    fn match_address(
+        &mut self,
+        address: &Address,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        log_event!(trace, "match address {}", address);
+        let result = self
+            .write_rom_command(RomCommand::MatchRom, delay)
+            .and_then(|()| self.write_bytes(&address.to_bytes(), delay));
+        noted(self, Operation::MatchRom, result)
+    }
+
+    /// Address all devices on the bus simultaneously.
+    /// This should only be called after a reset, and should be immediately followed by another command
+    fn skip_address(&mut self, delay: &mut impl Delay) -> OneWireResult<(), Self::Error> {
+        self.write_rom_command(RomCommand::SkipRom, delay)?;
+        Ok(())
+    }
+
+    /// Sends a reset, followed with either a SKIP_ROM or MATCH_ROM (with an address), and then the supplied command
+    /// This should be followed by any reading/writing, if needed by the command used
+    fn send_command(
+        &mut self,
+        command: u8,
+        address: Option<&Address>,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        let addressing = match address {
+            Some(address) => Addressing::Match(address),
+            None => Addressing::Skip,
+        };
+        self.send_command_with(command, addressing, delay)
+    }
+
+    /// The same as `send_command`, but with a choice of ROM command used to address the devices
+    fn send_command_with(
+        &mut self,
+        command: u8,
+        addressing: Addressing,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.set_speed(Speed::Standard);
+        self.reset(delay)?;
+        match addressing {
+            Addressing::Skip => self.skip_address(delay)?,
+            Addressing::Match(address) => self.match_address(address, delay)?,
+            Addressing::Resume => self.write_rom_command(RomCommand::Resume, delay)?,
+            Addressing::OverdriveSkip => {
+                self.write_rom_command(RomCommand::OverdriveSkip, delay)?;
+                self.set_speed(Speed::Overdrive);
+            }
+            Addressing::OverdriveMatch(address) => {
+                self.write_rom_command(RomCommand::OverdriveMatch, delay)?;
+                self.set_speed(Speed::Overdrive);
+                self.write_bytes(&address.to_bytes(), delay)?;
+            }
+        }
+        self.write_byte(command, delay)?;
+        Ok(())
+    }
+
+    /// Reads the scratchpad of a device using the conventional READ SCRATCHPAD (0xBE) command.
+    /// The last byte of `output` is the crc, which is checked against the rest of the scratchpad
+    fn read_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.send_command(commands::READ_SCRATCHPAD, address, delay)?;
+        self.read_bytes(output, delay)?;
+        crc_checked(self, Operation::Read, crc::check_crc8(output))
+    }
+
+    /// Writes to the scratchpad of a device using the conventional WRITE SCRATCHPAD (0x4E) command
+    fn write_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        data: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Self::Error> {
+        self.send_command(commands::WRITE_SCRATCHPAD, address, delay)?;
+        self.write_bytes(data, delay)
+    }
+
+    /// Issues read slots until one returns 1, which is how busy devices (such as a DS18B20 while it
+    /// converts) signal that they've finished. Returns the time taken by the read slots in µs (from
+    /// `read_slot_micros`), or `OneWireError::Timeout` if the bus is still held low after
+    /// `max_micros`. The idle hook is called between slots
+    fn poll_until_high(
+        &mut self,
+        max_micros: u32,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u32, Self::Error> {
+        let slot_micros = self.read_slot_micros().max(1);
+        let slots = (max_micros / slot_micros).max(1);
+        for slot in 1..=slots {
+            if self.read_bit(delay)? {
+                return Ok(slot * slot_micros);
+            }
+            self.idle();
+        }
+        Err(OneWireError::Timeout(max_micros))
+    }
+
+    /// Sends `command` (such as the DS18B20's CONVERT T) to every device with SKIP ROM, then waits
+    /// for them all to finish. Returns how long the wait took in µs (see `poll_until_high` for how
+    /// polling is timed)
+    fn convert_all(
+        &mut self,
+        command: u8,
+        wait: ConversionWait<'_, Self::Error>,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u32, Self::Error> {
+        self.send_command(command, None, delay)?;
+        match wait {
+            ConversionWait::Poll { max_micros } => self.poll_until_high(max_micros, delay),
+            ConversionWait::Fixed {
+                micros,
+                strong_pullup,
+            } => {
+                bus_strong_pullup_delay(self, strong_pullup, micros, delay)?;
+                Ok(micros)
+            }
+        }
+    }
+
+    /// Called by device drivers while polling a busy device, where a little extra time doesn't affect
+    /// the bus. `OneWire` calls its idle hook (see `OneWire::set_idle_hook`). Does nothing by default
+    fn idle(&mut self) {}
+
+    /// Called when a crc checked by the bus (in `read_bytes_crc8`, `read_scratchpad`, or a device
+    /// search) doesn't match, before the error is returned. `OneWire` counts these in its stats.
+    /// Does nothing by default
+    fn crc_failed(&mut self, _len: usize) {}
+
+    /// Called when `operation` fails, before its error is returned. The calls are nested, so a pin
+    /// error that stops a search is reported as a `Read`, then as a `Search`. `OneWire` keeps the
+    /// last one (see `OneWire::last_failed_operation`). Does nothing by default
+    fn operation_failed(&mut self, _operation: Operation) {}
+
+    /// Enables or disables the bus's own strong pull-up (see `OneWireBuilder::strong_pullup_pin`),
+    /// which is used when a strong pull-up isn't passed to an operation that needs one. Returns
+    /// false if the bus doesn't have one, which is the default
+    fn set_strong_pullup(&mut self, _enabled: bool) -> OneWireResult<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Switches the bus between standard and overdrive timings. `send_command_with` goes back to
+    /// standard speed before its reset, and switches to overdrive right after an overdrive ROM
+    /// command, so the rest of the transaction runs at overdrive. Does nothing by default
+    fn set_speed(&mut self, _speed: Speed) {}
+
+    /// The speed set with `set_speed`. Always `Speed::Standard` by default
+    fn speed(&self) -> Speed {
+        Speed::Standard
+    }
+
+    /// How long a read slot takes at the bus's current timings, in µs. Used to turn a time limit into
+    /// a number of slots when polling a busy device. The default is `READ_SLOT_DURATION_MICROS`
+    fn read_slot_micros(&self) -> u32 {
+        READ_SLOT_DURATION_MICROS as u32
+    }
+
+    /// One step of a device search, the same as the DS2482's 1-Wire Triplet command. Reads a bit of
+    /// the address and its complement, then writes the bit to follow: if devices with both values
+    /// responded, this is `direction`, otherwise it's the value they sent. Returns the address bit,
+    /// the complement bit (as read from the bus) and the bit that was written.
+    ///
+    /// If no devices responded (both bits are 1), a 1 is written
+    fn triplet(
+        &mut self,
+        direction: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(bool, bool, bool), Self::Error> {
+        let id_bit = self.read_bit(delay)?;
+        let cmp_bit = self.read_bit(delay)?;
+        let taken = triplet_bit(id_bit, cmp_bit, direction);
+        self.write_bit(taken, delay)?;
+        Ok((id_bit, cmp_bit, taken))
+    }
+
+    /// Finds the first device after `address`, in the order of the search, as if a search had just
+    /// found it. This continues a search from a known address (such as one saved in a device
+    /// table) without repeating the passes before it, and `address` doesn't need to still be on
+    /// the bus. The returned state continues the search as normal.
+    ///
+    /// Each branch of the search after `address` that turns out to have no devices on it takes an
+    /// extra pass, so this is only quicker than a new search when it skips several devices
+    fn device_search_after(
+        &mut self,
+        address: &Address,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, Self::Error> {
+        // the devices after `address` share its lowest bits, then take the later branch where it
+        // took the first one
+        let later = order == SearchOrder::Ascending;
+        let mut branches = if later { !address.0 } else { address.0 };
+        while branches != 0 {
+            let index = 63 - branches.leading_zeros() as u8;
+            let prefix = (address.0 ^ (1 << index)) & (2_u64 << index).wrapping_sub(1);
+            let pass = prefix_pass(self, prefix, index + 1, only_alarming, order, delay);
+            match noted(self, Operation::Search, pass)? {
+                PrefixPass::Found(search) => {
+                    return crc_checked(self, Operation::Search, search.finish()).map(Some)
+                }
+                PrefixPass::NoDevices => return Ok(None),
+                PrefixPass::Missed { bit_index, value } => {
+                    // every device on this branch has `value` here, so they're all after `address`
+                    // if that's the later branch, and all before it otherwise
+                    let mask = 1_u64 << bit_index;
+                    branches &= if value == later {
+                        (mask << 1).wrapping_sub(1)
+                    } else {
+                        mask - 1
+                    };
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the first device with the given family code, without the passes of the search for the
+    /// devices before it. The search is continued as normal with the returned state: the devices
+    /// of a family are all found one after another, so there are no more of them once
+    /// `SearchState::last_discrepancy_index` is in the family code (below 8)
+    fn device_search_family(
+        &mut self,
+        family_code: u8,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, Self::Error> {
+        let pass = prefix_pass(self, family_code as u64, 8, only_alarming, order, delay);
+        match noted(self, Operation::Search, pass)? {
+            PrefixPass::Found(search) => {
+                crc_checked(self, Operation::Search, search.finish()).map(Some)
+            }
+            PrefixPass::NoDevices | PrefixPass::Missed { .. } => Ok(None),
+        }
+    }
+
+    /// Checks that the device with `address` still responds, with a pass of the search that only
+    /// follows that address. This takes about as long as finding one device with a search, and
+    /// checks every bit of the address, unlike a MATCH ROM (which a missing device doesn't
+    /// respond to)
+    fn is_device_present(
+        &mut self,
+        address: &Address,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<bool, Self::Error> {
+        let pass = prefix_pass(self, address.0, 64, false, SearchOrder::Ascending, delay);
+        match noted(self, Operation::Search, pass)? {
+            PrefixPass::Found(_) => Ok(true),
+            PrefixPass::NoDevices | PrefixPass::Missed { .. } => Ok(false),
+        }
+    }
+
+    /// Search for device addresses on the bus. This is the same as `OneWire::device_search`
+    fn device_search(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, Self::Error> {
+        self.device_search_ordered(search_state, only_alarming, SearchOrder::Ascending, delay)
+    }
+
+    /// The same as `device_search`, but finds the devices in the given order. Every pass of a
+    /// search must use the same order
+    fn device_search_ordered(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, Self::Error> {
+        self.device_search_with_deadline(search_state, only_alarming, order, delay, &mut NoDeadline)
+    }
+
+    /// The same as `device_search_ordered`, but gives up with `OneWireError::Timeout` once
+    /// `deadline` has passed. The same deadline can be used for every pass, to limit the time of a
+    /// whole search. The other searches use `NoDeadline`
+    fn device_search_with_deadline(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+        deadline: &mut impl Deadline,
+    ) -> OneWireResult<Option<(Address, SearchState)>, Self::Error> {
+        search(
+            self,
+            search_state,
+            only_alarming,
+            order,
+            true,
+            delay,
+            deadline,
+        )
+    }
+
+    /// Finds the addresses of all devices on the bus
+    #[cfg(feature = "alloc")]
+    fn scan(&mut self, delay: &mut impl Delay) -> OneWireResult<Vec<Address>, Self::Error> {
+        let mut addresses = Vec::new();
+        scan::for_each_device(self, false, delay, |address| {
+            addresses.push(address);
+            true
+        })?;
+        Ok(addresses)
+    }
+
+    /// Finds the addresses of all devices on the bus that are in an alarm state
+    #[cfg(feature = "alloc")]
+    fn scan_alarming(
+        &mut self,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Vec<Address>, Self::Error> {
+        let mut addresses = Vec::new();
+        scan::for_each_device(self, true, delay, |address| {
+            addresses.push(address);
+            true
+        })?;
+        Ok(addresses)
+    }
+
+    /// Returns the address of the first device found by a search, or `None` if there are no devices.
+    /// This is mostly for buses with a single device. A search is used instead of READ ROM, so an
+    /// address is still returned correctly if there turn out to be several devices
+    fn first_device(
+        &mut self,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<Address>, Self::Error> {
+        let result = self.device_search(None, false, delay)?;
+        Ok(result.map(|(address, _state)| address))
+    }
+
+    /// Searches the whole bus, and returns how many devices were found
+    fn device_count(
+        &mut self,
+        only_alarming: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<usize, Self::Error> {
+        let mut count = 0;
+        scan::for_each_device(self, only_alarming, delay, |_| {
+            count += 1;
+            true
+        })?;
+        Ok(count)
+    }
+
+    /// Finds the addresses of all devices on the bus, and compares them with the result of a
+    /// previous scan
+    #[cfg(feature = "alloc")]
+    fn scan_diff(
+        &mut self,
+        previous: &[Address],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<ScanDiff, Self::Error> {
+        let current = self.scan(delay)?;
+        Ok(ScanDiff::new(previous, current))
+    }
+
+    /// Finds the addresses of devices on the bus, storing them in `output`.
+    /// Returns the number of devices found. If `output` fills up, the search stops early.
+    fn scan_into(
+        &mut self,
+        output: &mut [Address],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<usize, Self::Error> {
+        if output.is_empty() {
+            return Ok(0);
+        }
+        let mut count = 0;
+        scan::for_each_device(self, false, delay, |address| {
+            output[count] = address;
+            count += 1;
+            count < output.len()
+        })?;
+        Ok(count)
+    }
+}
+
+/// Reports an error in `result` to the bus as a failure of `operation` (see
+/// `OneWireBus::operation_failed`)
+fn noted<B: OneWireBus + ?Sized, R>(
+    bus: &mut B,
+    operation: Operation,
+    result: OneWireResult<R, B::Error>,
+) -> OneWireResult<R, B::Error> {
+    if result.is_err() {
+        bus.operation_failed(operation);
+    }
+    result
+}
+
+/// Reports a crc failure in `result` to the bus (see `OneWireBus::crc_failed`), as a failure of
+/// `operation`
+fn crc_checked<B: OneWireBus + ?Sized, R>(
+    bus: &mut B,
+    operation: Operation,
+    result: OneWireResult<R, B::Error>,
+) -> OneWireResult<R, B::Error> {
+    if let Err(OneWireError::CrcMismatch { len, .. }) = result {
+        bus.crc_failed(len);
+    }
+    noted(bus, operation, result)
+}
+
+/// Reads the inverted crc16 sent by a device, and compares it to `digest` (of `len` bytes). A
+/// mismatch is reported to the bus (see `OneWireBus::crc_failed`)
+pub(crate) fn read_crc16<B: OneWireBus + ?Sized>(
+    bus: &mut B,
+    digest: crc::Crc16Digest,
+    len: usize,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error> {
+    let mut crc = [0; 2];
+    bus.read_bytes(&mut crc, delay)?;
+    let computed = digest.finalize();
+    let received = !u16::from_le_bytes(crc);
+    if computed != received {
+        bus.crc_failed(len);
+        return Err(OneWireError::CrcMismatch {
+            computed,
+            received,
+            len,
+        });
+    }
+    Ok(())
+}
+
+/// One pass of a search, with the crc check of the address that was found if `check_crc` is set
+fn search<B: OneWireBus + ?Sized>(
+    bus: &mut B,
+    search_state: Option<&SearchState>,
+    only_alarming: bool,
+    order: SearchOrder,
+    check_crc: bool,
+    delay: &mut impl Delay,
+    deadline: &mut impl Deadline,
+) -> OneWireResult<Option<(Address, SearchState)>, B::Error> {
+    let pass = search_pass(bus, search_state, only_alarming, order, delay, deadline);
+    match noted(bus, Operation::Search, pass)? {
+        Some(search) if check_crc => crc_checked(bus, Operation::Search, search.finish()).map(Some),
+        Some(search) => Ok(Some(search.finish_unchecked())),
+        None => Ok(None),
+    }
+}
+
+/// The result of a pass of the search that starts with a prefix
+enum PrefixPass {
+    Found(RomSearch),
+
+    /// No devices responded at all
+    NoDevices,
+
+    /// None of the devices have the prefix. Every device that was still being followed at
+    /// `bit_index` has `value` there
+    Missed {
+        bit_index: u8,
+        value: bool,
+    },
+}
+
+/// One pass of a search that only follows the devices with the lowest `len` bits of `prefix`
+fn prefix_pass<B: OneWireBus + ?Sized>(
+    bus: &mut B,
+    prefix: u64,
+    len: u8,
+    only_alarming: bool,
+    order: SearchOrder,
+    delay: &mut impl Delay,
+) -> OneWireResult<PrefixPass, B::Error> {
+    if !bus.reset(delay)? {
+        return Ok(PrefixPass::NoDevices);
+    }
+    if only_alarming {
+        bus.write_rom_command(RomCommand::AlarmSearch, delay)?;
+    } else {
+        bus.write_rom_command(RomCommand::SearchRom, delay)?;
+    }
+
+    let mut search = RomSearch::with_prefix(prefix, len, order);
+    while !search.is_complete() {
+        let bit_index = search.bit_index();
+        let (id_bit, cmp_bit, taken) = bus.triplet(search.direction(), delay)?;
+        match search.step(id_bit, cmp_bit) {
+            Decision::Take(bit) if bit == taken => {}
+            Decision::NoDevices if bit_index == 0 && id_bit && cmp_bit => {
+                return Ok(PrefixPass::NoDevices)
+            }
+            Decision::NoDevices if bit_index < len && id_bit != cmp_bit => {
+                return Ok(PrefixPass::Missed {
+                    bit_index,
+                    value: id_bit,
+                })
+            }
+            _ => return Err(OneWireError::UnexpectedResponse),
+        }
+    }
+    Ok(PrefixPass::Found(search))
+}
+
+/// One pass of a search, up to the end of the address (without checking its crc). Returns `None`
+/// if the search is finished, or no devices responded
+pub(crate) fn search_pass<B: OneWireBus + ?Sized>(
+    bus: &mut B,
+    search_state: Option<&SearchState>,
+    only_alarming: bool,
+    order: SearchOrder,
+    delay: &mut impl Delay,
+    deadline: &mut impl Deadline,
+) -> OneWireResult<Option<RomSearch>, B::Error> {
+    let mut search = match RomSearch::next_pass(search_state, order) {
+        Some(search) => search,
+        None => return Ok(None),
+    };
+
+    deadline.check()?;
+    if !bus.reset(delay)? {
+        return Ok(None);
+    }
+    if only_alarming {
+        bus.write_rom_command(RomCommand::AlarmSearch, delay)?;
+    } else {
+        bus.write_rom_command(RomCommand::SearchRom, delay)?;
+    }
+
+    while !search.is_complete() {
+        deadline.check()?;
+        search.take_triplet(bus.triplet(search.direction(), delay)?)?;
+    }
+    Ok(Some(search))
+}
+
+impl<T, E, P> OneWireBus for OneWire<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    type Error = E;
+
+    fn reset(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        let result = OneWire::reset(self, delay);
+        noted(self, Operation::Reset, result)
+    }
+
+    fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        let result = OneWire::read_bit(self, delay);
+        noted(self, Operation::Read, result)
+    }
+
+    fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let result = OneWire::write_bit(self, value, delay);
+        noted(self, Operation::Write, result)
+    }
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, E> {
+        let result = OneWire::read_byte(self, delay);
+        noted(self, Operation::Read, result)
+    }
+
+    fn write_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let result = OneWire::write_byte(self, value, delay);
+        noted(self, Operation::Write, result)
+    }
+
+    fn touch_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<u8, E> {
+        let result = OneWire::touch_byte(self, value, delay);
+        noted(self, Operation::Write, result)
+    }
+
+    fn idle(&mut self) {
+        OneWire::idle(self)
+    }
+
+    fn set_speed(&mut self, speed: Speed) {
+        OneWire::set_speed(self, speed)
+    }
+
+    fn speed(&self) -> Speed {
+        OneWire::speed(self)
+    }
+
+    fn read_slot_micros(&self) -> u32 {
+        OneWire::read_slot_micros(self)
+    }
+
+    fn crc_failed(&mut self, _len: usize) {
+        count!(self, crc_failures);
+        log_event!(warn, "crc mismatch in {} bytes", _len);
+    }
+
+    fn operation_failed(&mut self, operation: Operation) {
+        self.failed_operation = Some(operation);
+    }
+
+    fn set_strong_pullup(&mut self, enabled: bool) -> OneWireResult<bool, E> {
+        OneWire::set_strong_pullup(self, enabled)
+    }
+
+    fn device_search_with_deadline(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+        deadline: &mut impl Deadline,
+    ) -> OneWireResult<Option<(Address, SearchState)>, E> {
+        self.counted_search(search_state, only_alarming, order, true, delay, deadline)
+    }
+}
+
+impl<T, E, P> OneWire<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    /// A pass of the search, counted in the stats and logged. `DeviceSearch` skips devices without
+    /// checking their crc
+    pub(crate) fn counted_search(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        check_crc: bool,
+        delay: &mut impl Delay,
+        deadline: &mut impl Deadline,
+    ) -> OneWireResult<Option<(Address, SearchState)>, E> {
+        if search_state.is_none() {
+            count!(self, search_restarts);
+        }
+        let result = search(
+            self,
+            search_state,
+            only_alarming,
+            order,
+            check_crc,
+            delay,
+            deadline,
+        );
+        if let Err(OneWireError::UnexpectedResponse) = result {
+            trace!(self, crate::BusEvent::Error);
+        }
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(Some((address, _))) => log::debug!("found device {}", address),
+            Ok(None) => log::debug!("device search finished"),
+            Err(err) => log::warn!("device search failed: {:?}", err.kind()),
+        }
+        result
+    }
+}
@@ -0,0 +1,300 @@
+//! A software logic analyzer, for debugging marginal buses.
+//!
+//! `CaptureBus` wraps another bus, and records every reset, bit and byte into a ring buffer
+//! provided by the caller, which can be dumped after a failure. Bytes are passed to the wrapped bus
+//! whole (so its byte recovery time, stats and idle hook still apply), and recorded as bytes.
+//! Searches and everything else built on these are recorded as their individual operations. Once
+//! the buffer is full, the oldest operations are overwritten.
+
+use crate::delay::Delay;
+use crate::{OneWireBus, OneWireResult, Operation, Speed};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusOperation {
+    /// A reset pulse, and whether a device was present
+    Reset(bool),
+    BitWritten(bool),
+    BitRead(bool),
+    ByteWritten(u8),
+    ByteRead(u8),
+
+    /// A reset or slot that failed (a pin error, or the bus wasn't in the expected state)
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CapturedOperation {
+    pub operation: BusOperation,
+
+    /// The value of the clock when the operation finished, if the bus has one
+    pub timestamp: Option<u32>,
+}
+
+impl Default for CapturedOperation {
+    fn default() -> CapturedOperation {
+        CapturedOperation {
+            operation: BusOperation::Error,
+            timestamp: None,
+        }
+    }
+}
+
+pub struct CaptureBus<'a, B, C = fn() -> u32> {
+    bus: B,
+    clock: Option<C>,
+    buffer: &'a mut [CapturedOperation],
+    // the index the next operation is written to
+    next: usize,
+    len: usize,
+}
+
+impl<'a, B: OneWireBus> CaptureBus<'a, B> {
+    /// Records the operations of `bus` into `buffer`, without timestamps
+    pub fn new(bus: B, buffer: &'a mut [CapturedOperation]) -> CaptureBus<'a, B> {
+        CaptureBus {
+            bus,
+            clock: None,
+            buffer,
+            next: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<'a, B: OneWireBus, C: FnMut() -> u32> CaptureBus<'a, B, C> {
+    /// Records the operations of `bus` into `buffer`, timestamped by `clock`. The timestamps are
+    /// in whatever unit the clock counts in (a free-running µs timer works well)
+    pub fn with_clock(
+        bus: B,
+        buffer: &'a mut [CapturedOperation],
+        clock: C,
+    ) -> CaptureBus<'a, B, C> {
+        CaptureBus {
+            bus,
+            clock: Some(clock),
+            buffer,
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    /// The recorded operations, oldest first
+    pub fn captured(&self) -> impl Iterator<Item = &CapturedOperation> + '_ {
+        let start = (self.next + self.buffer.len() - self.len) % self.buffer.len().max(1);
+        self.buffer[start..]
+            .iter()
+            .chain(self.buffer[..start].iter())
+            .take(self.len)
+    }
+
+    /// Discards the recorded operations
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    fn record<V>(
+        &mut self,
+        result: OneWireResult<V, B::Error>,
+        operation: impl FnOnce(&V) -> BusOperation,
+    ) -> OneWireResult<V, B::Error> {
+        if self.buffer.is_empty() {
+            return result;
+        }
+        let operation = match &result {
+            Ok(value) => operation(value),
+            Err(_) => BusOperation::Error,
+        };
+        self.buffer[self.next] = CapturedOperation {
+            operation,
+            timestamp: self.clock.as_mut().map(|clock| clock()),
+        };
+        self.next = (self.next + 1) % self.buffer.len();
+        self.len = (self.len + 1).min(self.buffer.len());
+        result
+    }
+}
+
+impl<'a, B: OneWireBus, C: FnMut() -> u32> OneWireBus for CaptureBus<'a, B, C> {
+    type Error = B::Error;
+
+    fn reset(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        let result = self.bus.reset(delay);
+        self.record(result, |present| BusOperation::Reset(*present))
+    }
+
+    fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        let result = self.bus.read_bit(delay);
+        self.record(result, |value| BusOperation::BitRead(*value))
+    }
+
+    fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        let result = self.bus.write_bit(value, delay);
+        self.record(result, |_| BusOperation::BitWritten(value))
+    }
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        let result = self.bus.read_byte(delay);
+        self.record(result, |value| BusOperation::ByteRead(*value))
+    }
+
+    fn write_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        let result = self.bus.write_byte(value, delay);
+        self.record(result, |_| BusOperation::ByteWritten(value))
+    }
+
+    /// Recorded as the individual slots, the same as `touch_bit`: a 1 is a bit read, and a 0 is a
+    /// bit written
+    fn touch_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        let read = match self.bus.touch_byte(value, delay) {
+            Ok(read) => read,
+            Err(err) => return self.record(Err(err), |_| BusOperation::Error),
+        };
+        for bit_index in 0..8 {
+            let mask = 1 << bit_index;
+            let operation = if value & mask != 0 {
+                BusOperation::BitRead(read & mask != 0)
+            } else {
+                BusOperation::BitWritten(false)
+            };
+            self.record(Ok(()), |_| operation)?;
+        }
+        Ok(read)
+    }
+
+    fn idle(&mut self) {
+        self.bus.idle()
+    }
+
+    fn crc_failed(&mut self, len: usize) {
+        self.bus.crc_failed(len)
+    }
+
+    fn set_speed(&mut self, speed: Speed) {
+        self.bus.set_speed(speed)
+    }
+
+    fn speed(&self) -> Speed {
+        self.bus.speed()
+    }
+
+    fn operation_failed(&mut self, operation: Operation) {
+        self.bus.operation_failed(operation)
+    }
+
+    fn set_strong_pullup(&mut self, enabled: bool) -> OneWireResult<bool, B::Error> {
+        self.bus.set_strong_pullup(enabled)
+    }
+
+    fn read_slot_micros(&self) -> u32 {
+        self.bus.read_slot_micros()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::capture::{BusOperation, CaptureBus, CapturedOperation};
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    use crate::{commands, Address, OneWire, OneWireBus};
+    use core::time::Duration;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_capture() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut buffer = [CapturedOperation::default(); 4];
+        let mut time = 0;
+        let mut bus =
+            CaptureBus::with_clock(OneWire::new(simulator.pin()).unwrap(), &mut buffer, || {
+                time += 1;
+                time
+            });
+
+        assert_eq!(bus.reset(&mut delay), Ok(true));
+        bus.write_bits(0b110, 3, &mut delay).unwrap();
+        let captured: Vec<_> = bus.captured().copied().collect();
+        assert_eq!(
+            captured,
+            [
+                CapturedOperation {
+                    operation: BusOperation::Reset(true),
+                    timestamp: Some(1)
+                },
+                CapturedOperation {
+                    operation: BusOperation::BitWritten(false),
+                    timestamp: Some(2)
+                },
+                CapturedOperation {
+                    operation: BusOperation::BitWritten(true),
+                    timestamp: Some(3)
+                },
+                CapturedOperation {
+                    operation: BusOperation::BitWritten(true),
+                    timestamp: Some(4)
+                },
+            ]
+        );
+
+        // the oldest operation is overwritten once the buffer is full
+        bus.read_bit(&mut delay).unwrap();
+        let operations: Vec<_> = bus.captured().map(|captured| captured.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                BusOperation::BitWritten(false),
+                BusOperation::BitWritten(true),
+                BusOperation::BitWritten(true),
+                BusOperation::BitRead(true),
+            ]
+        );
+
+        bus.clear();
+        assert_eq!(bus.captured().count(), 0);
+    }
+
+    #[test]
+    fn test_wrapped_bus_settings() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0xA5];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(Timings {
+            byte_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+        let mut buffer = [CapturedOperation::default(); 8];
+        let mut bus = CaptureBus::new(one_wire, &mut buffer);
+
+        bus.send_command(commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        let start = simulator.time_micros();
+        assert_eq!(bus.read_byte(&mut delay), Ok(0xA5));
+        // the byte recovery time of the wrapped bus still applies
+        let slot = Timings::STANDARD.read_slot().as_micros() as u64;
+        assert_eq!(simulator.time_micros() - start, 8 * slot + 100);
+
+        let operations: Vec<_> = bus.captured().map(|captured| captured.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                BusOperation::Reset(true),
+                BusOperation::ByteWritten(0xCC),
+                BusOperation::ByteWritten(commands::READ_SCRATCHPAD),
+                BusOperation::ByteRead(0xA5),
+            ]
+        );
+        #[cfg(feature = "stats")]
+        assert_eq!(bus.into_inner().stats().bytes_read, 1);
+    }
+}
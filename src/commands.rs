@@ -1,5 +1,72 @@
+use crate::Address;
+
 pub const SEARCH_NORMAL: u8 = 0xF0;
 pub const MATCH_ROM: u8 = 0x55;
 pub const SKIP_ROM: u8 = 0xCC;
 pub const SEARCH_ALARM: u8 = 0xEC;
 pub const READ_POWER_SUPPLY: u8 = 0xB4;
+pub const READ_ROM: u8 = 0x33;
+pub const RESUME: u8 = 0xA5;
+pub const OVERDRIVE_SKIP_ROM: u8 = 0x3C;
+pub const OVERDRIVE_MATCH_ROM: u8 = 0x69;
+
+/// The ROM commands, which are sent after a reset to select devices. Unlike the constants, these
+/// can't be mixed up with function commands
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RomCommand {
+    SearchRom,
+    ReadRom,
+    MatchRom,
+    SkipRom,
+    AlarmSearch,
+
+    /// Addresses the device that was last addressed with MATCH ROM (or found by a search) again,
+    /// without sending its address
+    Resume,
+
+    /// The same as SKIP ROM, but also switches the devices to overdrive speed
+    OverdriveSkip,
+
+    /// The same as MATCH ROM, but also switches the device to overdrive speed. The address is
+    /// already sent at overdrive speed
+    OverdriveMatch,
+}
+
+impl From<RomCommand> for u8 {
+    fn from(command: RomCommand) -> u8 {
+        match command {
+            RomCommand::SearchRom => SEARCH_NORMAL,
+            RomCommand::ReadRom => READ_ROM,
+            RomCommand::MatchRom => MATCH_ROM,
+            RomCommand::SkipRom => SKIP_ROM,
+            RomCommand::AlarmSearch => SEARCH_ALARM,
+            RomCommand::Resume => RESUME,
+            RomCommand::OverdriveSkip => OVERDRIVE_SKIP_ROM,
+            RomCommand::OverdriveMatch => OVERDRIVE_MATCH_ROM,
+        }
+    }
+}
+
+/// How `send_command_with` addresses devices before sending the function command.
+///
+/// The overdrive variants switch the devices to overdrive speed, and the bus along with them (see
+/// `OneWireBus::set_speed`). The bus stays at overdrive until the next `send_command_with`, which
+/// starts with a standard speed reset
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Addressing<'a> {
+    /// All devices (SKIP ROM)
+    Skip,
+
+    /// A single device (MATCH ROM)
+    Match(&'a Address),
+
+    /// The device that was addressed last (RESUME)
+    Resume,
+
+    OverdriveSkip,
+    OverdriveMatch(&'a Address),
+}
+
+// Function commands used by most devices
+pub const READ_SCRATCHPAD: u8 = 0xBE;
+pub const WRITE_SCRATCHPAD: u8 = 0x4E;
@@ -2,19 +2,9 @@ use crate::error::{OneWireError, OneWireResult};
 
 /// Calculates the crc8 of the input data.
 pub fn crc8(data: &[u8]) -> u8 {
-    let mut crc = 0;
-    for byte in data {
-        let mut byte = *byte;
-        for _ in 0..8 {
-            let x = (byte ^ crc) & 0x01;
-            crc >>= 1;
-            if x != 0 {
-                crc ^= 0x8C;
-            }
-            byte >>= 1;
-        }
-    }
-    crc
+    let mut digest = Crc8Digest::new();
+    digest.update(data);
+    digest.finalize()
 }
 
 /// Checks to see if data (including the crc byte) passes the crc check.
@@ -25,13 +15,152 @@ pub fn check_crc8<E>(data: &[u8]) -> OneWireResult<(), E> {
     if crc8(data) == 0 {
         Ok(())
     } else {
-        Err(OneWireError::CrcMismatch)
+        // the crc of no data is 0, so there is always a crc byte here
+        let (data, crc) = data.split_at(data.len() - 1);
+        Err(OneWireError::CrcMismatch {
+            computed: crc8(data) as u16,
+            received: crc[0] as u16,
+            len: data.len(),
+        })
+    }
+}
+
+/// Calculates a crc8 incrementally, so bytes can be added as they are read from the bus
+/// instead of first buffering the whole frame.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Crc8Digest {
+    crc: u8,
+}
+
+impl Crc8Digest {
+    pub fn new() -> Crc8Digest {
+        Crc8Digest { crc: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = crc8_byte(self.crc, *byte);
+        }
+    }
+
+    /// Returns the crc of all data passed to `update`. If the data included the crc byte,
+    /// this will be 0 when the crc check passes
+    pub fn finalize(self) -> u8 {
+        self.crc
+    }
+}
+
+const fn crc8_bitwise(crc: u8, byte: u8) -> u8 {
+    let mut crc = crc;
+    let mut byte = byte;
+    let mut i = 0;
+    while i < 8 {
+        let x = (byte ^ crc) & 0x01;
+        crc >>= 1;
+        if x != 0 {
+            crc ^= 0x8C;
+        }
+        byte >>= 1;
+        i += 1;
+    }
+    crc
+}
+
+#[cfg(not(feature = "crc8-table"))]
+fn crc8_byte(crc: u8, byte: u8) -> u8 {
+    crc8_bitwise(crc, byte)
+}
+
+/// Lookup table of the crc8 of every byte value. Enabled with the `crc8-table` feature, this trades
+/// 256 bytes of flash for a much faster crc8 when reading long memory pages.
+#[cfg(feature = "crc8-table")]
+const CRC8_TABLE: [u8; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = crc8_bitwise(0, i as u8);
+        i += 1;
+    }
+    table
+};
+
+#[cfg(feature = "crc8-table")]
+fn crc8_byte(crc: u8, byte: u8) -> u8 {
+    CRC8_TABLE[(crc ^ byte) as usize]
+}
+
+/// Calculates the crc16 of the input data, as used by memory devices (DS2433, DS28EC20, etc).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut digest = Crc16Digest::new();
+    digest.update(data);
+    digest.finalize()
+}
+
+/// Checks to see if data (including the 2 crc bytes) passes the crc16 check.
+///
+/// Devices send the inverted crc16, least significant byte first. Including an inverted crc in the
+/// data always results in a crc of `0xB001`, so like `check_crc8` it's not needed to separate
+/// the data from the crc value
+pub fn check_crc16<E>(data: &[u8]) -> OneWireResult<(), E> {
+    if crc16(data) == 0xB001 {
+        Ok(())
+    } else {
+        let (data, crc) = data.split_at(data.len().saturating_sub(2));
+        let mut received = [0xFF; 2];
+        received[..crc.len()].copy_from_slice(crc);
+        Err(OneWireError::CrcMismatch {
+            computed: crc16(data),
+            received: !u16::from_le_bytes(received),
+            len: data.len(),
+        })
+    }
+}
+
+/// Calculates a crc16 incrementally. This is useful when the crc covers bytes that were
+/// written to the bus (such as the command and target address) as well as the bytes that were read.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Crc16Digest {
+    crc: u16,
+}
+
+impl Crc16Digest {
+    pub fn new() -> Crc16Digest {
+        Crc16Digest { crc: 0 }
+    }
+
+    /// Starts from `crc` instead of 0. The 1-Wire File Structure starts the crc of each page from
+    /// the page number
+    pub fn with_initial(crc: u16) -> Crc16Digest {
+        Crc16Digest { crc }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            let mut byte = *byte;
+            for _ in 0..8 {
+                let x = (byte as u16 ^ self.crc) & 0x01;
+                self.crc >>= 1;
+                if x != 0 {
+                    self.crc ^= 0xA001;
+                }
+                byte >>= 1;
+            }
+        }
+    }
+
+    /// Returns the crc of all data passed to `update`. This is the non-inverted value,
+    /// so it must be inverted before comparing to the value sent by a device
+    pub fn finalize(self) -> u16 {
+        self.crc
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::crc::crc8;
+    use crate::crc::{check_crc16, check_crc8, crc16, crc8, Crc16Digest, Crc8Digest};
+    #[cfg(feature = "crc8-table")]
+    use crate::crc::{crc8_bitwise, crc8_byte};
+    use crate::OneWireError;
 
     #[test]
     fn test_crc8() {
@@ -44,4 +173,61 @@ mod test {
         assert_eq!(crc8(&[95, 1, 75, 70, 127, 255, 1, 16]), 155);
         assert_eq!(crc8(&[95, 1, 75, 70, 127, 255, 1, 16, 155]), 0);
     }
+
+    #[cfg(feature = "crc8-table")]
+    #[test]
+    fn test_crc8_table() {
+        for crc in 0..=255 {
+            for byte in 0..=255 {
+                assert_eq!(crc8_byte(crc, byte), crc8_bitwise(crc, byte));
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc8_digest() {
+        let mut digest = Crc8Digest::new();
+        digest.update(&[99, 1, 75, 70]);
+        digest.update(&[127, 255, 13, 16]);
+        assert_eq!(digest.finalize(), 21);
+
+        digest.update(&[21]);
+        assert_eq!(digest.finalize(), 0);
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+        assert_eq!(crc16(&[0x0F, 0x00, 0x00, 0x12, 0x34]), 0x7659);
+
+        assert!(check_crc16::<()>(&[0x0F, 0x00, 0x00, 0x12, 0x34, 0xA6, 0x89]).is_ok());
+        assert_eq!(
+            check_crc16::<()>(&[0x0F, 0x00, 0x00, 0x12, 0x35, 0xA6, 0x89]),
+            Err(OneWireError::CrcMismatch {
+                computed: crc16(&[0x0F, 0x00, 0x00, 0x12, 0x35]),
+                received: 0x7659,
+                len: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_crc8() {
+        assert_eq!(
+            check_crc8::<()>(&[99, 1, 75, 70, 127, 255, 13, 16, 20]),
+            Err(OneWireError::CrcMismatch {
+                computed: 21,
+                received: 20,
+                len: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn test_crc16_digest() {
+        let mut digest = Crc16Digest::new();
+        digest.update(b"1234");
+        digest.update(b"56789");
+        assert_eq!(digest.finalize(), 0xBB3D);
+    }
 }
@@ -0,0 +1,181 @@
+//! Deadlines for long-running operations (searches, and memory reads), so they give up with
+//! `OneWireError::Timeout` instead of holding the CPU indefinitely when the bus misbehaves.
+//!
+//! A deadline is only checked between slots (or bytes), so an operation can run over it by the
+//! length of one slot.
+
+use crate::timing::PreciseTimer;
+use crate::{OneWireError, OneWireResult};
+
+pub trait Deadline {
+    /// Returns true once the deadline has passed
+    fn has_passed(&mut self) -> bool;
+
+    /// The time that was allowed, in µs. This is the value returned in `OneWireError::Timeout`
+    fn limit_micros(&self) -> u32;
+
+    /// Returns `OneWireError::Timeout` if the deadline has passed
+    fn check<E>(&mut self) -> OneWireResult<(), E> {
+        if self.has_passed() {
+            return Err(OneWireError::Timeout(self.limit_micros()));
+        }
+        Ok(())
+    }
+}
+
+/// A deadline that never passes, for the operations that can be given a deadline but don't have one
+pub struct NoDeadline;
+
+impl Deadline for NoDeadline {
+    fn has_passed(&mut self) -> bool {
+        false
+    }
+
+    fn limit_micros(&self) -> u32 {
+        u32::MAX
+    }
+}
+
+/// A deadline measured with a `PreciseTimer`. The limit must be shorter than the time it takes the
+/// timer to wrap around
+pub struct TimerDeadline<C> {
+    timer: C,
+    start: u32,
+    limit_micros: u32,
+}
+
+impl<C: PreciseTimer> TimerDeadline<C> {
+    /// Starts a deadline that passes `limit_micros` from now
+    pub fn new(mut timer: C, limit_micros: u32) -> TimerDeadline<C> {
+        let start = timer.now();
+        TimerDeadline {
+            timer,
+            start,
+            limit_micros,
+        }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.timer
+    }
+}
+
+impl<C: PreciseTimer> Deadline for TimerDeadline<C> {
+    fn has_passed(&mut self) -> bool {
+        let ticks = self
+            .limit_micros
+            .saturating_mul(self.timer.ticks_per_micro());
+        self.timer.now().wrapping_sub(self.start) >= ticks
+    }
+
+    fn limit_micros(&self) -> u32 {
+        self.limit_micros
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::deadline::{Deadline, TimerDeadline};
+    use crate::memory;
+    use crate::search::SearchOrder;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::timing::PreciseTimer;
+    use crate::{Address, OneWire, OneWireBus, OneWireError};
+
+    struct SimulatedTimer<'a>(&'a Simulator);
+
+    impl<'a> PreciseTimer for SimulatedTimer<'a> {
+        fn now(&mut self) -> u32 {
+            self.0.time_micros() as u32
+        }
+
+        fn ticks_per_micro(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_search_deadline() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x7000_0000_0000_0228)));
+        simulator.add_device(VirtualDevice::new(Address(0x2900_0000_0000_0128)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // a single pass of the search takes about 15 ms
+        let mut deadline = TimerDeadline::new(SimulatedTimer(&simulator), 5_000);
+        assert_eq!(
+            one_wire.device_search_with_deadline(
+                None,
+                false,
+                SearchOrder::Ascending,
+                &mut delay,
+                &mut deadline
+            ),
+            Err(OneWireError::Timeout(5_000))
+        );
+        assert!(deadline.has_passed());
+
+        let mut deadline = TimerDeadline::new(SimulatedTimer(&simulator), 50_000);
+        let (address, state) = one_wire
+            .device_search_with_deadline(
+                None,
+                false,
+                SearchOrder::Ascending,
+                &mut delay,
+                &mut deadline,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(address, Address(0x7000_0000_0000_0228));
+        let (address, _) = one_wire
+            .device_search_with_deadline(
+                Some(&state),
+                false,
+                SearchOrder::Ascending,
+                &mut delay,
+                &mut deadline,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(address, Address(0x2900_0000_0000_0128));
+
+        // the search order is kept, and the pass goes through the bus's own search
+        let mut deadline = TimerDeadline::new(SimulatedTimer(&simulator), 50_000);
+        let (address, _) = one_wire
+            .device_search_with_deadline(
+                None,
+                false,
+                SearchOrder::Descending,
+                &mut delay,
+                &mut deadline,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(address, Address(0x2900_0000_0000_0128));
+        #[cfg(feature = "stats")]
+        assert_eq!(one_wire.stats().search_restarts, 3);
+    }
+
+    #[test]
+    fn test_memory_deadline() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut deadline = TimerDeadline::new(SimulatedTimer(&simulator), 0);
+        let mut output = [0; 4];
+        assert_eq!(
+            memory::read_memory_with_deadline(
+                None,
+                0,
+                &mut output,
+                &mut one_wire,
+                &mut delay,
+                &mut deadline
+            ),
+            Err(OneWireError::Timeout(0))
+        );
+    }
+}
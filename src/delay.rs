@@ -0,0 +1,110 @@
+//! The delay every bus operation takes.
+//!
+//! `Delay` is implemented for every `DelayUs<u32>`, which nearly every embedded-hal 0.2 HAL
+//! provides, so a HAL's delay can be passed straight in. Delays that only implement
+//! `DelayUs<u16>`, or the embedded-hal 1.0 `DelayNs`, are wrapped in `DelayU16` or `DelayNs`.
+//! Those can't be covered by more blanket impls, since a type could implement several of the
+//! traits, and the impls would overlap.
+//!
+//! A delay that only implements `DelayMs` can't be used, since the time slots need microsecond
+//! resolution.
+
+use embedded_hal::blocking::delay::DelayUs;
+
+pub trait Delay {
+    /// Waits for at least `us` microseconds
+    fn delay_us(&mut self, us: u16);
+}
+
+impl<D: DelayUs<u32>> Delay for D {
+    fn delay_us(&mut self, us: u16) {
+        DelayUs::delay_us(self, us as u32);
+    }
+}
+
+/// Adapts a delay that only implements `DelayUs<u16>`
+#[derive(Debug, Copy, Clone)]
+pub struct DelayU16<D>(pub D);
+
+impl<D: DelayUs<u16>> Delay for DelayU16<D> {
+    fn delay_us(&mut self, us: u16) {
+        self.0.delay_us(us);
+    }
+}
+
+/// Adapts an embedded-hal 1.0 `DelayNs`
+#[cfg(feature = "embedded-hal-1")]
+#[derive(Debug, Copy, Clone)]
+pub struct DelayNs<D>(pub D);
+
+#[cfg(feature = "embedded-hal-1")]
+impl<D: embedded_hal_1::delay::DelayNs> Delay for DelayNs<D> {
+    fn delay_us(&mut self, us: u16) {
+        self.0.delay_us(us as u32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::delay::DelayU16;
+    use crate::simulator::{SimulatedDelay, Simulator, VirtualDevice};
+    use crate::{Address, OneWire};
+    use embedded_hal::blocking::delay::DelayUs;
+
+    /// A delay that only implements `DelayUs<u32>`, like most HALs
+    struct Delay32<'a>(SimulatedDelay<'a>);
+
+    impl<'a> DelayUs<u32> for Delay32<'a> {
+        fn delay_us(&mut self, us: u32) {
+            self.0.delay_us(us);
+        }
+    }
+
+    /// A delay that only implements `DelayUs<u16>`
+    struct Delay16<'a>(SimulatedDelay<'a>);
+
+    impl<'a> DelayUs<u16> for Delay16<'a> {
+        fn delay_us(&mut self, us: u16) {
+            self.0.delay_us(us as u32);
+        }
+    }
+
+    #[test]
+    fn test_delay_u32() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = Delay32(simulator.delay());
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+    }
+
+    #[test]
+    fn test_delay_u16() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = DelayU16(Delay16(simulator.delay()));
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+    }
+
+    #[cfg(feature = "embedded-hal-1")]
+    #[test]
+    fn test_delay_ns() {
+        use crate::delay::DelayNs;
+
+        /// An embedded-hal 1.0 delay
+        struct Delay1<'a>(SimulatedDelay<'a>);
+
+        impl<'a> embedded_hal_1::delay::DelayNs for Delay1<'a> {
+            fn delay_ns(&mut self, ns: u32) {
+                self.0.delay_us(ns.div_ceil(1000));
+            }
+        }
+
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = DelayNs(Delay1(simulator.delay()));
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+    }
+}
@@ -0,0 +1,154 @@
+//! A bus that owns its delay, so the operations don't take one.
+//!
+//! This is easier to store inside another driver than a bus and a delay that have to be passed to
+//! every call. `DelayedBus::parts` gives both back for functions (such as the device drivers) that
+//! take them separately.
+
+use crate::delay::Delay;
+use crate::pullup::StrongPullupPin;
+use crate::{Address, DeviceSearch, OneWire, OneWireBus, OneWireResult, SearchState};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+pub struct DelayedBus<B, D> {
+    bus: B,
+    delay: D,
+}
+
+impl<T, E> OneWire<T>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+{
+    /// Creates a bus that owns `delay`, so it doesn't have to be passed to each operation
+    pub fn with_delay<D: Delay>(pin: T, delay: D) -> OneWireResult<DelayedBus<OneWire<T>, D>, E> {
+        Ok(DelayedBus::new(OneWire::new(pin)?, delay))
+    }
+}
+
+impl<B: OneWireBus, D: Delay> DelayedBus<B, D> {
+    pub fn new(bus: B, delay: D) -> DelayedBus<B, D> {
+        DelayedBus { bus, delay }
+    }
+
+    pub fn into_inner(self) -> (B, D) {
+        (self.bus, self.delay)
+    }
+
+    /// Borrows the bus and the delay separately, for functions that take both
+    pub fn parts(&mut self) -> (&mut B, &mut D) {
+        (&mut self.bus, &mut self.delay)
+    }
+
+    pub fn reset(&mut self) -> OneWireResult<bool, B::Error> {
+        self.bus.reset(&mut self.delay)
+    }
+
+    pub fn read_bit(&mut self) -> OneWireResult<bool, B::Error> {
+        self.bus.read_bit(&mut self.delay)
+    }
+
+    pub fn read_byte(&mut self) -> OneWireResult<u8, B::Error> {
+        self.bus.read_byte(&mut self.delay)
+    }
+
+    pub fn read_bytes(&mut self, output: &mut [u8]) -> OneWireResult<(), B::Error> {
+        self.bus.read_bytes(output, &mut self.delay)
+    }
+
+    pub fn write_bit(&mut self, value: bool) -> OneWireResult<(), B::Error> {
+        self.bus.write_bit(value, &mut self.delay)
+    }
+
+    pub fn write_byte(&mut self, value: u8) -> OneWireResult<(), B::Error> {
+        self.bus.write_byte(value, &mut self.delay)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> OneWireResult<(), B::Error> {
+        self.bus.write_bytes(bytes, &mut self.delay)
+    }
+
+    pub fn match_address(&mut self, address: &Address) -> OneWireResult<(), B::Error> {
+        self.bus.match_address(address, &mut self.delay)
+    }
+
+    pub fn skip_address(&mut self) -> OneWireResult<(), B::Error> {
+        self.bus.skip_address(&mut self.delay)
+    }
+
+    /// See `OneWireBus::send_command`
+    pub fn send_command(
+        &mut self,
+        command: u8,
+        address: Option<&Address>,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.send_command(command, address, &mut self.delay)
+    }
+
+    /// See `OneWireBus::read_scratchpad`
+    pub fn read_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        output: &mut [u8],
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.read_scratchpad(address, output, &mut self.delay)
+    }
+
+    /// See `OneWireBus::write_scratchpad`
+    pub fn write_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        data: &[u8],
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.write_scratchpad(address, data, &mut self.delay)
+    }
+
+    /// See `OneWire::device_search`
+    pub fn device_search(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+    ) -> OneWireResult<Option<(Address, SearchState)>, B::Error> {
+        self.bus
+            .device_search(search_state, only_alarming, &mut self.delay)
+    }
+}
+
+impl<T, E, D, P> DelayedBus<OneWire<T, P>, D>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    D: Delay,
+    P: StrongPullupPin<E>,
+{
+    /// See `OneWire::devices`
+    pub fn devices(&mut self, only_alarming: bool) -> DeviceSearch<'_, '_, T, D, P> {
+        self.bus.devices(only_alarming, &mut self.delay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWire};
+
+    #[test]
+    fn test_delayed_bus() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x7000_0000_0000_0228));
+        device.scratchpad = std::vec![0x12];
+        simulator.add_device(device);
+        let mut bus = OneWire::with_delay(simulator.pin(), simulator.delay()).unwrap();
+
+        let devices: std::vec::Vec<_> = bus.devices(false).map(Result::unwrap).collect();
+        assert_eq!(devices, [Address(0x7000_0000_0000_0228)]);
+
+        bus.send_command(commands::READ_SCRATCHPAD, Some(&devices[0]))
+            .unwrap();
+        assert_eq!(bus.read_byte(), Ok(0x12));
+
+        let (one_wire, delay) = bus.parts();
+        assert_eq!(one_wire.reset(delay), Ok(true));
+    }
+}
@@ -0,0 +1,334 @@
+//! Driver for the DS18B20 digital thermometer
+
+use crate::delay::Delay;
+use crate::pullup::delay_micros;
+use crate::{Address, Family, OneWireBus, OneWireResult, TypedAddress};
+
+pub const FAMILY_CODE: u8 = 0x28;
+
+pub mod commands {
+    pub const CONVERT_TEMP: u8 = 0x44;
+    pub const WRITE_SCRATCHPAD: u8 = 0x4E;
+    pub const READ_SCRATCHPAD: u8 = 0xBE;
+    pub const COPY_SCRATCHPAD: u8 = 0x48;
+    pub const RECALL_EEPROM: u8 = 0xB8;
+}
+
+/// Recalling the eeprom takes up to 10ms
+const RECALL_EEPROM_TIMEOUT_MICROS: u16 = 10_000;
+
+/// The resolution of temperature measurements. Higher resolutions take longer to measure
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Resolution {
+    Bits9 = 0b0001_1111,
+    Bits10 = 0b0011_1111,
+    Bits11 = 0b0101_1111,
+    Bits12 = 0b0111_1111,
+}
+
+impl Resolution {
+    /// The maximum time a temperature measurement can take at this resolution
+    pub fn max_measurement_time_millis(&self) -> u16 {
+        match self {
+            Resolution::Bits9 => 94,
+            Resolution::Bits10 => 188,
+            Resolution::Bits11 => 375,
+            Resolution::Bits12 => 750,
+        }
+    }
+
+    /// Reads the resolution from the configuration register byte of the scratchpad
+    pub fn from_config_register(config: u8) -> Resolution {
+        match (config >> 5) & 0b11 {
+            0b00 => Resolution::Bits9,
+            0b01 => Resolution::Bits10,
+            0b10 => Resolution::Bits11,
+            _ => Resolution::Bits12,
+        }
+    }
+
+    pub fn to_config_register(self) -> u8 {
+        self as u8
+    }
+}
+
+/// All of the data that can be read from the scratchpad
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SensorData {
+    /// The temperature in degrees Celsius, from the last measurement
+    pub temperature: f32,
+
+    pub resolution: Resolution,
+
+    /// The device is alarming if the temperature is less than or equal to this value
+    pub alarm_temp_low: i8,
+
+    /// The device is alarming if the temperature is greater than or equal to this value
+    pub alarm_temp_high: i8,
+}
+
+impl SensorData {
+    /// Decodes the scratchpad contents. The crc should already have been checked
+    pub fn from_scratchpad(scratchpad: &[u8; 9]) -> SensorData {
+        let resolution = Resolution::from_config_register(scratchpad[4]);
+        SensorData {
+            temperature: decode_temperature([scratchpad[0], scratchpad[1]], resolution),
+            resolution,
+            alarm_temp_high: scratchpad[2] as i8,
+            alarm_temp_low: scratchpad[3] as i8,
+        }
+    }
+}
+
+/// Converts the raw temperature bytes (least significant byte first) into degrees Celsius.
+/// Bits that are undefined at lower resolutions are ignored
+pub fn decode_temperature(raw: [u8; 2], resolution: Resolution) -> f32 {
+    let undefined_bits = match resolution {
+        Resolution::Bits9 => 0b111,
+        Resolution::Bits10 => 0b011,
+        Resolution::Bits11 => 0b001,
+        Resolution::Bits12 => 0b000,
+    };
+    let raw = i16::from_le_bytes(raw) & !undefined_bits;
+    raw as f32 / 16.0
+}
+
+pub struct Ds18b20 {
+    address: Address,
+}
+
+impl Family for Ds18b20 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds18b20>> for Ds18b20 {
+    fn from(address: TypedAddress<Ds18b20>) -> Ds18b20 {
+        Ds18b20 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds18b20 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds18b20, E> {
+        Ok(Ds18b20::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Starts a temperature measurement for just this device.
+    /// You should wait for the measurement to finish before reading the measurement.
+    /// The amount of time you need to wait depends on the current resolution configuration
+    pub fn start_temp_measurement<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::CONVERT_TEMP, Some(&self.address), delay)
+    }
+
+    pub fn read_data<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<SensorData, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let scratchpad = read_scratchpad(&self.address, onewire, delay)?;
+        Ok(SensorData::from_scratchpad(&scratchpad))
+    }
+
+    /// Writes the alarm thresholds and resolution to the scratchpad. These are lost on power loss
+    /// unless saved with `save_to_eeprom`
+    pub fn set_config<B>(
+        &self,
+        alarm_temp_low: i8,
+        alarm_temp_high: i8,
+        resolution: Resolution,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.write_scratchpad(
+            Some(&self.address),
+            &[
+                alarm_temp_high as u8,
+                alarm_temp_low as u8,
+                resolution.to_config_register(),
+            ],
+            delay,
+        )
+    }
+
+    /// Copies the alarm thresholds and resolution from the scratchpad to eeprom
+    pub fn save_to_eeprom<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::COPY_SCRATCHPAD, Some(&self.address), delay)?;
+        delay_micros(10_000, delay, || onewire.idle()); // Maxim recommended wait time
+        Ok(())
+    }
+
+    /// Copies the alarm thresholds and resolution from eeprom to the scratchpad
+    pub fn recall_from_eeprom<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::RECALL_EEPROM, Some(&self.address), delay)?;
+        // the device responds with 0 until the recall is finished
+        onewire.poll_until_high(RECALL_EEPROM_TIMEOUT_MICROS as u32, delay)?;
+        Ok(())
+    }
+}
+
+/// Starts a temperature measurement for all devices on this bus simultaneously
+pub fn start_simultaneous_temp_measurement<B>(
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    onewire.send_command(commands::CONVERT_TEMP, None, delay)
+}
+
+/// Reads the full scratchpad of a device, and verifies its crc
+pub fn read_scratchpad<B>(
+    address: &Address,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<[u8; 9], B::Error>
+where
+    B: OneWireBus,
+{
+    let mut scratchpad = [0; 9];
+    onewire.read_scratchpad(Some(address), &mut scratchpad, delay)?;
+    Ok(scratchpad)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crc::crc8;
+    use crate::devices::ds18b20::{
+        commands, decode_temperature, Ds18b20, Resolution, SensorData, RECALL_EEPROM_TIMEOUT_MICROS,
+    };
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::{Address, OneWireError, READ_SLOT_DURATION_MICROS};
+
+    #[test]
+    fn test_decode_temperature() {
+        assert_eq!(decode_temperature([0xD0, 0x07], Resolution::Bits12), 125.0);
+        assert_eq!(
+            decode_temperature([0x91, 0x01], Resolution::Bits12),
+            25.0625
+        );
+        assert_eq!(decode_temperature([0x00, 0x00], Resolution::Bits12), 0.0);
+        assert_eq!(
+            decode_temperature([0x5E, 0xFF], Resolution::Bits12),
+            -10.125
+        );
+        assert_eq!(decode_temperature([0x90, 0xFC], Resolution::Bits12), -55.0);
+
+        assert_eq!(decode_temperature([0x91, 0x01], Resolution::Bits9), 25.0);
+        assert_eq!(decode_temperature([0x5E, 0xFF], Resolution::Bits10), -10.25);
+    }
+
+    #[test]
+    fn test_sensor_data_from_scratchpad() {
+        let data =
+            SensorData::from_scratchpad(&[0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0]);
+        assert_eq!(
+            data,
+            SensorData {
+                temperature: 25.0625,
+                resolution: Resolution::Bits12,
+                alarm_temp_low: 70,
+                alarm_temp_high: 75,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_data() {
+        let address = Address(0x3A00_0000_0000_0128);
+        let sensor = Ds18b20::new::<()>(address).unwrap();
+        let mut scratchpad = [0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        let mut transactions = Transaction::command(commands::READ_SCRATCHPAD, Some(&address));
+        transactions.extend(Transaction::read_bytes(&scratchpad));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            sensor.read_data(&mut mock, &mut NoDelay),
+            Ok(SensorData {
+                temperature: 25.0625,
+                resolution: Resolution::Bits12,
+                alarm_temp_low: 70,
+                alarm_temp_high: 75,
+            })
+        );
+        mock.done();
+
+        // a corrupted temperature fails the crc check
+        scratchpad[0] ^= 0x01;
+        let mut transactions = Transaction::command(commands::READ_SCRATCHPAD, Some(&address));
+        transactions.extend(Transaction::read_bytes(&scratchpad));
+        let mut mock = Mock::new(&transactions);
+        assert!(matches!(
+            sensor.read_data(&mut mock, &mut NoDelay),
+            Err(OneWireError::CrcMismatch { len: 8, .. })
+        ));
+        assert_eq!(mock.crc_failures(), &[8]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_config() {
+        let address = Address(0x3A00_0000_0000_0128);
+        let sensor = Ds18b20::new::<()>(address).unwrap();
+        // the high threshold (TH) is written first, then the low threshold (TL) and the config
+        let mut transactions = Transaction::command(commands::WRITE_SCRATCHPAD, Some(&address));
+        transactions.extend(Transaction::write_bytes(&[75, -10i8 as u8, 0b0011_1111]));
+        let mut mock = Mock::new(&transactions);
+        sensor
+            .set_config(-10, 75, Resolution::Bits10, &mut mock, &mut NoDelay)
+            .unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_recall_from_eeprom_timeout() {
+        let address = Address(0x3A00_0000_0000_0128);
+        let sensor = Ds18b20::new::<()>(address).unwrap();
+        let mut mock = Mock::new(&Transaction::command(
+            commands::RECALL_EEPROM,
+            Some(&address),
+        ));
+        for _ in 0..(RECALL_EEPROM_TIMEOUT_MICROS / READ_SLOT_DURATION_MICROS) {
+            mock.expect(&[Transaction::ReadBit(false)]);
+        }
+        assert_eq!(
+            sensor.recall_from_eeprom(&mut mock, &mut NoDelay),
+            Err(OneWireError::Timeout(10_000))
+        );
+        mock.done();
+    }
+}
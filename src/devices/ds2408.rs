@@ -0,0 +1,423 @@
+//! Driver for the DS2408 8 channel addressable switch
+
+use crate::bus::read_crc16;
+use crate::crc::Crc16Digest;
+use crate::delay::Delay;
+use crate::{Address, Family, OneWireBus, OneWireError, OneWireResult, TypedAddress};
+
+pub const FAMILY_CODE: u8 = 0x29;
+
+pub mod commands {
+    pub const READ_PIO_REGISTERS: u8 = 0xF0;
+    pub const CHANNEL_ACCESS_READ: u8 = 0xF5;
+    pub const CHANNEL_ACCESS_WRITE: u8 = 0x5A;
+    pub const WRITE_CONDITIONAL_SEARCH_REGISTER: u8 = 0xCC;
+    pub const RESET_ACTIVITY_LATCHES: u8 = 0xC3;
+}
+
+/// Address of the first register (PIO logic state)
+const REGISTERS_ADDRESS: u16 = 0x0088;
+
+/// Address of the first writable register (conditional search channel selection mask)
+const CONDITIONAL_SEARCH_ADDRESS: u16 = 0x008B;
+
+/// Sent by the device after a successful channel write or activity latch reset
+const CONFIRMATION: u8 = 0xAA;
+
+// Bits of the control/status register
+const CONTROL_PLS: u8 = 0x01;
+const CONTROL_CT: u8 = 0x02;
+const CONTROL_ROS: u8 = 0x04;
+const CONTROL_PORL: u8 = 0x08;
+const CONTROL_VCCP: u8 = 0x80;
+
+/// The function of the RSTZ pin
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RstzMode {
+    /// RSTZ is a reset input. Pulling it low turns off all outputs
+    ResetInput,
+
+    /// RSTZ is a strobe output, pulsed low after each channel access read/write
+    StrobeOutput,
+}
+
+/// The contents of the control/status register
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ControlStatus {
+    /// Selects activity latches (true) or PIO pin states (false) for the conditional search
+    pub pin_or_activity_latch_select: bool,
+
+    /// Selects AND (true) or OR (false) of the conditional search channels
+    pub conditional_search_logical_term: bool,
+
+    pub rstz_mode: RstzMode,
+
+    /// Set when the device was powered on or reset using RSTZ. Outputs are off until this is cleared
+    pub power_on_reset_latch: bool,
+
+    /// True if the device has VCC power, false if parasite powered
+    pub vcc_powered: bool,
+}
+
+impl ControlStatus {
+    pub fn from_register(register: u8) -> ControlStatus {
+        ControlStatus {
+            pin_or_activity_latch_select: register & CONTROL_PLS != 0,
+            conditional_search_logical_term: register & CONTROL_CT != 0,
+            rstz_mode: if register & CONTROL_ROS != 0 {
+                RstzMode::StrobeOutput
+            } else {
+                RstzMode::ResetInput
+            },
+            power_on_reset_latch: register & CONTROL_PORL != 0,
+            vcc_powered: register & CONTROL_VCCP != 0,
+        }
+    }
+
+    /// The value to write to the register. The power on reset latch can only be cleared,
+    /// and the VCC power status is read only
+    pub fn to_register(&self) -> u8 {
+        let mut register = 0;
+        if self.pin_or_activity_latch_select {
+            register |= CONTROL_PLS;
+        }
+        if self.conditional_search_logical_term {
+            register |= CONTROL_CT;
+        }
+        if self.rstz_mode == RstzMode::StrobeOutput {
+            register |= CONTROL_ROS;
+        }
+        if self.power_on_reset_latch {
+            register |= CONTROL_PORL;
+        }
+        register
+    }
+}
+
+/// All of the PIO registers. Each bit of a `u8` is a channel, with channel 0 as the least significant bit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Registers {
+    /// The logic level of each PIO pin
+    pub pio_logic_state: u8,
+
+    /// The output latch of each channel. When a bit is 0, that pin is being pulled low by the device
+    pub pio_output_latch_state: u8,
+
+    /// Set for each channel that has had an edge since the activity latches were last reset
+    pub activity_latch_state: u8,
+
+    pub conditional_search_channel_mask: u8,
+    pub conditional_search_channel_polarity: u8,
+    pub control_status: ControlStatus,
+}
+
+impl Registers {
+    /// Decodes the registers, starting with the PIO logic state register (0x88)
+    pub fn from_bytes(bytes: &[u8; 6]) -> Registers {
+        Registers {
+            pio_logic_state: bytes[0],
+            pio_output_latch_state: bytes[1],
+            activity_latch_state: bytes[2],
+            conditional_search_channel_mask: bytes[3],
+            conditional_search_channel_polarity: bytes[4],
+            control_status: ControlStatus::from_register(bytes[5]),
+        }
+    }
+}
+
+pub struct Ds2408 {
+    address: Address,
+}
+
+impl Family for Ds2408 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds2408>> for Ds2408 {
+    fn from(address: TypedAddress<Ds2408>) -> Ds2408 {
+        Ds2408 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds2408 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds2408, E> {
+        Ok(Ds2408::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Reads all of the PIO registers, verifying the crc16 sent after them
+    pub fn read_registers<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Registers, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let target_address = REGISTERS_ADDRESS.to_le_bytes();
+        onewire.send_command(commands::READ_PIO_REGISTERS, Some(&self.address), delay)?;
+        onewire.write_bytes(&target_address, delay)?;
+        // The crc is only sent after the end of the register page (0x8F), so the 2 unused
+        // registers after the control/status register are read as well
+        let mut registers = [0; 8];
+        onewire.read_bytes(&mut registers, delay)?;
+
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::READ_PIO_REGISTERS]);
+        digest.update(&target_address);
+        digest.update(&registers);
+        let len = 1 + target_address.len() + registers.len();
+        read_crc16(onewire, digest, len, delay)?;
+        let mut bytes = [0; 6];
+        bytes.copy_from_slice(&registers[..6]);
+        Ok(Registers::from_bytes(&bytes))
+    }
+
+    /// Reads the logic level of all 8 PIO pins
+    pub fn read_channels<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u8, B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::CHANNEL_ACCESS_READ, Some(&self.address), delay)?;
+        onewire.read_byte(delay)
+    }
+
+    /// Sets the output latches of all 8 channels. A 0 bit turns on the output transistor, pulling that
+    /// pin low. Returns the logic level of the pins after the write
+    pub fn write_channels<B>(
+        &self,
+        latches: u8,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u8, B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::CHANNEL_ACCESS_WRITE, Some(&self.address), delay)?;
+        // the complement is sent to protect against transmission errors
+        onewire.write_bytes(&[latches, !latches], delay)?;
+        if onewire.read_byte(delay)? != CONFIRMATION {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        onewire.read_byte(delay)
+    }
+
+    /// Clears the activity latches of all channels
+    pub fn reset_activity_latches<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::RESET_ACTIVITY_LATCHES, Some(&self.address), delay)?;
+        if onewire.read_byte(delay)? != CONFIRMATION {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    /// Writes the conditional search mask, polarity, and control/status registers
+    pub fn write_conditional_search<B>(
+        &self,
+        channel_mask: u8,
+        channel_polarity: u8,
+        control_status: &ControlStatus,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(
+            commands::WRITE_CONDITIONAL_SEARCH_REGISTER,
+            Some(&self.address),
+            delay,
+        )?;
+        onewire.write_bytes(&CONDITIONAL_SEARCH_ADDRESS.to_le_bytes(), delay)?;
+        onewire.write_bytes(
+            &[channel_mask, channel_polarity, control_status.to_register()],
+            delay,
+        )
+    }
+
+    /// Changes the function of the RSTZ pin, leaving the rest of the configuration unchanged
+    pub fn set_rstz_mode<B>(
+        &self,
+        rstz_mode: RstzMode,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let registers = self.read_registers(onewire, delay)?;
+        let control_status = ControlStatus {
+            rstz_mode,
+            ..registers.control_status
+        };
+        self.write_conditional_search(
+            registers.conditional_search_channel_mask,
+            registers.conditional_search_channel_polarity,
+            &control_status,
+            onewire,
+            delay,
+        )
+    }
+
+    /// Clears the power on reset latch. Outputs can't be turned on until this is cleared after
+    /// power up, or after a reset from the RSTZ pin
+    pub fn clear_power_on_reset<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let registers = self.read_registers(onewire, delay)?;
+        let control_status = ControlStatus {
+            power_on_reset_latch: false,
+            ..registers.control_status
+        };
+        self.write_conditional_search(
+            registers.conditional_search_channel_mask,
+            registers.conditional_search_channel_polarity,
+            &control_status,
+            onewire,
+            delay,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::crc::Crc16Digest;
+    use crate::devices::ds2408::{commands, ControlStatus, Ds2408, Registers, RstzMode};
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::{Address, OneWireError};
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    fn address() -> Address {
+        Address::for_test(0x29, 0x1234)
+    }
+
+    /// Reading the registers, followed by the inverted crc16 (corrupted if `bad_crc`)
+    fn read_registers(registers: &[u8; 8], bad_crc: bool) -> Vec<Transaction> {
+        let mut transactions = Transaction::command(commands::READ_PIO_REGISTERS, Some(&address()));
+        transactions.extend(Transaction::write_bytes(&[0x88, 0x00]));
+        transactions.extend(Transaction::read_bytes(registers));
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::READ_PIO_REGISTERS, 0x88, 0x00]);
+        digest.update(registers);
+        let mut crc = !digest.finalize();
+        if bad_crc {
+            crc ^= 0x0100;
+        }
+        transactions.extend(Transaction::read_bytes(&crc.to_le_bytes()));
+        transactions
+    }
+
+    #[test]
+    fn test_registers_from_bytes() {
+        let registers = Registers::from_bytes(&[0xF0, 0xFF, 0x01, 0x00, 0x00, 0x8C]);
+        assert_eq!(
+            registers,
+            Registers {
+                pio_logic_state: 0xF0,
+                pio_output_latch_state: 0xFF,
+                activity_latch_state: 0x01,
+                conditional_search_channel_mask: 0x00,
+                conditional_search_channel_polarity: 0x00,
+                control_status: ControlStatus {
+                    pin_or_activity_latch_select: false,
+                    conditional_search_logical_term: false,
+                    rstz_mode: RstzMode::StrobeOutput,
+                    power_on_reset_latch: true,
+                    vcc_powered: true,
+                },
+            }
+        );
+        // VCC power status is read only
+        assert_eq!(registers.control_status.to_register(), 0x0C);
+    }
+
+    #[test]
+    fn test_read_registers() {
+        let device = Ds2408::new::<Infallible>(address()).unwrap();
+        let registers = [0xF0, 0xFF, 0x01, 0x00, 0x00, 0x8C, 0xFF, 0xFF];
+        let mut mock = Mock::new(&read_registers(&registers, false));
+        assert_eq!(
+            device.read_registers(&mut mock, &mut NoDelay),
+            Ok(Registers::from_bytes(&[0xF0, 0xFF, 0x01, 0x00, 0x00, 0x8C]))
+        );
+        mock.done();
+
+        let mut mock = Mock::new(&read_registers(&registers, true));
+        assert!(matches!(
+            device.read_registers(&mut mock, &mut NoDelay),
+            Err(OneWireError::CrcMismatch { len: 11, .. })
+        ));
+        assert_eq!(mock.crc_failures(), &[11]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_write_channels() {
+        let device = Ds2408::new::<Infallible>(address()).unwrap();
+        let mut transactions =
+            Transaction::command(commands::CHANNEL_ACCESS_WRITE, Some(&address()));
+        transactions.extend(Transaction::write_bytes(&[0x0F, 0xF0]));
+        transactions.extend(Transaction::read_bytes(&[0xAA, 0x0F]));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.write_channels(0x0F, &mut mock, &mut NoDelay),
+            Ok(0x0F)
+        );
+        mock.done();
+
+        // the write failed, so the device doesn't confirm it
+        transactions.truncate(transactions.len() - 2);
+        transactions.push(Transaction::ReadByte(0xFF));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.write_channels(0x0F, &mut mock, &mut NoDelay),
+            Err(OneWireError::UnexpectedResponse)
+        );
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_rstz_mode() {
+        let device = Ds2408::new::<Infallible>(address()).unwrap();
+        // the mask, polarity, and the rest of the control register are written back unchanged,
+        // without the read only VCC power status
+        let mut transactions =
+            read_registers(&[0xF0, 0xFF, 0x01, 0x12, 0x34, 0x8B, 0xFF, 0xFF], false);
+        transactions.extend(Transaction::command(
+            commands::WRITE_CONDITIONAL_SEARCH_REGISTER,
+            Some(&address()),
+        ));
+        transactions.extend(Transaction::write_bytes(&[0x8B, 0x00, 0x12, 0x34, 0x0F]));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.set_rstz_mode(RstzMode::StrobeOutput, &mut mock, &mut NoDelay),
+            Ok(())
+        );
+        mock.done();
+    }
+}
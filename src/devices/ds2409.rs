@@ -0,0 +1,529 @@
+//! Driver for the DS2409 MicroLAN coupler, which switches between a main and an auxiliary branch.
+//!
+//! Devices behind couplers are addressed with a `PathAddress`, which includes each coupler and branch
+//! that has to be switched on to reach the device.
+
+use crate::delay::Delay;
+use crate::pullup::delay_micros;
+use crate::{Address, Family, OneWireBus, OneWireError, OneWireResult, TypedAddress};
+
+pub const FAMILY_CODE: u8 = 0x1F;
+
+pub mod commands {
+    pub const READ_WRITE_STATUS: u8 = 0x5A;
+    pub const ALL_LINES_OFF: u8 = 0x66;
+    pub const DISCHARGE_LINES: u8 = 0x99;
+    pub const DIRECT_ON_MAIN: u8 = 0xA5;
+    pub const SMART_ON_MAIN: u8 = 0xCC;
+    pub const SMART_ON_AUXILIARY: u8 = 0x33;
+}
+
+/// The maximum number of couplers in a `CouplerPath`
+pub const MAX_PATH_DEPTH: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Branch {
+    Main,
+    Auxiliary,
+}
+
+/// A sequence of couplers (starting with the coupler closest to the master) and the branch
+/// of each that has to be switched on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CouplerPath {
+    couplers: [(Address, Branch); MAX_PATH_DEPTH],
+    len: usize,
+}
+
+impl CouplerPath {
+    /// A path to the main bus, with no couplers
+    pub fn new() -> CouplerPath {
+        CouplerPath {
+            couplers: [(Address(0), Branch::Main); MAX_PATH_DEPTH],
+            len: 0,
+        }
+    }
+
+    /// Returns a new path that continues through the given coupler and branch,
+    /// or `None` if the path is already `MAX_PATH_DEPTH` couplers long
+    pub fn then(&self, coupler: Address, branch: Branch) -> Option<CouplerPath> {
+        if self.len == MAX_PATH_DEPTH {
+            return None;
+        }
+        let mut path = *self;
+        path.couplers[path.len] = (coupler, branch);
+        path.len += 1;
+        Some(path)
+    }
+
+    pub fn couplers(&self) -> &[(Address, Branch)] {
+        &self.couplers[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for CouplerPath {
+    fn default() -> CouplerPath {
+        CouplerPath::new()
+    }
+}
+
+/// The address of a device, along with the couplers that need to be switched on to reach it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathAddress {
+    pub path: CouplerPath,
+    pub address: Address,
+}
+
+pub struct Ds2409 {
+    address: Address,
+}
+
+impl Family for Ds2409 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds2409>> for Ds2409 {
+    fn from(address: TypedAddress<Ds2409>) -> Ds2409 {
+        Ds2409 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds2409 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds2409, E> {
+        Ok(Ds2409::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Connects a branch, and generates a reset pulse on it. Returns true if any devices on the
+    /// branch responded with a presence pulse. Devices on the branch can be addressed immediately
+    /// after this, without another reset.
+    pub fn smart_on<B>(
+        &self,
+        branch: Branch,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<bool, B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.reset(delay)?;
+        onewire.match_address(&self.address, delay)?;
+        smart_on(branch, onewire, delay)
+    }
+
+    /// Connects the main branch, without generating a reset pulse on it
+    pub fn direct_on_main<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        self.confirmed_command(commands::DIRECT_ON_MAIN, onewire, delay)
+    }
+
+    /// Disconnects both branches
+    pub fn all_lines_off<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        self.confirmed_command(commands::ALL_LINES_OFF, onewire, delay)
+    }
+
+    /// Disconnects both branches and pulls them low for 100ms, to reset parasite powered devices
+    /// that are stuck
+    pub fn discharge_lines<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        self.confirmed_command(commands::DISCHARGE_LINES, onewire, delay)?;
+        delay_micros(100_000, delay, || onewire.idle());
+        self.all_lines_off(onewire, delay)
+    }
+
+    /// Writes the control byte, and returns the resulting status byte
+    pub fn read_write_status<B>(
+        &self,
+        control: u8,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u8, B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::READ_WRITE_STATUS, Some(&self.address), delay)?;
+        onewire.write_byte(control, delay)?;
+        // The status is sent twice
+        let status = onewire.read_byte(delay)?;
+        if onewire.read_byte(delay)? != status {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(status)
+    }
+
+    /// Sends a command that the coupler confirms by sending the command back
+    fn confirmed_command<B>(
+        &self,
+        command: u8,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(command, Some(&self.address), delay)?;
+        if onewire.read_byte(delay)? != command {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(())
+    }
+}
+
+/// Sends a smart-on command to a coupler that was just addressed
+fn smart_on<B>(
+    branch: Branch,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<bool, B::Error>
+where
+    B: OneWireBus,
+{
+    let command = match branch {
+        Branch::Main => commands::SMART_ON_MAIN,
+        Branch::Auxiliary => commands::SMART_ON_AUXILIARY,
+    };
+    onewire.write_byte(command, delay)?;
+    // reset stimulus, the coupler generates a reset pulse on the branch after this
+    onewire.write_byte(0xFF, delay)?;
+    // the first bit is 0 if a presence pulse was detected on the branch
+    let devices_present = onewire.read_byte(delay)? & 0x01 == 0;
+    if onewire.read_byte(delay)? != command {
+        return Err(OneWireError::UnexpectedResponse);
+    }
+    Ok(devices_present)
+}
+
+/// Switches off all couplers on the main bus, then switches on each coupler in the path so that
+/// the end of the path is connected to the master. Returns true if any devices are present at
+/// the end of the path.
+///
+/// After this, devices at the end of the path can be addressed without another reset.
+pub fn select_path<B>(
+    path: &CouplerPath,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<bool, B::Error>
+where
+    B: OneWireBus,
+{
+    // The confirmation byte isn't checked, since there may not be any couplers on the main bus
+    onewire.send_command(commands::ALL_LINES_OFF, None, delay)?;
+    onewire.read_byte(delay)?;
+
+    let mut devices_present = onewire.reset(delay)?;
+    for (coupler, branch) in path.couplers() {
+        onewire.match_address(coupler, delay)?;
+        devices_present = smart_on(*branch, onewire, delay)?;
+    }
+    Ok(devices_present)
+}
+
+/// Like `OneWire::send_command`, but for a device behind couplers. This activates the path to
+/// the device, addresses it, and then sends the command
+pub fn send_command<B>(
+    command: u8,
+    address: &PathAddress,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    select_path(&address.path, onewire, delay)?;
+    onewire.match_address(&address.address, delay)?;
+    onewire.write_byte(command, delay)
+}
+
+/// Finds every device on the bus, including devices behind couplers, along with the path to each one.
+/// The main and auxiliary branches of each coupler are searched recursively, up to `MAX_PATH_DEPTH`
+/// couplers deep. Couplers are included in the output (with the path to the coupler itself), and all
+/// couplers are switched off when finished.
+///
+/// Returns the number of devices found. If `output` fills up, the discovery stops early.
+pub fn discover_topology<B>(
+    output: &mut [PathAddress],
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<usize, B::Error>
+where
+    B: OneWireBus,
+{
+    let mut count = 0;
+    discover_branch(&CouplerPath::new(), output, &mut count, onewire, delay)?;
+    Ok(count)
+}
+
+fn discover_branch<B>(
+    path: &CouplerPath,
+    output: &mut [PathAddress],
+    count: &mut usize,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    // Couplers on this branch may have been left on, which would make the devices behind them look
+    // like they are on this branch. They are first found and switched off (using the unused part
+    // of `output` to store them), then the branch is searched again.
+    select_path(path, onewire, delay)?;
+    let mut new_couplers = 0;
+    let mut state = None;
+    while let Some((address, next_state)) = onewire.device_search(state.as_ref(), false, delay)? {
+        state = Some(next_state);
+        if address.family_code() == FAMILY_CODE
+            && *count + new_couplers < output.len()
+            && !is_known(&output[..*count], &address)
+        {
+            output[*count + new_couplers] = PathAddress {
+                path: *path,
+                address,
+            };
+            new_couplers += 1;
+        }
+    }
+    for coupler in &output[*count..(*count + new_couplers)] {
+        Ds2409 {
+            address: coupler.address,
+        }
+        .all_lines_off(onewire, delay)?;
+    }
+
+    let start = *count;
+    let mut state = None;
+    while let Some((address, next_state)) = onewire.device_search(state.as_ref(), false, delay)? {
+        state = Some(next_state);
+        if *count == output.len() {
+            break;
+        }
+        // Devices on the branches leading up to this one are also found, but were already recorded
+        if !is_known(&output[..*count], &address) {
+            output[*count] = PathAddress {
+                path: *path,
+                address,
+            };
+            *count += 1;
+        }
+    }
+
+    for i in start..*count {
+        let coupler = output[i].address;
+        if coupler.family_code() != FAMILY_CODE {
+            continue;
+        }
+        for branch in &[Branch::Main, Branch::Auxiliary] {
+            if let Some(branch_path) = path.then(coupler, *branch) {
+                discover_branch(&branch_path, output, count, onewire, delay)?;
+            }
+        }
+        select_path(path, onewire, delay)?;
+        Ds2409 { address: coupler }.all_lines_off(onewire, delay)?;
+    }
+    Ok(())
+}
+
+fn is_known(known: &[PathAddress], address: &Address) -> bool {
+    known.iter().any(|known| known.address == *address)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::devices::ds2409::{
+        discover_topology, Branch, CouplerPath, PathAddress, MAX_PATH_DEPTH,
+    };
+    use crate::shared::SharedBus;
+    use crate::simulator::{CouplerBranch, Simulator, VirtualDevice};
+    use crate::{Address, OneWire};
+
+    #[test]
+    fn test_coupler_path() {
+        let root = CouplerPath::new();
+        assert!(root.is_empty());
+
+        let path = root
+            .then(Address(1), Branch::Main)
+            .unwrap()
+            .then(Address(2), Branch::Auxiliary)
+            .unwrap();
+        assert_eq!(
+            path.couplers(),
+            &[(Address(1), Branch::Main), (Address(2), Branch::Auxiliary)]
+        );
+
+        let mut path = CouplerPath::new();
+        for _ in 0..MAX_PATH_DEPTH {
+            path = path.then(Address(1), Branch::Main).unwrap();
+        }
+        assert_eq!(path.then(Address(1), Branch::Main), None);
+    }
+
+    #[test]
+    fn test_discover_topology() {
+        let simulator = Simulator::new();
+        let first = Address::for_test(0x28, 1);
+        let second = Address::for_test(0x28, 2);
+        simulator.add_device(VirtualDevice::new(first));
+        simulator.add_device(VirtualDevice::new(second));
+        let mut delay = simulator.delay();
+
+        // any bus can be used, not just a OneWire
+        let shared = SharedBus::new(OneWire::new(simulator.pin()).unwrap());
+        let empty = PathAddress {
+            path: CouplerPath::new(),
+            address: Address(0),
+        };
+        let mut output = [empty; 4];
+        assert_eq!(
+            discover_topology(&mut output, &mut shared.handle(), &mut delay),
+            Ok(2)
+        );
+        // in the order of the search, least significant bit first
+        assert_eq!(output[0].address, second);
+        assert_eq!(output[1].address, first);
+        assert!(output[0].path.is_empty() && output[1].path.is_empty());
+    }
+
+    fn path_of(output: &[PathAddress], address: Address) -> Option<&[(Address, Branch)]> {
+        output
+            .iter()
+            .find(|found| found.address == address)
+            .map(|found| found.path.couplers())
+    }
+
+    #[test]
+    fn test_discover_through_coupler() {
+        let simulator = Simulator::new();
+        let coupler = Address::for_test(0x1F, 1);
+        let on_bus = Address::for_test(0x28, 1);
+        let on_main = Address::for_test(0x28, 2);
+        let on_auxiliary = Address::for_test(0x28, 3);
+        simulator.add_device(VirtualDevice::coupler(coupler));
+        simulator.add_device(VirtualDevice::new(on_bus));
+        for (address, branch) in &[
+            (on_main, CouplerBranch::Main),
+            (on_auxiliary, CouplerBranch::Auxiliary),
+        ] {
+            let mut device = VirtualDevice::new(*address);
+            device.connection = Some((coupler, *branch));
+            simulator.add_device(device);
+        }
+        let mut delay = simulator.delay();
+        let mut onewire = OneWire::new(simulator.pin()).unwrap();
+
+        let empty = PathAddress {
+            path: CouplerPath::new(),
+            address: Address(0),
+        };
+        let mut output = [empty; 8];
+        assert_eq!(
+            discover_topology(&mut output, &mut onewire, &mut delay),
+            Ok(4)
+        );
+        assert_eq!(path_of(&output, coupler), Some(&[][..]));
+        assert_eq!(path_of(&output, on_bus), Some(&[][..]));
+        assert_eq!(
+            path_of(&output, on_main),
+            Some(&[(coupler, Branch::Main)][..])
+        );
+        assert_eq!(
+            path_of(&output, on_auxiliary),
+            Some(&[(coupler, Branch::Auxiliary)][..])
+        );
+        // the coupler is switched off again afterwards
+        assert_eq!(
+            simulator.with_device(&coupler, |device| device.connected_branch()),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn test_discover_nested_couplers() {
+        let simulator = Simulator::new();
+        let outer = Address::for_test(0x1F, 1);
+        let inner = Address::for_test(0x1F, 2);
+        let on_bus = Address::for_test(0x28, 1);
+        let on_outer = Address::for_test(0x28, 2);
+        let on_outer_auxiliary = Address::for_test(0x28, 3);
+        let on_inner = Address::for_test(0x28, 4);
+        simulator.add_device(VirtualDevice::coupler(outer));
+        simulator.add_device(VirtualDevice::new(on_bus));
+        let mut inner_coupler = VirtualDevice::coupler(inner);
+        inner_coupler.connection = Some((outer, CouplerBranch::Main));
+        simulator.add_device(inner_coupler);
+        for (address, connection) in &[
+            (on_outer, (outer, CouplerBranch::Main)),
+            (on_outer_auxiliary, (outer, CouplerBranch::Auxiliary)),
+            (on_inner, (inner, CouplerBranch::Main)),
+        ] {
+            let mut device = VirtualDevice::new(*address);
+            device.connection = Some(*connection);
+            simulator.add_device(device);
+        }
+        let mut delay = simulator.delay();
+        let mut onewire = OneWire::new(simulator.pin()).unwrap();
+
+        let empty = PathAddress {
+            path: CouplerPath::new(),
+            address: Address(0),
+        };
+        let mut output = [empty; 8];
+        assert_eq!(
+            discover_topology(&mut output, &mut onewire, &mut delay),
+            Ok(6)
+        );
+        assert_eq!(path_of(&output, outer), Some(&[][..]));
+        assert_eq!(path_of(&output, on_bus), Some(&[][..]));
+        assert_eq!(path_of(&output, inner), Some(&[(outer, Branch::Main)][..]));
+        assert_eq!(
+            path_of(&output, on_outer),
+            Some(&[(outer, Branch::Main)][..])
+        );
+        assert_eq!(
+            path_of(&output, on_outer_auxiliary),
+            Some(&[(outer, Branch::Auxiliary)][..])
+        );
+        assert_eq!(
+            path_of(&output, on_inner),
+            Some(&[(outer, Branch::Main), (inner, Branch::Main)][..])
+        );
+        for coupler in &[outer, inner] {
+            assert_eq!(
+                simulator.with_device(coupler, |device| device.connected_branch()),
+                Some(None)
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Driver for the DS2413 dual channel addressable switch
+
+use crate::delay::Delay;
+use crate::{Address, Family, OneWireBus, OneWireError, OneWireResult, TypedAddress};
+use core::cell::RefCell;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+pub const FAMILY_CODE: u8 = 0x3A;
+
+pub mod commands {
+    pub const PIO_ACCESS_READ: u8 = 0xF5;
+    pub const PIO_ACCESS_WRITE: u8 = 0x5A;
+}
+
+/// Sent by the device after a successful PIO write
+const WRITE_CONFIRMATION: u8 = 0xAA;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+/// The state of both channels, as read from the device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PioState {
+    /// The logic level of the PIO A pin
+    pub pio_a: bool,
+
+    /// The output latch of PIO A. When false, the pin is being pulled low by the device
+    pub latch_a: bool,
+
+    /// The logic level of the PIO B pin
+    pub pio_b: bool,
+
+    /// The output latch of PIO B. When false, the pin is being pulled low by the device
+    pub latch_b: bool,
+}
+
+impl PioState {
+    /// Decodes the PIO status byte. The upper 4 bits are the complement of the lower 4 bits,
+    /// so `None` is returned if they don't match
+    pub fn from_status(status: u8) -> Option<PioState> {
+        if status >> 4 != !status & 0x0F {
+            return None;
+        }
+        Some(PioState {
+            pio_a: status & 0b0001 != 0,
+            latch_a: status & 0b0010 != 0,
+            pio_b: status & 0b0100 != 0,
+            latch_b: status & 0b1000 != 0,
+        })
+    }
+
+    pub fn pin(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::A => self.pio_a,
+            Channel::B => self.pio_b,
+        }
+    }
+
+    pub fn latch(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::A => self.latch_a,
+            Channel::B => self.latch_b,
+        }
+    }
+}
+
+pub struct Ds2413 {
+    address: Address,
+}
+
+impl Family for Ds2413 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds2413>> for Ds2413 {
+    fn from(address: TypedAddress<Ds2413>) -> Ds2413 {
+        Ds2413 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds2413 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds2413, E> {
+        Ok(Ds2413::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Reads the pin and latch states of both channels
+    pub fn read_pio<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<PioState, B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::PIO_ACCESS_READ, Some(&self.address), delay)?;
+        let status = onewire.read_byte(delay)?;
+        PioState::from_status(status).ok_or(OneWireError::UnexpectedResponse)
+    }
+
+    /// Sets the output latches of both channels. A latch value of false turns on the output
+    /// transistor, pulling the pin low. Returns the state of the pins after the write
+    pub fn write_pio<B>(
+        &self,
+        latch_a: bool,
+        latch_b: bool,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<PioState, B::Error>
+    where
+        B: OneWireBus,
+    {
+        // unused bits must be written as 1s
+        let data = 0xFC | (latch_a as u8) | ((latch_b as u8) << 1);
+        onewire.send_command(commands::PIO_ACCESS_WRITE, Some(&self.address), delay)?;
+        // the complement is sent to protect against transmission errors
+        onewire.write_bytes(&[data, !data], delay)?;
+        if onewire.read_byte(delay)? != WRITE_CONFIRMATION {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        let status = onewire.read_byte(delay)?;
+        PioState::from_status(status).ok_or(OneWireError::UnexpectedResponse)
+    }
+
+    /// Sets the output latch of a single channel, leaving the other channel unchanged
+    pub fn write_channel<B>(
+        &self,
+        channel: Channel,
+        latch: bool,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<PioState, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let state = self.read_pio(onewire, delay)?;
+        match channel {
+            Channel::A => self.write_pio(latch, state.latch_b, onewire, delay),
+            Channel::B => self.write_pio(state.latch_a, latch, onewire, delay),
+        }
+    }
+
+    /// Returns a single channel that implements `InputPin` and `OutputPin`, so it can be used with other
+    /// drivers. The bus and delay are shared using a `RefCell` so both channels can be used at once.
+    pub fn channel<'a, B, D>(
+        &'a self,
+        channel: Channel,
+        onewire: &'a RefCell<B>,
+        delay: &'a RefCell<D>,
+    ) -> Ds2413Pin<'a, B, D> {
+        Ds2413Pin {
+            device: self,
+            channel,
+            onewire,
+            delay,
+        }
+    }
+}
+
+/// A single channel of a DS2413. Setting the pin low turns on the output transistor
+pub struct Ds2413Pin<'a, B, D> {
+    device: &'a Ds2413,
+    channel: Channel,
+    onewire: &'a RefCell<B>,
+    delay: &'a RefCell<D>,
+}
+
+impl<'a, B, D> Ds2413Pin<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    fn read_pio(&self) -> OneWireResult<PioState, B::Error> {
+        self.device.read_pio(
+            &mut *self.onewire.borrow_mut(),
+            &mut *self.delay.borrow_mut(),
+        )
+    }
+
+    fn write_latch(&mut self, latch: bool) -> OneWireResult<(), B::Error> {
+        self.device.write_channel(
+            self.channel,
+            latch,
+            &mut *self.onewire.borrow_mut(),
+            &mut *self.delay.borrow_mut(),
+        )?;
+        Ok(())
+    }
+}
+
+impl<'a, B, D> InputPin for Ds2413Pin<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    type Error = OneWireError<B::Error>;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.read_pio()?.pin(self.channel))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<'a, B, D> OutputPin for Ds2413Pin<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    type Error = OneWireError<B::Error>;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.write_latch(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.write_latch(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::devices::ds2413::{commands, Ds2413, PioState};
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::{Address, OneWireError};
+    use core::convert::Infallible;
+
+    fn address() -> Address {
+        Address::for_test(0x3A, 0x1234)
+    }
+
+    #[test]
+    fn test_pio_state_from_status() {
+        assert_eq!(
+            PioState::from_status(0b1010_0101),
+            Some(PioState {
+                pio_a: true,
+                latch_a: false,
+                pio_b: true,
+                latch_b: false,
+            })
+        );
+        assert_eq!(
+            PioState::from_status(0b0000_1111),
+            Some(PioState {
+                pio_a: true,
+                latch_a: true,
+                pio_b: true,
+                latch_b: true,
+            })
+        );
+        assert_eq!(PioState::from_status(0b0000_0101), None);
+    }
+
+    #[test]
+    fn test_read_pio() {
+        let device = Ds2413::new::<Infallible>(address()).unwrap();
+        let mut transactions = Transaction::command(commands::PIO_ACCESS_READ, Some(&address()));
+        transactions.push(Transaction::ReadByte(0b1010_0101));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.read_pio(&mut mock, &mut NoDelay),
+            Ok(PioState {
+                pio_a: true,
+                latch_a: false,
+                pio_b: true,
+                latch_b: false,
+            })
+        );
+        mock.done();
+
+        // the upper 4 bits aren't the complement of the lower 4
+        transactions.pop();
+        transactions.push(Transaction::ReadByte(0b0000_0101));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.read_pio(&mut mock, &mut NoDelay),
+            Err(OneWireError::UnexpectedResponse)
+        );
+        mock.done();
+    }
+
+    #[test]
+    fn test_write_pio() {
+        let device = Ds2413::new::<Infallible>(address()).unwrap();
+        // the unused bits are written as 1s, followed by the complement of the byte
+        let mut transactions = Transaction::command(commands::PIO_ACCESS_WRITE, Some(&address()));
+        transactions.extend(Transaction::write_bytes(&[0xFE, 0x01]));
+        transactions.extend(Transaction::read_bytes(&[0xAA, 0b0011_1100]));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.write_pio(false, true, &mut mock, &mut NoDelay),
+            Ok(PioState {
+                pio_a: false,
+                latch_a: false,
+                pio_b: true,
+                latch_b: true,
+            })
+        );
+        mock.done();
+
+        // the device didn't accept the write, so it doesn't confirm it
+        transactions.truncate(transactions.len() - 2);
+        transactions.push(Transaction::ReadByte(0xFF));
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.write_pio(false, true, &mut mock, &mut NoDelay),
+            Err(OneWireError::UnexpectedResponse)
+        );
+        mock.done();
+    }
+}
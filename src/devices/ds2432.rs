@@ -0,0 +1,591 @@
+//! Driver for the DS2432 1Kb protected EEPROM with SHA-1 (and the DS1961S, its iButton version).
+//!
+//! The device holds an 8 byte secret that can't be read back. It proves it knows the secret by
+//! sending a MAC (a SHA-1 hash of the secret, the data, its ROM ID, and a challenge) along with the
+//! data of a page, and data can only be written with a MAC computed from the secret. This makes
+//! the device usable as a token that can't be copied, unlike the ROM ID alone.
+//!
+//! `read_mac` and `write_mac` compute the same MACs as the device, so they can also be used by a
+//! host that holds the secret and receives the data some other way.
+
+use crate::bus::read_crc16;
+use crate::crc::Crc16Digest;
+use crate::delay::Delay;
+use crate::pullup::bus_strong_pullup_delay;
+use crate::{Address, Family, OneWireBus, OneWireError, OneWireResult, TypedAddress};
+use embedded_hal::digital::v2::OutputPin;
+
+pub const FAMILY_CODE: u8 = 0x33;
+
+pub mod commands {
+    pub const WRITE_SCRATCHPAD: u8 = 0x0F;
+    pub const READ_SCRATCHPAD: u8 = 0xAA;
+    pub const LOAD_FIRST_SECRET: u8 = 0x5A;
+    pub const COPY_SCRATCHPAD: u8 = 0x55;
+    pub const READ_MEMORY: u8 = 0xF0;
+    pub const READ_AUTHENTICATED_PAGE: u8 = 0xA5;
+}
+
+pub const PAGE_SIZE: usize = 32;
+
+/// The number of 32 byte pages of data memory
+pub const PAGES: u8 = 4;
+
+/// The address of the secret, which must be the target address of LOAD FIRST SECRET
+const SECRET_ADDRESS: u16 = 0x0080;
+
+/// The time the device takes to compute a MAC
+const SHA_MICROS: u32 = 2_000;
+
+/// The time the device takes to program the EEPROM
+const PROGRAM_MICROS: u32 = 10_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ds2432Error<E> {
+    OneWire(OneWireError<E>),
+
+    /// The MAC sent by the device didn't match the one computed from the secret. The device has a
+    /// different secret, or the data was changed
+    MacMismatch,
+
+    /// The device didn't accept a write. The MAC didn't match its secret, or the memory is write
+    /// protected
+    WriteRejected,
+}
+
+impl<E> From<OneWireError<E>> for Ds2432Error<E> {
+    fn from(err: OneWireError<E>) -> Ds2432Error<E> {
+        Ds2432Error::OneWire(err)
+    }
+}
+
+pub struct Ds2432 {
+    address: Address,
+}
+
+impl Family for Ds2432 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds2432>> for Ds2432 {
+    fn from(address: TypedAddress<Ds2432>) -> Ds2432 {
+        Ds2432 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds2432 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds2432, E> {
+        Ok(Ds2432::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Reads `output.len()` bytes of memory, starting at `target_address`, without any
+    /// authentication
+    pub fn read_memory<B>(
+        &self,
+        target_address: u16,
+        output: &mut [u8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::READ_MEMORY, Some(&self.address), delay)?;
+        onewire.write_bytes(&target_address.to_le_bytes(), delay)?;
+        onewire.read_bytes(output, delay)
+    }
+
+    /// Sets the secret. This doesn't need the current secret, so it should only be used to set up
+    /// new devices (the secret can be write protected afterwards).
+    ///
+    /// Parasite powered devices need a strong pull-up while the EEPROM is programmed, which
+    /// `strong_pullup` is set high for, as with `OneWire::copy_scratchpad`
+    pub fn load_first_secret<B>(
+        &self,
+        secret: &[u8; 8],
+        strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let authorization = self.write_scratchpad(SECRET_ADDRESS, secret, onewire, delay)?;
+        onewire.send_command(commands::LOAD_FIRST_SECRET, Some(&self.address), delay)?;
+        onewire.write_bytes(&authorization, delay)?;
+        bus_strong_pullup_delay(onewire, strong_pullup, PROGRAM_MICROS, delay)?;
+        match onewire.read_byte(delay)? {
+            0xAA | 0x55 => Ok(()),
+            _ => Err(OneWireError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads a page of data, along with the MAC the device computes from it, its secret, and
+    /// `challenge`. The MAC should be compared to `read_mac`, or use `read_verified_page` to do that.
+    ///
+    /// The challenge should be random, so a recording of an earlier response can't be replayed
+    pub fn read_authenticated_page<B>(
+        &self,
+        page: u8,
+        challenge: &[u8; 3],
+        strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<([u8; PAGE_SIZE], [u8; 20]), B::Error>
+    where
+        B: OneWireBus,
+    {
+        assert!(page < PAGES, "the DS2432 has 4 pages");
+        let target_address = page as u16 * PAGE_SIZE as u16;
+        // the device uses bytes 4-6 of the scratchpad as the challenge
+        let [c0, c1, c2] = *challenge;
+        self.write_scratchpad(target_address, &[0, 0, 0, 0, c0, c1, c2, 0], onewire, delay)?;
+
+        onewire.send_command(
+            commands::READ_AUTHENTICATED_PAGE,
+            Some(&self.address),
+            delay,
+        )?;
+        let [ta1, ta2] = target_address.to_le_bytes();
+        onewire.write_bytes(&[ta1, ta2], delay)?;
+        let mut data = [0; PAGE_SIZE];
+        onewire.read_bytes(&mut data, delay)?;
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::READ_AUTHENTICATED_PAGE, ta1, ta2]);
+        digest.update(&data);
+        // the page is followed by a byte of 1s, then the crc
+        digest.update(&[onewire.read_byte(delay)?]);
+        read_crc16(onewire, digest, 3 + PAGE_SIZE + 1, delay)?;
+
+        bus_strong_pullup_delay(onewire, strong_pullup, SHA_MICROS, delay)?;
+        let mut mac = [0; 20];
+        onewire.read_bytes(&mut mac, delay)?;
+        let mut digest = Crc16Digest::new();
+        digest.update(&mac);
+        read_crc16(onewire, digest, mac.len(), delay)?;
+        Ok((data, mac))
+    }
+
+    /// Reads a page of data, and checks the MAC sent with it was computed with `secret`
+    pub fn read_verified_page<B>(
+        &self,
+        page: u8,
+        secret: &[u8; 8],
+        challenge: &[u8; 3],
+        strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<[u8; PAGE_SIZE], Ds2432Error<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        let (data, mac) =
+            self.read_authenticated_page(page, challenge, strong_pullup, onewire, delay)?;
+        if mac != read_mac(secret, page, &data, &self.address, challenge) {
+            return Err(Ds2432Error::MacMismatch);
+        }
+        Ok(data)
+    }
+
+    /// Writes 8 bytes of data memory, at `target_address` (which must be a multiple of 8). The
+    /// device only accepts the write with a MAC computed from `secret`, which covers the rest of
+    /// the page as well, so the page is read first.
+    ///
+    /// `strong_pullup` is set high while the device computes the MAC, and while the EEPROM is
+    /// programmed
+    pub fn write_authenticated<B>(
+        &self,
+        target_address: u16,
+        data: &[u8; 8],
+        secret: &[u8; 8],
+        mut strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<(), Ds2432Error<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        assert!(
+            target_address % 8 == 0 && target_address < PAGES as u16 * PAGE_SIZE as u16,
+            "writes must be 8 bytes, within data memory"
+        );
+        let mut page_data = [0; 28];
+        self.read_memory(target_address & !0x1F, &mut page_data, onewire, delay)?;
+        let authorization = self.write_scratchpad(target_address, data, onewire, delay)?;
+        let mac = write_mac(secret, target_address, &page_data, data, &self.address);
+
+        onewire.send_command(commands::COPY_SCRATCHPAD, Some(&self.address), delay)?;
+        onewire.write_bytes(&authorization, delay)?;
+        bus_strong_pullup_delay(
+            onewire,
+            strong_pullup
+                .as_mut()
+                .map(|pin| &mut **pin as &mut dyn OutputPin<Error = B::Error>),
+            SHA_MICROS,
+            delay,
+        )?;
+        onewire.write_bytes(&mac, delay)?;
+        bus_strong_pullup_delay(onewire, strong_pullup, PROGRAM_MICROS, delay)?;
+        match onewire.read_byte(delay)? {
+            0xAA | 0x55 => Ok(()),
+            _ => Err(Ds2432Error::WriteRejected),
+        }
+    }
+
+    /// Writes 8 bytes to the scratchpad and checks them, then returns the target address and E/S
+    /// byte, which authorize copying the scratchpad
+    fn write_scratchpad<B>(
+        &self,
+        target_address: u16,
+        data: &[u8; 8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<[u8; 3], B::Error>
+    where
+        B: OneWireBus,
+    {
+        let [ta1, ta2] = target_address.to_le_bytes();
+        onewire.send_command(commands::WRITE_SCRATCHPAD, Some(&self.address), delay)?;
+        onewire.write_bytes(&[ta1, ta2], delay)?;
+        onewire.write_bytes(data, delay)?;
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::WRITE_SCRATCHPAD, ta1, ta2]);
+        digest.update(data);
+        read_crc16(onewire, digest, 3 + data.len(), delay)?;
+
+        onewire.send_command(commands::READ_SCRATCHPAD, Some(&self.address), delay)?;
+        let mut header = [0; 3];
+        onewire.read_bytes(&mut header, delay)?;
+        let mut scratchpad = [0; 8];
+        onewire.read_bytes(&mut scratchpad, delay)?;
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::READ_SCRATCHPAD]);
+        digest.update(&header);
+        digest.update(&scratchpad);
+        read_crc16(onewire, digest, 1 + header.len() + scratchpad.len(), delay)?;
+        // the E/S byte is 7 when all 8 bytes were written, without the partial flag
+        if header != [ta1, ta2, 0x07] || scratchpad != *data {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(header)
+    }
+}
+
+/// The MAC sent by `read_authenticated_page`
+pub fn read_mac(
+    secret: &[u8; 8],
+    page: u8,
+    page_data: &[u8; PAGE_SIZE],
+    address: &Address,
+    challenge: &[u8; 3],
+) -> [u8; 20] {
+    let mut block = [0; 64];
+    block[..4].copy_from_slice(&secret[..4]);
+    block[4..36].copy_from_slice(page_data);
+    block[36..40].copy_from_slice(&[0xFF; 4]);
+    block[40] = 0x40 | (page & 0x07);
+    block[48..52].copy_from_slice(&secret[4..]);
+    block[52..55].copy_from_slice(challenge);
+    mac(block, address)
+}
+
+/// The MAC that authorizes writing `data` at `target_address`, where `page_data` is the first 28
+/// bytes of the page being written (before the write)
+pub fn write_mac(
+    secret: &[u8; 8],
+    target_address: u16,
+    page_data: &[u8; 28],
+    data: &[u8; 8],
+    address: &Address,
+) -> [u8; 20] {
+    let mut block = [0; 64];
+    block[..4].copy_from_slice(&secret[..4]);
+    block[4..32].copy_from_slice(page_data);
+    block[32..40].copy_from_slice(data);
+    block[40] = (target_address >> 5) as u8 & 0x07;
+    block[48..52].copy_from_slice(&secret[4..]);
+    block[52..55].copy_from_slice(&[0xFF; 3]);
+    mac(block, address)
+}
+
+/// Fills in the ROM ID and the SHA-1 padding of a 55 byte message, and hashes it. The MAC is the
+/// hash in the order the device sends it: E, D, C, B, then A, each least significant byte first
+fn mac(mut block: [u8; 64], address: &Address) -> [u8; 20] {
+    // the ROM ID without its crc
+    block[41..48].copy_from_slice(&address.0.to_le_bytes()[..7]);
+    block[55] = 0x80;
+    // the message length, in bits
+    block[62..].copy_from_slice(&(55_u16 * 8).to_be_bytes());
+    let hash = sha1_block(&block);
+    let mut mac = [0; 20];
+    for (output, word) in mac.chunks_mut(4).zip(hash.iter().rev()) {
+        output.copy_from_slice(&word.to_le_bytes());
+    }
+    mac
+}
+
+/// SHA-1 of a single, already padded, block
+fn sha1_block(block: &[u8; 64]) -> [u32; 5] {
+    const INITIAL: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut w = [0_u32; 80];
+    for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = INITIAL;
+    for (i, word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    let mut hash = INITIAL;
+    for (h, value) in hash.iter_mut().zip([a, b, c, d, e]) {
+        *h = h.wrapping_add(value);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::crc::Crc16Digest;
+    use crate::devices::ds2432::{commands, read_mac, sha1_block, write_mac, Ds2432, Ds2432Error};
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::{Address, OneWireError};
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    fn address() -> Address {
+        Address::for_test(0x33, 0x1234)
+    }
+
+    /// The inverted crc16 of `data`, as sent by the device
+    fn crc(data: &[u8]) -> Vec<Transaction> {
+        let mut digest = Crc16Digest::new();
+        digest.update(data);
+        Transaction::read_bytes(&(!digest.finalize()).to_le_bytes())
+    }
+
+    /// Writing 8 bytes to the scratchpad, and reading them back
+    fn write_scratchpad(target_address: u16, data: &[u8; 8]) -> Vec<Transaction> {
+        let [ta1, ta2] = target_address.to_le_bytes();
+        let mut transactions = Transaction::command(commands::WRITE_SCRATCHPAD, Some(&address()));
+        transactions.extend(Transaction::write_bytes(&[ta1, ta2]));
+        transactions.extend(Transaction::write_bytes(data));
+        let mut written = std::vec![commands::WRITE_SCRATCHPAD, ta1, ta2];
+        written.extend_from_slice(data);
+        transactions.extend(crc(&written));
+
+        transactions.extend(Transaction::command(
+            commands::READ_SCRATCHPAD,
+            Some(&address()),
+        ));
+        let mut read = std::vec![ta1, ta2, 0x07];
+        read.extend_from_slice(data);
+        transactions.extend(Transaction::read_bytes(&read));
+        read.insert(0, commands::READ_SCRATCHPAD);
+        transactions.extend(crc(&read));
+        transactions
+    }
+
+    #[test]
+    fn test_sha1() {
+        // "abc", from FIPS 180-1
+        let mut block = [0; 64];
+        block[..4].copy_from_slice(b"abc\x80");
+        block[63] = 24;
+        assert_eq!(
+            sha1_block(&block),
+            [
+                0xA999_3E36,
+                0x4706_816A,
+                0xBA3E_2571,
+                0x7850_C26C,
+                0x9CD0_D89D
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mac() {
+        let secret = [1, 2, 3, 4, 5, 6, 7, 8];
+        let page = [0xA5; 32];
+        let mac = read_mac(&secret, 1, &page, &address(), &[9, 10, 11]);
+        // every input changes the MAC
+        assert_ne!(mac, read_mac(&[0; 8], 1, &page, &address(), &[9, 10, 11]));
+        assert_ne!(mac, read_mac(&secret, 2, &page, &address(), &[9, 10, 11]));
+        assert_ne!(
+            mac,
+            read_mac(&secret, 1, &[0; 32], &address(), &[9, 10, 11])
+        );
+        assert_ne!(mac, read_mac(&secret, 1, &page, &address(), &[9, 10, 12]));
+        assert_ne!(
+            write_mac(&secret, 0x20, &[0; 28], &[0; 8], &address()),
+            write_mac(&secret, 0x40, &[0; 28], &[0; 8], &address())
+        );
+
+        // Hashed independently of sha1_block, from the 55 byte messages laid out as in the
+        // datasheet: M10 starts with the MP byte (0x40 | page, or the page of the target address for
+        // writes), followed by the 7 bytes of the ROM ID without the crc. The MAC is E, D, C, B, A,
+        // each least significant byte first
+        let page_data: [u8; 32] = core::array::from_fn(|i| i as u8);
+        assert_eq!(
+            read_mac(&secret, 1, &page_data, &address(), &[9, 10, 11]),
+            [
+                0xF2, 0x9C, 0xBD, 0x70, 0x3B, 0x06, 0x89, 0xE1, 0xA9, 0x56, 0xBC, 0x1D, 0x8A, 0xBD,
+                0x5B, 0x15, 0x72, 0xDB, 0x4A, 0x5E
+            ]
+        );
+        let page_data: [u8; 28] = core::array::from_fn(|i| i as u8);
+        let data: [u8; 8] = core::array::from_fn(|i| 0xA0 + i as u8);
+        assert_eq!(
+            write_mac(&secret, 0x28, &page_data, &data, &address()),
+            [
+                0xCF, 0xCE, 0x35, 0xAD, 0x56, 0x50, 0x20, 0x65, 0xC9, 0x7B, 0x57, 0x0E, 0x9D, 0xEB,
+                0x96, 0x8A, 0x49, 0xFF, 0x8F, 0xCD
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_verified_page() {
+        let secret = [1, 2, 3, 4, 5, 6, 7, 8];
+        let challenge = [9, 10, 11];
+        let page_data: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let mac = read_mac(&secret, 1, &page_data, &address(), &challenge);
+
+        let mut transactions = write_scratchpad(0x20, &[0, 0, 0, 0, 9, 10, 11, 0]);
+        transactions.extend(Transaction::command(
+            commands::READ_AUTHENTICATED_PAGE,
+            Some(&address()),
+        ));
+        transactions.extend(Transaction::write_bytes(&[0x20, 0x00]));
+        transactions.extend(Transaction::read_bytes(&page_data));
+        transactions.push(Transaction::ReadByte(0xFF));
+        let mut read = std::vec![commands::READ_AUTHENTICATED_PAGE, 0x20, 0x00];
+        read.extend_from_slice(&page_data);
+        read.push(0xFF);
+        transactions.extend(crc(&read));
+        transactions.extend(Transaction::read_bytes(&mac));
+        transactions.extend(crc(&mac));
+
+        let device = Ds2432::new::<Infallible>(address()).unwrap();
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.read_verified_page(1, &secret, &challenge, None, &mut mock, &mut NoDelay),
+            Ok(page_data)
+        );
+        mock.done();
+
+        let mut mock = Mock::new(&transactions);
+        assert_eq!(
+            device.read_verified_page(1, &[0; 8], &challenge, None, &mut mock, &mut NoDelay),
+            Err(Ds2432Error::MacMismatch)
+        );
+
+        // a corrupted crc after the MAC is reported to the bus
+        let mut corrupted = transactions.clone();
+        corrupted.pop();
+        corrupted.push(Transaction::ReadByte(0x00));
+        let mut mock = Mock::new(&corrupted);
+        assert!(matches!(
+            device.read_verified_page(1, &secret, &challenge, None, &mut mock, &mut NoDelay),
+            Err(Ds2432Error::OneWire(OneWireError::CrcMismatch {
+                len: 20,
+                ..
+            }))
+        ));
+        assert_eq!(mock.crc_failures(), &[20]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_write_authenticated() {
+        let secret = [1, 2, 3, 4, 5, 6, 7, 8];
+        let data = [0x11; 8];
+        let page_data = [0x22; 28];
+
+        let mut transactions = Transaction::command(commands::READ_MEMORY, Some(&address()));
+        transactions.extend(Transaction::write_bytes(&[0x40, 0x00]));
+        transactions.extend(Transaction::read_bytes(&page_data));
+        transactions.extend(write_scratchpad(0x48, &data));
+        transactions.extend(Transaction::command(
+            commands::COPY_SCRATCHPAD,
+            Some(&address()),
+        ));
+        transactions.extend(Transaction::write_bytes(&[0x48, 0x00, 0x07]));
+        transactions.extend(Transaction::write_bytes(&write_mac(
+            &secret,
+            0x48,
+            &page_data,
+            &data,
+            &address(),
+        )));
+        transactions.push(Transaction::ReadByte(0xAA));
+
+        let device = Ds2432::new::<Infallible>(address()).unwrap();
+        let mut mock = Mock::new(&transactions);
+        device
+            .write_authenticated(0x48, &data, &secret, None, &mut mock, &mut NoDelay)
+            .unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_load_first_secret() {
+        let secret = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut transactions = write_scratchpad(0x80, &secret);
+        transactions.extend(Transaction::command(
+            commands::LOAD_FIRST_SECRET,
+            Some(&address()),
+        ));
+        transactions.extend(Transaction::write_bytes(&[0x80, 0x00, 0x07]));
+
+        let device = Ds2432::new::<Infallible>(address()).unwrap();
+        let mut succeeded = transactions.clone();
+        succeeded.push(Transaction::ReadByte(0xAA));
+        let mut mock = Mock::new(&succeeded);
+        assert_eq!(
+            device.load_first_secret(&secret, None, &mut mock, &mut NoDelay),
+            Ok(())
+        );
+        mock.done();
+
+        transactions.push(Transaction::ReadByte(0xFF));
+        let mut mock = Mock::new(&transactions);
+        assert!(device
+            .load_first_secret(&secret, None, &mut mock, &mut NoDelay)
+            .is_err());
+        mock.done();
+    }
+}
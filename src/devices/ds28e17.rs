@@ -0,0 +1,518 @@
+//! Driver for the DS28E17 1-Wire to I2C bridge.
+//!
+//! `Ds28e17::i2c` returns an I2C bus that implements the `embedded_hal` blocking I2C traits (and the
+//! embedded-hal 1.0 `I2c` trait, with the `embedded-hal-1` feature), so existing I2C drivers can be
+//! used with sensors connected at the end of a long 1-Wire bus.
+
+use crate::crc::Crc16Digest;
+use crate::delay::Delay;
+use crate::{Address, Family, OneWireBus, OneWireError, OneWireResult, TypedAddress};
+use embedded_hal::blocking::i2c;
+
+pub const FAMILY_CODE: u8 = 0x19;
+
+pub mod commands {
+    pub const WRITE_DATA_WITH_STOP: u8 = 0x4B;
+    pub const WRITE_DATA_NO_STOP: u8 = 0x5A;
+    pub const WRITE_DATA_ONLY: u8 = 0x69;
+    pub const WRITE_DATA_ONLY_WITH_STOP: u8 = 0x78;
+    pub const READ_DATA_WITH_STOP: u8 = 0x87;
+    pub const WRITE_READ_DATA_WITH_STOP: u8 = 0x2D;
+    pub const WRITE_CONFIGURATION: u8 = 0xD2;
+    pub const READ_CONFIGURATION: u8 = 0xE1;
+    pub const ENABLE_SLEEP_MODE: u8 = 0x1E;
+    pub const READ_DEVICE_REVISION: u8 = 0xC4;
+}
+
+// Bits of the status byte
+const STATUS_CRC_ERROR: u8 = 0x01;
+const STATUS_ADDRESS_NACK: u8 = 0x02;
+const STATUS_START_ERROR: u8 = 0x08;
+
+/// The longest I2C transaction (255 bytes of 9 bits each, at 100kHz) is about 25ms.
+/// This allows twice as long before giving up
+const MAX_BUSY_MICROS: u32 = 50_000;
+
+/// Errors from an I2C transaction through the bridge
+#[derive(Debug, Copy, Clone)]
+pub enum I2cError<E> {
+    OneWire(OneWireError<E>),
+
+    /// No I2C device acknowledged the address
+    AddressNack,
+
+    /// The I2C device didn't acknowledge a data byte. The value is the number of bytes that were
+    /// not written
+    DataNack(u8),
+
+    /// The bridge wasn't able to generate a start condition (the I2C bus is busy or stuck)
+    StartError,
+
+    /// Transactions must be between 1 and 255 bytes
+    InvalidLength,
+
+    /// The bridge received a packet with an invalid crc
+    CrcError,
+
+    /// A transaction with a read before a write. The bridge always ends a read with a stop, so
+    /// reads can only come at the end of a transaction
+    UnsupportedTransaction,
+}
+
+impl<E> From<OneWireError<E>> for I2cError<E> {
+    fn from(err: OneWireError<E>) -> I2cError<E> {
+        I2cError::OneWire(err)
+    }
+}
+
+/// The speed of the I2C bus
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum I2cSpeed {
+    Khz100 = 0x00,
+    Khz400 = 0x01,
+    Khz900 = 0x02,
+}
+
+pub struct Ds28e17 {
+    address: Address,
+}
+
+impl Family for Ds28e17 {
+    const FAMILY_CODE: u8 = FAMILY_CODE;
+}
+
+impl From<TypedAddress<Ds28e17>> for Ds28e17 {
+    fn from(address: TypedAddress<Ds28e17>) -> Ds28e17 {
+        Ds28e17 {
+            address: address.into(),
+        }
+    }
+}
+
+impl Ds28e17 {
+    /// Checks that the given address contains the correct family code
+    pub fn new<E>(address: Address) -> OneWireResult<Ds28e17, E> {
+        Ok(Ds28e17::from(TypedAddress::new(address)?))
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Sets the speed of the I2C bus. This is 400kHz after power up
+    pub fn set_i2c_speed<B>(
+        &self,
+        speed: I2cSpeed,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::WRITE_CONFIGURATION, Some(&self.address), delay)?;
+        onewire.write_byte(speed as u8, delay)
+    }
+
+    /// Puts the bridge into a low power mode. It wakes up again on the next 1-Wire activity
+    pub fn enable_sleep_mode<B>(
+        &self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        onewire.send_command(commands::ENABLE_SLEEP_MODE, Some(&self.address), delay)
+    }
+
+    /// Writes `bytes` to the I2C device at the 7-bit `i2c_address`, followed by a stop condition
+    pub fn write<B>(
+        &self,
+        i2c_address: u8,
+        bytes: &[u8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<(), I2cError<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        let length = packet_length(bytes.len())?;
+        self.write_packet(
+            commands::WRITE_DATA_WITH_STOP,
+            &[i2c_address << 1, length],
+            bytes,
+            onewire,
+            delay,
+        )
+    }
+
+    /// Reads `buffer.len()` bytes from the I2C device at the 7-bit `i2c_address`, followed by a stop condition
+    pub fn read<B>(
+        &self,
+        i2c_address: u8,
+        buffer: &mut [u8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<(), I2cError<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        let length = packet_length(buffer.len())?;
+        self.send_packet(
+            commands::READ_DATA_WITH_STOP,
+            &[&[(i2c_address << 1) | 0x01, length]],
+            onewire,
+            delay,
+        )?;
+        check_status(onewire.read_byte(delay)?, None)?;
+        onewire.read_bytes(buffer, delay)?;
+        Ok(())
+    }
+
+    /// Writes `bytes` then reads `buffer.len()` bytes from the I2C device at the 7-bit `i2c_address`,
+    /// using a repeated start between them, followed by a stop condition
+    pub fn write_read<B>(
+        &self,
+        i2c_address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<(), I2cError<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        let write_length = packet_length(bytes.len())?;
+        let read_length = packet_length(buffer.len())?;
+        self.send_packet(
+            commands::WRITE_READ_DATA_WITH_STOP,
+            &[&[i2c_address << 1, write_length], bytes, &[read_length]],
+            onewire,
+            delay,
+        )?;
+        let mut status = [0; 2];
+        onewire.read_bytes(&mut status, delay)?;
+        check_status(status[0], Some(status[1]))?;
+        onewire.read_bytes(buffer, delay)?;
+        Ok(())
+    }
+
+    /// Returns an I2C bus that implements the `embedded_hal` blocking I2C traits
+    pub fn i2c<'a, B, D>(&'a self, onewire: &'a mut B, delay: &'a mut D) -> Ds28e17I2c<'a, B, D> {
+        Ds28e17I2c {
+            device: self,
+            onewire,
+            delay,
+        }
+    }
+
+    /// Sends one of the write commands, with `header` (the I2C address and / or length) before the
+    /// data, then checks the status bytes the bridge returns
+    fn write_packet<B>(
+        &self,
+        command: u8,
+        header: &[u8],
+        bytes: &[u8],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> Result<(), I2cError<B::Error>>
+    where
+        B: OneWireBus,
+    {
+        self.send_packet(command, &[header, bytes], onewire, delay)?;
+        let mut status = [0; 2];
+        onewire.read_bytes(&mut status, delay)?;
+        check_status(status[0], Some(status[1]))
+    }
+
+    /// Sends a command followed by its parameters and the inverted crc16 of the whole packet,
+    /// then waits for the bridge to finish the I2C transaction
+    fn send_packet<B>(
+        &self,
+        command: u8,
+        parameters: &[&[u8]],
+        onewire: &mut B,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut digest = Crc16Digest::new();
+        digest.update(&[command]);
+        onewire.send_command(command, Some(&self.address), delay)?;
+        for bytes in parameters {
+            digest.update(bytes);
+            onewire.write_bytes(bytes, delay)?;
+        }
+        onewire.write_bytes(&(!digest.finalize()).to_le_bytes(), delay)?;
+
+        // The bridge responds with 1s while the I2C transaction is in progress
+        for _ in 0..(MAX_BUSY_MICROS / onewire.read_slot_micros().max(1)) {
+            if !onewire.read_bit(delay)? {
+                return Ok(());
+            }
+            onewire.idle();
+        }
+        Err(OneWireError::Timeout(MAX_BUSY_MICROS))
+    }
+}
+
+fn packet_length<E>(length: usize) -> Result<u8, I2cError<E>> {
+    if length == 0 || length > 255 {
+        Err(I2cError::InvalidLength)
+    } else {
+        Ok(length as u8)
+    }
+}
+
+fn check_status<E>(status: u8, write_status: Option<u8>) -> Result<(), I2cError<E>> {
+    if status & STATUS_CRC_ERROR != 0 {
+        return Err(I2cError::CrcError);
+    }
+    if status & STATUS_ADDRESS_NACK != 0 {
+        return Err(I2cError::AddressNack);
+    }
+    if status & STATUS_START_ERROR != 0 {
+        return Err(I2cError::StartError);
+    }
+    match write_status {
+        Some(0) | None => Ok(()),
+        Some(remaining) => Err(I2cError::DataNack(remaining)),
+    }
+}
+
+/// An I2C bus behind a DS28E17 bridge
+pub struct Ds28e17I2c<'a, B, D> {
+    device: &'a Ds28e17,
+    onewire: &'a mut B,
+    delay: &'a mut D,
+}
+
+impl<'a, B, D> i2c::Write for Ds28e17I2c<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    type Error = I2cError<B::Error>;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.device.write(address, bytes, self.onewire, self.delay)
+    }
+}
+
+impl<'a, B, D> i2c::Read for Ds28e17I2c<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    type Error = I2cError<B::Error>;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.device.read(address, buffer, self.onewire, self.delay)
+    }
+}
+
+impl<'a, B, D> i2c::WriteRead for Ds28e17I2c<'a, B, D>
+where
+    B: OneWireBus,
+    D: Delay,
+{
+    type Error = I2cError<B::Error>;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.device
+            .write_read(address, bytes, buffer, self.onewire, self.delay)
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<E: core::fmt::Debug> embedded_hal_1::i2c::Error for I2cError<E> {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource};
+        match self {
+            I2cError::AddressNack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            I2cError::DataNack(_) => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+            I2cError::StartError => ErrorKind::Bus,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<'a, B, D> embedded_hal_1::i2c::ErrorType for Ds28e17I2c<'a, B, D>
+where
+    B: OneWireBus,
+    B::Error: core::fmt::Debug,
+{
+    type Error = I2cError<B::Error>;
+}
+
+/// Consecutive writes are sent without a start between them (with the bridge's WRITE DATA ONLY
+/// commands), and the reads at the end of a transaction are done as one read after a repeated
+/// start. A transaction with a read anywhere else returns `I2cError::UnsupportedTransaction`
+#[cfg(feature = "embedded-hal-1")]
+impl<'a, B, D> embedded_hal_1::i2c::I2c for Ds28e17I2c<'a, B, D>
+where
+    B: OneWireBus,
+    B::Error: core::fmt::Debug,
+    D: Delay,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        use embedded_hal_1::i2c::Operation;
+
+        let reads = operations
+            .iter()
+            .rev()
+            .take_while(|operation| matches!(operation, Operation::Read(_)))
+            .count();
+        let (writes, reads) = operations.split_at_mut(operations.len() - reads);
+        for (index, operation) in writes.iter().enumerate() {
+            let bytes = match operation {
+                Operation::Write(bytes) => bytes,
+                Operation::Read(_) => return Err(I2cError::UnsupportedTransaction),
+            };
+            let length = packet_length(bytes.len())?;
+            let stop = reads.is_empty() && index == writes.len() - 1;
+            let (command, header) = match (index, stop) {
+                (0, true) => (commands::WRITE_DATA_WITH_STOP, [address << 1, length]),
+                (0, false) => (commands::WRITE_DATA_NO_STOP, [address << 1, length]),
+                (_, true) => (commands::WRITE_DATA_ONLY_WITH_STOP, [length, 0]),
+                (_, false) => (commands::WRITE_DATA_ONLY, [length, 0]),
+            };
+            let header = if index == 0 {
+                &header[..]
+            } else {
+                &header[..1]
+            };
+            self.device
+                .write_packet(command, header, bytes, self.onewire, self.delay)?;
+        }
+        if reads.is_empty() {
+            return Ok(());
+        }
+
+        let total: usize = reads
+            .iter()
+            .map(|operation| match operation {
+                Operation::Read(buffer) => buffer.len(),
+                Operation::Write(_) => 0,
+            })
+            .sum();
+        let length = packet_length(total)?;
+        self.device.send_packet(
+            commands::READ_DATA_WITH_STOP,
+            &[&[(address << 1) | 0x01, length]],
+            self.onewire,
+            self.delay,
+        )?;
+        check_status(self.onewire.read_byte(self.delay)?, None)?;
+        for operation in reads {
+            if let Operation::Read(buffer) = operation {
+                self.onewire.read_bytes(buffer, self.delay)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::devices::ds28e17::{check_status, I2cError};
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status::<()>(0x00, Some(0x00)).is_ok());
+        assert!(check_status::<()>(0x00, None).is_ok());
+        assert!(matches!(
+            check_status::<()>(0x01, Some(0x00)),
+            Err(I2cError::CrcError)
+        ));
+        assert!(matches!(
+            check_status::<()>(0x02, None),
+            Err(I2cError::AddressNack)
+        ));
+        assert!(matches!(
+            check_status::<()>(0x08, None),
+            Err(I2cError::StartError)
+        ));
+        assert!(matches!(
+            check_status::<()>(0x00, Some(0x03)),
+            Err(I2cError::DataNack(3))
+        ));
+    }
+
+    #[cfg(feature = "embedded-hal-1")]
+    #[test]
+    fn test_i2c_transaction() {
+        use crate::crc::Crc16Digest;
+        use crate::devices::ds28e17::{commands, Ds28e17};
+        use crate::mock::{Mock, NoDelay, Transaction};
+        use crate::Address;
+        use embedded_hal_1::i2c::{I2c, Operation};
+        use std::vec::Vec;
+
+        fn packet(address: &Address, command: u8, parameters: &[u8]) -> Vec<Transaction> {
+            let mut digest = Crc16Digest::new();
+            digest.update(&[command]);
+            digest.update(parameters);
+            let mut transactions = Transaction::command(command, Some(address));
+            transactions.extend(Transaction::write_bytes(parameters));
+            transactions.extend(Transaction::write_bytes(
+                &(!digest.finalize()).to_le_bytes(),
+            ));
+            // the bridge is no longer busy
+            transactions.push(Transaction::ReadBit(false));
+            transactions
+        }
+
+        let address = Address::for_test(0x19, 1);
+        let bridge = Ds28e17::new::<()>(address).unwrap();
+        let mut expected = packet(&address, commands::WRITE_DATA_NO_STOP, &[0x90, 1, 0x01]);
+        expected.extend(Transaction::read_bytes(&[0, 0]));
+        expected.extend(packet(&address, commands::WRITE_DATA_ONLY, &[1, 0x02]));
+        expected.extend(Transaction::read_bytes(&[0, 0]));
+        expected.extend(packet(&address, commands::READ_DATA_WITH_STOP, &[0x91, 3]));
+        expected.extend(Transaction::read_bytes(&[0, 0xAB, 0xCD, 0xEF]));
+        let mut mock = Mock::new(&expected);
+
+        let mut first = [0; 1];
+        let mut rest = [0; 2];
+        bridge
+            .i2c(&mut mock, &mut NoDelay)
+            .transaction(
+                0x48,
+                &mut [
+                    Operation::Write(&[0x01]),
+                    Operation::Write(&[0x02]),
+                    Operation::Read(&mut first),
+                    Operation::Read(&mut rest),
+                ],
+            )
+            .unwrap();
+        mock.done();
+        assert_eq!(first, [0xAB]);
+        assert_eq!(rest, [0xCD, 0xEF]);
+
+        let mut buffer = [0; 1];
+        assert!(matches!(
+            bridge.i2c(&mut mock, &mut NoDelay).transaction(
+                0x48,
+                &mut [Operation::Read(&mut buffer), Operation::Write(&[0x01])],
+            ),
+            Err(I2cError::UnsupportedTransaction)
+        ));
+        mock.done();
+    }
+}
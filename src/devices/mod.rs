@@ -0,0 +1,20 @@
+//! Drivers for specific 1-Wire devices, built on top of `OneWire`. Each driver is enabled with a
+//! feature of the same name
+
+#[cfg(feature = "ds18b20")]
+pub mod ds18b20;
+
+#[cfg(feature = "ds2408")]
+pub mod ds2408;
+
+#[cfg(feature = "ds2409")]
+pub mod ds2409;
+
+#[cfg(feature = "ds2413")]
+pub mod ds2413;
+
+#[cfg(feature = "ds2432")]
+pub mod ds2432;
+
+#[cfg(feature = "ds28e17")]
+pub mod ds28e17;
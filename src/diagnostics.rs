@@ -0,0 +1,246 @@
+use crate::delay::Delay;
+use crate::pullup::StrongPullupPin;
+use crate::timing::PreciseTimer;
+use crate::{OneWire, OneWireError, OneWireResult, ResetResult};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// The result of `OneWire::diagnose`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusDiagnostics {
+    /// At least one device responded to a reset
+    Healthy,
+
+    /// The bus is working, but no devices responded to a reset
+    NoDevices,
+
+    /// The bus is held low while it's released. The data line is shorted to ground (or a device is
+    /// stuck holding it low)
+    ShortToGround,
+
+    /// The bus was high, but didn't return high after being pulled low. This is usually a missing
+    /// or disconnected pull-up resistor, where the bus just floats
+    NoPullUp,
+}
+
+/// Measurements of a reset, from `OneWire::measure_bus_quality`. All times are in µs, from when the
+/// reset pulse was released
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusQuality {
+    /// How long the pull-up took to bring the bus high. `None` if it never did
+    pub rise_micros: Option<u32>,
+
+    /// When a device started the presence pulse. `None` if no devices responded
+    pub presence_start_micros: Option<u32>,
+
+    /// How long the presence pulse lasted. `None` if no devices responded, or the bus didn't
+    /// return high before the end of the reset
+    pub presence_micros: Option<u32>,
+}
+
+impl BusQuality {
+    /// Returns true if a presence pulse was seen, and it started (15 - 60 µs) and lasted
+    /// (60 - 240 µs) within the limits of the 1-Wire specification. A slow rise time makes the
+    /// presence pulse start late, and appear shorter
+    pub fn presence_in_spec(&self) -> bool {
+        match (self.presence_start_micros, self.presence_micros) {
+            (Some(start), Some(length)) => {
+                (15..=60).contains(&start) && (60..=240).contains(&length)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<T, E, P> OneWire<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    /// Checks the wiring of the bus, and whether any devices are present.
+    ///
+    /// This is intended for reporting wiring problems from field / setup tools, rather than being
+    /// called before every transaction.
+    pub fn diagnose(&mut self, delay: &mut impl Delay) -> OneWireResult<BusDiagnostics, E> {
+        self.release_bus()?;
+        match self.wait_for_high(delay) {
+            Err(OneWireError::BusNotHigh) => return Ok(BusDiagnostics::ShortToGround),
+            result => result?,
+        }
+
+        // A pulled up bus recovers from a short low pulse almost immediately. Without a pull-up,
+        // the bus stays low
+        self.set_bus_low()?;
+        delay.delay_us(6);
+        self.release_bus()?;
+        match self.wait_for_high(delay) {
+            Err(OneWireError::BusNotHigh) => return Ok(BusDiagnostics::NoPullUp),
+            result => result?,
+        }
+
+        match self.reset_detailed(delay)? {
+            ResetResult::DevicesPresent => Ok(BusDiagnostics::Healthy),
+            ResetResult::NoDevices => Ok(BusDiagnostics::NoDevices),
+            ResetResult::ShortToGround => Ok(BusDiagnostics::ShortToGround),
+        }
+    }
+
+    /// Sends a reset pulse, measuring the rise time of the bus and the presence pulse with `timer`,
+    /// to check whether a long cable run is marginal. The timer is polled continuously, so its
+    /// resolution (and the time it takes to read the pin) limits the accuracy.
+    pub fn measure_bus_quality(
+        &mut self,
+        timer: &mut impl PreciseTimer,
+    ) -> OneWireResult<BusQuality, E> {
+        let ticks_per_micro = timer.ticks_per_micro().max(1);
+        let reset_low = self.times.reset_low as u32 * ticks_per_micro;
+        let reset_length = (self.times.presence_sample as u32 + self.times.reset_recovery as u32)
+            * ticks_per_micro;
+
+        let start = timer.now();
+        while !self.is_bus_high()? {
+            if timer.now().wrapping_sub(start)
+                >= self.bus_high_timeout_micros as u32 * ticks_per_micro
+            {
+                return Err(OneWireError::BusNotHigh);
+            }
+        }
+
+        self.set_bus_low()?;
+        let start = timer.now();
+        while timer.now().wrapping_sub(start) < reset_low {}
+
+        let mut quality = BusQuality {
+            rise_micros: None,
+            presence_start_micros: None,
+            presence_micros: None,
+        };
+        let start = timer.now();
+        self.release_bus()?;
+        loop {
+            let elapsed = timer.now().wrapping_sub(start);
+            if elapsed >= reset_length {
+                break;
+            }
+            let micros = elapsed / ticks_per_micro;
+            let high = self.is_bus_high()?;
+            match (quality.rise_micros, quality.presence_start_micros) {
+                (None, _) if high => quality.rise_micros = Some(micros),
+                (Some(_), None) if !high => quality.presence_start_micros = Some(micros),
+                (Some(_), Some(presence_start)) if high && quality.presence_micros.is_none() => {
+                    quality.presence_micros = Some(micros - presence_start)
+                }
+                _ => {}
+            }
+        }
+        Ok(quality)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use crate::timing::PreciseTimer;
+    use crate::{Address, BusDiagnostics, OneWire};
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use embedded_hal::blocking::delay::DelayUs;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+    /// A bus without a pull-up. It reads high until it's first pulled low, then floats low
+    struct Floating<'a> {
+        pin: SimulatedPin<'a>,
+        pulled_low: Cell<bool>,
+    }
+
+    impl<'a> OutputPin for Floating<'a> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.pulled_low.set(true);
+            self.pin.set_low()
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.pin.set_high()
+        }
+    }
+
+    impl<'a> InputPin for Floating<'a> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(!self.pulled_low.get() && self.pin.is_high()?)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(!self.is_high()?)
+        }
+    }
+
+    /// Advances the simulation by 1 µs every time it's read
+    struct SimulatedTimer<'a> {
+        simulator: &'a Simulator,
+        delay: SimulatedDelay<'a>,
+    }
+
+    impl<'a> PreciseTimer for SimulatedTimer<'a> {
+        fn now(&mut self) -> u32 {
+            self.delay.delay_us(1u32);
+            self.simulator.time_micros() as u32
+        }
+
+        fn ticks_per_micro(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.diagnose(&mut delay), Ok(BusDiagnostics::NoDevices));
+
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        assert_eq!(one_wire.diagnose(&mut delay), Ok(BusDiagnostics::Healthy));
+
+        simulator.set_stuck_low(true);
+        assert_eq!(
+            one_wire.diagnose(&mut delay),
+            Ok(BusDiagnostics::ShortToGround)
+        );
+        simulator.set_stuck_low(false);
+        assert_eq!(one_wire.diagnose(&mut delay), Ok(BusDiagnostics::Healthy));
+
+        let mut one_wire = OneWire::new(Floating {
+            pin: simulator.pin(),
+            pulled_low: Cell::new(false),
+        })
+        .unwrap();
+        assert_eq!(one_wire.diagnose(&mut delay), Ok(BusDiagnostics::NoPullUp));
+    }
+
+    #[test]
+    fn test_bus_quality() {
+        let simulator = Simulator::new();
+        let mut timer = SimulatedTimer {
+            simulator: &simulator,
+            delay: simulator.delay(),
+        };
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let quality = one_wire.measure_bus_quality(&mut timer).unwrap();
+        assert!(quality.rise_micros.is_some());
+        assert_eq!(quality.presence_start_micros, None);
+        assert!(!quality.presence_in_spec());
+
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let quality = one_wire.measure_bus_quality(&mut timer).unwrap();
+        assert!(quality.rise_micros.unwrap() <= 2);
+        let presence_start = quality.presence_start_micros.unwrap();
+        let presence = quality.presence_micros.unwrap();
+        assert!((15..=17).contains(&presence_start), "{}", presence_start);
+        assert!((118..=122).contains(&presence), "{}", presence);
+        assert!(quality.presence_in_spec());
+    }
+}
@@ -0,0 +1,40 @@
+//! Constructors for Embassy firmware, enabled with the `embassy` feature. These use
+//! `embassy_time::Delay`, so the delay doesn't have to be passed to every operation.
+//!
+//! The blocking bus busy-waits on the Embassy time driver, so the tick rate needs to be at least
+//! 1 MHz for the slot timings to be accurate. The async bus (with the `async` feature) awaits an
+//! Embassy `Timer` instead, so other tasks can run during the longer waits.
+
+#[cfg(feature = "async")]
+use crate::asynch::AsyncOneWire;
+use crate::delayed::DelayedBus;
+use crate::{OneWire, OneWireResult};
+pub use embassy_time::Delay;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A blocking bus using the Embassy time driver for its delays
+pub type EmbassyOneWire<T> = DelayedBus<OneWire<T>, Delay>;
+
+impl<T, E> OneWire<T>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+{
+    /// Creates a bus that owns an `embassy_time::Delay`. See `OneWire::with_delay`
+    pub fn with_embassy_delay(pin: T) -> OneWireResult<EmbassyOneWire<T>, E> {
+        OneWire::with_delay(pin, Delay)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> AsyncOneWire<T, Delay>
+where
+    T: embedded_hal_1::digital::InputPin
+        + embedded_hal_1::digital::OutputPin
+        + embedded_hal_async::digital::Wait,
+{
+    /// Creates an async bus that waits on Embassy timers
+    pub fn with_embassy_delay(pin: T) -> OneWireResult<AsyncOneWire<T, Delay>, T::Error> {
+        AsyncOneWire::new(pin, Delay)
+    }
+}
@@ -2,7 +2,7 @@ use core::fmt::Debug;
 
 pub type OneWireResult<T, E> = Result<T, OneWireError<E>>;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum OneWireError<E> {
     /// The Bus was expected to be pulled high by a ~5K ohm pull-up resistor, but it wasn't
     BusNotHigh,
@@ -14,6 +14,187 @@ pub enum OneWireError<E> {
     UnexpectedResponse,
 
     FamilyCodeMismatch,
-    CrcMismatch,
+
+    /// The crc sent with some data didn't match the data. `computed` is the crc of the data that was
+    /// received, `received` is the crc sent along with it, and `len` is the number of bytes the crc
+    /// covers. 8 bit crcs are stored in the low byte
+    CrcMismatch {
+        computed: u16,
+        received: u16,
+        len: usize,
+    },
+
+    /// A device didn't finish an operation in time. The value is how long was waited, in µs
+    Timeout(u32),
+
+    /// Data read back after writing it didn't match what was written
+    VerificationFailed,
+
+    /// No devices responded to a reset with a presence pulse
+    NoDevicePresent,
+}
+
+/// A broad category of error, so retry / backoff logic can handle errors without matching every
+/// variant of `OneWireError`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A problem with the bus itself, such as a pin error, the bus not being pulled high, or no
+    /// devices responding
+    Bus,
+
+    /// A device responded in a way that doesn't follow the protocol, or was the wrong type
+    Protocol,
+
+    /// Data was corrupted (a crc mismatch, or data that didn't read back correctly)
+    Data,
+
+    /// A device didn't finish an operation in time
     Timeout,
 }
+
+impl<E> OneWireError<E> {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            OneWireError::BusNotHigh
+            | OneWireError::PinError(_)
+            | OneWireError::NoDevicePresent => ErrorKind::Bus,
+            OneWireError::UnexpectedResponse | OneWireError::FamilyCodeMismatch => {
+                ErrorKind::Protocol
+            }
+            OneWireError::CrcMismatch { .. } | OneWireError::VerificationFailed => ErrorKind::Data,
+            OneWireError::Timeout(_) => ErrorKind::Timeout,
+        }
+    }
+}
+
+/// Wraps a pin error, so `?` can be used on pin operations directly
+impl<E> From<E> for OneWireError<E> {
+    fn from(err: E) -> OneWireError<E> {
+        OneWireError::PinError(err)
+    }
+}
+
+impl<E: Debug> core::fmt::Display for OneWireError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            OneWireError::BusNotHigh => write!(f, "the bus was not pulled high"),
+            OneWireError::PinError(err) => write!(f, "pin error: {:?}", err),
+            OneWireError::UnexpectedResponse => write!(f, "unexpected response from device"),
+            OneWireError::FamilyCodeMismatch => write!(f, "family code mismatch"),
+            OneWireError::CrcMismatch {
+                computed,
+                received,
+                len,
+            } => write!(
+                f,
+                "crc mismatch (computed {:02X}, received {:02X}, over {} bytes)",
+                computed, received, len
+            ),
+            OneWireError::Timeout(micros) => write!(f, "timed out after {} µs", micros),
+            OneWireError::VerificationFailed => write!(f, "data read back didn't match"),
+            OneWireError::NoDevicePresent => write!(f, "no devices responded to the reset"),
+        }
+    }
+}
+
+/// The operation an error happened during
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Reset,
+    Search,
+    MatchRom,
+    Read,
+    Write,
+}
+
+impl core::fmt::Display for Operation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Operation::Reset => "reset",
+            Operation::Search => "search",
+            Operation::MatchRom => "match rom",
+            Operation::Read => "read",
+            Operation::Write => "write",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An error, along with the operation it happened during. Created with `OneWireError::during`, or
+/// `OperationContext::during` on a result
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OperationError<E> {
+    pub operation: Operation,
+    pub error: OneWireError<E>,
+}
+
+impl<E> OneWireError<E> {
+    /// Attaches the operation the error happened during
+    pub fn during(self, operation: Operation) -> OperationError<E> {
+        OperationError {
+            operation,
+            error: self,
+        }
+    }
+}
+
+impl<E> From<OperationError<E>> for OneWireError<E> {
+    fn from(error: OperationError<E>) -> OneWireError<E> {
+        error.error
+    }
+}
+
+impl<E: Debug> core::fmt::Display for OperationError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} failed: {}", self.operation, self.error)
+    }
+}
+
+/// Attaches an `Operation` to the error of a result
+pub trait OperationContext<T, E> {
+    fn during(self, operation: Operation) -> Result<T, OperationError<E>>;
+}
+
+impl<T, E> OperationContext<T, E> for OneWireResult<T, E> {
+    fn during(self, operation: Operation) -> Result<T, OperationError<E>> {
+        self.map_err(|error| error.during(operation))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::{ErrorKind, OneWireError, OneWireResult, Operation, OperationContext};
+    use std::string::ToString;
+
+    #[test]
+    fn test_operation_context() {
+        let result: OneWireResult<(), ()> = Err(OneWireError::UnexpectedResponse);
+        let error = result.during(Operation::Search).unwrap_err();
+        assert_eq!(error.operation, Operation::Search);
+        assert_eq!(
+            error.to_string(),
+            "search failed: unexpected response from device"
+        );
+        assert_eq!(
+            OneWireError::<()>::from(error),
+            OneWireError::UnexpectedResponse
+        );
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(OneWireError::PinError(()).kind(), ErrorKind::Bus);
+        assert_eq!(
+            OneWireError::<()>::UnexpectedResponse.kind(),
+            ErrorKind::Protocol
+        );
+        assert_eq!(
+            OneWireError::<()>::VerificationFailed.kind(),
+            ErrorKind::Data
+        );
+        assert_eq!(OneWireError::<()>::Timeout(100).kind(), ErrorKind::Timeout);
+        assert_eq!(OneWireError::<()>::NoDevicePresent.kind(), ErrorKind::Bus);
+    }
+}
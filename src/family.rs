@@ -0,0 +1,106 @@
+/// Defines `FamilyCode`, with a variant for each known family, the conversions to and from the
+/// family code byte, and `family_name`
+macro_rules! families {
+    ($($(#[$doc:meta])* $variant:ident = $code:literal => $name:literal,)*) => {
+        /// The family code of a device (the first byte of its address), which identifies what type of
+        /// device it is. Families without a variant are `Unknown`
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum FamilyCode {
+            $($(#[$doc])* $variant,)*
+            Unknown(u8),
+        }
+
+        impl From<u8> for FamilyCode {
+            fn from(code: u8) -> FamilyCode {
+                match code {
+                    $($code => FamilyCode::$variant,)*
+                    code => FamilyCode::Unknown(code),
+                }
+            }
+        }
+
+        impl From<FamilyCode> for u8 {
+            fn from(family: FamilyCode) -> u8 {
+                match family {
+                    $(FamilyCode::$variant => $code,)*
+                    FamilyCode::Unknown(code) => code,
+                }
+            }
+        }
+
+        /// Returns the name of the device (or the most common device) with this family code, for
+        /// displaying the results of a device search
+        #[cfg(feature = "family-names")]
+        pub fn family_name(code: u8) -> Option<&'static str> {
+            match code {
+                $($code => Some($name),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+families! {
+    /// Silicon serial number (also DS1990A iButton)
+    Ds2401 = 0x01 => "DS2401",
+    /// Addressable switch
+    Ds2405 = 0x05 => "DS2405",
+    /// 112 byte EEPROM, which only communicates at overdrive speed
+    Ds28e05 = 0x0D => "DS28E05",
+    /// Temperature sensor (also DS1920)
+    Ds18s20 = 0x10 => "DS18S20",
+    /// Dual addressable switch
+    Ds2406 = 0x12 => "DS2406",
+    /// 1-Wire to I2C master bridge
+    Ds28e17 = 0x19 => "DS28E17",
+    /// Counter
+    Ds2423 = 0x1D => "DS2423",
+    /// Microlan coupler
+    Ds2409 = 0x1F => "DS2409",
+    /// Quad A/D converter
+    Ds2450 = 0x20 => "DS2450",
+    /// Temperature sensor
+    Ds1822 = 0x22 => "DS1822",
+    /// 4 Kbit EEPROM
+    Ds2433 = 0x23 => "DS2433",
+    /// Battery monitor
+    Ds2438 = 0x26 => "DS2438",
+    /// Temperature sensor
+    Ds18b20 = 0x28 => "DS18B20",
+    /// 8 channel addressable switch
+    Ds2408 = 0x29 => "DS2408",
+    /// 1 Kbit EEPROM
+    Ds2431 = 0x2D => "DS2431",
+    /// 1 Kbit EEPROM with SHA-1 authentication (also DS1961S)
+    Ds2432 = 0x33 => "DS2432",
+    /// Dual channel addressable switch
+    Ds2413 = 0x3A => "DS2413",
+    /// Temperature sensor
+    Ds1825 = 0x3B => "DS1825",
+    /// Temperature sensor with sequence detect
+    Ds28ea00 = 0x42 => "DS28EA00",
+    /// 20 Kbit EEPROM
+    Ds28ec20 = 0x43 => "DS28EC20",
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Address, FamilyCode};
+
+    #[test]
+    fn test_family_code() {
+        assert_eq!(Address(0xE800000B1FCD1028).family(), FamilyCode::Ds18b20);
+        assert_eq!(FamilyCode::from(0x2D), FamilyCode::Ds2431);
+        assert_eq!(FamilyCode::from(0x0D), FamilyCode::Ds28e05);
+        assert_eq!(FamilyCode::from(0xEE), FamilyCode::Unknown(0xEE));
+        assert_eq!(u8::from(FamilyCode::Ds18s20), 0x10);
+        assert_eq!(u8::from(FamilyCode::Unknown(0xEE)), 0xEE);
+    }
+
+    #[cfg(feature = "family-names")]
+    #[test]
+    fn test_family_name() {
+        assert_eq!(crate::family_name(0x28), Some("DS18B20"));
+        assert_eq!(crate::family_name(0xEE), None);
+    }
+}
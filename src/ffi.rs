@@ -0,0 +1,407 @@
+//! C bindings, enabled with the `ffi` feature.
+//!
+//! The pin and delay are supplied as callbacks in `OwPins`, and the caller owns the storage of each
+//! `OwBus` (a global or stack variable, so no allocator is needed). The bus is opaque to C, which
+//! only reserves `OW_BUS_SIZE` bytes for it. The functions return 0 (or a positive value where
+//! documented) on success, and one of the negative `OW_ERR_*` codes on failure. From C:
+//!
+//! ```c
+//! typedef struct {
+//!     void *context;
+//!     void (*set_level)(void *context, bool high);
+//!     bool (*is_high)(void *context);
+//!     void (*delay_us)(void *context, uint16_t micros);
+//! } ow_pins_t;
+//!
+//! #define OW_BUS_SIZE 256
+//!
+//! typedef struct {
+//!     _Alignas(8) uint8_t storage[OW_BUS_SIZE];
+//! } ow_bus_t;
+//! ```
+//!
+//! To link the bindings into firmware, build a `staticlib` crate that depends on this crate with the
+//! `ffi` feature enabled (and provides a panic handler).
+
+use crate::delay::Delay;
+use crate::{Address, OneWire, OneWireError, OneWireResult, SearchState};
+use core::convert::Infallible;
+use core::ffi::c_void;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+pub const OW_OK: i32 = 0;
+pub const OW_ERR_BUS_NOT_HIGH: i32 = -1;
+pub const OW_ERR_PIN: i32 = -2;
+pub const OW_ERR_UNEXPECTED_RESPONSE: i32 = -3;
+pub const OW_ERR_FAMILY_CODE_MISMATCH: i32 = -4;
+pub const OW_ERR_CRC_MISMATCH: i32 = -5;
+pub const OW_ERR_TIMEOUT: i32 = -6;
+pub const OW_ERR_VERIFICATION_FAILED: i32 = -7;
+
+/// A null pointer was passed
+pub const OW_ERR_NULL: i32 = -8;
+
+pub const OW_ERR_NO_DEVICE_PRESENT: i32 = -9;
+
+/// Returns the `OW_ERR_*` code for an error
+pub fn error_code<E>(error: &OneWireError<E>) -> i32 {
+    match error {
+        OneWireError::BusNotHigh => OW_ERR_BUS_NOT_HIGH,
+        OneWireError::PinError(_) => OW_ERR_PIN,
+        OneWireError::UnexpectedResponse => OW_ERR_UNEXPECTED_RESPONSE,
+        OneWireError::FamilyCodeMismatch => OW_ERR_FAMILY_CODE_MISMATCH,
+        OneWireError::CrcMismatch { .. } => OW_ERR_CRC_MISMATCH,
+        OneWireError::Timeout(_) => OW_ERR_TIMEOUT,
+        OneWireError::VerificationFailed => OW_ERR_VERIFICATION_FAILED,
+        OneWireError::NoDevicePresent => OW_ERR_NO_DEVICE_PRESENT,
+    }
+}
+
+/// The callbacks used to drive the bus. Each is called with `context`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct OwPins {
+    pub context: *mut c_void,
+
+    /// Sets the pin high (releasing the bus) or low
+    pub set_level: extern "C" fn(context: *mut c_void, high: bool),
+
+    /// Reads the level of the bus
+    pub is_high: extern "C" fn(context: *mut c_void) -> bool,
+
+    /// Waits for at least `micros` µs
+    pub delay_us: extern "C" fn(context: *mut c_void, micros: u16),
+}
+
+impl InputPin for OwPins {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok((self.is_high)(self.context))
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!(self.is_high)(self.context))
+    }
+}
+
+impl OutputPin for OwPins {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        (self.set_level)(self.context, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        (self.set_level)(self.context, true);
+        Ok(())
+    }
+}
+
+impl Delay for OwPins {
+    fn delay_us(&mut self, us: u16) {
+        (self.delay_us)(self.context, us)
+    }
+}
+
+/// The number of bytes C reserves for an `OwBus`, which must be aligned to 8 bytes
+pub const OW_BUS_SIZE: usize = 256;
+
+/// A bus, along with the state of a device search. Initialize it with `ow_bus_init`, which creates
+/// the `OneWire` that every other call uses
+pub struct OwBus {
+    one_wire: OneWire<OwPins>,
+    // the callbacks are also the delay
+    delay: OwPins,
+    /// The state of the search, or `None` to start the next search from the beginning
+    search: Option<SearchState>,
+}
+
+const _: () = assert!(
+    core::mem::size_of::<OwBus>() <= OW_BUS_SIZE && core::mem::align_of::<OwBus>() <= 8,
+    "OW_BUS_SIZE is too small"
+);
+
+impl OwBus {
+    fn run<V>(
+        &mut self,
+        f: impl FnOnce(&mut OneWire<OwPins>, &mut OwPins) -> OneWireResult<V, Infallible>,
+    ) -> Result<V, i32> {
+        f(&mut self.one_wire, &mut self.delay).map_err(|err| error_code(&err))
+    }
+}
+
+/// Converts a result to a return code
+fn to_code(result: Result<(), i32>) -> i32 {
+    match result {
+        Ok(()) => OW_OK,
+        Err(code) => code,
+    }
+}
+
+/// Initializes a bus, and releases the pin.
+///
+/// # Safety
+///
+/// `bus` must be valid for writes of `OW_BUS_SIZE` bytes, and aligned to 8 bytes. The callbacks
+/// must stay valid for as long as the bus is used
+#[no_mangle]
+pub unsafe extern "C" fn ow_bus_init(bus: *mut OwBus, pins: OwPins) -> i32 {
+    if bus.is_null() {
+        return OW_ERR_NULL;
+    }
+    let one_wire = match OneWire::new(pins) {
+        Ok(one_wire) => one_wire,
+        Err(err) => return error_code(&err),
+    };
+    bus.write(OwBus {
+        one_wire,
+        delay: pins,
+        search: None,
+    });
+    OW_OK
+}
+
+/// Sends a reset pulse. Returns 1 if a device responded, or 0 if there are no devices.
+///
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`
+#[no_mangle]
+pub unsafe extern "C" fn ow_reset(bus: *mut OwBus) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    match bus.run(|one_wire, delay| one_wire.reset(delay)) {
+        Ok(present) => present as i32,
+        Err(code) => code,
+    }
+}
+
+/// Sends MATCH ROM (after a reset) to address a single device.
+///
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`
+#[no_mangle]
+pub unsafe extern "C" fn ow_match_address(bus: *mut OwBus, address: u64) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    to_code(bus.run(|one_wire, delay| {
+        one_wire.reset(delay)?;
+        one_wire.match_address(&Address(address), delay)
+    }))
+}
+
+/// Sends SKIP ROM (after a reset) to address all devices.
+///
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`
+#[no_mangle]
+pub unsafe extern "C" fn ow_skip_address(bus: *mut OwBus) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    to_code(bus.run(|one_wire, delay| {
+        one_wire.reset(delay)?;
+        one_wire.skip_address(delay)
+    }))
+}
+
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`, and `output` must be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn ow_read_byte(bus: *mut OwBus, output: *mut u8) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    let output = match output.as_mut() {
+        Some(output) => output,
+        None => return OW_ERR_NULL,
+    };
+    to_code(bus.run(|one_wire, delay| {
+        *output = one_wire.read_byte(delay)?;
+        Ok(())
+    }))
+}
+
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`
+#[no_mangle]
+pub unsafe extern "C" fn ow_write_byte(bus: *mut OwBus, value: u8) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    to_code(bus.run(|one_wire, delay| one_wire.write_byte(value, delay)))
+}
+
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`, and `output` must be valid for writes of
+/// `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn ow_read_bytes(bus: *mut OwBus, output: *mut u8, len: usize) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    if output.is_null() {
+        return OW_ERR_NULL;
+    }
+    let output = core::slice::from_raw_parts_mut(output, len);
+    to_code(bus.run(|one_wire, delay| one_wire.read_bytes(output, delay)))
+}
+
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`, and `bytes` must be valid for reads of
+/// `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn ow_write_bytes(bus: *mut OwBus, bytes: *const u8, len: usize) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    if bytes.is_null() {
+        return OW_ERR_NULL;
+    }
+    let bytes = core::slice::from_raw_parts(bytes, len);
+    to_code(bus.run(|one_wire, delay| one_wire.write_bytes(bytes, delay)))
+}
+
+/// Starts the next device search from the beginning.
+///
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`
+#[no_mangle]
+pub unsafe extern "C" fn ow_search_restart(bus: *mut OwBus) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    bus.search = None;
+    OW_OK
+}
+
+/// Finds the next device, and writes its address to `address`. Returns 1 if a device was found, or
+/// 0 if the search is finished.
+///
+/// # Safety
+///
+/// `bus` must have been initialized with `ow_bus_init`, and `address` must be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn ow_search_next(
+    bus: *mut OwBus,
+    only_alarming: bool,
+    address: *mut u64,
+) -> i32 {
+    let bus = match bus.as_mut() {
+        Some(bus) => bus,
+        None => return OW_ERR_NULL,
+    };
+    let address = match address.as_mut() {
+        Some(address) => address,
+        None => return OW_ERR_NULL,
+    };
+    let state = bus.search;
+    match bus.run(|one_wire, delay| one_wire.device_search(state.as_ref(), only_alarming, delay)) {
+        Ok(Some((found, state))) => {
+            *address = found.0;
+            bus.search = Some(state);
+            1
+        }
+        Ok(None) => 0,
+        Err(code) => code,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use core::mem::MaybeUninit;
+
+    const FIRST: Address = Address(0x7000_0000_0000_0228);
+    const SECOND: Address = Address(0x2900_0000_0000_0128);
+
+    struct Context<'a> {
+        pin: SimulatedPin<'a>,
+        delay: SimulatedDelay<'a>,
+    }
+
+    fn context<'a>(context: *mut c_void) -> &'a mut Context<'a> {
+        unsafe { &mut *(context as *mut Context<'a>) }
+    }
+
+    extern "C" fn set_level(pointer: *mut c_void, high: bool) {
+        let pin = &mut context(pointer).pin;
+        if high {
+            pin.set_high().unwrap();
+        } else {
+            pin.set_low().unwrap();
+        }
+    }
+
+    extern "C" fn is_high(pointer: *mut c_void) -> bool {
+        context(pointer).pin.is_high().unwrap()
+    }
+
+    extern "C" fn delay_us(pointer: *mut c_void, micros: u16) {
+        context(pointer).delay.delay_us(micros);
+    }
+
+    #[test]
+    fn test_ffi() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        simulator.add_device(VirtualDevice::new(SECOND));
+        let mut context = Context {
+            pin: simulator.pin(),
+            delay: simulator.delay(),
+        };
+        let pins = OwPins {
+            context: &mut context as *mut Context as *mut c_void,
+            set_level,
+            is_high,
+            delay_us,
+        };
+
+        unsafe {
+            let mut bus = MaybeUninit::uninit();
+            assert_eq!(ow_bus_init(bus.as_mut_ptr(), pins), OW_OK);
+            let bus = bus.as_mut_ptr();
+            assert_eq!(ow_reset(bus), 1);
+
+            let mut address = 0;
+            assert_eq!(ow_search_next(bus, false, &mut address), 1);
+            assert_eq!(address, FIRST.0);
+            assert_eq!(ow_search_next(bus, false, &mut address), 1);
+            assert_eq!(address, SECOND.0);
+            assert_eq!(ow_search_next(bus, false, &mut address), 0);
+            assert_eq!(ow_search_restart(bus), OW_OK);
+            assert_eq!(ow_search_next(bus, false, &mut address), 1);
+            assert_eq!(address, FIRST.0);
+
+            let mut byte = 0;
+            assert_eq!(ow_match_address(bus, SECOND.0), OW_OK);
+            assert_eq!(ow_write_byte(bus, crate::commands::READ_SCRATCHPAD), OW_OK);
+            assert_eq!(ow_read_byte(bus, &mut byte), OW_OK);
+            assert_eq!(ow_read_byte(bus, core::ptr::null_mut()), OW_ERR_NULL);
+
+            // the same bus is used for every call
+            #[cfg(feature = "stats")]
+            assert_eq!((*bus).one_wire.stats().resets, 5);
+        }
+    }
+}
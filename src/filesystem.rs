@@ -0,0 +1,821 @@
+//! The 1-Wire File Structure (Maxim application note 114), which TMEX and the iButton tools use to
+//! store named files in the memory of EEPROM devices. Enabled with the `file-system` feature.
+//!
+//! Memory is split into 32 byte pages, and each page holds one packet: a length byte, up to 29
+//! bytes of data, and a crc16 of the length and data. The crc is started from the page number, and
+//! stored inverted (like the crcs sent by devices). The last data byte of each packet is the page
+//! the file continues on, or 0 on its last page.
+//!
+//! Page 0 holds the root directory: a 7 byte control field with a bitmap of the pages in use,
+//! followed by a 7 byte entry for each file. When more entries are needed, the directory continues
+//! on another page, which starts with its own control field. Only bitmaps kept in the control field
+//! are supported, which limits devices to 32 pages, and subdirectories are listed but can't be
+//! opened.
+
+use crate::crc::Crc16Digest;
+use crate::delay::Delay;
+use crate::memory::{self, MemoryLayout};
+use crate::{Address, OneWireBus, OneWireError};
+use core::fmt::{self, Display, Formatter};
+use embedded_hal::digital::v2::OutputPin;
+
+pub const PAGE_SIZE: usize = 32;
+
+/// The most file data a page can hold, after the length byte, the pointer to the next page, and
+/// the crc
+pub const PAGE_DATA_SIZE: usize = PAGE_SIZE - 4;
+
+/// The extension of a subdirectory
+pub const DIRECTORY_EXTENSION: u8 = 0x7F;
+
+/// The first byte of a directory control field
+const DIRECTORY_MARKER: u8 = 0xAA;
+
+/// Set in the control field when the bitmap is kept in a separate file
+const REMOTE_BITMAP: u8 = 0x80;
+
+/// Set in the extension byte of a read-only file
+const READ_ONLY: u8 = 0x80;
+
+const CONTROL_SIZE: usize = 7;
+const ENTRY_SIZE: usize = 7;
+const ENTRIES_PER_PAGE: usize = (PAGE_DATA_SIZE - CONTROL_SIZE) / ENTRY_SIZE;
+
+pub type FileSystemResult<T, E> = Result<T, FileSystemError<E>>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileSystemError<E> {
+    Bus(OneWireError<E>),
+
+    /// Page 0 doesn't hold a directory. The device needs to be formatted with `FileSystem::format`
+    NotFormatted,
+
+    /// A page doesn't hold a valid packet (the length or crc is wrong), or doesn't link to the next
+    /// page of a file or directory correctly
+    InvalidPage(u8),
+
+    /// The directory keeps its bitmap in a separate file, or the file is a subdirectory
+    Unsupported,
+
+    NotFound,
+
+    ReadOnly,
+
+    /// There aren't enough free pages for the file
+    Full,
+
+    /// The buffer given isn't large enough for the file or directory
+    BufferTooSmall,
+}
+
+impl<E> From<OneWireError<E>> for FileSystemError<E> {
+    fn from(error: OneWireError<E>) -> FileSystemError<E> {
+        FileSystemError::Bus(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileName {
+    /// Up to 4 ASCII characters, padded with spaces
+    pub name: [u8; 4],
+
+    /// 0 to 99 for files, or `DIRECTORY_EXTENSION` for a subdirectory
+    pub extension: u8,
+}
+
+impl FileName {
+    /// Returns `None` if `name` isn't 1 to 4 printable ASCII characters, or the extension is more
+    /// than 99
+    pub fn new(name: &str, extension: u8) -> Option<FileName> {
+        let valid = (1..=4).contains(&name.len()) && name.bytes().all(|b| b.is_ascii_graphic());
+        if !valid || extension > 99 {
+            return None;
+        }
+        let mut padded = [b' '; 4];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        Some(FileName {
+            name: padded,
+            extension,
+        })
+    }
+}
+
+/// Formats as `NAME.EXT`, without the padding
+impl Display for FileName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for byte in self.name.iter().filter(|b| **b != b' ') {
+            write!(f, "{}", *byte as char)?;
+        }
+        write!(f, ".{:03}", self.extension)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: FileName,
+    pub read_only: bool,
+
+    /// The first page of the file
+    pub start_page: u8,
+
+    /// The number of pages the file uses
+    pub pages: u8,
+}
+
+impl DirectoryEntry {
+    const EMPTY: DirectoryEntry = DirectoryEntry {
+        name: FileName {
+            name: [b' '; 4],
+            extension: 0,
+        },
+        read_only: false,
+        start_page: 0,
+        pages: 0,
+    };
+
+    pub fn is_directory(&self) -> bool {
+        self.name.extension == DIRECTORY_EXTENSION
+    }
+
+    fn decode(bytes: &[u8]) -> DirectoryEntry {
+        DirectoryEntry {
+            name: FileName {
+                name: [bytes[0], bytes[1], bytes[2], bytes[3]],
+                extension: bytes[4] & !READ_ONLY,
+            },
+            read_only: bytes[4] & READ_ONLY != 0,
+            start_page: bytes[5],
+            pages: bytes[6],
+        }
+    }
+
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let [a, b, c, d] = self.name.name;
+        let extension = self.name.extension | if self.read_only { READ_ONLY } else { 0 };
+        [a, b, c, d, extension, self.start_page, self.pages]
+    }
+}
+
+/// One page of a directory
+#[derive(Debug, Copy, Clone)]
+struct DirectoryPage {
+    page: u8,
+    // only used on page 0
+    bitmap: u32,
+    entries: [DirectoryEntry; ENTRIES_PER_PAGE],
+    len: usize,
+    next: u8,
+}
+
+impl DirectoryPage {
+    fn new(page: u8) -> DirectoryPage {
+        DirectoryPage {
+            page,
+            bitmap: 0,
+            entries: [DirectoryEntry::EMPTY; ENTRIES_PER_PAGE],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    fn entries(&self) -> &[DirectoryEntry] {
+        &self.entries[..self.len]
+    }
+
+    fn push(&mut self, entry: DirectoryEntry) {
+        self.entries[self.len] = entry;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.entries.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+/// What `FileSystem::scan` found in the directory
+struct Scan {
+    root: DirectoryPage,
+
+    /// The directory page the file was found on, and its index in the page
+    found: Option<(DirectoryPage, usize)>,
+
+    /// The first directory page with room for another entry
+    free: Option<DirectoryPage>,
+
+    last: DirectoryPage,
+}
+
+/// The file system on the memory of one device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileSystem {
+    address: Option<Address>,
+    layout: MemoryLayout,
+    pages: u8,
+}
+
+impl FileSystem {
+    /// The device at `address` (or the only device on the bus, if `None`), which must have 32 byte
+    /// pages, and at most 32 of them
+    pub fn new(address: Option<Address>, layout: MemoryLayout) -> FileSystem {
+        assert_eq!(
+            layout.page_size as usize, PAGE_SIZE,
+            "pages must be 32 bytes"
+        );
+        assert!(layout.pages() <= 32, "at most 32 pages are supported");
+        FileSystem {
+            address,
+            layout,
+            pages: layout.pages() as u8,
+        }
+    }
+
+    /// Writes an empty root directory, which discards every file on the device
+    pub fn format<B>(
+        &self,
+        one_wire: &mut B,
+        mut strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut root = DirectoryPage::new(0);
+        root.bitmap = 1;
+        self.write_directory_page(one_wire, &root, &mut strong_pullup, delay)
+    }
+
+    /// Reads the entries of the root directory into `entries`, and returns the number of entries
+    pub fn read_directory<B>(
+        &self,
+        one_wire: &mut B,
+        entries: &mut [DirectoryEntry],
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<usize, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut count = 0;
+        self.walk(one_wire, delay, |directory| {
+            for entry in directory.entries() {
+                if let Some(output) = entries.get_mut(count) {
+                    *output = *entry;
+                }
+                count += 1;
+            }
+        })?;
+        if count > entries.len() {
+            return Err(FileSystemError::BufferTooSmall);
+        }
+        Ok(count)
+    }
+
+    /// Finds a file in the root directory
+    pub fn find<B>(
+        &self,
+        one_wire: &mut B,
+        name: &FileName,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<DirectoryEntry, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let scan = self.scan(one_wire, name, delay)?;
+        let (directory, index) = scan.found.ok_or(FileSystemError::NotFound)?;
+        Ok(directory.entries[index])
+    }
+
+    /// Reads a file into `output`, and returns its length
+    pub fn read_file<B>(
+        &self,
+        one_wire: &mut B,
+        name: &FileName,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<usize, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let entry = self.find(one_wire, name, delay)?;
+        if entry.is_directory() {
+            return Err(FileSystemError::Unsupported);
+        }
+        let mut page = entry.start_page;
+        let mut len = 0;
+        for index in 0..entry.pages {
+            let (bytes, packet_len) = self.read_packet(one_wire, page, delay)?;
+            if packet_len == 0 {
+                return Err(FileSystemError::InvalidPage(page));
+            }
+            let data = &bytes[1..packet_len];
+            output
+                .get_mut(len..len + data.len())
+                .ok_or(FileSystemError::BufferTooSmall)?
+                .copy_from_slice(data);
+            len += data.len();
+
+            let next = bytes[packet_len];
+            if (next == 0) != (index + 1 == entry.pages) {
+                return Err(FileSystemError::InvalidPage(page));
+            }
+            page = next;
+        }
+        Ok(len)
+    }
+
+    /// Writes a file to the root directory, replacing it if it already exists. The file's pages are
+    /// written before the directory, so if the write is interrupted the previous contents of the
+    /// directory are kept (although a replaced file will have been deleted)
+    pub fn write_file<B>(
+        &self,
+        one_wire: &mut B,
+        name: &FileName,
+        data: &[u8],
+        mut strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut scan = self.scan(one_wire, name, delay)?;
+        if scan.found.is_some() {
+            self.delete(one_wire, scan, &mut strong_pullup, delay)?;
+            scan = self.scan(one_wire, name, delay)?;
+        }
+
+        let file_pages = data.len().div_ceil(PAGE_DATA_SIZE).max(1);
+        let needed = file_pages + scan.free.is_none() as usize;
+        let mut pages = [0; 32];
+        let mut allocated = 0;
+        for page in 1..self.pages {
+            if allocated < needed && scan.root.bitmap & (1 << page) == 0 {
+                pages[allocated] = page;
+                allocated += 1;
+            }
+        }
+        if allocated < needed {
+            return Err(FileSystemError::Full);
+        }
+
+        for index in 0..file_pages {
+            let chunk = data.chunks(PAGE_DATA_SIZE).nth(index).unwrap_or(&[]);
+            let mut packet = [0; PAGE_DATA_SIZE + 1];
+            packet[..chunk.len()].copy_from_slice(chunk);
+            if index + 1 < file_pages {
+                packet[chunk.len()] = pages[index + 1];
+            }
+            self.write_packet(
+                one_wire,
+                pages[index],
+                &packet[..chunk.len() + 1],
+                &mut strong_pullup,
+                delay,
+            )?;
+        }
+
+        for page in &pages[..needed] {
+            scan.root.bitmap |= 1 << page;
+        }
+        let entry = DirectoryEntry {
+            name: *name,
+            read_only: false,
+            start_page: pages[0],
+            pages: file_pages as u8,
+        };
+        match scan.free {
+            Some(mut directory) => {
+                directory.push(entry);
+                self.update_directory(one_wire, scan.root, directory, &mut strong_pullup, delay)
+            }
+            None => {
+                // the directory continues on a new page
+                let mut directory = DirectoryPage::new(pages[file_pages]);
+                directory.push(entry);
+                self.write_directory_page(one_wire, &directory, &mut strong_pullup, delay)?;
+                let mut last = scan.last;
+                last.next = directory.page;
+                self.update_directory(one_wire, scan.root, last, &mut strong_pullup, delay)
+            }
+        }
+    }
+
+    /// Deletes a file from the root directory, and frees its pages
+    pub fn delete_file<B>(
+        &self,
+        one_wire: &mut B,
+        name: &FileName,
+        mut strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let scan = self.scan(one_wire, name, delay)?;
+        if scan.found.is_none() {
+            return Err(FileSystemError::NotFound);
+        }
+        self.delete(one_wire, scan, &mut strong_pullup, delay)
+    }
+
+    fn delete<B>(
+        &self,
+        one_wire: &mut B,
+        scan: Scan,
+        strong_pullup: &mut Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let (mut directory, index) = scan.found.ok_or(FileSystemError::NotFound)?;
+        let entry = directory.entries[index];
+        if entry.read_only {
+            return Err(FileSystemError::ReadOnly);
+        }
+        if entry.is_directory() {
+            return Err(FileSystemError::Unsupported);
+        }
+
+        let mut root = scan.root;
+        let mut page = entry.start_page;
+        for _ in 0..entry.pages {
+            if page == 0 || page >= self.pages {
+                return Err(FileSystemError::InvalidPage(page));
+            }
+            root.bitmap &= !(1 << page);
+            let (bytes, len) = self.read_packet(one_wire, page, delay)?;
+            if len == 0 {
+                return Err(FileSystemError::InvalidPage(page));
+            }
+            page = bytes[len];
+        }
+        directory.remove(index);
+        self.update_directory(one_wire, root, directory, strong_pullup, delay)
+    }
+
+    /// Calls `f` with each page of the root directory, and returns the first and last pages
+    fn walk<B>(
+        &self,
+        one_wire: &mut B,
+        delay: &mut impl Delay,
+        mut f: impl FnMut(&DirectoryPage),
+    ) -> FileSystemResult<(DirectoryPage, DirectoryPage), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let root = self.read_directory_page(one_wire, 0, delay)?;
+        f(&root);
+        let mut directory = root;
+        // every page can only be part of the directory once, so more than this is a loop
+        for _ in 1..self.pages {
+            if directory.next == 0 {
+                return Ok((root, directory));
+            }
+            directory = self.read_directory_page(one_wire, directory.next, delay)?;
+            f(&directory);
+        }
+        if directory.next == 0 {
+            return Ok((root, directory));
+        }
+        Err(FileSystemError::InvalidPage(directory.next))
+    }
+
+    fn scan<B>(
+        &self,
+        one_wire: &mut B,
+        name: &FileName,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<Scan, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut found = None;
+        let mut free = None;
+        let (root, last) = self.walk(one_wire, delay, |directory| {
+            if let Some(index) = directory
+                .entries()
+                .iter()
+                .position(|entry| entry.name == *name)
+            {
+                found = found.or(Some((*directory, index)));
+            }
+            if directory.len < ENTRIES_PER_PAGE {
+                free = free.or(Some(*directory));
+            }
+        })?;
+        Ok(Scan {
+            root,
+            found,
+            free,
+            last,
+        })
+    }
+
+    fn read_directory_page<B>(
+        &self,
+        one_wire: &mut B,
+        page: u8,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<DirectoryPage, B::Error>
+    where
+        B: OneWireBus,
+    {
+        let invalid = if page == 0 {
+            FileSystemError::NotFormatted
+        } else {
+            FileSystemError::InvalidPage(page)
+        };
+        let (bytes, len) = match self.read_packet(one_wire, page, delay) {
+            Err(FileSystemError::InvalidPage(_)) => return Err(invalid),
+            result => result?,
+        };
+        let data = &bytes[1..=len];
+        if len < CONTROL_SIZE + 1
+            || data[0] != DIRECTORY_MARKER
+            || (len - CONTROL_SIZE - 1) % ENTRY_SIZE != 0
+        {
+            return Err(invalid);
+        }
+        if data[2] & REMOTE_BITMAP != 0 {
+            return Err(FileSystemError::Unsupported);
+        }
+
+        let mut directory = DirectoryPage::new(page);
+        directory.bitmap = u32::from_le_bytes([data[3], data[4], data[5], data[6]]);
+        for entry in data[CONTROL_SIZE..len - 1].chunks(ENTRY_SIZE) {
+            directory.push(DirectoryEntry::decode(entry));
+        }
+        directory.next = data[len - 1];
+        Ok(directory)
+    }
+
+    fn write_directory_page<B>(
+        &self,
+        one_wire: &mut B,
+        directory: &DirectoryPage,
+        strong_pullup: &mut Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut data = [0; PAGE_DATA_SIZE + 1];
+        data[0] = DIRECTORY_MARKER;
+        data[3..CONTROL_SIZE].copy_from_slice(&directory.bitmap.to_le_bytes());
+        let mut len = CONTROL_SIZE;
+        for entry in directory.entries() {
+            data[len..len + ENTRY_SIZE].copy_from_slice(&entry.encode());
+            len += ENTRY_SIZE;
+        }
+        data[len] = directory.next;
+        self.write_packet(
+            one_wire,
+            directory.page,
+            &data[..len + 1],
+            strong_pullup,
+            delay,
+        )
+    }
+
+    /// Writes a changed directory page, and the root directory (with its bitmap) if that's a
+    /// different page
+    fn update_directory<B>(
+        &self,
+        one_wire: &mut B,
+        root: DirectoryPage,
+        mut changed: DirectoryPage,
+        strong_pullup: &mut Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        if changed.page == 0 {
+            changed.bitmap = root.bitmap;
+            return self.write_directory_page(one_wire, &changed, strong_pullup, delay);
+        }
+        changed.bitmap = 0;
+        self.write_directory_page(one_wire, &changed, strong_pullup, delay)?;
+        self.write_directory_page(one_wire, &root, strong_pullup, delay)
+    }
+
+    /// Reads the packet in a page, and returns the whole page and the length of the packet. The
+    /// packet's data is `bytes[1..=len]`
+    fn read_packet<B>(
+        &self,
+        one_wire: &mut B,
+        page: u8,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<([u8; PAGE_SIZE], usize), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut bytes = [0; PAGE_SIZE];
+        memory::read_memory(
+            self.address.as_ref(),
+            page as u16 * PAGE_SIZE as u16,
+            &mut bytes,
+            one_wire,
+            delay,
+        )?;
+        let len = bytes[0] as usize;
+        if len > PAGE_SIZE - 3 {
+            return Err(FileSystemError::InvalidPage(page));
+        }
+        let mut digest = Crc16Digest::with_initial(page as u16);
+        digest.update(&bytes[..len + 3]);
+        if digest.finalize() != 0xB001 {
+            return Err(FileSystemError::InvalidPage(page));
+        }
+        Ok((bytes, len))
+    }
+
+    /// Writes `data` as the packet of a page. The rest of the page is filled with 0xFF, so whole
+    /// rows of the scratchpad are always written
+    fn write_packet<B>(
+        &self,
+        one_wire: &mut B,
+        page: u8,
+        data: &[u8],
+        strong_pullup: &mut Option<&mut dyn OutputPin<Error = B::Error>>,
+        delay: &mut impl Delay,
+    ) -> FileSystemResult<(), B::Error>
+    where
+        B: OneWireBus,
+    {
+        let mut bytes = [0xFF; PAGE_SIZE];
+        bytes[0] = data.len() as u8;
+        bytes[1..=data.len()].copy_from_slice(data);
+        let mut digest = Crc16Digest::with_initial(page as u16);
+        digest.update(&bytes[..=data.len()]);
+        bytes[data.len() + 1..data.len() + 3].copy_from_slice(&(!digest.finalize()).to_le_bytes());
+        memory::write_memory_with_layout(
+            self.address.as_ref(),
+            &self.layout,
+            self.layout.page_address(page as u16),
+            &bytes,
+            strong_pullup
+                .as_mut()
+                .map(|pin| &mut **pin as &mut dyn OutputPin<Error = B::Error>),
+            one_wire,
+            delay,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::crc::Crc16Digest;
+    use crate::filesystem::{DirectoryEntry, FileName, FileSystem, FileSystemError, PAGE_SIZE};
+    use crate::memory::MemoryLayout;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire};
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn name(name: &str) -> FileName {
+        FileName::new(name, 0).unwrap()
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(FileName::new("AB", 1).unwrap().name, *b"AB  ");
+        assert_eq!(FileName::new("LOG", 12).unwrap().to_string(), "LOG.012");
+        assert_eq!(FileName::new("", 0), None);
+        assert_eq!(FileName::new("NAMES", 0), None);
+        assert_eq!(FileName::new("A B", 0), None);
+        assert_eq!(FileName::new("A", 100), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x23, 1);
+        simulator.add_device(VirtualDevice::with_memory(address, &MemoryLayout::DS2433));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let fs = FileSystem::new(Some(address), MemoryLayout::DS2433);
+
+        let mut entries = [DirectoryEntry::EMPTY; 4];
+        assert_eq!(
+            fs.read_directory(&mut one_wire, &mut entries, &mut delay),
+            Err(FileSystemError::NotFormatted)
+        );
+        fs.format(&mut one_wire, None, &mut delay).unwrap();
+        assert_eq!(
+            fs.read_directory(&mut one_wire, &mut entries, &mut delay),
+            Ok(0)
+        );
+
+        // the control field, with page 0 in use, and no next page
+        let page = simulator
+            .with_device(&address, |device| device.memory[..PAGE_SIZE].to_vec())
+            .unwrap();
+        assert_eq!(page[..9], [0x08, 0xAA, 0, 0, 0x01, 0, 0, 0, 0]);
+        let mut digest = Crc16Digest::with_initial(0);
+        digest.update(&page[..11]);
+        assert_eq!(digest.finalize(), 0xB001);
+    }
+
+    #[test]
+    fn test_files() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x23, 1);
+        simulator.add_device(VirtualDevice::with_memory(address, &MemoryLayout::DS2433));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let fs = FileSystem::new(Some(address), MemoryLayout::DS2433);
+        fs.format(&mut one_wire, None, &mut delay).unwrap();
+
+        // more than one page
+        let data: Vec<u8> = (0..40).collect();
+        fs.write_file(&mut one_wire, &name("DATA"), &data, None, &mut delay)
+            .unwrap();
+        let mut output = [0; 64];
+        let len = fs
+            .read_file(&mut one_wire, &name("DATA"), &mut output, &mut delay)
+            .unwrap();
+        assert_eq!(output[..len], data[..]);
+        let entry = fs.find(&mut one_wire, &name("DATA"), &mut delay).unwrap();
+        assert_eq!((entry.start_page, entry.pages), (1, 2));
+        assert_eq!(
+            fs.read_file(&mut one_wire, &name("DATA"), &mut output[..39], &mut delay),
+            Err(FileSystemError::BufferTooSmall)
+        );
+
+        // the directory continues on another page after 3 entries
+        for file in ["A", "B", "C"] {
+            fs.write_file(
+                &mut one_wire,
+                &name(file),
+                file.as_bytes(),
+                None,
+                &mut delay,
+            )
+            .unwrap();
+        }
+        let mut entries = [DirectoryEntry::EMPTY; 4];
+        assert_eq!(
+            fs.read_directory(&mut one_wire, &mut entries, &mut delay),
+            Ok(4)
+        );
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.to_string()).collect();
+        assert_eq!(names, ["DATA.000", "A.000", "B.000", "C.000"]);
+        assert_eq!(
+            fs.read_file(&mut one_wire, &name("C"), &mut output, &mut delay),
+            Ok(1)
+        );
+
+        // replacing a file frees its old pages
+        fs.write_file(&mut one_wire, &name("DATA"), &[], None, &mut delay)
+            .unwrap();
+        assert_eq!(
+            fs.read_file(&mut one_wire, &name("DATA"), &mut output, &mut delay),
+            Ok(0)
+        );
+        fs.delete_file(&mut one_wire, &name("A"), None, &mut delay)
+            .unwrap();
+        assert_eq!(
+            fs.find(&mut one_wire, &name("A"), &mut delay),
+            Err(FileSystemError::NotFound)
+        );
+
+        // 5 of the 16 pages are in use, by the 2 directory pages and 3 files
+        let data = [0x55; 11 * 28 + 1];
+        assert_eq!(
+            fs.write_file(&mut one_wire, &name("BIG"), &data, None, &mut delay),
+            Err(FileSystemError::Full)
+        );
+        fs.write_file(
+            &mut one_wire,
+            &name("BIG"),
+            &data[..11 * 28],
+            None,
+            &mut delay,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_invalid_page() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x23, 1);
+        simulator.add_device(VirtualDevice::with_memory(address, &MemoryLayout::DS2433));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let fs = FileSystem::new(Some(address), MemoryLayout::DS2433);
+        fs.format(&mut one_wire, None, &mut delay).unwrap();
+        fs.write_file(&mut one_wire, &name("DATA"), &[1, 2, 3], None, &mut delay)
+            .unwrap();
+
+        simulator.with_device(&address, |device| device.memory[PAGE_SIZE + 2] ^= 0x01);
+        let mut output = [0; 8];
+        assert_eq!(
+            fs.read_file(&mut one_wire, &name("DATA"), &mut output, &mut delay),
+            Err(FileSystemError::InvalidPage(1))
+        );
+    }
+}
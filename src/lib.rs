@@ -1,21 +1,90 @@
 #![no_std]
 
-use embedded_hal::blocking::delay::DelayUs;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use commands::{Addressing, RomCommand};
+use core::iter::FusedIterator;
+use deadline::NoDeadline;
+use delay::Delay;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
+use pullup::{NoStrongPullup, StrongPullupPin};
+use search::SearchOrder;
+use timing::{SlotTimes, Timings};
+
+#[macro_use]
+mod stats;
+#[macro_use]
+mod trace;
 
+pub mod adapters;
 mod address;
+#[cfg(feature = "async")]
+pub mod asynch;
+mod builder;
+mod bus;
+pub mod capture;
 pub mod commands;
 pub mod crc;
+pub mod deadline;
+pub mod delay;
+pub mod delayed;
+pub mod devices;
+mod diagnostics;
+#[cfg(feature = "embassy")]
+pub mod embassy;
 mod error;
+mod family;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "file-system")]
+pub mod filesystem;
+pub mod memory;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod nonblocking;
+pub mod offload;
+pub mod pins;
+pub mod pullup;
+pub mod registry;
+pub mod replay;
+mod scan;
+pub mod search;
+pub mod session;
+pub mod shared;
+#[cfg(any(test, feature = "simulator"))]
+pub mod simulator;
+mod slave;
+pub mod timing;
+mod transaction;
 
-pub use address::Address;
-pub use error::{OneWireError, OneWireResult};
+pub use address::{Address, Family, InvalidAddress, ParseAddressError, TypedAddress};
+pub use builder::OneWireBuilder;
+pub use bus::{ConversionWait, OneWireBus};
+pub use diagnostics::{BusDiagnostics, BusQuality};
+pub use error::{
+    ErrorKind, OneWireError, OneWireResult, Operation, OperationContext, OperationError,
+};
+#[cfg(feature = "family-names")]
+pub use family::family_name;
+pub use family::FamilyCode;
+#[cfg(feature = "alloc")]
+pub use scan::ScanDiff;
+pub use slave::OneWireSlave;
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+#[cfg(feature = "trace")]
+pub use trace::BusEvent;
+pub use transaction::{Speed, Transaction};
 
 pub const READ_SLOT_DURATION_MICROS: u16 = 70;
 
 /// Implementation of the 1-Wire protocol.
 /// https://www.maximintegrated.com/en/design/technical-documents/app-notes/1/126.html
-#[derive(Debug)]
+///
+/// The state of a search is only valid if `last_discrepancy_index` is the index of the highest set
+/// bit of `discrepancies` (or `discrepancies` is 0, in which case the search is finished).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct SearchState {
     // The address of the last found device
     address: u64,
@@ -28,8 +97,109 @@ pub struct SearchState {
     last_discrepancy_index: u8,
 }
 
-pub struct OneWire<T> {
+impl SearchState {
+    /// The address of the last device found
+    pub fn address(&self) -> Address {
+        Address(self.address)
+    }
+
+    /// Bitflags of the address bits where devices with both values were found. Bit 0 is the first
+    /// bit of the address (the least significant bit of the family code)
+    pub fn discrepancies(&self) -> u64 {
+        self.discrepancies
+    }
+
+    /// The index of the highest bit set in `discrepancies`. The next search takes the other branch here
+    pub fn last_discrepancy_index(&self) -> u8 {
+        self.last_discrepancy_index
+    }
+
+    /// Returns true if there are no more devices to find
+    pub fn is_finished(&self) -> bool {
+        self.discrepancies == 0
+    }
+
+    /// Encodes the state as the address and discrepancies (both little endian), followed by the
+    /// last discrepancy index, so a search can be continued later (even after a reboot)
+    pub fn to_bytes(&self) -> [u8; 17] {
+        let mut bytes = [0; 17];
+        bytes[..8].copy_from_slice(&self.address.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.discrepancies.to_le_bytes());
+        bytes[16] = self.last_discrepancy_index;
+        bytes
+    }
+
+    /// Decodes a state created with `to_bytes`. Returns `None` if the state isn't valid
+    pub fn from_bytes(bytes: &[u8; 17]) -> Option<SearchState> {
+        let mut address = [0; 8];
+        address.copy_from_slice(&bytes[..8]);
+        let mut discrepancies = [0; 8];
+        discrepancies.copy_from_slice(&bytes[8..16]);
+        let state = SearchState {
+            address: u64::from_le_bytes(address),
+            discrepancies: u64::from_le_bytes(discrepancies),
+            last_discrepancy_index: bytes[16],
+        };
+        let valid = if state.discrepancies == 0 {
+            state.last_discrepancy_index < 64
+        } else {
+            state.last_discrepancy_index as u32 == 63 - state.discrepancies.leading_zeros()
+        };
+        if valid {
+            Some(state)
+        } else {
+            None
+        }
+    }
+}
+
+/// The result of `OneWire::reset_detailed`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetResult {
+    /// At least one device responded with a presence pulse
+    DevicesPresent,
+
+    NoDevices,
+
+    /// The bus was still low after the presence window ended
+    ShortToGround,
+}
+
+pub struct OneWire<T, P = NoStrongPullup> {
     pin: T,
+
+    // used by operations that need a strong pull-up, when one isn't passed to them
+    strong_pullup: P,
+
+    // sample each read slot 3 times, and use the majority
+    majority_reads: bool,
+
+    bus_high_timeout_micros: u16,
+
+    // the pin drives an inverting stage, so it's set high to pull the bus low
+    inverted_output: bool,
+
+    times: SlotTimes,
+
+    // the timings to go back to while the bus is at overdrive speed
+    standard_times: Option<SlotTimes>,
+
+    // the operation the last error happened during, since the last reset
+    failed_operation: Option<Operation>,
+
+    // retry failed overdrive transactions at standard speed
+    overdrive_fallback: bool,
+
+    // how many times a failed transaction is retried
+    retries: u8,
+
+    idle_hook: Option<fn()>,
+
+    #[cfg(feature = "trace")]
+    tracer: Option<fn(BusEvent)>,
+
+    #[cfg(feature = "stats")]
+    stats: Stats,
 }
 
 impl<T, E> OneWire<T>
@@ -38,80 +208,397 @@ where
     T: OutputPin<Error = E>,
 {
     pub fn new(pin: T) -> OneWireResult<OneWire<T>, E> {
-        let mut one_wire = OneWire { pin };
+        OneWire::with_polarity(pin, false, NoStrongPullup)
+    }
+
+    /// Creates a bus where the pin drives the bus through an inverting stage (such as a transistor
+    /// pulling the bus low, or an isolated line driver), so the pin is set high to pull the bus low
+    /// and low to release it. The pin is still read as the level of the bus.
+    pub fn new_inverted(pin: T) -> OneWireResult<OneWire<T>, E> {
+        OneWire::with_polarity(pin, true, NoStrongPullup)
+    }
+
+    /// Creates a bus without touching the pin, for when driving it during construction isn't
+    /// wanted (such as a bus shared with another master at boot). The pin should already be
+    /// released, or `release_bus` called before the first operation.
+    pub fn new_unchecked(pin: T) -> OneWire<T> {
+        OneWire::unreleased(pin, false, NoStrongPullup)
+    }
+}
+
+impl<T, E, P> OneWire<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    pub(crate) fn with_polarity(
+        pin: T,
+        inverted_output: bool,
+        strong_pullup: P,
+    ) -> OneWireResult<OneWire<T, P>, E> {
+        let mut one_wire = OneWire::unreleased(pin, inverted_output, strong_pullup);
         // Pin should be high during idle.
         one_wire.release_bus()?;
         Ok(one_wire)
     }
 
+    fn unreleased(pin: T, inverted_output: bool, strong_pullup: P) -> OneWire<T, P> {
+        OneWire {
+            pin,
+            strong_pullup,
+            majority_reads: false,
+            bus_high_timeout_micros: 250,
+            inverted_output,
+            times: SlotTimes::from(&Timings::STANDARD),
+            standard_times: None,
+            failed_operation: None,
+            overdrive_fallback: false,
+            retries: 0,
+            idle_hook: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+        }
+    }
+
     pub fn into_inner(self) -> T {
         self.pin
     }
 
+    /// The strong pull-up set with `OneWireBuilder::strong_pullup_pin`
+    pub fn strong_pullup_pin(&self) -> &P {
+        &self.strong_pullup
+    }
+
+    /// Enables or disables the strong pull-up set with `OneWireBuilder::strong_pullup_pin`.
+    /// Returns false if the bus doesn't have one. It must be disabled again before the bus is used
+    pub fn set_strong_pullup(&mut self, enabled: bool) -> OneWireResult<bool, E> {
+        let result = self.strong_pullup.set_enabled(enabled);
+        self.pin_result(result)
+    }
+
+    /// Gives access to the pin, to reconfigure it without losing the state of the bus. The pin
+    /// should be released again before the next operation (or use `with_pin`)
+    pub fn pin_mut(&mut self) -> &mut T {
+        &mut self.pin
+    }
+
+    /// Runs `f` with the pin, then releases the bus
+    pub fn with_pin<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> OneWireResult<R, E> {
+        let result = f(&mut self.pin);
+        self.release_bus()?;
+        Ok(result)
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Sets a function that is called between bytes, after each reset, and while polling a device
+    /// that is busy, where a little extra time doesn't affect the bus. This can be used to feed a
+    /// watchdog or yield to a scheduler during long operations. It should return within a few
+    /// milliseconds, since some devices time out if the bus is idle for too long part way through
+    /// a command.
+    pub fn set_idle_hook(&mut self, hook: Option<fn()>) {
+        self.idle_hook = hook;
+    }
+
+    pub(crate) fn idle(&self) {
+        if let Some(hook) = self.idle_hook {
+            hook();
+        }
+    }
+
+    /// Sets a function that is called for every event on the bus (resets, bits, bytes, and errors)
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<fn(BusEvent)>) {
+        self.tracer = tracer;
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace(&self, event: BusEvent) {
+        if let Some(tracer) = self.tracer {
+            tracer(event);
+        }
+    }
+
+    /// Sets how long to wait for the pull-up resistor to bring the bus high before a reset. If the bus
+    /// is still low after this, `BusNotHigh` is returned. The default is 250 µs, which may need to be
+    /// increased for long buses with a lot of capacitance.
+    pub fn set_bus_high_timeout(&mut self, micros: u16) {
+        self.bus_high_timeout_micros = micros;
+    }
+
+    pub fn bus_high_timeout(&self) -> u16 {
+        self.bus_high_timeout_micros
+    }
+
+    /// True for a bus created with `OneWire::new_inverted`
+    pub fn is_inverted(&self) -> bool {
+        self.inverted_output
+    }
+
+    /// Sets the timings of the reset pulse and time slots. The default is `Timings::STANDARD`
+    pub fn set_timings(&mut self, timings: Timings) {
+        self.times = SlotTimes::from(&timings);
+        self.standard_times = None;
+    }
+
+    /// Switches between the configured timings and `Timings::OVERDRIVE`. The devices have to be
+    /// switched to overdrive as well (with OVERDRIVE SKIP ROM or OVERDRIVE MATCH ROM), which
+    /// `send_command_with` does with the overdrive addressings
+    pub fn set_speed(&mut self, speed: Speed) {
+        match speed {
+            Speed::Standard => {
+                if let Some(times) = self.standard_times.take() {
+                    self.times = times;
+                }
+            }
+            Speed::Overdrive => {
+                if self.standard_times.is_none() {
+                    self.standard_times = Some(self.times);
+                    // an active pull-up still has to be switched off at overdrive
+                    self.times = SlotTimes {
+                        active_pull_up: self.times.active_pull_up,
+                        ..SlotTimes::from(&Timings::OVERDRIVE)
+                    };
+                }
+            }
+        }
+    }
+
+    /// How long a read slot takes with the current timings, including the slot recovery time
+    pub fn read_slot_micros(&self) -> u32 {
+        let sample = match self.majority_reads {
+            // the samples start 4 µs early, but can't start before the bus is released
+            true => self.times.read_sample.max(4),
+            false => self.times.read_sample,
+        };
+        self.times.write_one_low as u32
+            + sample as u32
+            + self.times.read_recovery as u32
+            + self.times.slot_recovery as u32
+    }
+
+    /// The operation the last error happened during, such as `Operation::Search` when a search
+    /// fails its crc check, so it can be attached to the error with `OperationContext::during`.
+    /// This is the outermost operation that failed, and is cleared by each reset
+    pub fn last_failed_operation(&self) -> Option<Operation> {
+        self.failed_operation
+    }
+
+    pub fn speed(&self) -> Speed {
+        match self.standard_times {
+            Some(_) => Speed::Overdrive,
+            None => Speed::Standard,
+        }
+    }
+
+    pub fn timings(&self) -> Timings {
+        Timings::from(&self.times)
+    }
+
+    /// When enabled, each read slot samples the bus 3 times (2 µs apart, ending at the usual sample
+    /// point) and uses the majority value. This can help on long unshielded cables where noise
+    /// occasionally flips a single sample. Disabled by default.
+    pub fn set_majority_reads(&mut self, enabled: bool) {
+        self.majority_reads = enabled;
+    }
+
+    pub fn majority_reads(&self) -> bool {
+        self.majority_reads
+    }
+
+    /// Sets how many times a `transaction` is retried when it fails with an `ErrorKind::Data` or
+    /// `ErrorKind::Protocol` error (such as a crc mismatch from a noisy read). Each retry starts
+    /// again with the reset. The default is 0
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
+    pub fn retries(&self) -> u8 {
+        self.retries
+    }
+
+    /// When enabled, an `overdrive_transaction` that fails is retried once at standard speed, and
+    /// the `Speed` it returns reports the downgrade. Marginal wiring often works at standard speed
+    /// but not with the much shorter overdrive timings. Disabled by default.
+    pub fn set_overdrive_fallback(&mut self, enabled: bool) {
+        self.overdrive_fallback = enabled;
+    }
+
+    pub fn overdrive_fallback(&self) -> bool {
+        self.overdrive_fallback
+    }
+
     /// Disconnects the bus, letting another device (or the pull-up resistor) set the bus value
     pub fn release_bus(&mut self) -> OneWireResult<(), E> {
-        self.pin
-            .set_high()
-            .map_err(|err| OneWireError::PinError(err))
+        let result = if self.inverted_output {
+            self.pin.set_low()
+        } else {
+            self.pin.set_high()
+        };
+        self.pin_result(result)
     }
 
     /// Drives the bus low
     pub fn set_bus_low(&mut self) -> OneWireResult<(), E> {
-        self.pin
-            .set_low()
-            .map_err(|err| OneWireError::PinError(err))
+        let result = if self.inverted_output {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        };
+        self.pin_result(result)
     }
 
     pub fn is_bus_high(&self) -> OneWireResult<bool, E> {
-        self.pin
-            .is_high()
-            .map_err(|err| OneWireError::PinError(err))
+        self.pin_result(self.pin.is_high())
     }
 
     pub fn is_bus_low(&self) -> OneWireResult<bool, E> {
-        self.pin.is_low().map_err(|err| OneWireError::PinError(err))
+        self.pin_result(self.pin.is_low())
+    }
+
+    fn pin_result<V>(&self, result: Result<V, E>) -> OneWireResult<V, E> {
+        result.map_err(|err| {
+            trace!(self, BusEvent::Error);
+            OneWireError::PinError(err)
+        })
+    }
+
+    /// Releases the bus, then waits for `micros`. An active pull-up is switched off again (by
+    /// releasing the bus a second time, see `pins::ActivePullUp`) once `Timings::active_pull_up`
+    /// of the wait has passed
+    pub(crate) fn release_for(
+        &mut self,
+        micros: u16,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        let remaining = self.release_pulsed(micros, delay)?;
+        delay.delay_us(remaining);
+        Ok(())
     }
 
-    fn wait_for_high(&self, delay: &mut impl DelayUs<u16>) -> OneWireResult<(), E> {
-        // wait up to 250 µs for the bus to become high (from the pull-up resistor)
-        for _ in 0..125 {
+    /// Releases the bus, and ends the active pull-up pulse (if there is one) within the next
+    /// `micros`. Returns how much of `micros` is left to wait
+    fn release_pulsed(&mut self, micros: u16, delay: &mut impl Delay) -> OneWireResult<u16, E> {
+        self.release_bus()?;
+        if self.times.active_pull_up == 0 {
+            return Ok(micros);
+        }
+        let pulse = self.times.active_pull_up.min(micros);
+        delay.delay_us(pulse);
+        self.release_bus()?;
+        Ok(micros - pulse)
+    }
+
+    /// Waits for the extra recovery time, which is usually 0 (and many delays take a few µs even
+    /// then)
+    fn recover(&self, micros: u16, delay: &mut impl Delay) {
+        if micros > 0 {
+            delay.delay_us(micros);
+        }
+    }
+
+    pub(crate) fn wait_for_high(&self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        // wait for the bus to become high (from the pull-up resistor)
+        for _ in 0..(self.bus_high_timeout_micros / 2).max(1) {
             if self.is_bus_high()? {
                 return Ok(());
             }
             delay.delay_us(2);
         }
+        trace!(self, BusEvent::Error);
         Err(OneWireError::BusNotHigh)
     }
 
     /// Sends a reset pulse, then returns true if a device is present
-    pub fn reset(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<bool, E> {
+    pub fn reset(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        Ok(self.reset_detailed(delay)? != ResetResult::NoDevices)
+    }
+
+    /// Sends a reset pulse, and also checks the bus after the presence pulse has ended, so a bus
+    /// that is shorted to ground isn't mistaken for a device being present
+    pub fn reset_detailed(&mut self, delay: &mut impl Delay) -> OneWireResult<ResetResult, E> {
+        self.start_reset();
         self.wait_for_high(delay)?;
 
         self.set_bus_low()?;
-        delay.delay_us(480); // Maxim recommended wait time
+        delay.delay_us(self.times.reset_low);
 
-        self.release_bus()?;
-        delay.delay_us(70); // Maxim recommended wait time
+        self.release_for(self.times.presence_sample, delay)?;
 
         let device_present = self.is_bus_low()?;
 
-        delay.delay_us(410); // Maxim recommended wait time
-        Ok(device_present)
+        delay.delay_us(self.times.reset_recovery);
+        self.finish_reset(device_present)
     }
 
-    pub fn read_bit(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<bool, E> {
-        self.set_bus_low()?;
-        delay.delay_us(6); // Maxim recommended wait time
+    /// Counts a reset, and clears the failed operation, before the reset pulse
+    pub(crate) fn start_reset(&mut self) {
+        count!(self, resets);
+        self.failed_operation = None;
+    }
 
-        self.release_bus()?;
-        delay.delay_us(9); // Maxim recommended wait time
+    /// Checks the bus once the reset recovery time has passed, and reports the result
+    pub(crate) fn finish_reset(&mut self, device_present: bool) -> OneWireResult<ResetResult, E> {
+        // presence pulses are at most 240 µs, so the bus should be high again by now
+        let result = if self.is_bus_low()? {
+            ResetResult::ShortToGround
+        } else if device_present {
+            ResetResult::DevicesPresent
+        } else {
+            ResetResult::NoDevices
+        };
+        trace!(self, BusEvent::Reset(result));
+        log_event!(debug, "reset: {:?}", result);
+        self.idle();
+        Ok(result)
+    }
 
-        let bit_value = self.is_bus_high()?;
-        delay.delay_us(55); // Maxim recommended wait time
+    pub fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        let bit_value = self.sample_read_slot(delay)?;
+        delay.delay_us(self.times.read_recovery);
+        self.recover(self.times.slot_recovery, delay);
+        trace!(self, BusEvent::BitRead(bit_value));
         Ok(bit_value)
     }
 
-    pub fn read_byte(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<u8, E> {
+    /// The timing critical part of a read slot: starts the slot, and samples the bus. The read
+    /// recovery time is left to wait
+    pub(crate) fn sample_read_slot(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        self.set_bus_low()?;
+        delay.delay_us(self.times.write_one_low);
+
+        let bit_value = if self.majority_reads {
+            // 3 samples, with the last at the same time as a normal read
+            self.release_for(self.times.read_sample.saturating_sub(4), delay)?;
+            let mut high_samples = 0;
+            for sample in 0..3 {
+                if sample > 0 {
+                    delay.delay_us(2);
+                }
+                if self.is_bus_high()? {
+                    high_samples += 1;
+                }
+            }
+            high_samples >= 2
+        } else {
+            self.release_for(self.times.read_sample, delay)?;
+            self.is_bus_high()?
+        };
+        Ok(bit_value)
+    }
+
+    pub fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, E> {
         let mut output: u8 = 0;
         for _ in 0..8 {
             output >>= 1;
@@ -119,70 +606,144 @@ where
                 output |= 0x80;
             }
         }
+        self.recover(self.times.byte_recovery, delay);
+        count!(self, bytes_read);
+        trace!(self, BusEvent::ByteRead(output));
+        self.idle();
         Ok(output)
     }
+
     pub fn read_bytes(
         &mut self,
         output: &mut [u8],
-        delay: &mut impl DelayUs<u16>,
+        delay: &mut impl Delay,
     ) -> OneWireResult<(), E> {
-        for i in 0..output.len() {
-            output[i] = self.read_byte(delay)?;
-        }
-        Ok(())
+        OneWireBus::read_bytes(self, output, delay)
     }
 
-    pub fn write_1_bit(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<(), E> {
-        self.set_bus_low()?;
-        delay.delay_us(6); // Maxim recommended wait time
+    /// Reads `output.len()` bytes, followed by a crc8 of them, which is checked
+    pub fn read_bytes_crc8(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::read_bytes_crc8(self, output, delay)
+    }
 
-        self.release_bus()?;
-        delay.delay_us(64); // Maxim recommended wait time
-        Ok(())
+    /// Reads `count` bits (at most 8), least significant bit first
+    pub fn read_bits(&mut self, count: u8, delay: &mut impl Delay) -> OneWireResult<u8, E> {
+        OneWireBus::read_bits(self, count, delay)
     }
 
-    pub fn write_0_bit(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<(), E> {
-        self.set_bus_low()?;
-        delay.delay_us(60); // Maxim recommended wait time
+    pub fn write_1_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let remaining = self.start_write_slot(true, delay)?;
+        delay.delay_us(remaining);
+        self.recover(self.times.slot_recovery, delay);
+        Ok(())
+    }
 
-        self.release_bus()?;
-        delay.delay_us(10); // Maxim recommended wait time
+    pub fn write_0_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let remaining = self.start_write_slot(false, delay)?;
+        delay.delay_us(remaining);
+        self.recover(self.times.slot_recovery, delay);
         Ok(())
     }
 
-    pub fn write_bit(
+    /// The timing critical part of a write slot: holds the bus low for the slot, then releases it.
+    /// Returns the recovery time left to wait
+    pub(crate) fn start_write_slot(
         &mut self,
         value: bool,
-        delay: &mut impl DelayUs<u16>,
-    ) -> OneWireResult<(), E> {
+        delay: &mut impl Delay,
+    ) -> OneWireResult<u16, E> {
+        let (low, recovery) = match value {
+            true => (self.times.write_one_low, self.times.write_one_recovery),
+            false => (self.times.write_zero_low, self.times.write_zero_recovery),
+        };
+        self.set_bus_low()?;
+        delay.delay_us(low);
+        self.release_pulsed(recovery, delay)
+    }
+
+    pub fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), E> {
         if value {
-            self.write_1_bit(delay)
+            self.write_1_bit(delay)?;
         } else {
-            self.write_0_bit(delay)
+            self.write_0_bit(delay)?;
         }
+        trace!(self, BusEvent::BitWritten(value));
+        Ok(())
     }
 
-    pub fn write_byte(
-        &mut self,
-        mut value: u8,
-        delay: &mut impl DelayUs<u16>,
-    ) -> OneWireResult<(), E> {
+    /// Sends a bit, and returns the value of the bus during the slot. Sending a 1 is the same as a
+    /// read slot, so this returns what the devices sent. Sending a 0 always returns false
+    pub fn touch_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        OneWireBus::touch_bit(self, value, delay)
+    }
+
+    /// Sends a byte using `touch_bit`, and returns the bits that were read. Sending 0xFF is the same
+    /// as `read_byte`, anything else is counted and traced as a byte written
+    pub fn touch_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<u8, E> {
+        if value == 0xFF {
+            return self.read_byte(delay);
+        }
+        let mut output: u8 = 0;
+        for bit_index in 0..8 {
+            if self.touch_bit(value & (1 << bit_index) != 0, delay)? {
+                output |= 1 << bit_index;
+            }
+        }
+        self.recover(self.times.byte_recovery, delay);
+        count!(self, bytes_written);
+        trace!(self, BusEvent::ByteWritten(value));
+        self.idle();
+        Ok(output)
+    }
+
+    pub fn write_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let mut remaining = value;
         for _ in 0..8 {
-            self.write_bit(value & 0x01 == 0x01, delay)?;
-            value >>= 1;
+            self.write_bit(remaining & 0x01 == 0x01, delay)?;
+            remaining >>= 1;
         }
+        self.recover(self.times.byte_recovery, delay);
+        count!(self, bytes_written);
+        trace!(self, BusEvent::ByteWritten(value));
+        self.idle();
         Ok(())
     }
 
-    pub fn write_bytes(
+    pub fn write_bytes(&mut self, bytes: &[u8], delay: &mut impl Delay) -> OneWireResult<(), E> {
+        OneWireBus::write_bytes(self, bytes, delay)
+    }
+
+    /// Writes `bytes`, then reads the same number of bytes back and checks that they match, for
+    /// devices that echo data back after it's written. Returns `VerificationFailed` if they don't
+    pub fn write_bytes_verified(
         &mut self,
         bytes: &[u8],
-        delay: &mut impl DelayUs<u16>,
+        delay: &mut impl Delay,
     ) -> OneWireResult<(), E> {
-        for i in 0..bytes.len() {
-            self.write_byte(bytes[i], delay)?;
-        }
-        Ok(())
+        OneWireBus::write_bytes_verified(self, bytes, delay)
+    }
+
+    /// Writes the lowest `count` bits (at most 8) of `value`, least significant bit first
+    pub fn write_bits(
+        &mut self,
+        value: u8,
+        count: u8,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::write_bits(self, value, count, delay)
+    }
+
+    /// Sends a ROM command. This should only be called after a reset
+    pub fn write_rom_command(
+        &mut self,
+        command: RomCommand,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::write_rom_command(self, command, delay)
     }
 
     /// Address a specific device. All others will wait for a reset pulse.
@@ -190,18 +751,15 @@ where
     pub fn match_address(
         &mut self,
         address: &Address,
-        delay: &mut impl DelayUs<u16>,
+        delay: &mut impl Delay,
     ) -> OneWireResult<(), E> {
-        self.write_byte(commands::MATCH_ROM, delay)?;
-        self.write_bytes(&address.0.to_le_bytes(), delay)?;
-        Ok(())
+        OneWireBus::match_address(self, address, delay)
     }
 
     /// Address all devices on the bus simultaneously.
     /// This should only be called after a reset, and should be immediately followed by another command
-    pub fn skip_address(&mut self, delay: &mut impl DelayUs<u16>) -> OneWireResult<(), E> {
-        self.write_byte(commands::SKIP_ROM, delay)?;
-        Ok(())
+    pub fn skip_address(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        OneWireBus::skip_address(self, delay)
     }
 
     /// Sends a reset, followed with either a SKIP_ROM or MATCH_ROM (with an address), and then the supplied command
@@ -210,40 +768,133 @@ where
         &mut self,
         command: u8,
         address: Option<&Address>,
-        delay: &mut impl DelayUs<u16>,
+        delay: &mut impl Delay,
     ) -> OneWireResult<(), E> {
-        self.reset(delay)?;
-        if let Some(address) = address {
-            self.match_address(address, delay)?;
-        } else {
-            self.skip_address(delay)?;
-        }
-        self.write_byte(command, delay)?;
-        Ok(())
+        OneWireBus::send_command(self, command, address, delay)
+    }
+
+    /// The same as `send_command`, but with a choice of ROM command used to address the devices
+    pub fn send_command_with(
+        &mut self,
+        command: u8,
+        addressing: Addressing,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::send_command_with(self, command, addressing, delay)
+    }
+
+    /// Reads the scratchpad of a device using the conventional READ SCRATCHPAD (0xBE) command.
+    /// The last byte of `output` is the crc, which is checked against the rest of the scratchpad
+    pub fn read_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::read_scratchpad(self, address, output, delay)
+    }
+
+    /// Writes to the scratchpad of a device using the conventional WRITE SCRATCHPAD (0x4E) command
+    pub fn write_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        data: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        OneWireBus::write_scratchpad(self, address, data, delay)
     }
 
     /// Returns an iterator that iterates over all device addresses on the bus
     /// They can be filtered to only alarming devices if needed
     /// There is no requirement to immediately finish iterating all devices, but if devices are
     /// added / removed / change alarm state, the search may return an error or fail to find a device
-    /// Device addresses will always be returned in the same order (lowest to highest, Little Endian)
+    /// Device addresses will always be returned in the same order (lowest to highest, Little Endian,
+    /// unless the order is changed with `DeviceSearch::order`)
     pub fn devices<'a, 'b, D>(
         &'a mut self,
         only_alarming: bool,
         delay: &'b mut D,
-    ) -> DeviceSearch<'a, 'b, T, D>
+    ) -> DeviceSearch<'a, 'b, T, D, P>
+    where
+        D: Delay,
+    {
+        self.devices_from(None, only_alarming, delay)
+    }
+
+    /// The same as `devices`, but continues a search from a state returned by `DeviceSearch::into_state`.
+    /// A state of `None` starts a new search
+    pub fn devices_from<'a, 'b, D>(
+        &'a mut self,
+        state: Option<SearchState>,
+        only_alarming: bool,
+        delay: &'b mut D,
+    ) -> DeviceSearch<'a, 'b, T, D, P>
     where
-        D: DelayUs<u16>,
+        D: Delay,
     {
         DeviceSearch {
             onewire: self,
             delay,
-            state: None,
+            state,
+            after: None,
+            order: SearchOrder::Ascending,
+            skip: 0,
+            targeted: false,
             finished: false,
             only_alarming,
+            family_code: None,
         }
     }
 
+    /// The same as `devices`, but only returns devices with the given family code, and only
+    /// searches for them: the first pass goes straight to the family (see
+    /// `OneWireBus::device_search_family`), and the search stops after its last device
+    pub fn devices_of_family<'a, 'b, D>(
+        &'a mut self,
+        family_code: u8,
+        only_alarming: bool,
+        delay: &'b mut D,
+    ) -> DeviceSearch<'a, 'b, T, D, P>
+    where
+        D: Delay,
+    {
+        let mut search = self.devices_filtered(only_alarming, Some(family_code), delay);
+        search.targeted = true;
+        search
+    }
+
+    /// The same as `devices`, but only returns the devices after `address` (see
+    /// `OneWireBus::device_search_after`), for continuing from a known device
+    pub fn devices_after<'a, 'b, D>(
+        &'a mut self,
+        address: Address,
+        only_alarming: bool,
+        delay: &'b mut D,
+    ) -> DeviceSearch<'a, 'b, T, D, P>
+    where
+        D: Delay,
+    {
+        let mut search = self.devices(only_alarming, delay);
+        search.after = Some(address);
+        search
+    }
+
+    /// The same as `devices`, but only returns devices with the given family code (if it's not
+    /// `None`). The whole bus is still searched, see `devices_of_family` for a quicker search
+    pub fn devices_filtered<'a, 'b, D>(
+        &'a mut self,
+        only_alarming: bool,
+        family_code: Option<u8>,
+        delay: &'b mut D,
+    ) -> DeviceSearch<'a, 'b, T, D, P>
+    where
+        D: Delay,
+    {
+        let mut search = self.devices(only_alarming, delay);
+        search.family_code = family_code;
+        search
+    }
+
     /// Search for device addresses on the bus
     /// They can be filtered to only alarming devices if needed
     /// Start the first search with a search_state of `None`, then use the returned state for subsequent searches
@@ -254,149 +905,430 @@ where
         &mut self,
         search_state: Option<&SearchState>,
         only_alarming: bool,
-        delay: &mut impl DelayUs<u16>,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, E> {
+        self.device_search_ordered(search_state, only_alarming, SearchOrder::Ascending, delay)
+    }
+
+    /// The same as `device_search`, but finds the devices in the given order. Every pass of a
+    /// search must use the same order
+    pub fn device_search_ordered(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, E> {
+        OneWireBus::device_search_ordered(self, search_state, only_alarming, order, delay)
+    }
+
+    /// The same as `device_search`, but at overdrive speed. Each call sends a standard speed reset
+    /// and an OVERDRIVE SKIP ROM, which switches every overdrive capable device to overdrive speed,
+    /// then does the search with `Timings::OVERDRIVE`. The bus timings are restored afterwards.
+    ///
+    /// Devices that don't support overdrive are not found. The devices found stay at overdrive
+    /// speed until the next standard speed reset (which the next bus operation will normally send).
+    pub fn device_search_overdrive(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        delay: &mut impl Delay,
     ) -> OneWireResult<Option<(Address, SearchState)>, E> {
         if let Some(search_state) = search_state {
-            if search_state.discrepancies == 0 {
+            if search_state.is_finished() {
                 return Ok(None);
             }
         }
-
         if !self.reset(delay)? {
             return Ok(None);
         }
-        if only_alarming {
-            self.write_byte(commands::SEARCH_ALARM, delay)?;
-        } else {
-            self.write_byte(commands::SEARCH_NORMAL, delay)?;
-        }
+        self.write_rom_command(RomCommand::OverdriveSkip, delay)?;
 
-        let mut last_discrepancy_index: u8 = 0;
-        let mut address;
-        let mut discrepancies;
-        let continue_start_bit;
+        let speed = self.speed();
+        self.set_speed(Speed::Overdrive);
+        let result = self.device_search(search_state, only_alarming, delay);
+        self.set_speed(speed);
+        result
+    }
+}
 
-        if let Some(search_state) = search_state {
-            // follow up to the last discrepancy
-            for bit_index in 0..search_state.last_discrepancy_index {
-                let _false_bit = !self.read_bit(delay)?;
-                let _true_bit = !self.read_bit(delay)?;
-                let was_discrepancy_bit =
-                    (search_state.discrepancies & (1_u64 << (bit_index as u64))) != 0;
-                if was_discrepancy_bit {
-                    last_discrepancy_index = bit_index;
-                }
-                let previous_chosen_bit =
-                    (search_state.address & (1_u64 << (bit_index as u64))) != 0;
+pub struct DeviceSearch<'a, 'b, T, D, P = NoStrongPullup> {
+    onewire: &'a mut OneWire<T, P>,
+    delay: &'b mut D,
+    state: Option<SearchState>,
+    // where the search starts, until the first device is found
+    after: Option<Address>,
+    order: SearchOrder,
+    // the number of devices still to skip
+    skip: usize,
+    // the search goes straight to `family_code`, and stops after it
+    targeted: bool,
+    finished: bool,
+    only_alarming: bool,
+    family_code: Option<u8>,
+}
 
-                // choose the same as last time
-                self.write_bit(previous_chosen_bit, delay)?;
-            }
-            address = search_state.address;
-            // This is the discrepancy bit. False is always chosen to start, so choose true this time
-            {
-                let false_bit = !self.read_bit(delay)?;
-                let true_bit = !self.read_bit(delay)?;
-                if !(false_bit && true_bit) {
-                    // A different response was received than last search
-                    return Err(OneWireError::UnexpectedResponse);
-                }
-                let address_mask = 1_u64 << (search_state.last_discrepancy_index as u64);
-                address |= address_mask;
-                self.write_bit(true, delay)?;
-            }
+impl<'a, 'b, T, D, P> DeviceSearch<'a, 'b, T, D, P> {
+    /// Stops the search, returning its state so it can be continued later with `OneWire::devices_from`
+    /// (the bus can be used for other things in the meantime).
+    ///
+    /// This is `None` if no devices have been found yet, or the search failed.
+    pub fn into_state(self) -> Option<SearchState> {
+        self.state
+    }
 
-            //keep all discrepancies except the last one
-            discrepancies = search_state.discrepancies
-                & !(1_u64 << (search_state.last_discrepancy_index as u64));
-            continue_start_bit = search_state.last_discrepancy_index + 1;
-        } else {
-            address = 0;
-            discrepancies = 0;
-            continue_start_bit = 0;
-        }
-        for bit_index in continue_start_bit..64 {
-            let false_bit = !self.read_bit(delay)?;
-            let true_bit = !self.read_bit(delay)?;
-            let chosen_bit = match (false_bit, true_bit) {
-                (false, false) => {
-                    // No devices responded to the search request
-                    return Err(OneWireError::UnexpectedResponse);
-                }
-                (false, true) => {
-                    // All remaining devices have the true bit set
-                    true
+    /// Sets the order the devices are found in, which must be done before the first device is
+    /// found. A search continued with `OneWire::devices_from` must use the same order
+    pub fn order(mut self, order: SearchOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Skips the next `count` devices the search finds, such as the devices already shown on
+    /// earlier pages of a list. This is cheaper than `Iterator::skip`, since skipped addresses
+    /// aren't crc checked, so a device with a bad address doesn't stop the search either. The
+    /// search still takes a pass for each device that's skipped
+    pub fn skip_devices(mut self, count: usize) -> Self {
+        self.skip = count;
+        self
+    }
+}
+
+impl<'a, 'b, T, E, D, P> Iterator for DeviceSearch<'a, 'b, T, D, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+    D: Delay,
+{
+    type Item = OneWireResult<Address, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            let result = match (&self.state, self.after) {
+                (None, Some(after)) => self.onewire.device_search_after(
+                    &after,
+                    self.only_alarming,
+                    self.order,
+                    self.delay,
+                ),
+                (None, None) if self.targeted => self.onewire.device_search_family(
+                    self.family_code.unwrap_or(0),
+                    self.only_alarming,
+                    self.order,
+                    self.delay,
+                ),
+                // the rest of the devices aren't in the family
+                (Some(state), _) if self.targeted && state.last_discrepancy_index() < 8 => Ok(None),
+                (state, _) if self.skip > 0 => self.onewire.counted_search(
+                    state.as_ref(),
+                    self.only_alarming,
+                    self.order,
+                    false,
+                    self.delay,
+                    &mut NoDeadline,
+                ),
+                _ => self.onewire.device_search_ordered(
+                    self.state.as_ref(),
+                    self.only_alarming,
+                    self.order,
+                    self.delay,
+                ),
+            };
+            match result {
+                Ok(Some((address, search_state))) => {
+                    self.state = Some(search_state);
+                    if self.family_code.is_none() || self.family_code == Some(address.family_code())
+                    {
+                        if self.skip == 0 {
+                            return Some(Ok(address));
+                        }
+                        self.skip -= 1;
+                    }
                 }
-                (true, false) => {
-                    // All remaining devices have the false bit set
-                    false
+                Ok(None) => {
+                    self.finished = true;
                 }
-                (true, true) => {
-                    // Discrepancy, multiple values reported
-                    // choosing the lower value here
-                    discrepancies |= 1_u64 << (bit_index as u64);
-                    last_discrepancy_index = bit_index;
-                    false
+                Err(err) => {
+                    self.state = None;
+                    self.finished = true;
+                    return Some(Err(err));
                 }
-            };
-            let address_mask = 1_u64 << (bit_index as u64);
-            if chosen_bit {
-                address |= address_mask;
-            } else {
-                address &= !address_mask;
             }
-            self.write_bit(chosen_bit, delay)?;
         }
-        crc::check_crc8(&address.to_le_bytes())?;
-        Ok(Some((
-            Address(address),
-            SearchState {
-                address,
-                discrepancies,
-                last_discrepancy_index,
-            },
-        )))
+        None
     }
-}
 
-pub struct DeviceSearch<'a, 'b, T, D> {
-    onewire: &'a mut OneWire<T>,
-    delay: &'b mut D,
-    state: Option<SearchState>,
-    finished: bool,
-    only_alarming: bool,
+    /// Every discrepancy still to be taken leads to at least one more device, but a search that fails
+    /// part way only returns the error, so the lower bound is 1 while a discrepancy is pending. It's
+    /// 0 when devices are skipped or filtered by family, and there's only an upper bound once the
+    /// search has finished
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.state {
+            _ if self.finished => (0, Some(0)),
+            Some(state) if state.is_finished() => (0, Some(0)),
+            Some(_) if self.skip == 0 && self.family_code.is_none() => (1, None),
+            _ => (0, None),
+        }
+    }
 }
 
-impl<'a, 'b, T, E, D> Iterator for DeviceSearch<'a, 'b, T, D>
+impl<'a, 'b, T, E, D, P> FusedIterator for DeviceSearch<'a, 'b, T, D, P>
 where
     T: InputPin<Error = E>,
     T: OutputPin<Error = E>,
-    D: DelayUs<u16>,
+    P: StrongPullupPin<E>,
+    D: Delay,
 {
-    type Item = OneWireResult<Address, E>;
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::search::SearchOrder;
+    use crate::simulator::{SimulatedPin, Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWire, OneWireBus, OneWireError};
+    use core::convert::Infallible;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Inverts the output of a pin, like a transistor pulling the bus low
+    struct Inverter<'a>(SimulatedPin<'a>);
+
+    impl<'a> OutputPin for Inverter<'a> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set_high()
         }
-        let result =
-            self.onewire
-                .device_search(self.state.as_ref(), self.only_alarming, self.delay);
-        match result {
-            Ok(Some((address, search_state))) => {
-                self.state = Some(search_state);
-                Some(Ok(address))
-            }
-            Ok(None) => {
-                self.state = None;
-                self.finished = true;
-                None
-            }
-            Err(err) => {
-                self.state = None;
-                self.finished = true;
-                Some(Err(err))
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set_low()
+        }
+    }
+
+    impl<'a> InputPin for Inverter<'a> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            self.0.is_high()
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            self.0.is_low()
+        }
+    }
+
+    #[test]
+    fn test_inverted_output() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new_inverted(Inverter(simulator.pin())).unwrap();
+        assert!(one_wire.is_bus_high().unwrap());
+        let found: Vec<_> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(found, vec![Address::for_test(0x28, 1)]);
+    }
+
+    #[test]
+    fn test_new_unchecked() {
+        let simulator = Simulator::new();
+        let mut pin = simulator.pin();
+        pin.set_low().unwrap();
+        let mut one_wire = OneWire::new_unchecked(pin);
+        assert!(one_wire.is_bus_low().unwrap());
+        one_wire.release_bus().unwrap();
+        assert!(one_wire.is_bus_high().unwrap());
+    }
+
+    #[test]
+    fn test_with_pin() {
+        let simulator = Simulator::new();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.pin_mut().set_low().unwrap();
+        assert!(one_wire.is_bus_low().unwrap());
+
+        let low = one_wire.with_pin(|pin| pin.is_low().unwrap());
+        assert_eq!(low, Ok(true));
+        assert!(one_wire.is_bus_high().unwrap());
+    }
+
+    #[test]
+    fn test_idle_hook() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        fn hook() {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_idle_hook(Some(hook));
+
+        one_wire
+            .send_command(commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        one_wire.read_bit(&mut delay).unwrap();
+        // the reset, SKIP ROM, and the command
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_devices_after() {
+        let simulator = Simulator::new();
+        for (family, serial_number) in [(0x28, 1), (0x28, 6), (0x10, 3), (0x3A, 2), (0x10, 9)] {
+            simulator.add_device(VirtualDevice::new(Address::for_test(family, serial_number)));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let all: Vec<Address> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(all.len(), 5);
+
+        for (index, address) in all.iter().enumerate() {
+            let after: Vec<Address> = one_wire
+                .devices_after(*address, false, &mut delay)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(after, all[index + 1..]);
+        }
+
+        // an address that isn't on the bus, between the second and third devices
+        let missing = Address((all[1].0.reverse_bits() + 1).reverse_bits());
+        assert!(missing.0.reverse_bits() < all[2].0.reverse_bits());
+        let after: Vec<Address> = one_wire
+            .devices_after(missing, false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(after, all[2..]);
+
+        // in descending order, the devices after the third are the first two
+        let after: Vec<Address> = one_wire
+            .devices_after(all[2], false, &mut delay)
+            .order(SearchOrder::Descending)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(after, [all[1], all[0]]);
+    }
+
+    #[test]
+    fn test_skip_devices() {
+        let simulator = Simulator::new();
+        let mut addresses: Vec<Address> = (1..=6)
+            .map(|serial| Address::for_test(0x28, serial))
+            .collect();
+        // an address with a bad crc, which stops a normal search
+        addresses.push(Address(0x28));
+        for address in &addresses {
+            simulator.add_device(VirtualDevice::new(*address));
+        }
+        addresses.sort_by_key(|address| address.0.reverse_bits());
+        let bad = addresses
+            .iter()
+            .position(|address| *address == Address(0x28))
+            .unwrap();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let found: Vec<Address> = one_wire
+            .devices(false, &mut delay)
+            .skip_devices(bad + 1)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(found, addresses[bad + 1..]);
+        // the skipped passes go through the bus's search, so they're counted like any other
+        #[cfg(feature = "stats")]
+        {
+            assert_eq!(one_wire.stats().search_restarts, 1);
+            assert_eq!(one_wire.stats().crc_failures, 0);
+        }
+
+        assert!(matches!(
+            one_wire.devices(false, &mut delay).nth(bad),
+            Some(Err(OneWireError::CrcMismatch { .. }))
+        ));
+        assert_eq!(
+            one_wire
+                .devices(false, &mut delay)
+                .skip_devices(addresses.len())
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_devices_of_family() {
+        let simulator = Simulator::new();
+        for serial_number in 1..=4 {
+            for family in [0x28, 0x10, 0x3A, 0x29] {
+                simulator.add_device(VirtualDevice::new(Address::for_test(family, serial_number)));
             }
         }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        for family in [0x28, 0x10, 0x3A, 0x29] {
+            let start = simulator.time_micros();
+            let filtered: Vec<_> = one_wire
+                .devices_filtered(false, Some(family), &mut delay)
+                .map(|address| address.unwrap())
+                .collect();
+            let filtered_micros = simulator.time_micros() - start;
+
+            let start = simulator.time_micros();
+            let found: Vec<_> = one_wire
+                .devices_of_family(family, false, &mut delay)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(found, filtered);
+            assert_eq!(found.len(), 4);
+            // only the passes that find the family's devices
+            assert!((simulator.time_micros() - start) * 3 < filtered_micros);
+
+            let found: Vec<_> = one_wire
+                .devices_of_family(family, false, &mut delay)
+                .order(SearchOrder::Descending)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(found, filtered.iter().rev().copied().collect::<Vec<_>>());
+        }
+
+        assert_eq!(
+            one_wire.devices_of_family(0x2D, false, &mut delay).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_is_device_present() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let address = Address::for_test(0x28, 1);
+        assert_eq!(one_wire.is_device_present(&address, &mut delay), Ok(false));
+
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 2)));
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x10, 1)));
+        assert_eq!(one_wire.is_device_present(&address, &mut delay), Ok(false));
+        // differs from a device on the bus only in the last bit
+        let other = Address(Address::for_test(0x28, 2).0 ^ (1 << 63));
+        assert_eq!(one_wire.is_device_present(&other, &mut delay), Ok(false));
+
+        simulator.add_device(VirtualDevice::new(address));
+        assert_eq!(one_wire.is_device_present(&address, &mut delay), Ok(true));
+        assert_eq!(
+            one_wire.is_device_present(&Address::for_test(0x10, 1), &mut delay),
+            Ok(true)
+        );
     }
 }
@@ -0,0 +1,657 @@
+//! Helpers for EEPROM memory devices (DS2431, DS2433, DS28EC20, etc). These all share the same
+//! READ MEMORY / WRITE SCRATCHPAD / COPY SCRATCHPAD sequence, addressed with a 16-bit target
+//! address sent as 2 bytes (TA1, TA2).
+//!
+//! The parts differ in their page and scratchpad sizes, programming times, and whether they send a
+//! crc16 with reads, which is described by a `MemoryLayout`. Some small parts (such as the DS28E05)
+//! have no scratchpad, and are written a segment at a time with WRITE MEMORY instead.
+
+use crate::bus::read_crc16;
+use crate::crc::Crc16Digest;
+use crate::deadline::{Deadline, NoDeadline};
+use crate::delay::Delay;
+use crate::pullup::bus_strong_pullup_delay;
+use crate::{Address, OneWireBus, OneWireError, OneWireResult, Speed};
+use embedded_hal::digital::v2::OutputPin;
+
+pub mod commands {
+    pub const READ_MEMORY: u8 = 0xF0;
+    pub const WRITE_SCRATCHPAD: u8 = 0x0F;
+    pub const READ_SCRATCHPAD: u8 = 0xAA;
+    pub const COPY_SCRATCHPAD: u8 = 0x55;
+
+    /// READ MEMORY, with a crc16 after each page
+    pub const EXTENDED_READ_MEMORY: u8 = 0xA5;
+
+    /// Programs memory directly, for devices without a scratchpad
+    pub const WRITE_MEMORY: u8 = 0x55;
+}
+
+/// Set in the E/S byte if the scratchpad was not completely written (the write was interrupted)
+const PARTIAL_FLAG: u8 = 0x20;
+
+/// Sent after each segment of WRITE MEMORY, to start programming it
+const RELEASE: u8 = 0xFF;
+
+/// The Maxim recommended wait time for `copy_scratchpad`
+const COPY_MICROS: u16 = 10_000;
+
+/// How a device writes to its memory
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WriteMethod {
+    /// WRITE SCRATCHPAD, then READ SCRATCHPAD to check it, then COPY SCRATCHPAD
+    Scratchpad,
+
+    /// WRITE MEMORY, which programs each segment as it's sent. The device echoes each segment back
+    /// inverted, so it can be checked before it's programmed
+    Segments,
+}
+
+/// The layout of a device's memory, and how it's written. Drivers for memory devices declare one of
+/// these, instead of passing sizes to every call
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryLayout {
+    /// The size of the memory, in bytes
+    pub size: u16,
+
+    pub page_size: u16,
+
+    /// The number of bytes written at once: the size of the scratchpad, or of a segment. This must
+    /// be a power of 2
+    pub write_size: u16,
+
+    /// The time the device takes to program each write
+    pub program_micros: u16,
+
+    pub write_method: WriteMethod,
+
+    /// The device supports EXTENDED READ MEMORY, which sends a crc16 after each page
+    pub page_crc: bool,
+}
+
+impl MemoryLayout {
+    pub const DS2431: MemoryLayout = MemoryLayout {
+        size: 128,
+        page_size: 32,
+        write_size: 8,
+        program_micros: 10_000,
+        write_method: WriteMethod::Scratchpad,
+        page_crc: false,
+    };
+
+    /// The iButton version of the DS2431
+    pub const DS1972: MemoryLayout = MemoryLayout::DS2431;
+
+    pub const DS2433: MemoryLayout = MemoryLayout {
+        size: 512,
+        page_size: 32,
+        write_size: 32,
+        program_micros: 5_000,
+        write_method: WriteMethod::Scratchpad,
+        page_crc: false,
+    };
+
+    /// The iButton version of the DS2433
+    pub const DS1973: MemoryLayout = MemoryLayout::DS2433;
+
+    pub const DS28EC20: MemoryLayout = MemoryLayout {
+        size: 2560,
+        page_size: 32,
+        write_size: 32,
+        program_micros: 10_000,
+        write_method: WriteMethod::Scratchpad,
+        page_crc: true,
+    };
+
+    /// The DS28E05 only communicates at overdrive speed, including the reset and ROM command, so
+    /// the bus has to be switched with `OneWire::set_speed(Speed::Overdrive)` before using the
+    /// helpers with it (and switched back for any standard speed devices). The helpers keep the
+    /// bus at overdrive for their resets
+    pub const DS28E05: MemoryLayout = MemoryLayout {
+        size: 112,
+        page_size: 16,
+        write_size: 2,
+        program_micros: 16_000,
+        write_method: WriteMethod::Segments,
+        page_crc: false,
+    };
+
+    pub fn pages(&self) -> u16 {
+        self.size / self.page_size
+    }
+
+    /// The address of the first byte of a page
+    pub fn page_address(&self, page: u16) -> u16 {
+        page * self.page_size
+    }
+}
+
+/// Sends `command` to the device the same way as `OneWireBus::send_command`, but keeps the bus at
+/// overdrive speed if it was switched to overdrive, for devices that only communicate at overdrive
+/// speed (such as the DS28E05). `send_command` goes back to standard speed for its reset
+fn send_command<B: OneWireBus>(
+    command: u8,
+    address: Option<&Address>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error> {
+    if onewire.speed() == Speed::Standard {
+        return onewire.send_command(command, address, delay);
+    }
+    onewire.reset(delay)?;
+    match address {
+        Some(address) => onewire.match_address(address, delay)?,
+        None => onewire.skip_address(delay)?,
+    }
+    onewire.write_byte(command, delay)
+}
+
+/// Reads `output.len()` bytes of memory, starting at `target_address`.
+/// Reading past the end of memory returns all 1s (0xFF)
+pub fn read_memory<B>(
+    address: Option<&Address>,
+    target_address: u16,
+    output: &mut [u8],
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    read_memory_with_deadline(
+        address,
+        target_address,
+        output,
+        onewire,
+        delay,
+        &mut NoDeadline,
+    )
+}
+
+/// The same as `read_memory`, but gives up with `OneWireError::Timeout` once `deadline` has
+/// passed. The deadline is checked before each byte
+pub fn read_memory_with_deadline<B>(
+    address: Option<&Address>,
+    target_address: u16,
+    output: &mut [u8],
+    onewire: &mut B,
+    delay: &mut impl Delay,
+    deadline: &mut impl Deadline,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    deadline.check()?;
+    send_command(commands::READ_MEMORY, address, onewire, delay)?;
+    onewire.write_bytes(&target_address.to_le_bytes(), delay)?;
+    for byte in output.iter_mut() {
+        deadline.check()?;
+        *byte = onewire.read_byte(delay)?;
+    }
+    Ok(())
+}
+
+/// Reads whole pages, starting at `page`, and checks the crc16 the device sends after each
+/// page. This needs a device with `page_crc` in its layout, and `output.len()` must be a multiple
+/// of the page size
+pub fn read_pages_crc16<B>(
+    address: Option<&Address>,
+    layout: &MemoryLayout,
+    page: u16,
+    output: &mut [u8],
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    assert!(
+        layout.page_crc,
+        "the device doesn't send a crc16 with reads"
+    );
+    assert!(
+        output.len() % layout.page_size as usize == 0,
+        "only whole pages can be read"
+    );
+    let [ta1, ta2] = layout.page_address(page).to_le_bytes();
+    send_command(commands::EXTENDED_READ_MEMORY, address, onewire, delay)?;
+    onewire.write_bytes(&[ta1, ta2], delay)?;
+    for (index, page) in output.chunks_mut(layout.page_size as usize).enumerate() {
+        let mut digest = Crc16Digest::new();
+        // the crc of the first page also covers the command and target address
+        if index == 0 {
+            digest.update(&[commands::EXTENDED_READ_MEMORY, ta1, ta2]);
+        }
+        onewire.read_bytes(page, delay)?;
+        digest.update(page);
+        let len = page.len() + if index == 0 { 3 } else { 0 };
+        read_crc16(onewire, digest, len, delay)?;
+    }
+    Ok(())
+}
+
+/// Writes `data` to memory, starting at `target_address`.
+///
+/// The data is split up into rows of `scratchpad_size` bytes (8 for a DS2431, 32 for a DS2433),
+/// which must be a power of 2. Each row is written to the scratchpad, verified (with the crc16 when
+/// the device sends one, and by reading back the scratchpad), then copied to memory. Some devices
+/// only accept writes of a full row (such as the DS2431), in which case `target_address` and the
+/// length of `data` must be multiples of `scratchpad_size`. See `copy_scratchpad` for when a
+/// `strong_pullup` is needed.
+pub fn write_memory<B>(
+    address: Option<&Address>,
+    target_address: u16,
+    data: &[u8],
+    scratchpad_size: u16,
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    let layout = MemoryLayout {
+        size: u16::MAX,
+        page_size: scratchpad_size,
+        write_size: scratchpad_size,
+        program_micros: COPY_MICROS,
+        write_method: WriteMethod::Scratchpad,
+        page_crc: false,
+    };
+    write_memory_with_layout(
+        address,
+        &layout,
+        target_address,
+        data,
+        strong_pullup,
+        onewire,
+        delay,
+    )
+}
+
+/// Writes `data` to memory, starting at `target_address`, the way `layout` describes. This is
+/// the same as `write_memory` for devices with a scratchpad. Devices written in segments
+/// only program whole segments, so the rest of a partly written segment is read first
+pub fn write_memory_with_layout<B>(
+    address: Option<&Address>,
+    layout: &MemoryLayout,
+    target_address: u16,
+    data: &[u8],
+    mut strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    let write_size = layout.write_size;
+    let mut written = 0;
+    while written < data.len() {
+        let row_address = target_address.wrapping_add(written as u16);
+        let remaining_in_row = (write_size - row_address % write_size) as usize;
+        let row_len = remaining_in_row.min(data.len() - written);
+        let row = &data[written..(written + row_len)];
+        let strong_pullup = strong_pullup
+            .as_mut()
+            .map(|pin| &mut **pin as &mut dyn OutputPin<Error = B::Error>);
+        match layout.write_method {
+            WriteMethod::Scratchpad => write_memory_row(
+                address,
+                row_address,
+                row,
+                layout,
+                strong_pullup,
+                onewire,
+                delay,
+            )?,
+            WriteMethod::Segments => write_memory_segment(
+                address,
+                row_address,
+                row,
+                layout,
+                strong_pullup,
+                onewire,
+                delay,
+            )?,
+        }
+        written += row_len;
+    }
+    Ok(())
+}
+
+fn write_memory_segment<B>(
+    address: Option<&Address>,
+    target_address: u16,
+    data: &[u8],
+    layout: &MemoryLayout,
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    let write_size = layout.write_size as usize;
+    let segment_address = target_address & !(layout.write_size - 1);
+    let mut segment = [0; 8];
+    assert!(write_size <= segment.len(), "segments are at most 8 bytes");
+    let segment = &mut segment[..write_size];
+    if data.len() < write_size {
+        read_memory(address, segment_address, segment, onewire, delay)?;
+    }
+    let offset = (target_address - segment_address) as usize;
+    segment[offset..offset + data.len()].copy_from_slice(data);
+
+    send_command(commands::WRITE_MEMORY, address, onewire, delay)?;
+    onewire.write_bytes(&segment_address.to_le_bytes(), delay)?;
+    onewire.write_bytes(segment, delay)?;
+    for byte in segment.iter() {
+        if onewire.read_byte(delay)? != !*byte {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+    }
+    onewire.write_byte(RELEASE, delay)?;
+    bus_strong_pullup_delay(onewire, strong_pullup, layout.program_micros as u32, delay)?;
+    match onewire.read_byte(delay)? {
+        0xAA => Ok(()),
+        _ => Err(OneWireError::UnexpectedResponse),
+    }
+}
+
+fn write_memory_row<B>(
+    address: Option<&Address>,
+    target_address: u16,
+    data: &[u8],
+    layout: &MemoryLayout,
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    let [ta1, ta2] = target_address.to_le_bytes();
+    let offset_mask = (layout.write_size - 1) as u8;
+    let ending_offset = (ta1 as usize + data.len() - 1) as u8 & offset_mask;
+    let fills_scratchpad = ending_offset == offset_mask;
+
+    send_command(commands::WRITE_SCRATCHPAD, address, onewire, delay)?;
+    onewire.write_bytes(&[ta1, ta2], delay)?;
+    onewire.write_bytes(data, delay)?;
+    if fills_scratchpad {
+        // The device only sends the crc when the end of the scratchpad is reached
+        let mut digest = Crc16Digest::new();
+        digest.update(&[commands::WRITE_SCRATCHPAD, ta1, ta2]);
+        digest.update(data);
+        read_crc16(onewire, digest, 3 + data.len(), delay)?;
+    }
+
+    // The scratchpad is read back to confirm the data, and to get the E/S byte needed to authorize the copy
+    send_command(commands::READ_SCRATCHPAD, address, onewire, delay)?;
+    let mut header = [0; 3];
+    onewire.read_bytes(&mut header, delay)?;
+    let [read_ta1, read_ta2, es] = header;
+    if read_ta1 != ta1
+        || read_ta2 != ta2
+        || es & PARTIAL_FLAG != 0
+        || es & offset_mask != ending_offset
+    {
+        return Err(OneWireError::UnexpectedResponse);
+    }
+    for byte in data {
+        if onewire.read_byte(delay)? != *byte {
+            return Err(OneWireError::UnexpectedResponse);
+        }
+    }
+
+    copy_scratchpad_for(
+        address,
+        [ta1, ta2, es],
+        layout.program_micros,
+        strong_pullup,
+        onewire,
+        delay,
+    )
+}
+
+/// Copies the scratchpad to memory. The `authorization` bytes are the TA1, TA2, and E/S bytes
+/// read from the scratchpad.
+///
+/// Devices need extra current while the EEPROM is being programmed. Parasite powered devices
+/// need a strong pull-up during this time, so if `strong_pullup` is given (or the bus has its own,
+/// see `OneWireBuilder::strong_pullup_pin`) it is set high for the 10ms programming window, and
+/// then set low again.
+/// Once finished, the device responds with alternating 1s and 0s which is checked to confirm
+/// the copy succeeded.
+pub fn copy_scratchpad<B>(
+    address: Option<&Address>,
+    authorization: [u8; 3],
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    copy_scratchpad_for(
+        address,
+        authorization,
+        COPY_MICROS,
+        strong_pullup,
+        onewire,
+        delay,
+    )
+}
+
+fn copy_scratchpad_for<B>(
+    address: Option<&Address>,
+    authorization: [u8; 3],
+    program_micros: u16,
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    onewire: &mut B,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus,
+{
+    send_command(commands::COPY_SCRATCHPAD, address, onewire, delay)?;
+    onewire.write_bytes(&authorization, delay)?;
+    bus_strong_pullup_delay(onewire, strong_pullup, program_micros as u32, delay)?;
+    match onewire.read_byte(delay)? {
+        0xAA | 0x55 => Ok(()),
+        _ => Err(OneWireError::UnexpectedResponse),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::memory::{self, MemoryLayout};
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire, OneWireError, Speed};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_memory() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x23, 1);
+        simulator.add_device(VirtualDevice::with_memory(address, &MemoryLayout::DS2433));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // a partial row, then a write spanning 2 rows
+        memory::write_memory(
+            Some(&address),
+            0x10,
+            &[1, 2, 3],
+            32,
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        let data: Vec<u8> = (0..40).collect();
+        memory::write_memory(
+            Some(&address),
+            0x30,
+            &data,
+            32,
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        let mut output = [0; 3];
+        memory::read_memory(Some(&address), 0x10, &mut output, &mut one_wire, &mut delay).unwrap();
+        assert_eq!(output, [1, 2, 3]);
+        let mut output = [0; 42];
+        memory::read_memory(Some(&address), 0x2F, &mut output, &mut one_wire, &mut delay).unwrap();
+        assert_eq!(output[0], 0xFF);
+        assert_eq!(output[1..41], data[..]);
+        assert_eq!(output[41], 0xFF);
+
+        // past the end of memory reads as 1s
+        let mut output = [0; 2];
+        memory::read_memory(Some(&address), 511, &mut output, &mut one_wire, &mut delay).unwrap();
+        assert_eq!(output, [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_memory_layouts() {
+        let simulator = Simulator::new();
+        let ds2431 = Address::for_test(0x2D, 1);
+        let ds28ec20 = Address::for_test(0x43, 2);
+        simulator.add_device(VirtualDevice::with_memory(ds2431, &MemoryLayout::DS2431));
+        simulator.add_device(VirtualDevice::with_memory(
+            ds28ec20,
+            &MemoryLayout::DS28EC20,
+        ));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let data: Vec<u8> = (0..64).collect();
+        memory::write_memory_with_layout(
+            Some(&ds2431),
+            &MemoryLayout::DS2431,
+            0x40,
+            &data,
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        simulator.with_device(&ds2431, |device| {
+            assert_eq!(device.memory[0x40..], data[..]);
+        });
+
+        // past the first 2 KiB, which needs both bytes of the target address
+        memory::write_memory_with_layout(
+            Some(&ds28ec20),
+            &MemoryLayout::DS28EC20,
+            MemoryLayout::DS28EC20.page_address(70),
+            &data,
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        let mut output = [0; 96];
+        memory::read_pages_crc16(
+            Some(&ds28ec20),
+            &MemoryLayout::DS28EC20,
+            70,
+            &mut output,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        assert_eq!(output[..64], data[..]);
+        assert_eq!(output[64..], [0xFF; 32]);
+
+        // two devices answering at once corrupt the crc
+        let other = Address::for_test(0x43, 3);
+        let mut device = VirtualDevice::with_memory(other, &MemoryLayout::DS28EC20);
+        device.memory[0] = 0x0F;
+        simulator.add_device(device);
+        simulator.with_device(&ds28ec20, |device| device.memory[0] = 0xF0);
+        let mut output = [0; 32];
+        assert!(matches!(
+            memory::read_pages_crc16(
+                None,
+                &MemoryLayout::DS28EC20,
+                0,
+                &mut output,
+                &mut one_wire,
+                &mut delay
+            ),
+            Err(OneWireError::CrcMismatch { len: 35, .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_segments() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x0D, 1);
+        let mut device = VirtualDevice::with_memory(address, &MemoryLayout::DS28E05);
+        device.memory[0] = 0xA5;
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // starts and ends part way through a segment
+        memory::write_memory_with_layout(
+            Some(&address),
+            &MemoryLayout::DS28E05,
+            1,
+            &[1, 2, 3, 4],
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        let mut output = [0; 7];
+        memory::read_memory(Some(&address), 0, &mut output, &mut one_wire, &mut delay).unwrap();
+        assert_eq!(output, [0xA5, 1, 2, 3, 4, 0xFF, 0xFF]);
+
+        // the DS28E05 doesn't have a scratchpad
+        one_wire
+            .send_command(
+                crate::memory::commands::READ_SCRATCHPAD,
+                Some(&address),
+                &mut delay,
+            )
+            .unwrap();
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0xFF));
+    }
+
+    #[test]
+    fn test_overdrive_only() {
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x0D, 1);
+        let mut device = VirtualDevice::with_memory(address, &MemoryLayout::DS28E05);
+        device.overdrive_only = true;
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // the device doesn't answer at standard speed
+        assert_eq!(one_wire.reset(&mut delay), Ok(false));
+
+        one_wire.set_speed(Speed::Overdrive);
+        memory::write_memory_with_layout(
+            Some(&address),
+            &MemoryLayout::DS28E05,
+            2,
+            &[1, 2],
+            None,
+            &mut one_wire,
+            &mut delay,
+        )
+        .unwrap();
+        let mut output = [0; 4];
+        memory::read_memory(Some(&address), 1, &mut output, &mut one_wire, &mut delay).unwrap();
+        assert_eq!(output, [0xFF, 1, 2, 0xFF]);
+        assert_eq!(one_wire.speed(), Speed::Overdrive);
+    }
+}
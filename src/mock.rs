@@ -0,0 +1,475 @@
+//! A mock bus for unit testing device drivers, in the style of `embedded-hal-mock`. Enabled with the
+//! `mock` feature (which requires std).
+//!
+//! The mock is created with the exact sequence of transactions a driver is expected to perform. Each
+//! call made by the driver is checked against the next expected transaction, and panics if it
+//! doesn't match. Data to be read by the driver is supplied in the expectations.
+//!
+//! Expectations can be given as bytes or as individual bits. Bytes read / written by the driver
+//! match either a single byte expectation, or 8 bit expectations (least significant bit first).
+//!
+//! `NoDelay`, `CountingDelay`, and `Pullup` are the delays and strong pull-up pin to pass to the
+//! driver along with the mock.
+
+extern crate std;
+
+use crate::delay::Delay;
+use crate::{commands, Address, OneWireBus, OneWireResult};
+use core::convert::Infallible;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+/// A single expected operation on the bus
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transaction {
+    /// A reset pulse. The value is whether a device responds with a presence pulse
+    Reset(bool),
+
+    /// A bit read by the driver. The value is returned to the driver
+    ReadBit(bool),
+
+    /// A bit written by the driver. The value is what the driver is expected to write
+    WriteBit(bool),
+
+    /// A byte read by the driver. The value is returned to the driver
+    ReadByte(u8),
+
+    /// A byte written by the driver. The value is what the driver is expected to write
+    WriteByte(u8),
+}
+
+impl Transaction {
+    pub fn read_bytes(bytes: &[u8]) -> Vec<Transaction> {
+        bytes
+            .iter()
+            .map(|byte| Transaction::ReadByte(*byte))
+            .collect()
+    }
+
+    pub fn write_bytes(bytes: &[u8]) -> Vec<Transaction> {
+        bytes
+            .iter()
+            .map(|byte| Transaction::WriteByte(*byte))
+            .collect()
+    }
+
+    /// The transactions sent by `send_command`: a reset (with a device present), followed by either
+    /// SKIP ROM or MATCH ROM (with the address), and then the command
+    pub fn command(command: u8, address: Option<&Address>) -> Vec<Transaction> {
+        let mut transactions = std::vec![Transaction::Reset(true)];
+        if let Some(address) = address {
+            transactions.push(Transaction::WriteByte(commands::MATCH_ROM));
+            transactions.extend(Transaction::write_bytes(&address.to_bytes()));
+        } else {
+            transactions.push(Transaction::WriteByte(commands::SKIP_ROM));
+        }
+        transactions.push(Transaction::WriteByte(command));
+        transactions
+    }
+}
+
+/// A bus that checks every operation against a list of expected transactions
+#[derive(Debug, Clone, Default)]
+pub struct Mock {
+    expected: VecDeque<Transaction>,
+    crc_failures: Vec<usize>,
+}
+
+impl Mock {
+    pub fn new(expected: &[Transaction]) -> Mock {
+        Mock {
+            expected: expected.iter().copied().collect(),
+            crc_failures: Vec::new(),
+        }
+    }
+
+    /// Adds more expected transactions, after any that are still outstanding
+    pub fn expect(&mut self, expected: &[Transaction]) {
+        self.expected.extend(expected.iter().copied());
+    }
+
+    /// Panics if any of the expected transactions were not performed
+    pub fn done(&mut self) {
+        assert!(
+            self.expected.is_empty(),
+            "not all expected transactions were performed, remaining: {:?}",
+            self.expected
+        );
+    }
+
+    /// The lengths passed to `crc_failed`, in the order the failures were reported
+    pub fn crc_failures(&self) -> &[usize] {
+        &self.crc_failures
+    }
+
+    fn next(&mut self, operation: &str) -> Transaction {
+        match self.expected.pop_front() {
+            Some(transaction) => transaction,
+            None => panic!(
+                "unexpected {}, no more transactions were expected",
+                operation
+            ),
+        }
+    }
+
+    fn peek(&self) -> Option<Transaction> {
+        self.expected.front().copied()
+    }
+}
+
+impl OneWireBus for Mock {
+    type Error = Infallible;
+
+    fn reset(&mut self, _delay: &mut impl Delay) -> OneWireResult<bool, Infallible> {
+        match self.next("reset") {
+            Transaction::Reset(presence) => Ok(presence),
+            other => panic!("expected {:?}, but the driver sent a reset", other),
+        }
+    }
+
+    fn read_bit(&mut self, _delay: &mut impl Delay) -> OneWireResult<bool, Infallible> {
+        match self.next("bit read") {
+            Transaction::ReadBit(value) => Ok(value),
+            other => panic!("expected {:?}, but the driver read a bit", other),
+        }
+    }
+
+    fn write_bit(&mut self, value: bool, _delay: &mut impl Delay) -> OneWireResult<(), Infallible> {
+        match self.next("bit write") {
+            Transaction::WriteBit(expected) if expected == value => Ok(()),
+            other => panic!("expected {:?}, but the driver wrote bit {}", other, value),
+        }
+    }
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, Infallible> {
+        if let Some(Transaction::ReadBit(_)) = self.peek() {
+            let mut output: u8 = 0;
+            for _ in 0..8 {
+                output >>= 1;
+                if self.read_bit(delay)? {
+                    output |= 0x80;
+                }
+            }
+            return Ok(output);
+        }
+        match self.next("byte read") {
+            Transaction::ReadByte(value) => Ok(value),
+            other => panic!("expected {:?}, but the driver read a byte", other),
+        }
+    }
+
+    fn write_byte(
+        &mut self,
+        mut value: u8,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), Infallible> {
+        if let Some(Transaction::WriteBit(_)) = self.peek() {
+            for _ in 0..8 {
+                self.write_bit(value & 0x01 == 0x01, delay)?;
+                value >>= 1;
+            }
+            return Ok(());
+        }
+        match self.next("byte write") {
+            Transaction::WriteByte(expected) if expected == value => Ok(()),
+            other => panic!(
+                "expected {:?}, but the driver wrote byte {:#04X}",
+                other, value
+            ),
+        }
+    }
+
+    fn crc_failed(&mut self, len: usize) {
+        self.crc_failures.push(len);
+    }
+}
+
+/// A delay that returns immediately, since the mock doesn't need any timing
+pub struct NoDelay;
+
+impl Delay for NoDelay {
+    fn delay_us(&mut self, _us: u16) {}
+}
+
+/// A delay that returns immediately, but adds up the time it was asked to wait (in µs)
+pub struct CountingDelay(pub u32);
+
+impl Delay for CountingDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.0 += us as u32;
+    }
+}
+
+/// A strong pull-up pin that records every state it's set to
+pub struct Pullup<E = Infallible> {
+    pub states: Vec<bool>,
+    failure: Option<(usize, E)>,
+}
+
+impl Pullup {
+    pub fn new() -> Pullup {
+        Pullup {
+            states: Vec::new(),
+            failure: None,
+        }
+    }
+}
+
+impl Default for Pullup {
+    fn default() -> Pullup {
+        Pullup::new()
+    }
+}
+
+impl<E> Pullup<E> {
+    /// A pull-up that returns `error` instead of changing state, once `changes` changes have been
+    /// made
+    pub fn failing(changes: usize, error: E) -> Pullup<E> {
+        Pullup {
+            states: Vec::new(),
+            failure: Some((changes, error)),
+        }
+    }
+}
+
+impl<E: Copy> Pullup<E> {
+    fn set(&mut self, state: bool) -> Result<(), E> {
+        match self.failure {
+            Some((changes, error)) if self.states.len() == changes => Err(error),
+            _ => {
+                self.states.push(state);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<E: Copy> embedded_hal::digital::v2::OutputPin for Pullup<E> {
+    type Error = E;
+
+    fn set_low(&mut self) -> Result<(), E> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), E> {
+        self.set(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::commands::{Addressing, RomCommand};
+    use crate::mock::{CountingDelay, Mock, NoDelay, Pullup, Transaction};
+    use crate::{commands, crc, Address, ConversionWait, OneWireBus, OneWireError};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_send_command() {
+        let address = Address(0x3A00_0000_0000_0128);
+        let mut mock = Mock::new(&Transaction::command(0x44, Some(&address)));
+        mock.send_command(0x44, Some(&address), &mut NoDelay)
+            .unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_rom_command() {
+        let mut mock = Mock::new(&[Transaction::Reset(true), Transaction::WriteByte(0x33)]);
+        mock.reset(&mut NoDelay).unwrap();
+        mock.write_rom_command(RomCommand::ReadRom, &mut NoDelay)
+            .unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_send_command_with() {
+        let mut mock = Mock::new(&[
+            Transaction::Reset(true),
+            Transaction::WriteByte(commands::RESUME),
+            Transaction::WriteByte(0x44),
+        ]);
+        mock.send_command_with(0x44, Addressing::Resume, &mut NoDelay)
+            .unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_partial_bits() {
+        let mut mock = Mock::new(&[
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(true),
+            Transaction::ReadBit(true),
+            Transaction::WriteBit(true),
+            Transaction::WriteBit(false),
+        ]);
+        assert_eq!(mock.read_bits(3, &mut NoDelay), Ok(0b110));
+        mock.write_bits(0b01, 2, &mut NoDelay).unwrap();
+        assert_eq!(mock.read_bits(0, &mut NoDelay), Ok(0));
+        mock.done();
+    }
+
+    #[test]
+    fn test_triplet() {
+        let mut mock = Mock::new(&[
+            // a discrepancy takes the direction
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::WriteBit(true),
+            // otherwise, the bit the devices sent
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(true),
+            Transaction::WriteBit(false),
+            // no devices
+            Transaction::ReadBit(true),
+            Transaction::ReadBit(true),
+            Transaction::WriteBit(true),
+        ]);
+        assert_eq!(mock.triplet(true, &mut NoDelay), Ok((false, false, true)));
+        assert_eq!(mock.triplet(true, &mut NoDelay), Ok((false, true, false)));
+        assert_eq!(mock.triplet(false, &mut NoDelay), Ok((true, true, true)));
+        mock.done();
+    }
+
+    #[test]
+    fn test_bits_and_bytes() {
+        let mut mock = Mock::new(&[
+            Transaction::ReadBit(true),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(true),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::ReadByte(0x42),
+        ]);
+        mock.expect(
+            &(0..8)
+                .map(|_| Transaction::WriteBit(true))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(mock.read_byte(&mut NoDelay), Ok(0x05));
+        assert_eq!(mock.read_byte(&mut NoDelay), Ok(0x42));
+        mock.write_byte(0xFF, &mut NoDelay).unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_read_scratchpad() {
+        let mut expected = Transaction::command(commands::READ_SCRATCHPAD, None);
+        expected.extend(Transaction::read_bytes(&[0x01, 0x02, 0x00]));
+        let mut mock = Mock::new(&expected);
+        let mut scratchpad = [0; 3];
+        assert_eq!(
+            mock.read_scratchpad(None, &mut scratchpad, &mut NoDelay),
+            Err(OneWireError::CrcMismatch {
+                computed: crc::crc8(&[0x01, 0x02]) as u16,
+                received: 0x00,
+                len: 2,
+            })
+        );
+        mock.done();
+    }
+
+    #[test]
+    fn test_read_bytes_crc8() {
+        let data = [0x12, 0x34];
+        let mut expected = Transaction::read_bytes(&data);
+        expected.push(Transaction::ReadByte(crc::crc8(&data)));
+        expected.extend(Transaction::read_bytes(&[0x12, 0x34, 0x00]));
+        let mut mock = Mock::new(&expected);
+        let mut output = [0; 2];
+        mock.read_bytes_crc8(&mut output, &mut NoDelay).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(
+            mock.read_bytes_crc8(&mut output, &mut NoDelay),
+            Err(OneWireError::CrcMismatch {
+                computed: crc::crc8(&data) as u16,
+                received: 0x00,
+                len: 2,
+            })
+        );
+        assert_eq!(mock.crc_failures(), &[2]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_write_bytes_verified() {
+        let mut expected = Transaction::write_bytes(&[0x12, 0x34]);
+        expected.extend(Transaction::read_bytes(&[0x12, 0x34]));
+        expected.extend(Transaction::write_bytes(&[0x12, 0x34]));
+        expected.extend(Transaction::read_bytes(&[0x12, 0x35]));
+        let mut mock = Mock::new(&expected);
+        mock.write_bytes_verified(&[0x12, 0x34], &mut NoDelay)
+            .unwrap();
+        assert_eq!(
+            mock.write_bytes_verified(&[0x12, 0x34], &mut NoDelay),
+            Err(OneWireError::VerificationFailed)
+        );
+        mock.done();
+    }
+
+    #[test]
+    fn test_poll_until_high() {
+        let mut mock = Mock::new(&[Transaction::ReadBit(true)]);
+        assert_eq!(mock.poll_until_high(1_000, &mut NoDelay), Ok(70));
+        // a timeout shorter than a slot still polls once
+        mock.expect(&[Transaction::ReadBit(false)]);
+        assert_eq!(
+            mock.poll_until_high(10, &mut NoDelay),
+            Err(OneWireError::Timeout(10))
+        );
+        mock.done();
+    }
+
+    #[test]
+    fn test_convert_all() {
+        let mut mock = Mock::new(&Transaction::command(0x44, None));
+        mock.expect(&[
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(false),
+            Transaction::ReadBit(true),
+        ]);
+        let wait = ConversionWait::Poll { max_micros: 1_000 };
+        assert_eq!(mock.convert_all(0x44, wait, &mut NoDelay), Ok(210));
+        mock.done();
+
+        mock.expect(&Transaction::command(0x44, None));
+        mock.expect(&[Transaction::ReadBit(false), Transaction::ReadBit(false)]);
+        let wait = ConversionWait::Poll { max_micros: 140 };
+        assert_eq!(
+            mock.convert_all(0x44, wait, &mut NoDelay),
+            Err(OneWireError::Timeout(140))
+        );
+        mock.done();
+
+        // parasite power, with the strong pull-up held for the whole conversion
+        let mut pullup = Pullup::new();
+        let mut delay = CountingDelay(0);
+        mock.expect(&Transaction::command(0x44, None));
+        let wait = ConversionWait::Fixed {
+            micros: 750_000,
+            strong_pullup: Some(&mut pullup),
+        };
+        assert_eq!(mock.convert_all(0x44, wait, &mut delay), Ok(750_000));
+        mock.done();
+        assert_eq!(delay.0, 750_000);
+        assert_eq!(pullup.states, [true, false]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unexpected_write() {
+        let mut mock = Mock::new(&[Transaction::WriteByte(0x44)]);
+        let _ = mock.write_byte(0x48, &mut NoDelay);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_not_done() {
+        let mut mock = Mock::new(&[Transaction::Reset(true)]);
+        mock.done();
+    }
+}
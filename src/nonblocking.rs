@@ -0,0 +1,444 @@
+//! A non-blocking version of the bus primitives, for cooperative schedulers.
+//!
+//! Operations return `WouldBlock` during the long parts of the protocol (the 480 µs reset pulse, and
+//! the recovery time after each slot), and should be called again until they complete. The short,
+//! timing critical parts (the start of each slot, and sampling the bus) are still done by blocking
+//! on the timer, since they must not be delayed by other work.
+//!
+//! The slots use the timings and options of the wrapped `OneWire` (see `OneWire::set_timings` and
+//! `OneWire::set_majority_reads`).
+
+use crate::delay::Delay;
+use crate::pullup::{NoStrongPullup, StrongPullupPin};
+use crate::{OneWire, OneWireError, ResetResult};
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A one-shot timer counting in microseconds. This can usually be implemented with a HAL's
+/// `CountDown` timer
+pub trait Timer {
+    /// Starts (or restarts) the timer
+    fn start(&mut self, micros: u16);
+
+    /// Returns `WouldBlock` until the time given to `start` has passed
+    fn wait(&mut self) -> nb::Result<(), Infallible>;
+}
+
+/// Blocks on the timer, so it can be used for the timing critical parts of a slot
+struct TimerDelay<'a, C>(&'a mut C);
+
+impl<'a, C: Timer> Delay for TimerDelay<'a, C> {
+    fn delay_us(&mut self, us: u16) {
+        self.0.start(us);
+        while self.0.wait().is_err() {}
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    ResetLow,
+    ResetRecovery { device_present: bool },
+    ReadRecovery { value: bool },
+    WriteRecovery,
+}
+
+/// A byte operation, which is made of several bit operations
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ByteOperation {
+    Read,
+    Write,
+}
+
+pub struct NbOneWire<T, C, P = NoStrongPullup> {
+    onewire: OneWire<T, P>,
+    timer: C,
+    state: State,
+
+    // progress of the byte currently being read / written
+    byte_operation: Option<ByteOperation>,
+    byte: u8,
+    bits: u8,
+}
+
+impl<T, E, C, P> NbOneWire<T, C, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    C: Timer,
+    P: StrongPullupPin<E>,
+{
+    pub fn new(onewire: OneWire<T, P>, timer: C) -> NbOneWire<T, C, P> {
+        NbOneWire {
+            onewire,
+            timer,
+            state: State::Idle,
+            byte_operation: None,
+            byte: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> (OneWire<T, P>, C) {
+        (self.onewire, self.timer)
+    }
+
+    /// Returns true if no operation is in progress
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle && self.byte_operation.is_none()
+    }
+
+    fn timer_finished(&mut self) -> bool {
+        self.timer.wait().is_ok()
+    }
+
+    /// Resets the state after an error, so the next call starts a new operation
+    fn check<V>(
+        &mut self,
+        result: nb::Result<V, OneWireError<E>>,
+    ) -> nb::Result<V, OneWireError<E>> {
+        if let Err(nb::Error::Other(_)) = result {
+            self.state = State::Idle;
+            self.byte_operation = None;
+            self.bits = 0;
+        }
+        result
+    }
+
+    /// Fails if a byte operation is in progress, other than `operation`
+    fn check_byte_operation(
+        &self,
+        operation: Option<ByteOperation>,
+    ) -> nb::Result<(), OneWireError<E>> {
+        match self.byte_operation {
+            Some(current) if Some(current) != operation => {
+                Err(nb::Error::Other(OneWireError::UnexpectedResponse))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends a reset pulse, then returns true if a device is present. Each operation must be polled
+    /// until it completes before another is started, otherwise `UnexpectedResponse` is returned.
+    pub fn reset(&mut self) -> nb::Result<bool, OneWireError<E>> {
+        let result = self.poll_reset();
+        self.check(result)
+    }
+
+    fn poll_reset(&mut self) -> nb::Result<bool, OneWireError<E>> {
+        self.check_byte_operation(None)?;
+        match self.state {
+            State::Idle => {
+                self.onewire.start_reset();
+                self.onewire
+                    .wait_for_high(&mut TimerDelay(&mut self.timer))?;
+                self.onewire.set_bus_low()?;
+                self.timer.start(self.onewire.times.reset_low);
+                self.state = State::ResetLow;
+                Err(nb::Error::WouldBlock)
+            }
+            State::ResetLow => {
+                if !self.timer_finished() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                let presence_sample = self.onewire.times.presence_sample;
+                self.onewire
+                    .release_for(presence_sample, &mut TimerDelay(&mut self.timer))?;
+                let device_present = self.onewire.is_bus_low()?;
+                self.timer.start(self.onewire.times.reset_recovery);
+                self.state = State::ResetRecovery { device_present };
+                Err(nb::Error::WouldBlock)
+            }
+            State::ResetRecovery { device_present } => {
+                if !self.timer_finished() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.state = State::Idle;
+                Ok(self.onewire.finish_reset(device_present)? != ResetResult::NoDevices)
+            }
+            _ => Err(nb::Error::Other(OneWireError::UnexpectedResponse)),
+        }
+    }
+
+    pub fn read_bit(&mut self) -> nb::Result<bool, OneWireError<E>> {
+        let result = self
+            .check_byte_operation(None)
+            .and_then(|()| self.poll_read_bit());
+        self.check(result)
+    }
+
+    fn poll_read_bit(&mut self) -> nb::Result<bool, OneWireError<E>> {
+        match self.state {
+            State::Idle => {
+                let value = self
+                    .onewire
+                    .sample_read_slot(&mut TimerDelay(&mut self.timer))?;
+                let times = &self.onewire.times;
+                self.timer
+                    .start(times.read_recovery.saturating_add(times.slot_recovery));
+                self.state = State::ReadRecovery { value };
+                Err(nb::Error::WouldBlock)
+            }
+            State::ReadRecovery { value } => {
+                if !self.timer_finished() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.state = State::Idle;
+                Ok(value)
+            }
+            _ => Err(nb::Error::Other(OneWireError::UnexpectedResponse)),
+        }
+    }
+
+    pub fn write_bit(&mut self, value: bool) -> nb::Result<(), OneWireError<E>> {
+        let result = self
+            .check_byte_operation(None)
+            .and_then(|()| self.poll_write_bit(value));
+        self.check(result)
+    }
+
+    fn poll_write_bit(&mut self, value: bool) -> nb::Result<(), OneWireError<E>> {
+        match self.state {
+            State::Idle => {
+                let remaining = self
+                    .onewire
+                    .start_write_slot(value, &mut TimerDelay(&mut self.timer))?;
+                self.timer
+                    .start(remaining.saturating_add(self.onewire.times.slot_recovery));
+                self.state = State::WriteRecovery;
+                Err(nb::Error::WouldBlock)
+            }
+            State::WriteRecovery => {
+                if !self.timer_finished() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.state = State::Idle;
+                Ok(())
+            }
+            _ => Err(nb::Error::Other(OneWireError::UnexpectedResponse)),
+        }
+    }
+
+    pub fn read_byte(&mut self) -> nb::Result<u8, OneWireError<E>> {
+        self.start_byte(ByteOperation::Read)?;
+        while self.bits < 8 {
+            let result = self.poll_read_bit();
+            let bit = self.check(result)?;
+            self.byte >>= 1;
+            if bit {
+                self.byte |= 0x80;
+            }
+            self.next_bit();
+        }
+        self.finish_byte()?;
+        Ok(self.byte)
+    }
+
+    /// Writes a byte. This must be called with the same value until it completes
+    pub fn write_byte(&mut self, value: u8) -> nb::Result<(), OneWireError<E>> {
+        self.start_byte(ByteOperation::Write)?;
+        while self.bits < 8 {
+            let result = self.poll_write_bit((value >> self.bits) & 0x01 == 0x01);
+            self.check(result)?;
+            self.next_bit();
+        }
+        self.finish_byte()
+    }
+
+    /// Starts `operation`, or continues it if it's already in progress. Another operation can't be
+    /// started part way through a byte, since they share its progress
+    fn start_byte(&mut self, operation: ByteOperation) -> nb::Result<(), OneWireError<E>> {
+        let result = self.check_byte_operation(Some(operation));
+        self.check(result)?;
+        if self.byte_operation.is_none() {
+            self.byte_operation = Some(operation);
+        }
+        Ok(())
+    }
+
+    /// Counts a bit of the byte, and starts the byte recovery time after the last one
+    fn next_bit(&mut self) {
+        self.bits += 1;
+        if self.bits == 8 {
+            self.timer.start(self.onewire.times.byte_recovery);
+        }
+    }
+
+    /// Waits for the byte recovery time
+    fn finish_byte(&mut self) -> nb::Result<(), OneWireError<E>> {
+        if !self.timer_finished() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.byte_operation = None;
+        self.bits = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::mock::Pullup;
+    use crate::nonblocking::{NbOneWire, Timer};
+    use crate::simulator::{SimulatedDelay, Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    use crate::{commands, Address, OneWire, OneWireBus, OneWireError, Operation};
+    use core::convert::Infallible;
+    use core::time::Duration;
+    use embedded_hal::blocking::delay::DelayUs;
+
+    struct SimulatedTimer<'a> {
+        simulator: &'a Simulator,
+        delay: SimulatedDelay<'a>,
+        end: u64,
+    }
+
+    impl<'a> Timer for SimulatedTimer<'a> {
+        fn start(&mut self, micros: u16) {
+            self.end = self.simulator.time_micros() + micros as u64;
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Infallible> {
+            if self.simulator.time_micros() >= self.end {
+                Ok(())
+            } else {
+                // some other work happens while waiting
+                self.delay.delay_us(1u32);
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+
+    #[test]
+    fn test_nonblocking() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0xA5];
+        simulator.add_device(device);
+        let timer = SimulatedTimer {
+            simulator: &simulator,
+            delay: simulator.delay(),
+            end: 0,
+        };
+        let mut one_wire = NbOneWire::new(OneWire::new(simulator.pin()).unwrap(), timer);
+
+        let mut polls = 0;
+        let device_present = loop {
+            match one_wire.reset() {
+                Err(nb::Error::WouldBlock) => polls += 1,
+                result => break result.unwrap(),
+            }
+        };
+        assert!(device_present);
+        assert!(polls > 2);
+        assert!(one_wire.is_idle());
+
+        nb::block!(one_wire.write_byte(commands::SKIP_ROM)).unwrap();
+        nb::block!(one_wire.write_byte(commands::READ_SCRATCHPAD)).unwrap();
+        assert_eq!(nb::block!(one_wire.read_byte()), Ok(0xA5));
+        assert!(matches!(one_wire.read_bit(), Err(nb::Error::WouldBlock)));
+        assert!(matches!(
+            one_wire.reset(),
+            Err(nb::Error::Other(crate::OneWireError::UnexpectedResponse))
+        ));
+    }
+
+    #[test]
+    fn test_nonblocking_interleaved() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let timer = SimulatedTimer {
+            simulator: &simulator,
+            delay: simulator.delay(),
+            end: 0,
+        };
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(Timings {
+            byte_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+        let mut one_wire = NbOneWire::new(one_wire, timer);
+        nb::block!(one_wire.reset()).unwrap();
+
+        // a byte can't be read part way through writing one, since they share the bit count
+        assert!(matches!(
+            one_wire.write_byte(commands::SKIP_ROM),
+            Err(nb::Error::WouldBlock)
+        ));
+        assert!(matches!(
+            one_wire.read_byte(),
+            Err(nb::Error::Other(OneWireError::UnexpectedResponse))
+        ));
+        assert!(one_wire.is_idle());
+
+        // nor can a bit be read or written
+        assert!(matches!(one_wire.read_byte(), Err(nb::Error::WouldBlock)));
+        assert!(matches!(
+            one_wire.write_bit(true),
+            Err(nb::Error::Other(OneWireError::UnexpectedResponse))
+        ));
+        assert!(one_wire.is_idle());
+
+        // or a reset started during the byte recovery time, after the last bit
+        while one_wire.bits < 8 {
+            assert!(matches!(one_wire.read_byte(), Err(nb::Error::WouldBlock)));
+        }
+        assert!(matches!(
+            one_wire.reset(),
+            Err(nb::Error::Other(OneWireError::UnexpectedResponse))
+        ));
+        assert!(one_wire.is_idle());
+    }
+
+    #[test]
+    fn test_nonblocking_reset_bookkeeping() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let timer = SimulatedTimer {
+            simulator: &simulator,
+            delay: simulator.delay(),
+            end: 0,
+        };
+        // a bus with a strong pull-up can be used as well
+        let mut one_wire = OneWire::builder(simulator.pin())
+            .strong_pullup_pin(Pullup::new())
+            .build()
+            .unwrap();
+        one_wire.operation_failed(Operation::Read);
+        let mut one_wire = NbOneWire::new(one_wire, timer);
+        assert_eq!(nb::block!(one_wire.reset()), Ok(true));
+
+        let (one_wire, _) = one_wire.into_inner();
+        assert_eq!(one_wire.last_failed_operation(), None);
+        #[cfg(feature = "stats")]
+        assert_eq!(one_wire.stats().resets, 1);
+    }
+
+    #[test]
+    fn test_nonblocking_timings() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let timer = SimulatedTimer {
+            simulator: &simulator,
+            delay: simulator.delay(),
+            end: 0,
+        };
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(Timings {
+            reset_low: Duration::from_micros(600),
+            read_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+        one_wire.set_majority_reads(true);
+        let mut one_wire = NbOneWire::new(one_wire, timer);
+
+        let start = simulator.time_micros();
+        assert_eq!(nb::block!(one_wire.reset()), Ok(true));
+        assert!(simulator.time_micros() - start >= 600 + 70 + 410);
+
+        let start = simulator.time_micros();
+        assert_eq!(nb::block!(one_wire.read_bit()), Ok(true));
+        assert!(simulator.time_micros() - start >= 6 + 9 + 100);
+    }
+}
@@ -0,0 +1,197 @@
+//! Support for platforms that generate the 1-Wire waveforms in hardware (such as the ESP32 RMT
+//! peripheral, or an RP2040 PIO program).
+//!
+//! The platform only has to implement `WaveformTransport`. `OffloadedBus` then provides the rest
+//! (commands, device search, and CRC checks) through `OneWireBus`. Since the hardware does all of
+//! the timing, the delays passed to it are never used.
+
+use crate::delay::Delay;
+use crate::{OneWireBus, OneWireResult};
+
+/// A peripheral that generates reset pulses and time slots in hardware
+pub trait WaveformTransport {
+    type Error;
+
+    /// Generates a reset pulse, then returns true if a device responded with a presence pulse
+    fn generate_reset(&mut self) -> Result<bool, Self::Error>;
+
+    /// Generates a time slot for each of the first `bit_count` bits of `bits` (least significant bit
+    /// of each byte first). A 0 is sent as a write-zero slot, and a 1 as a write-one / read slot.
+    /// Each bit is then replaced with the value sampled during its slot.
+    fn exchange_bits(&mut self, bits: &mut [u8], bit_count: usize) -> Result<(), Self::Error>;
+}
+
+/// A bus using a `WaveformTransport`
+pub struct OffloadedBus<W> {
+    transport: W,
+}
+
+impl<W: WaveformTransport> OffloadedBus<W> {
+    pub fn new(transport: W) -> OffloadedBus<W> {
+        OffloadedBus { transport }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.transport
+    }
+
+    fn exchange(&mut self, bits: &mut [u8], bit_count: usize) -> OneWireResult<(), W::Error> {
+        Ok(self.transport.exchange_bits(bits, bit_count)?)
+    }
+}
+
+impl<W: WaveformTransport> OneWireBus for OffloadedBus<W> {
+    type Error = W::Error;
+
+    fn reset(&mut self, _delay: &mut impl Delay) -> OneWireResult<bool, W::Error> {
+        Ok(self.transport.generate_reset()?)
+    }
+
+    fn read_bit(&mut self, _delay: &mut impl Delay) -> OneWireResult<bool, W::Error> {
+        let mut bits = [0x01];
+        self.exchange(&mut bits, 1)?;
+        Ok(bits[0] & 0x01 == 0x01)
+    }
+
+    fn write_bit(&mut self, value: bool, _delay: &mut impl Delay) -> OneWireResult<(), W::Error> {
+        self.exchange(&mut [value as u8], 1)
+    }
+
+    fn read_byte(&mut self, _delay: &mut impl Delay) -> OneWireResult<u8, W::Error> {
+        let mut bits = [0xFF];
+        self.exchange(&mut bits, 8)?;
+        Ok(bits[0])
+    }
+
+    fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        _delay: &mut impl Delay,
+    ) -> OneWireResult<(), W::Error> {
+        output.fill(0xFF);
+        self.exchange(output, output.len() * 8)
+    }
+
+    fn write_byte(&mut self, value: u8, _delay: &mut impl Delay) -> OneWireResult<(), W::Error> {
+        self.exchange(&mut [value], 8)
+    }
+
+    fn read_bits(&mut self, count: u8, _delay: &mut impl Delay) -> OneWireResult<u8, W::Error> {
+        assert!(count <= 8, "at most 8 bits can be read at once");
+        let mut bits = [0xFF];
+        self.exchange(&mut bits, count as usize)?;
+        Ok(bits[0] & ((1_u16 << count) - 1) as u8)
+    }
+
+    fn write_bits(
+        &mut self,
+        value: u8,
+        count: u8,
+        _delay: &mut impl Delay,
+    ) -> OneWireResult<(), W::Error> {
+        assert!(count <= 8, "at most 8 bits can be written at once");
+        self.exchange(&mut [value], count as usize)
+    }
+
+    fn touch_bit(&mut self, value: bool, _delay: &mut impl Delay) -> OneWireResult<bool, W::Error> {
+        let mut bits = [value as u8];
+        self.exchange(&mut bits, 1)?;
+        Ok(bits[0] & 0x01 == 0x01)
+    }
+
+    fn touch_byte(&mut self, value: u8, _delay: &mut impl Delay) -> OneWireResult<u8, W::Error> {
+        let mut bits = [value];
+        self.exchange(&mut bits, 8)?;
+        Ok(bits[0])
+    }
+
+    fn write_bytes(
+        &mut self,
+        bytes: &[u8],
+        _delay: &mut impl Delay,
+    ) -> OneWireResult<(), W::Error> {
+        // the transport overwrites the bits it sends, so they are copied into a buffer first
+        let mut buffer = [0; 16];
+        for chunk in bytes.chunks(buffer.len()) {
+            let buffer = &mut buffer[..chunk.len()];
+            buffer.copy_from_slice(chunk);
+            self.exchange(buffer, chunk.len() * 8)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::mock::NoDelay;
+    use crate::offload::{OffloadedBus, WaveformTransport};
+    use crate::simulator::{SimulatedDelay, SimulatedPin, Simulator, VirtualDevice};
+    use crate::{crc, Address, OneWire, OneWireBus};
+    use core::convert::Infallible;
+
+    /// Generates the waveforms with a regular bus, as a stand-in for the hardware
+    struct SoftwareTransport<'a> {
+        one_wire: OneWire<SimulatedPin<'a>>,
+        delay: SimulatedDelay<'a>,
+    }
+
+    impl<'a> WaveformTransport for SoftwareTransport<'a> {
+        type Error = Infallible;
+
+        fn generate_reset(&mut self) -> Result<bool, Infallible> {
+            Ok(self.one_wire.reset(&mut self.delay).unwrap())
+        }
+
+        fn exchange_bits(&mut self, bits: &mut [u8], bit_count: usize) -> Result<(), Infallible> {
+            for index in 0..bit_count {
+                let mask = 1 << (index % 8);
+                let byte = &mut bits[index / 8];
+                if *byte & mask != 0 {
+                    if !self.one_wire.read_bit(&mut self.delay).unwrap() {
+                        *byte &= !mask;
+                    }
+                } else {
+                    self.one_wire.write_bit(false, &mut self.delay).unwrap();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_offloaded_bus() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address::for_test(0x28, 1));
+        device.scratchpad = std::vec![0x12, 0x34, crc::crc8(&[0x12, 0x34])];
+        simulator.add_device(device);
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 2)));
+        let mut bus = OffloadedBus::new(SoftwareTransport {
+            one_wire: OneWire::new(simulator.pin()).unwrap(),
+            delay: simulator.delay(),
+        });
+
+        let (first, state) = bus
+            .device_search(None, false, &mut NoDelay)
+            .unwrap()
+            .unwrap();
+        let (second, state) = bus
+            .device_search(Some(&state), false, &mut NoDelay)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (first, second),
+            (Address::for_test(0x28, 2), Address::for_test(0x28, 1))
+        );
+        assert_eq!(
+            bus.device_search(Some(&state), false, &mut NoDelay),
+            Ok(None)
+        );
+
+        let mut scratchpad = [0; 3];
+        bus.read_scratchpad(Some(&second), &mut scratchpad, &mut NoDelay)
+            .unwrap();
+        assert_eq!(&scratchpad[..2], &[0x12, 0x34]);
+    }
+}
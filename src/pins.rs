@@ -0,0 +1,504 @@
+//! Adapters for pins that don't implement both `InputPin` and `OutputPin` as open-drain.
+
+use crate::timing::Timings;
+use crate::{OneWire, OneWireResult};
+use core::mem;
+use core::time::Duration;
+use embedded_hal::digital::v2::{InputPin, IoPin, OutputPin, PinState};
+
+/// Combines a separate input and output pin into one, for when the line is split (by a level
+/// shifter, or a buffer with separate TX / RX lines). The output pin must only pull the bus low,
+/// and release it when set high.
+pub struct SplitPin<I, O> {
+    input: I,
+    output: O,
+}
+
+impl<I, O> SplitPin<I, O> {
+    pub fn new(input: I, output: O) -> SplitPin<I, O> {
+        SplitPin { input, output }
+    }
+
+    pub fn into_inner(self) -> (I, O) {
+        (self.input, self.output)
+    }
+}
+
+impl<I, O, E> InputPin for SplitPin<I, O>
+where
+    I: InputPin<Error = E>,
+{
+    type Error = E;
+
+    fn is_high(&self) -> Result<bool, E> {
+        self.input.is_high()
+    }
+
+    fn is_low(&self) -> Result<bool, E> {
+        self.input.is_low()
+    }
+}
+
+impl<I, O, E> OutputPin for SplitPin<I, O>
+where
+    O: OutputPin<Error = E>,
+{
+    type Error = E;
+
+    fn set_low(&mut self) -> Result<(), E> {
+        self.output.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), E> {
+        self.output.set_high()
+    }
+}
+
+impl<I, O, E> OneWire<SplitPin<I, O>>
+where
+    I: InputPin<Error = E>,
+    O: OutputPin<Error = E>,
+{
+    /// Creates a bus that reads the line with `input`, and drives it with `output`
+    pub fn new_split(input: I, output: O) -> OneWireResult<OneWire<SplitPin<I, O>>, E> {
+        OneWire::new(SplitPin::new(input, output))
+    }
+}
+
+/// An error from a `DirectionalPin`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DirectionalPinError<E> {
+    /// An error from the pin
+    Pin(E),
+
+    /// An earlier mode change failed, and the pin was lost with it
+    Lost,
+}
+
+enum Mode<I, O> {
+    Input(I),
+    Output(O),
+
+    /// Converting the pin failed, so it no longer exists
+    Lost,
+}
+
+/// Adapts a pin that has to be switched between input and output mode (`IoPin`), for MCUs that can't
+/// read back the value of an open-drain output. The bus is released by switching to input mode
+/// (letting the pull-up resistor bring it high), and pulled low by switching to output mode.
+///
+/// `IoPin` consumes the pin when converting it, so if a conversion fails the pin is lost, and any
+/// further use of the bus returns `DirectionalPinError::Lost`.
+pub struct DirectionalPin<I, O> {
+    mode: Mode<I, O>,
+}
+
+impl<I, O, E> DirectionalPin<I, O>
+where
+    I: InputPin<Error = E> + IoPin<I, O, Error = E>,
+    O: OutputPin<Error = E> + IoPin<I, O, Error = E>,
+{
+    /// `pin` must be in input mode
+    pub fn new(pin: I) -> DirectionalPin<I, O> {
+        DirectionalPin {
+            mode: Mode::Input(pin),
+        }
+    }
+
+    /// Returns the pin in input mode
+    pub fn into_inner(self) -> Result<I, DirectionalPinError<E>> {
+        match self.mode {
+            Mode::Input(pin) => Ok(pin),
+            Mode::Output(pin) => pin.into_input_pin().map_err(DirectionalPinError::Pin),
+            Mode::Lost => Err(DirectionalPinError::Lost),
+        }
+    }
+}
+
+impl<I, O, E> InputPin for DirectionalPin<I, O>
+where
+    I: InputPin<Error = E> + IoPin<I, O, Error = E>,
+    O: OutputPin<Error = E> + IoPin<I, O, Error = E>,
+{
+    type Error = DirectionalPinError<E>;
+
+    fn is_high(&self) -> Result<bool, DirectionalPinError<E>> {
+        match &self.mode {
+            Mode::Input(pin) => pin.is_high().map_err(DirectionalPinError::Pin),
+            // the pin is only in output mode while it pulls the bus low
+            Mode::Output(_) => Ok(false),
+            Mode::Lost => Err(DirectionalPinError::Lost),
+        }
+    }
+
+    fn is_low(&self) -> Result<bool, DirectionalPinError<E>> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<I, O, E> OutputPin for DirectionalPin<I, O>
+where
+    I: InputPin<Error = E> + IoPin<I, O, Error = E>,
+    O: OutputPin<Error = E> + IoPin<I, O, Error = E>,
+{
+    type Error = DirectionalPinError<E>;
+
+    fn set_low(&mut self) -> Result<(), DirectionalPinError<E>> {
+        self.mode = match mem::replace(&mut self.mode, Mode::Lost) {
+            Mode::Input(pin) => Mode::Output(
+                pin.into_output_pin(PinState::Low)
+                    .map_err(DirectionalPinError::Pin)?,
+            ),
+            Mode::Output(mut pin) => {
+                // the pin is still there, so it's put back even if this fails
+                let result = pin.set_low();
+                self.mode = Mode::Output(pin);
+                return result.map_err(DirectionalPinError::Pin);
+            }
+            Mode::Lost => return Err(DirectionalPinError::Lost),
+        };
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), DirectionalPinError<E>> {
+        self.mode = match mem::replace(&mut self.mode, Mode::Lost) {
+            Mode::Input(pin) => Mode::Input(pin),
+            Mode::Output(pin) => {
+                Mode::Input(pin.into_input_pin().map_err(DirectionalPinError::Pin)?)
+            }
+            Mode::Lost => return Err(DirectionalPinError::Lost),
+        };
+        Ok(())
+    }
+}
+
+impl<I, O, E> OneWire<DirectionalPin<I, O>>
+where
+    I: InputPin<Error = E> + IoPin<I, O, Error = E>,
+    O: OutputPin<Error = E> + IoPin<I, O, Error = E>,
+{
+    /// Creates a bus from a pin that has to be switched between input and output mode. `pin` must
+    /// be in input mode
+    pub fn new_io(pin: I) -> OneWireResult<OneWire<DirectionalPin<I, O>>, DirectionalPinError<E>> {
+        OneWire::new(DirectionalPin::new(pin))
+    }
+}
+
+/// How long `OneWire::new_active_pull_up` switches the active pull-up on for, which is about as
+/// long as the DS2482 switches on its own
+pub const DEFAULT_ACTIVE_PULL_UP: Duration = Duration::from_micros(2);
+
+/// Adds an active pull-up (usually a FET in parallel with the pull-up resistor) to a pin, to speed up
+/// the rising edge on long or heavily loaded buses, like the DS2480B does.
+///
+/// Each time the bus is released after pulling it low, the pull-up is switched on (by setting its
+/// pin high). The bus switches it off again by releasing the bus a second time, once
+/// `Timings::active_pull_up` has passed, so that must be set for the bus using this pin
+/// (`OneWire::new_active_pull_up` sets it to `DEFAULT_ACTIVE_PULL_UP`). The pull-up should be
+/// current limited, since a device can start pulling the bus low while it's on.
+pub struct ActivePullUp<T, P> {
+    pin: T,
+    pull_up: P,
+    // the pin is pulling the bus low
+    low: bool,
+    // the pull-up is switched on
+    pulsing: bool,
+}
+
+impl<T, P> ActivePullUp<T, P> {
+    pub fn new(pin: T, pull_up: P) -> ActivePullUp<T, P> {
+        ActivePullUp {
+            pin,
+            pull_up,
+            low: false,
+            pulsing: false,
+        }
+    }
+
+    pub fn into_inner(self) -> (T, P) {
+        (self.pin, self.pull_up)
+    }
+}
+
+impl<T, P, E> OneWire<ActivePullUp<T, P>>
+where
+    T: InputPin<Error = E> + OutputPin<Error = E>,
+    P: OutputPin<Error = E>,
+{
+    /// Creates a bus that switches on `pull_up` for `DEFAULT_ACTIVE_PULL_UP` each time it's released.
+    /// Setting `Timings::active_pull_up` to 0 afterwards leaves the pull-up switched on
+    pub fn new_active_pull_up(pin: T, pull_up: P) -> OneWireResult<OneWire<ActivePullUp<T, P>>, E> {
+        let mut onewire = OneWire::new(ActivePullUp::new(pin, pull_up))?;
+        onewire.set_timings(Timings {
+            active_pull_up: DEFAULT_ACTIVE_PULL_UP,
+            ..Timings::STANDARD
+        });
+        Ok(onewire)
+    }
+}
+
+impl<T, P, E> InputPin for ActivePullUp<T, P>
+where
+    T: InputPin<Error = E>,
+{
+    type Error = E;
+
+    fn is_high(&self) -> Result<bool, E> {
+        self.pin.is_high()
+    }
+
+    fn is_low(&self) -> Result<bool, E> {
+        self.pin.is_low()
+    }
+}
+
+impl<T, P, E> OutputPin for ActivePullUp<T, P>
+where
+    T: OutputPin<Error = E>,
+    P: OutputPin<Error = E>,
+{
+    type Error = E;
+
+    fn set_low(&mut self) -> Result<(), E> {
+        if self.pulsing {
+            self.pulsing = false;
+            self.pull_up.set_low()?;
+        }
+        self.pin.set_low()?;
+        self.low = true;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), E> {
+        if self.pulsing {
+            // the bus is already released, so this ends the pulse
+            self.pulsing = false;
+            return self.pull_up.set_low();
+        }
+        self.pin.set_high()?;
+        if self.low {
+            self.low = false;
+            self.pull_up.set_high()?;
+            self.pulsing = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::pins::{ActivePullUp, DirectionalPin, DirectionalPinError};
+    use crate::simulator::{SimulatedPin, Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    use crate::{Address, OneWire, Speed};
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use core::time::Duration;
+    use embedded_hal::digital::v2::{InputPin, IoPin, OutputPin, PinState};
+    use std::vec::Vec;
+
+    struct Input<'a>(SimulatedPin<'a>);
+    struct Output<'a>(SimulatedPin<'a>);
+
+    impl<'a> InputPin for Input<'a> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            self.0.is_high()
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            self.0.is_low()
+        }
+    }
+
+    impl<'a> OutputPin for Output<'a> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set_low()
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set_high()
+        }
+    }
+
+    impl<'a> IoPin<Input<'a>, Output<'a>> for Input<'a> {
+        type Error = Infallible;
+
+        fn into_input_pin(self) -> Result<Input<'a>, Infallible> {
+            Ok(self)
+        }
+
+        fn into_output_pin(self, state: PinState) -> Result<Output<'a>, Infallible> {
+            let mut output = Output(self.0);
+            output.set_state(state)?;
+            Ok(output)
+        }
+    }
+
+    impl<'a> IoPin<Input<'a>, Output<'a>> for Output<'a> {
+        type Error = Infallible;
+
+        fn into_input_pin(mut self) -> Result<Input<'a>, Infallible> {
+            // an input pin doesn't drive the bus
+            self.0.set_high()?;
+            Ok(Input(self.0))
+        }
+
+        fn into_output_pin(mut self, state: PinState) -> Result<Output<'a>, Infallible> {
+            self.set_state(state)?;
+            Ok(self)
+        }
+    }
+
+    #[test]
+    fn test_split_pin() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new_split(simulator.pin(), simulator.pin()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        let (_input, _output) = one_wire.into_inner().into_inner();
+    }
+
+    #[test]
+    fn test_directional_pin() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new_io(Input(simulator.pin())).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0xFF));
+        assert!(one_wire.into_inner().into_inner().is_ok());
+    }
+
+    /// A pin that can't be switched to output mode
+    struct Stuck;
+
+    impl InputPin for Stuck {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            Ok(true)
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            Ok(false)
+        }
+    }
+
+    impl OutputPin for Stuck {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    impl IoPin<Stuck, Stuck> for Stuck {
+        type Error = ();
+
+        fn into_input_pin(self) -> Result<Stuck, ()> {
+            Ok(self)
+        }
+
+        fn into_output_pin(self, _state: PinState) -> Result<Stuck, ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn test_directional_pin_lost() {
+        let mut pin: DirectionalPin<Stuck, Stuck> = DirectionalPin::new(Stuck);
+        assert_eq!(pin.is_high(), Ok(true));
+        assert_eq!(pin.set_low(), Err(DirectionalPinError::Pin(())));
+        // the pin went with the failed conversion, but the bus doesn't panic
+        assert_eq!(pin.set_low(), Err(DirectionalPinError::Lost));
+        assert_eq!(pin.set_high(), Err(DirectionalPinError::Lost));
+        assert_eq!(pin.is_high(), Err(DirectionalPinError::Lost));
+        assert!(matches!(pin.into_inner(), Err(DirectionalPinError::Lost)));
+    }
+
+    /// Records the times the pull-up was switched on and off
+    struct PullUp<'a> {
+        simulator: &'a Simulator,
+        changes: &'a RefCell<Vec<(bool, u64)>>,
+    }
+
+    impl<'a> OutputPin for PullUp<'a> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            let time = self.simulator.time_micros();
+            self.changes.borrow_mut().push((false, time));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            let time = self.simulator.time_micros();
+            self.changes.borrow_mut().push((true, time));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_active_pull_up() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let changes = RefCell::new(Vec::new());
+        let pull_up = PullUp {
+            simulator: &simulator,
+            changes: &changes,
+        };
+        let mut one_wire = OneWire::new(ActivePullUp::new(simulator.pin(), pull_up)).unwrap();
+        one_wire.set_timings(Timings {
+            active_pull_up: Duration::from_micros(3),
+            ..Timings::STANDARD
+        });
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        one_wire.write_byte(0x00, &mut delay).unwrap();
+        // releasing a bus that is already high doesn't switch it on
+        one_wire.release_bus().unwrap();
+
+        // after the reset pulse, and after each bit, for 3 µs each time
+        let changes = changes.borrow();
+        assert_eq!(changes.len(), 2 + 2 * 8);
+        for pulse in changes.chunks(2) {
+            assert!(pulse[0].0 && !pulse[1].0);
+            assert_eq!(pulse[1].1 - pulse[0].1, 3);
+        }
+    }
+
+    #[test]
+    fn test_default_active_pull_up() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let changes = RefCell::new(Vec::new());
+        let pull_up = PullUp {
+            simulator: &simulator,
+            changes: &changes,
+        };
+        let mut one_wire = OneWire::new_active_pull_up(simulator.pin(), pull_up).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        // the pulse is kept at overdrive
+        one_wire.set_speed(Speed::Overdrive);
+        one_wire.write_bit(true, &mut delay).unwrap();
+
+        let changes = changes.borrow();
+        assert_eq!(changes.len(), 4);
+        for pulse in changes.chunks(2) {
+            assert!(pulse[0].0 && !pulse[1].0);
+            assert_eq!(pulse[1].1 - pulse[0].1, 2);
+        }
+    }
+}
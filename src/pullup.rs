@@ -0,0 +1,163 @@
+//! Strong pull-ups, for powering parasite powered devices while they convert or program EEPROM.
+//!
+//! The pull-up resistor can't supply enough current for these, so the bus is held at the supply by
+//! a strong pull-up for the duration: usually a pin switching a FET between the bus and the
+//! supply, or the data pin itself driven push-pull (with a pin wrapper whose `set_high` switches it
+//! to push-pull, and `set_low` back to open-drain). Either way, the strong pull-up is an
+//! `OutputPin` that supplies the bus while it's high.
+//!
+//! The strong pull-up must be enabled right after the last bit of the command that starts the
+//! operation, and must be disabled again before the bus is used, or the next slot can't pull the
+//! bus low. The helpers here always disable it, even if the operation fails.
+//!
+//! The strong pull-up can either be passed to each operation that needs it, or stored on the bus
+//! with `OneWireBuilder::strong_pullup_pin`, which is used whenever one isn't passed.
+
+use crate::delay::Delay;
+use crate::{OneWireBus, OneWireResult};
+use embedded_hal::digital::v2::OutputPin;
+
+/// The strong pull-up of a bus that doesn't have one, which is the default
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct NoStrongPullup;
+
+/// A strong pull-up stored on the bus. This is implemented for every `OutputPin`, and for
+/// `NoStrongPullup`
+pub trait StrongPullupPin<E> {
+    /// Enables or disables the strong pull-up. Returns false if there isn't one
+    fn set_enabled(&mut self, enabled: bool) -> Result<bool, E>;
+}
+
+impl<E> StrongPullupPin<E> for NoStrongPullup {
+    fn set_enabled(&mut self, _enabled: bool) -> Result<bool, E> {
+        Ok(false)
+    }
+}
+
+impl<P, E> StrongPullupPin<E> for P
+where
+    P: OutputPin<Error = E>,
+{
+    fn set_enabled(&mut self, enabled: bool) -> Result<bool, E> {
+        match enabled {
+            true => self.set_high()?,
+            false => self.set_low()?,
+        }
+        Ok(true)
+    }
+}
+
+/// Runs `f` with the strong pull-up enabled, then disables it, even if `f` returned an error.
+/// Without a strong pull-up this just runs `f`. Since the bus is held high, `f` must not use it.
+///
+/// If `f` fails, its error is returned, even if disabling the strong pull-up also failed
+pub fn with_strong_pullup<E, R>(
+    strong_pullup: Option<&mut dyn OutputPin<Error = E>>,
+    f: impl FnOnce() -> OneWireResult<R, E>,
+) -> OneWireResult<R, E> {
+    let strong_pullup = match strong_pullup {
+        Some(strong_pullup) => strong_pullup,
+        None => return f(),
+    };
+    strong_pullup.set_high()?;
+    let result = f();
+    let released = strong_pullup.set_low();
+    let value = result?;
+    released?;
+    Ok(value)
+}
+
+/// Waits for `micros`, with the strong pull-up enabled if there is one. The wait is split into
+/// milliseconds, with `idle` (usually the bus's idle hook) called in between
+pub fn strong_pullup_delay<E>(
+    strong_pullup: Option<&mut dyn OutputPin<Error = E>>,
+    micros: u32,
+    delay: &mut impl Delay,
+    idle: impl FnMut(),
+) -> OneWireResult<(), E> {
+    with_strong_pullup(strong_pullup, || {
+        delay_micros(micros, delay, idle);
+        Ok(())
+    })
+}
+
+/// The same as `strong_pullup_delay`, but uses the bus's own strong pull-up (see
+/// `OneWireBus::set_strong_pullup`) when `strong_pullup` is `None`. The bus's idle hook is called in
+/// between milliseconds
+pub fn bus_strong_pullup_delay<B>(
+    onewire: &mut B,
+    strong_pullup: Option<&mut dyn OutputPin<Error = B::Error>>,
+    micros: u32,
+    delay: &mut impl Delay,
+) -> OneWireResult<(), B::Error>
+where
+    B: OneWireBus + ?Sized,
+{
+    if strong_pullup.is_some() {
+        return strong_pullup_delay(strong_pullup, micros, delay, || onewire.idle());
+    }
+    let enabled = onewire.set_strong_pullup(true)?;
+    delay_micros(micros, delay, || onewire.idle());
+    if enabled {
+        onewire.set_strong_pullup(false)?;
+    }
+    Ok(())
+}
+
+/// Waits for `micros` a millisecond at a time, calling `idle` in between
+pub(crate) fn delay_micros(micros: u32, delay: &mut impl Delay, mut idle: impl FnMut()) {
+    let mut remaining = micros;
+    while remaining > 0 {
+        let step = remaining.min(1_000);
+        delay.delay_us(step as u16);
+        remaining -= step;
+        if remaining > 0 {
+            idle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mock::{CountingDelay, Pullup};
+    use crate::pullup::{strong_pullup_delay, with_strong_pullup};
+    use crate::OneWireError;
+
+    #[test]
+    fn test_strong_pullup_delay() {
+        let mut pullup = Pullup::new();
+        let mut delay = CountingDelay(0);
+        let mut idles = 0;
+        strong_pullup_delay(Some(&mut pullup), 750_000, &mut delay, || idles += 1).unwrap();
+        assert_eq!(delay.0, 750_000);
+        assert_eq!(pullup.states, [true, false]);
+        assert_eq!(idles, 749);
+
+        strong_pullup_delay::<()>(None, 10_000, &mut delay, || {}).unwrap();
+        assert_eq!(delay.0, 760_000);
+    }
+
+    #[test]
+    fn test_released_on_error() {
+        let mut pullup = Pullup::new();
+        let result: Result<(), _> =
+            with_strong_pullup(Some(&mut pullup), || Err(OneWireError::Timeout(10)));
+        assert_eq!(result, Err(OneWireError::Timeout(10)));
+        assert_eq!(pullup.states, [true, false]);
+
+        // the error from the closure is kept over the error disabling the pull-up
+        let mut pullup = Pullup::failing(1, ());
+        let result: Result<(), _> =
+            with_strong_pullup(Some(&mut pullup), || Err(OneWireError::Timeout(10)));
+        assert_eq!(result, Err(OneWireError::Timeout(10)));
+        let result = with_strong_pullup(Some(&mut Pullup::failing(1, ())), || Ok(1));
+        assert_eq!(result, Err(OneWireError::PinError(())));
+
+        // the closure isn't run if the pull-up can't be enabled
+        let mut pullup = Pullup::failing(0, ());
+        let result = with_strong_pullup(Some(&mut pullup), || -> Result<(), _> {
+            panic!("run without the strong pull-up")
+        });
+        assert_eq!(result, Err(OneWireError::PinError(())));
+    }
+}
@@ -0,0 +1,185 @@
+//! Keeping track of the devices on a bus as they are plugged in and removed.
+
+use crate::delay::Delay;
+use crate::scan::for_each_device;
+use crate::{Address, OneWireBus, OneWireResult};
+
+/// A change found by `DeviceRegistry::rescan`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegistryEvent {
+    Added(Address),
+    Removed(Address),
+}
+
+/// The addresses of the devices found by the last scan of a bus, storing up to `N` devices
+#[derive(Debug, Clone)]
+pub struct DeviceRegistry<const N: usize> {
+    addresses: [Address; N],
+    len: usize,
+}
+
+impl<const N: usize> DeviceRegistry<N> {
+    pub fn new() -> DeviceRegistry<N> {
+        DeviceRegistry {
+            addresses: [Address(0); N],
+            len: 0,
+        }
+    }
+
+    /// The known addresses, in the order they were found by the last scan
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses[..self.len]
+    }
+
+    pub fn contains(&self, address: &Address) -> bool {
+        self.addresses().contains(address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Searches the bus, replacing the known addresses with the devices that were found, and calls
+    /// `f` for each device that was added or removed since the last scan. Removed devices are
+    /// reported first.
+    ///
+    /// Returns the number of devices on the bus. If that is more than `N`, the known devices that
+    /// are still on the bus are kept, and new devices are only added (and reported) while there is
+    /// room, so the rest are added by a later scan once devices are removed. If the search fails,
+    /// the registry is not changed and no events are reported.
+    pub fn rescan<B: OneWireBus>(
+        &mut self,
+        onewire: &mut B,
+        delay: &mut impl Delay,
+        mut f: impl FnMut(RegistryEvent),
+    ) -> OneWireResult<usize, B::Error> {
+        let mut seen = [false; N];
+        let mut added = DeviceRegistry::<N>::new();
+        let mut total = 0;
+        for_each_device(onewire, false, delay, |address| {
+            match self.addresses().iter().position(|known| *known == address) {
+                Some(index) => seen[index] = true,
+                None if added.len < N => {
+                    added.addresses[added.len] = address;
+                    added.len += 1;
+                }
+                None => {}
+            }
+            total += 1;
+            true
+        })?;
+
+        let mut found = DeviceRegistry::<N>::new();
+        for (address, seen) in self.addresses().iter().zip(seen) {
+            if seen {
+                found.addresses[found.len] = *address;
+                found.len += 1;
+            } else {
+                f(RegistryEvent::Removed(*address));
+            }
+        }
+        for address in added.addresses() {
+            if found.len == N {
+                break;
+            }
+            found.addresses[found.len] = *address;
+            found.len += 1;
+            f(RegistryEvent::Added(*address));
+        }
+        *self = found;
+        Ok(total)
+    }
+}
+
+impl<const N: usize> Default for DeviceRegistry<N> {
+    fn default() -> DeviceRegistry<N> {
+        DeviceRegistry::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::registry::{DeviceRegistry, RegistryEvent};
+    use crate::shared::SharedBus;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire};
+    use std::vec::Vec;
+
+    const FIRST: Address = Address(0x7000_0000_0000_0228);
+    const SECOND: Address = Address(0x2900_0000_0000_0128);
+
+    #[test]
+    fn test_rescan() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let mut registry = DeviceRegistry::<1>::new();
+
+        let mut events = Vec::new();
+        assert_eq!(
+            registry.rescan(&mut one_wire, &mut delay, |event| events.push(event)),
+            Ok(1)
+        );
+        assert_eq!(events, [RegistryEvent::Added(FIRST)]);
+
+        simulator.remove_device(&FIRST);
+        simulator.add_device(VirtualDevice::new(SECOND));
+        events.clear();
+        registry
+            .rescan(&mut one_wire, &mut delay, |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            events,
+            [RegistryEvent::Removed(FIRST), RegistryEvent::Added(SECOND)]
+        );
+        assert_eq!(registry.addresses(), [SECOND]);
+
+        // more devices than fit, so the known device is kept, and the new one isn't added
+        simulator.add_device(VirtualDevice::new(FIRST));
+        events.clear();
+        assert_eq!(
+            registry.rescan(&mut one_wire, &mut delay, |event| events.push(event)),
+            Ok(2)
+        );
+        assert_eq!(events, []);
+        assert_eq!(registry.addresses(), [SECOND]);
+
+        // until there's room for it
+        simulator.remove_device(&SECOND);
+        registry
+            .rescan(&mut one_wire, &mut delay, |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            events,
+            [RegistryEvent::Removed(SECOND), RegistryEvent::Added(FIRST)]
+        );
+        assert_eq!(registry.addresses(), [FIRST]);
+    }
+
+    #[test]
+    fn test_rescan_bus_handle() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        simulator.add_device(VirtualDevice::new(SECOND));
+        let mut delay = simulator.delay();
+        let shared = SharedBus::new(OneWire::new(simulator.pin()).unwrap());
+        let mut registry = DeviceRegistry::<2>::new();
+
+        let mut events = Vec::new();
+        assert_eq!(
+            registry.rescan(&mut shared.handle(), &mut delay, |event| events.push(event)),
+            Ok(2)
+        );
+        assert_eq!(
+            events,
+            [RegistryEvent::Added(FIRST), RegistryEvent::Added(SECOND)]
+        );
+    }
+}
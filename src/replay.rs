@@ -0,0 +1,298 @@
+//! A text format for the operations recorded by `CaptureBus`, and replaying them against another
+//! bus (usually the simulator), so a problem seen in the field can be reproduced on a developer
+//! machine.
+//!
+//! A record is a list of items separated by whitespace:
+//!
+//! - `P` / `N`: a reset, with / without a presence pulse
+//! - `W28` / `RA5`: a whole byte written / read, in hex
+//! - `w1` / `r0`: a single bit written / read, for bits that aren't part of a whole byte
+//! - `E`: an operation that failed
+//!
+//! For example, reading 2 bytes of a scratchpad is `P WCC WBE R12 R34`.
+
+use crate::capture::BusOperation;
+use crate::delay::Delay;
+use crate::{OneWireBus, OneWireError};
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+/// One item of a record
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Recorded {
+    Reset(bool),
+    WriteByte(u8),
+    ReadByte(u8),
+    WriteBit(bool),
+    ReadBit(bool),
+    Error,
+}
+
+impl Display for Recorded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Recorded::Reset(true) => write!(f, "P"),
+            Recorded::Reset(false) => write!(f, "N"),
+            Recorded::WriteByte(byte) => write!(f, "W{:02X}", byte),
+            Recorded::ReadByte(byte) => write!(f, "R{:02X}", byte),
+            Recorded::WriteBit(bit) => write!(f, "w{}", *bit as u8),
+            Recorded::ReadBit(bit) => write!(f, "r{}", *bit as u8),
+            Recorded::Error => write!(f, "E"),
+        }
+    }
+}
+
+/// The error returned when an item of a record isn't valid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidRecord;
+
+impl FromStr for Recorded {
+    type Err = InvalidRecord;
+
+    fn from_str(s: &str) -> Result<Recorded, InvalidRecord> {
+        let parse_byte = |hex: &str| {
+            if hex.len() != 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(InvalidRecord);
+            }
+            u8::from_str_radix(hex, 16).map_err(|_| InvalidRecord)
+        };
+        let parse_bit = |bit: &str| match bit {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(InvalidRecord),
+        };
+        if !s.is_ascii() {
+            return Err(InvalidRecord);
+        }
+        match s.split_at(s.len().min(1)) {
+            ("P", "") => Ok(Recorded::Reset(true)),
+            ("N", "") => Ok(Recorded::Reset(false)),
+            ("E", "") => Ok(Recorded::Error),
+            ("W", hex) => Ok(Recorded::WriteByte(parse_byte(hex)?)),
+            ("R", hex) => Ok(Recorded::ReadByte(parse_byte(hex)?)),
+            ("w", bit) => Ok(Recorded::WriteBit(parse_bit(bit)?)),
+            ("r", bit) => Ok(Recorded::ReadBit(parse_bit(bit)?)),
+            _ => Err(InvalidRecord),
+        }
+    }
+}
+
+/// Writes captured operations as a record, combining each 8 consecutive bits read or written
+/// (following a reset, or another whole byte) into a byte
+pub fn write_record<'a>(
+    operations: impl IntoIterator<Item = &'a BusOperation>,
+    out: &mut impl fmt::Write,
+) -> fmt::Result {
+    let mut writer = RecordWriter {
+        out,
+        first: true,
+        bits: 0,
+        count: 0,
+        reading: false,
+    };
+    for operation in operations {
+        match *operation {
+            BusOperation::Reset(present) => {
+                writer.flush_bits()?;
+                writer.item(Recorded::Reset(present))?;
+            }
+            BusOperation::Error => {
+                writer.flush_bits()?;
+                writer.item(Recorded::Error)?;
+            }
+            BusOperation::ByteWritten(byte) => {
+                writer.flush_bits()?;
+                writer.item(Recorded::WriteByte(byte))?;
+            }
+            BusOperation::ByteRead(byte) => {
+                writer.flush_bits()?;
+                writer.item(Recorded::ReadByte(byte))?;
+            }
+            BusOperation::BitWritten(bit) => writer.bit(false, bit)?,
+            BusOperation::BitRead(bit) => writer.bit(true, bit)?,
+        }
+    }
+    writer.flush_bits()
+}
+
+struct RecordWriter<'a, W> {
+    out: &'a mut W,
+    first: bool,
+    // bits of the current byte, least significant bit first
+    bits: u8,
+    count: u8,
+    reading: bool,
+}
+
+impl<'a, W: fmt::Write> RecordWriter<'a, W> {
+    fn item(&mut self, item: Recorded) -> fmt::Result {
+        if !self.first {
+            self.out.write_char(' ')?;
+        }
+        self.first = false;
+        write!(self.out, "{}", item)
+    }
+
+    fn bit(&mut self, reading: bool, bit: bool) -> fmt::Result {
+        if reading != self.reading {
+            self.flush_bits()?;
+            self.reading = reading;
+        }
+        self.bits |= (bit as u8) << self.count;
+        self.count += 1;
+        if self.count == 8 {
+            let byte = self.bits;
+            self.count = 0;
+            self.bits = 0;
+            let item = match reading {
+                true => Recorded::ReadByte(byte),
+                false => Recorded::WriteByte(byte),
+            };
+            self.item(item)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the bits of an incomplete byte individually
+    fn flush_bits(&mut self) -> fmt::Result {
+        for index in 0..self.count {
+            let bit = self.bits & (1 << index) != 0;
+            let item = match self.reading {
+                true => Recorded::ReadBit(bit),
+                false => Recorded::WriteBit(bit),
+            };
+            self.item(item)?;
+        }
+        self.count = 0;
+        self.bits = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplayError<E> {
+    /// The item at `position` (counting from 0) isn't valid
+    InvalidRecord { position: usize },
+
+    /// The bus responded differently than in the record. `replayed` is what was read instead
+    Mismatch {
+        position: usize,
+        recorded: Recorded,
+        replayed: Recorded,
+    },
+
+    /// The bus failed, at the item at `position`
+    Bus {
+        position: usize,
+        error: OneWireError<E>,
+    },
+}
+
+/// Sends the resets and writes of a record to `bus`, and checks that the presence pulses and the
+/// values read match the record. Operations that failed when they were recorded are skipped
+pub fn replay<B: OneWireBus>(
+    record: &str,
+    bus: &mut B,
+    delay: &mut impl Delay,
+) -> Result<(), ReplayError<B::Error>> {
+    for (position, item) in record.split_whitespace().enumerate() {
+        let recorded: Recorded = item
+            .parse()
+            .map_err(|_| ReplayError::InvalidRecord { position })?;
+        let bus_error = |error| ReplayError::Bus { position, error };
+        let replayed = match recorded {
+            Recorded::Reset(_) => Recorded::Reset(bus.reset(delay).map_err(bus_error)?),
+            Recorded::ReadByte(_) => Recorded::ReadByte(bus.read_byte(delay).map_err(bus_error)?),
+            Recorded::ReadBit(_) => Recorded::ReadBit(bus.read_bit(delay).map_err(bus_error)?),
+            Recorded::WriteByte(byte) => {
+                bus.write_byte(byte, delay).map_err(bus_error)?;
+                recorded
+            }
+            Recorded::WriteBit(bit) => {
+                bus.write_bit(bit, delay).map_err(bus_error)?;
+                recorded
+            }
+            Recorded::Error => recorded,
+        };
+        if replayed != recorded {
+            return Err(ReplayError::Mismatch {
+                position,
+                recorded,
+                replayed,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::capture::{BusOperation, CaptureBus, CapturedOperation};
+    use crate::replay::{replay, write_record, Recorded, ReplayError};
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{commands, Address, OneWire, OneWireBus};
+    use std::string::String;
+
+    #[test]
+    fn test_record() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x12, 0x34];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut buffer = [CapturedOperation::default(); 64];
+        let mut bus = CaptureBus::new(OneWire::new(simulator.pin()).unwrap(), &mut buffer);
+        bus.send_command(commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        let mut output = [0; 2];
+        bus.read_bytes(&mut output, &mut delay).unwrap();
+        bus.write_bits(0b01, 2, &mut delay).unwrap();
+
+        let mut record = String::new();
+        let operations = bus.captured().map(|captured| &captured.operation);
+        write_record(operations, &mut record).unwrap();
+        assert_eq!(record, "P WCC WBE R12 R34 w1 w0");
+
+        let mut record = String::new();
+        let operations = [BusOperation::BitRead(true), BusOperation::Error];
+        write_record(&operations, &mut record).unwrap();
+        assert_eq!(record, "r1 E");
+    }
+
+    #[test]
+    fn test_replay() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x12, 0x35];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        assert_eq!(replay("P WCC WBE R12", &mut one_wire, &mut delay), Ok(()));
+        assert_eq!(
+            replay("P WCC WBE R12 R34", &mut one_wire, &mut delay),
+            Err(ReplayError::Mismatch {
+                position: 4,
+                recorded: Recorded::ReadByte(0x34),
+                replayed: Recorded::ReadByte(0x35),
+            })
+        );
+        assert_eq!(
+            replay("P WCC X", &mut one_wire, &mut delay),
+            Err(ReplayError::InvalidRecord { position: 2 })
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        for item in ["P", "N", "W0A", "RFF", "w1", "r0", "E"] {
+            let recorded: Recorded = item.parse().unwrap();
+            assert_eq!(std::format!("{}", recorded), item);
+        }
+        for item in ["", "W", "W1", "W+1", "w2", "PP", "X", "µ"] {
+            assert!(item.parse::<Recorded>().is_err());
+        }
+    }
+}
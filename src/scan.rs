@@ -0,0 +1,161 @@
+use crate::delay::Delay;
+use crate::{Address, OneWireBus, OneWireResult};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// The result of `OneWireBus::scan_diff`
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanDiff {
+    /// All devices found by the scan, which can be passed to the next `scan_diff`
+    pub current: Vec<Address>,
+
+    /// Devices found that weren't in the previous scan
+    pub added: Vec<Address>,
+
+    /// Devices in the previous scan that weren't found
+    pub removed: Vec<Address>,
+}
+
+#[cfg(feature = "alloc")]
+impl ScanDiff {
+    pub(crate) fn new(previous: &[Address], current: Vec<Address>) -> ScanDiff {
+        let added = current
+            .iter()
+            .filter(|address| !previous.contains(address))
+            .copied()
+            .collect();
+        let removed = previous
+            .iter()
+            .filter(|address| !current.contains(address))
+            .copied()
+            .collect();
+        ScanDiff {
+            current,
+            added,
+            removed,
+        }
+    }
+}
+
+/// Searches the bus, calling `f` with each address found until it returns false
+pub(crate) fn for_each_device<B: OneWireBus + ?Sized>(
+    onewire: &mut B,
+    only_alarming: bool,
+    delay: &mut impl Delay,
+    mut f: impl FnMut(Address) -> bool,
+) -> OneWireResult<(), B::Error> {
+    let mut state = None;
+    while let Some((address, next_state)) =
+        onewire.device_search(state.as_ref(), only_alarming, delay)?
+    {
+        if !f(address) {
+            break;
+        }
+        state = Some(next_state);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::shared::SharedBus;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire, OneWireBus};
+
+    const FIRST: Address = Address(0x7000_0000_0000_0228);
+    const SECOND: Address = Address(0x2900_0000_0000_0128);
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_scan() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.scan(&mut delay), Ok(alloc::vec![]));
+
+        let mut device = VirtualDevice::new(FIRST);
+        device.alarming = true;
+        simulator.add_device(device);
+        assert_eq!(one_wire.scan(&mut delay), Ok(alloc::vec![FIRST]));
+        assert_eq!(one_wire.scan_alarming(&mut delay), Ok(alloc::vec![FIRST]));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_scan_diff() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let diff = one_wire.scan_diff(&[SECOND], &mut delay).unwrap();
+        assert_eq!(diff.current, [FIRST]);
+        assert_eq!(diff.added, [FIRST]);
+        assert_eq!(diff.removed, [SECOND]);
+
+        let diff = one_wire.scan_diff(&diff.current, &mut delay).unwrap();
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+
+    #[test]
+    fn test_scan_into() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        simulator.add_device(VirtualDevice::new(SECOND));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut output = [Address(0); 3];
+        assert_eq!(one_wire.scan_into(&mut output, &mut delay), Ok(2));
+        assert_eq!(output[..2], [FIRST, SECOND]);
+
+        let mut output = [Address(0); 1];
+        assert_eq!(one_wire.scan_into(&mut output, &mut delay), Ok(1));
+        assert_eq!(output, [FIRST]);
+    }
+
+    #[test]
+    fn test_first_device() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.first_device(&mut delay), Ok(None));
+
+        simulator.add_device(VirtualDevice::new(SECOND));
+        assert_eq!(one_wire.first_device(&mut delay), Ok(Some(SECOND)));
+        simulator.add_device(VirtualDevice::new(FIRST));
+        assert_eq!(one_wire.first_device(&mut delay), Ok(Some(FIRST)));
+    }
+
+    #[test]
+    fn test_device_count() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.device_count(false, &mut delay), Ok(0));
+
+        simulator.add_device(VirtualDevice::new(FIRST));
+        let mut device = VirtualDevice::new(SECOND);
+        device.alarming = true;
+        simulator.add_device(device);
+        assert_eq!(one_wire.device_count(false, &mut delay), Ok(2));
+        assert_eq!(one_wire.device_count(true, &mut delay), Ok(1));
+    }
+
+    #[test]
+    fn test_scan_bus_handle() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(FIRST));
+        simulator.add_device(VirtualDevice::new(SECOND));
+        let mut delay = simulator.delay();
+        let shared = SharedBus::new(OneWire::new(simulator.pin()).unwrap());
+        let mut handle = shared.handle();
+
+        assert_eq!(handle.device_count(false, &mut delay), Ok(2));
+        assert_eq!(handle.first_device(&mut delay), Ok(Some(FIRST)));
+        let mut output = [Address(0); 1];
+        assert_eq!(handle.scan_into(&mut output, &mut delay), Ok(1));
+        assert_eq!(output, [FIRST]);
+    }
+}
@@ -0,0 +1,440 @@
+//! The ROM search algorithm, without any bus I/O.
+//!
+//! `RomSearch` decides which branch to take at each bit of the address, from the address bit and
+//! its complement sent by the devices. It's used by `OneWireBus::device_search`, and can be used
+//! directly by transports that do the read-read-write sequence in hardware (such as the DS2482's
+//! 1-Wire Triplet command): pass `direction()` to the hardware, then give the 2 bits it read and the
+//! bit it wrote to `take_triplet` (or the 2 bits to `step`, and check that the hardware wrote the
+//! same bit that `step` returned).
+
+use crate::{crc, Address, OneWireError, OneWireResult, SearchState};
+
+/// The order a search finds devices in. Addresses are compared starting from their first bit (the
+/// least significant bit of the family code), so this isn't the numeric order of the addresses
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SearchOrder {
+    /// Takes the 0 branch first wherever devices with both values respond
+    #[default]
+    Ascending,
+
+    /// Takes the 1 branch first, which finds the devices in the reverse order
+    Descending,
+}
+
+impl SearchOrder {
+    /// The branch taken first at a discrepancy
+    fn first_branch(self) -> bool {
+        self == SearchOrder::Descending
+    }
+}
+
+/// The bit a triplet writes: `direction` if devices with both values responded (or a 1 if no
+/// devices did), otherwise the value the devices sent
+pub fn triplet_bit(id_bit: bool, cmp_bit: bool, direction: bool) -> bool {
+    if id_bit == cmp_bit {
+        direction || id_bit
+    } else {
+        id_bit
+    }
+}
+
+/// The result of one step of a search
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Write this bit, which selects the devices that continue the search
+    Take(bool),
+
+    /// No devices responded (both bits were 1), or for a search started with
+    /// `RomSearch::with_prefix`, none of the devices have the prefix
+    NoDevices,
+
+    /// The devices didn't respond the same way as in the previous search, so the branch it was
+    /// continuing from is gone. This generally means devices were removed during the search
+    Diverged,
+}
+
+/// The state of a single pass of the search, from the first bit of the address to the last
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RomSearch {
+    previous: Option<SearchState>,
+    bit_index: u8,
+    address: u64,
+    discrepancies: u64,
+    last_discrepancy_index: u8,
+    prefix: u64,
+    prefix_len: u8,
+    order: SearchOrder,
+}
+
+impl RomSearch {
+    /// Starts a pass of the search. This takes the same state as `OneWire::device_search`, which
+    /// must not be finished
+    pub fn new(previous: Option<&SearchState>) -> RomSearch {
+        RomSearch::with_order(previous, SearchOrder::Ascending)
+    }
+
+    /// The same as `new`, but finds devices in the given order. Every pass of a search must use the
+    /// same order
+    pub fn with_order(previous: Option<&SearchState>, order: SearchOrder) -> RomSearch {
+        RomSearch {
+            previous: previous.copied(),
+            bit_index: 0,
+            address: 0,
+            discrepancies: 0,
+            last_discrepancy_index: 0,
+            prefix: 0,
+            prefix_len: 0,
+            order,
+        }
+    }
+
+    /// Starts the next pass of a search, or returns `None` if the search had already finished (there
+    /// are no discrepancies left in `previous`)
+    pub fn next_pass(previous: Option<&SearchState>, order: SearchOrder) -> Option<RomSearch> {
+        match previous {
+            Some(previous) if previous.discrepancies == 0 => None,
+            _ => Some(RomSearch::with_order(previous, order)),
+        }
+    }
+
+    /// Starts a pass of the search that only follows devices whose address starts with the lowest
+    /// `len` bits of `prefix`, then searches the rest of the address as normal. If no devices have
+    /// the prefix, `step` returns `Decision::NoDevices` at the first bit where every device that
+    /// responded has the other value.
+    ///
+    /// The discrepancies are recorded as if the search had got here normally, so the state from
+    /// `finish` continues the search from the device that was found
+    pub fn with_prefix(prefix: u64, len: u8, order: SearchOrder) -> RomSearch {
+        assert!(len <= 64, "an address only has 64 bits");
+        RomSearch {
+            prefix,
+            prefix_len: len,
+            ..RomSearch::with_order(None, order)
+        }
+    }
+
+    /// The index of the next bit to search
+    pub fn bit_index(&self) -> u8 {
+        self.bit_index
+    }
+
+    /// Returns true once all 64 bits have been searched
+    pub fn is_complete(&self) -> bool {
+        self.bit_index == 64
+    }
+
+    /// The bit to take if devices with both values respond to the next bit
+    pub fn direction(&self) -> bool {
+        if self.bit_index < self.prefix_len {
+            return self.prefix & (1 << self.bit_index) != 0;
+        }
+        match self.previous {
+            Some(previous) if self.bit_index < previous.last_discrepancy_index => {
+                previous.address & (1 << self.bit_index) != 0
+            }
+            // the first branch was taken at the last discrepancy, so take the other one this time
+            Some(previous) if self.bit_index == previous.last_discrepancy_index => {
+                !self.order.first_branch()
+            }
+            _ => self.order.first_branch(),
+        }
+    }
+
+    /// Takes the next bit of the search, from the address bit and its complement sent by the
+    /// devices. The search can't continue after anything other than `Decision::Take`
+    pub fn step(&mut self, id_bit: bool, cmp_bit: bool) -> Decision {
+        assert!(!self.is_complete(), "the search is already complete");
+        let mask = 1_u64 << self.bit_index;
+        let taken = match self.previous {
+            _ if self.bit_index < self.prefix_len => {
+                let prefix_bit = self.prefix & mask != 0;
+                match (id_bit, cmp_bit) {
+                    (true, true) => return Decision::NoDevices,
+                    // the devices on the other branch come later in the search
+                    (false, false) if prefix_bit == self.order.first_branch() => {
+                        self.discrepancies |= mask;
+                        self.last_discrepancy_index = self.bit_index;
+                    }
+                    (false, false) => {}
+                    _ if id_bit != prefix_bit => return Decision::NoDevices,
+                    _ => {}
+                }
+                prefix_bit
+            }
+            Some(previous) if self.bit_index < previous.last_discrepancy_index => {
+                // follow the same path as last time, up to the last discrepancy
+                let previous_bit = previous.address & mask != 0;
+                match (id_bit, cmp_bit) {
+                    (true, true) => return Decision::NoDevices,
+                    (false, false) if previous.discrepancies & mask != 0 => {
+                        self.discrepancies |= mask;
+                        self.last_discrepancy_index = self.bit_index;
+                    }
+                    (false, false) => {}
+                    // the device that was followed last time isn't responding
+                    _ if id_bit != previous_bit => return Decision::Diverged,
+                    _ => {}
+                }
+                previous_bit
+            }
+            Some(previous) if self.bit_index == previous.last_discrepancy_index => {
+                // both values were seen here last time, so they should be again
+                if id_bit || cmp_bit {
+                    return Decision::Diverged;
+                }
+                !self.order.first_branch()
+            }
+            _ => match (id_bit, cmp_bit) {
+                (true, true) => return Decision::NoDevices,
+                (false, false) => {
+                    // taking the first branch if there is a discrepancy, and coming back for the
+                    // other one later
+                    self.discrepancies |= mask;
+                    self.last_discrepancy_index = self.bit_index;
+                    self.order.first_branch()
+                }
+                // all remaining devices have the same value for this bit
+                _ => id_bit,
+            },
+        };
+        if taken {
+            self.address |= mask;
+        }
+        self.bit_index += 1;
+        Decision::Take(taken)
+    }
+
+    /// Takes the next bit from the result of a triplet (the address bit, the complement bit, and the
+    /// bit that was written). Fails with `OneWireError::UnexpectedResponse` if the search can't
+    /// continue, or the wrong bit was written, which generally means devices were added / removed
+    /// during the search
+    pub fn take_triplet<E>(
+        &mut self,
+        (id_bit, cmp_bit, taken): (bool, bool, bool),
+    ) -> OneWireResult<(), E> {
+        match self.step(id_bit, cmp_bit) {
+            Decision::Take(bit) if bit == taken => Ok(()),
+            _ => Err(OneWireError::UnexpectedResponse),
+        }
+    }
+
+    /// Finishes a complete search, checking the crc of the address that was found
+    pub fn finish<E>(&self) -> OneWireResult<(Address, SearchState), E> {
+        assert!(self.is_complete(), "the search isn't complete");
+        crc::check_crc8(&self.address.to_le_bytes())?;
+        Ok(self.finish_unchecked())
+    }
+
+    /// Finishes a complete search without checking the crc, for devices that are being skipped
+    pub fn finish_unchecked(&self) -> (Address, SearchState) {
+        assert!(self.is_complete(), "the search isn't complete");
+        (
+            Address(self.address),
+            SearchState {
+                address: self.address,
+                discrepancies: self.discrepancies,
+                last_discrepancy_index: self.last_discrepancy_index,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::search::{triplet_bit, Decision, RomSearch, SearchOrder};
+    use crate::{Address, OneWireError, SearchState};
+    use std::vec::Vec;
+
+    /// Runs the search against devices with the given addresses, the same way they respond on a bus
+    fn search(addresses: &[u64], previous: Option<&SearchState>) -> Result<RomSearch, Decision> {
+        run(RomSearch::new(previous), addresses)
+    }
+
+    fn run(mut search: RomSearch, addresses: &[u64]) -> Result<RomSearch, Decision> {
+        let mut selected: Vec<u64> = addresses.to_vec();
+        while !search.is_complete() {
+            let mask = 1 << search.bit_index();
+            let id_bit = selected.iter().all(|address| address & mask != 0);
+            let cmp_bit = selected.iter().all(|address| address & mask == 0);
+            match search.step(id_bit, cmp_bit) {
+                Decision::Take(bit) => selected.retain(|address| (address & mask != 0) == bit),
+                decision => return Err(decision),
+            }
+        }
+        Ok(search)
+    }
+
+    fn search_all(addresses: &[u64], order: SearchOrder) -> Vec<u64> {
+        let mut found = Vec::new();
+        let mut state: Option<SearchState> = None;
+        loop {
+            let (address, next) = run(RomSearch::with_order(state.as_ref(), order), addresses)
+                .unwrap()
+                .finish::<()>()
+                .unwrap();
+            found.push(address.0);
+            if next.is_finished() {
+                return found;
+            }
+            state = Some(next);
+        }
+    }
+
+    #[test]
+    fn test_single_device() {
+        let search = search(&[0x7000_0000_0000_0228], None).unwrap();
+        let (address, state) = search.finish::<()>().unwrap();
+        assert_eq!(address, Address(0x7000_0000_0000_0228));
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn test_no_devices() {
+        assert_eq!(search(&[], None), Err(Decision::NoDevices));
+    }
+
+    #[test]
+    fn test_crc() {
+        let search = search(&[0x28], None).unwrap();
+        assert!(matches!(
+            search.finish::<()>(),
+            Err(OneWireError::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_direction() {
+        let addresses = [Address::for_test(0x28, 1).0, Address::for_test(0x29, 1).0];
+        let (_, state) = search(&addresses, None).unwrap().finish::<()>().unwrap();
+        // bit 0 is the only discrepancy between these
+        assert_eq!(state.last_discrepancy_index(), 0);
+
+        let second = RomSearch::new(Some(&state));
+        assert!(second.direction());
+        assert!(!RomSearch::new(None).direction());
+    }
+
+    #[test]
+    fn test_diverged() {
+        let (_, state) = search(&[0x7000_0000_0000_0228, 0x2900_0000_0000_0128], None)
+            .unwrap()
+            .finish::<()>()
+            .unwrap();
+        // the device on the other branch was removed
+        assert_eq!(
+            search(&[0x7000_0000_0000_0228], Some(&state)),
+            Err(Decision::Diverged)
+        );
+    }
+
+    #[test]
+    fn test_triplet() {
+        assert!(triplet_bit(false, false, true));
+        assert!(!triplet_bit(false, false, false));
+        assert!(!triplet_bit(false, true, true));
+        assert!(triplet_bit(true, true, false));
+
+        let mut search = RomSearch::next_pass(None, SearchOrder::Ascending).unwrap();
+        assert_eq!(search.take_triplet::<()>((false, true, false)), Ok(()));
+        // the bus wrote a different bit than the search took
+        assert_eq!(
+            search.take_triplet::<()>((true, false, false)),
+            Err(OneWireError::UnexpectedResponse)
+        );
+
+        let (_, state) = self::search(&[0x7000_0000_0000_0228], None)
+            .unwrap()
+            .finish::<()>()
+            .unwrap();
+        assert_eq!(
+            RomSearch::next_pass(Some(&state), SearchOrder::Ascending),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        let mut addresses = [
+            Address::for_test(0x28, 1).0,
+            Address::for_test(0x10, 1).0,
+            Address::for_test(0x10, 2).0,
+            Address::for_test(0x3A, 1).0,
+        ];
+        addresses.sort_by_key(|address| address.reverse_bits());
+        let start = addresses
+            .iter()
+            .position(|address| *address as u8 == 0x10)
+            .unwrap();
+        let first = run(
+            RomSearch::with_prefix(0x10, 8, SearchOrder::Ascending),
+            &addresses,
+        )
+        .unwrap();
+        let (address, state) = first.finish::<()>().unwrap();
+        assert_eq!(address.0, addresses[start]);
+
+        // the state continues the search from there, including past the prefix
+        let mut found = Vec::new();
+        let mut state = Some(state);
+        while let Some(previous) = state.filter(|state| !state.is_finished()) {
+            let (address, next) = search(&addresses, Some(&previous))
+                .unwrap()
+                .finish::<()>()
+                .unwrap();
+            found.push(address.0);
+            state = Some(next);
+        }
+        assert_eq!(found, addresses[start + 1..]);
+
+        assert_eq!(
+            run(
+                RomSearch::with_prefix(0x29, 8, SearchOrder::Ascending),
+                &addresses
+            )
+            .err(),
+            Some(Decision::NoDevices)
+        );
+    }
+
+    #[test]
+    fn test_search_order() {
+        // every combination of the lowest 4 bits of the serial number, so there are discrepancies
+        // at many levels of the tree
+        let mut addresses: Vec<u64> = (0..16)
+            .map(|serial| Address::for_test(0x28, serial).0)
+            .collect();
+        let found = search_all(&addresses, SearchOrder::Ascending);
+        addresses.sort_by_key(|address| address.reverse_bits());
+        assert_eq!(found, addresses);
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        // every subset of 6 devices, using a pseudo-random set of serial numbers
+        let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+        let devices: Vec<u64> = (0..6)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                Address::for_test(seed as u8, seed >> 8).0
+            })
+            .collect();
+        for subset in 1_u32..(1 << devices.len()) {
+            let mut addresses: Vec<u64> = devices
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| subset & (1 << index) != 0)
+                .map(|(_, address)| *address)
+                .collect();
+            let found = search_all(&addresses, SearchOrder::Ascending);
+            addresses.sort_by_key(|address| address.reverse_bits());
+            assert_eq!(found, addresses);
+            let found = search_all(&addresses, SearchOrder::Descending);
+            addresses.reverse();
+            assert_eq!(found, addresses);
+        }
+    }
+}
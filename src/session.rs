@@ -0,0 +1,165 @@
+//! A type-state wrapper around a bus, which only allows operations in the order the protocol
+//! requires: a reset, then addressing (MATCH ROM / SKIP ROM), then a function command and its data.
+//!
+//! Each step consumes the session and returns it in the next state, so the order is checked at
+//! compile time. A reset can be sent from any state to start over.
+
+use crate::delay::Delay;
+use crate::{Address, OneWireBus, OneWireResult};
+
+/// Nothing has been sent yet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Idle;
+
+/// A reset has been sent, so a device can be addressed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AfterReset {
+    presence: bool,
+}
+
+/// A device (or all devices) has been addressed, so function commands and data can be sent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Addressed;
+
+pub struct Session<'a, B, S> {
+    bus: &'a mut B,
+    state: S,
+}
+
+impl<'a, B> Session<'a, B, Idle>
+where
+    B: OneWireBus,
+{
+    pub fn new(bus: &'a mut B) -> Session<'a, B, Idle> {
+        Session { bus, state: Idle }
+    }
+}
+
+impl<'a, B, S> Session<'a, B, S>
+where
+    B: OneWireBus,
+{
+    pub fn reset(
+        self,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Session<'a, B, AfterReset>, B::Error> {
+        let presence = self.bus.reset(delay)?;
+        Ok(Session {
+            bus: self.bus,
+            state: AfterReset { presence },
+        })
+    }
+
+    /// Ends the session, returning the bus
+    pub fn into_inner(self) -> &'a mut B {
+        self.bus
+    }
+}
+
+impl<'a, B> Session<'a, B, AfterReset>
+where
+    B: OneWireBus,
+{
+    /// Returns true if a device responded to the reset with a presence pulse
+    pub fn is_present(&self) -> bool {
+        self.state.presence
+    }
+
+    /// Address a specific device. All others will wait for a reset pulse
+    pub fn match_address(
+        self,
+        address: &Address,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Session<'a, B, Addressed>, B::Error> {
+        self.bus.match_address(address, delay)?;
+        Ok(Session {
+            bus: self.bus,
+            state: Addressed,
+        })
+    }
+
+    /// Address all devices on the bus simultaneously
+    pub fn skip_address(
+        self,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Session<'a, B, Addressed>, B::Error> {
+        self.bus.skip_address(delay)?;
+        Ok(Session {
+            bus: self.bus,
+            state: Addressed,
+        })
+    }
+}
+
+impl<'a, B> Session<'a, B, Addressed>
+where
+    B: OneWireBus,
+{
+    /// Sends a function command. This is the same as `write_byte`, but reads better at the call site
+    pub fn command(&mut self, command: u8, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.write_byte(command, delay)
+    }
+
+    pub fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        self.bus.read_bit(delay)
+    }
+
+    pub fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        self.bus.read_byte(delay)
+    }
+
+    pub fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.read_bytes(output, delay)
+    }
+
+    pub fn write_bit(
+        &mut self,
+        value: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.write_bit(value, delay)
+    }
+
+    pub fn write_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.write_byte(value, delay)
+    }
+
+    pub fn write_bytes(
+        &mut self,
+        bytes: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.write_bytes(bytes, delay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::session::Session;
+    use crate::{commands, Address};
+
+    #[test]
+    fn test_session() {
+        let address = Address(0x3A00_0000_0000_0128);
+        let mut expected = Transaction::command(commands::READ_SCRATCHPAD, Some(&address));
+        expected.push(Transaction::ReadByte(0x91));
+        expected.push(Transaction::Reset(false));
+        let mut mock = Mock::new(&expected);
+
+        let session = Session::new(&mut mock).reset(&mut NoDelay).unwrap();
+        assert!(session.is_present());
+        let mut session = session.match_address(&address, &mut NoDelay).unwrap();
+        session
+            .command(commands::READ_SCRATCHPAD, &mut NoDelay)
+            .unwrap();
+        assert_eq!(session.read_byte(&mut NoDelay), Ok(0x91));
+        let session = session.reset(&mut NoDelay).unwrap();
+        assert!(!session.is_present());
+        session.into_inner().done();
+    }
+}
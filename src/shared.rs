@@ -0,0 +1,364 @@
+//! Sharing one bus between several device drivers.
+//!
+//! `SharedBus` owns the bus, and hands out any number of `BusHandle`s, which each implement
+//! `OneWireBus`. Every call through a handle borrows the bus for its whole duration, so the
+//! composite operations (`send_command`, `read_scratchpad`, `device_search`, ...) can't be
+//! interleaved with another driver. Use `BusHandle::transaction` to do the same for a sequence of
+//! operations.
+//!
+//! `SharedBus::new` uses a `RefCell`, so the handles only work within one thread / execution
+//! context (they aren't `Send`). Using a handle while another handle's transaction is running on
+//! the same bus panics. To share the bus with interrupt handlers or other cores, enable the
+//! `critical-section` feature and use `SharedBus::new_critical_section`, which runs each call in a
+//! critical section.
+
+use crate::commands::{Addressing, RomCommand};
+use crate::deadline::Deadline;
+use crate::delay::Delay;
+use crate::search::SearchOrder;
+use crate::{Address, OneWireBus, OneWireResult, Operation, SearchState, Speed};
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+/// How a `SharedBus` stores the bus, giving one handle at a time use of it
+pub trait BusCell<B> {
+    fn with_bus<R>(&self, f: impl FnOnce(&mut B) -> R) -> R;
+
+    fn into_bus(self) -> B;
+}
+
+impl<B> BusCell<B> for RefCell<B> {
+    fn with_bus<R>(&self, f: impl FnOnce(&mut B) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+
+    fn into_bus(self) -> B {
+        self.into_inner()
+    }
+}
+
+/// Each use of the bus runs in a critical section, which also keeps interrupts from stretching
+/// the time slots
+#[cfg(feature = "critical-section")]
+impl<B> BusCell<B> for critical_section::Mutex<RefCell<B>> {
+    fn with_bus<R>(&self, f: impl FnOnce(&mut B) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.borrow_ref_mut(cs)))
+    }
+
+    fn into_bus(self) -> B {
+        self.into_inner().into_inner()
+    }
+}
+
+pub struct SharedBus<B, C = RefCell<B>> {
+    bus: C,
+    _bus: PhantomData<B>,
+}
+
+impl<B: OneWireBus> SharedBus<B> {
+    pub fn new(bus: B) -> SharedBus<B> {
+        SharedBus {
+            bus: RefCell::new(bus),
+            _bus: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<B: OneWireBus> SharedBus<B, critical_section::Mutex<RefCell<B>>> {
+    /// Shares the bus using a `critical_section::Mutex`, so the handles can be used from interrupt
+    /// handlers and other cores. Each call through a handle (or `BusHandle::transaction`) runs in
+    /// a critical section for its whole duration
+    pub fn new_critical_section(bus: B) -> SharedBus<B, critical_section::Mutex<RefCell<B>>> {
+        SharedBus {
+            bus: critical_section::Mutex::new(RefCell::new(bus)),
+            _bus: PhantomData,
+        }
+    }
+}
+
+impl<B: OneWireBus, C: BusCell<B>> SharedBus<B, C> {
+    pub fn into_inner(self) -> B {
+        self.bus.into_bus()
+    }
+
+    /// Returns a new handle to the bus, which can be given to a device driver
+    pub fn handle(&self) -> BusHandle<'_, B, C> {
+        BusHandle {
+            bus: &self.bus,
+            _bus: PhantomData,
+        }
+    }
+}
+
+pub struct BusHandle<'a, B, C = RefCell<B>> {
+    bus: &'a C,
+    _bus: PhantomData<B>,
+}
+
+impl<'a, B, C> Clone for BusHandle<'a, B, C> {
+    fn clone(&self) -> Self {
+        BusHandle {
+            bus: self.bus,
+            _bus: PhantomData,
+        }
+    }
+}
+
+impl<'a, B: OneWireBus, C: BusCell<B>> BusHandle<'a, B, C> {
+    /// Runs `f` with exclusive use of the bus, so a whole transaction (reset, addressing, commands
+    /// and data) is sent without other handles using the bus in between
+    pub fn transaction<R>(&mut self, f: impl FnOnce(&mut B) -> R) -> R {
+        self.bus.with_bus(f)
+    }
+}
+
+impl<'a, B: OneWireBus, C: BusCell<B>> OneWireBus for BusHandle<'a, B, C> {
+    type Error = B::Error;
+
+    fn reset(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        self.bus.with_bus(|bus| bus.reset(delay))
+    }
+
+    fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        self.bus.with_bus(|bus| bus.read_bit(delay))
+    }
+
+    fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.write_bit(value, delay))
+    }
+
+    fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        self.bus.with_bus(|bus| bus.read_byte(delay))
+    }
+
+    fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.read_bytes(output, delay))
+    }
+
+    fn write_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.write_byte(value, delay))
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.write_bytes(bytes, delay))
+    }
+
+    fn read_bytes_crc8(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.read_bytes_crc8(output, delay))
+    }
+
+    fn read_bits(&mut self, count: u8, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        self.bus.with_bus(|bus| bus.read_bits(count, delay))
+    }
+
+    fn write_bytes_verified(
+        &mut self,
+        bytes: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.write_bytes_verified(bytes, delay))
+    }
+
+    fn write_bits(
+        &mut self,
+        value: u8,
+        count: u8,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.write_bits(value, count, delay))
+    }
+
+    fn touch_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<bool, B::Error> {
+        self.bus.with_bus(|bus| bus.touch_bit(value, delay))
+    }
+
+    fn touch_byte(&mut self, value: u8, delay: &mut impl Delay) -> OneWireResult<u8, B::Error> {
+        self.bus.with_bus(|bus| bus.touch_byte(value, delay))
+    }
+
+    fn write_rom_command(
+        &mut self,
+        command: RomCommand,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.write_rom_command(command, delay))
+    }
+
+    fn match_address(
+        &mut self,
+        address: &Address,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.match_address(address, delay))
+    }
+
+    fn skip_address(&mut self, delay: &mut impl Delay) -> OneWireResult<(), B::Error> {
+        self.bus.with_bus(|bus| bus.skip_address(delay))
+    }
+
+    fn send_command(
+        &mut self,
+        command: u8,
+        address: Option<&Address>,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.send_command(command, address, delay))
+    }
+
+    fn send_command_with(
+        &mut self,
+        command: u8,
+        addressing: Addressing,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.send_command_with(command, addressing, delay))
+    }
+
+    fn read_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.read_scratchpad(address, output, delay))
+    }
+
+    fn write_scratchpad(
+        &mut self,
+        address: Option<&Address>,
+        data: &[u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), B::Error> {
+        self.bus
+            .with_bus(|bus| bus.write_scratchpad(address, data, delay))
+    }
+
+    fn idle(&mut self) {
+        self.bus.with_bus(|bus| bus.idle())
+    }
+
+    fn crc_failed(&mut self, len: usize) {
+        self.bus.with_bus(|bus| bus.crc_failed(len))
+    }
+
+    fn set_speed(&mut self, speed: Speed) {
+        self.bus.with_bus(|bus| bus.set_speed(speed))
+    }
+
+    fn speed(&self) -> Speed {
+        self.bus.with_bus(|bus| bus.speed())
+    }
+
+    fn operation_failed(&mut self, operation: Operation) {
+        self.bus.with_bus(|bus| bus.operation_failed(operation))
+    }
+
+    fn set_strong_pullup(&mut self, enabled: bool) -> OneWireResult<bool, B::Error> {
+        self.bus.with_bus(|bus| bus.set_strong_pullup(enabled))
+    }
+
+    fn read_slot_micros(&self) -> u32 {
+        self.bus.with_bus(|bus| bus.read_slot_micros())
+    }
+
+    fn triplet(
+        &mut self,
+        direction: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(bool, bool, bool), B::Error> {
+        self.bus.with_bus(|bus| bus.triplet(direction, delay))
+    }
+
+    fn device_search(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        delay: &mut impl Delay,
+    ) -> OneWireResult<Option<(Address, SearchState)>, B::Error> {
+        self.bus
+            .with_bus(|bus| bus.device_search(search_state, only_alarming, delay))
+    }
+    fn device_search_with_deadline(
+        &mut self,
+        search_state: Option<&SearchState>,
+        only_alarming: bool,
+        order: SearchOrder,
+        delay: &mut impl Delay,
+        deadline: &mut impl Deadline,
+    ) -> OneWireResult<Option<(Address, SearchState)>, B::Error> {
+        self.bus.with_bus(|bus| {
+            bus.device_search_with_deadline(search_state, only_alarming, order, delay, deadline)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mock::{Mock, NoDelay, Transaction};
+    use crate::shared::SharedBus;
+    use crate::{commands, Address, OneWireBus};
+
+    #[test]
+    fn test_shared_bus() {
+        let first = Address(0x3A00_0000_0000_0128);
+        let second = Address(0x7000_0000_0000_0228);
+        let mut expected = Transaction::command(commands::WRITE_SCRATCHPAD, Some(&first));
+        expected.extend(Transaction::command(
+            commands::READ_SCRATCHPAD,
+            Some(&second),
+        ));
+        expected.push(Transaction::ReadByte(0x91));
+        let shared = SharedBus::new(Mock::new(&expected));
+
+        // e.g. one handle for each driver
+        let mut a = shared.handle();
+        let mut b = a.clone();
+        a.send_command(commands::WRITE_SCRATCHPAD, Some(&first), &mut NoDelay)
+            .unwrap();
+        let value = b.transaction(|bus| {
+            bus.send_command(commands::READ_SCRATCHPAD, Some(&second), &mut NoDelay)?;
+            bus.read_byte(&mut NoDelay)
+        });
+        assert_eq!(value, Ok(0x91));
+        shared.into_inner().done();
+    }
+
+    #[cfg(feature = "critical-section")]
+    #[test]
+    fn test_critical_section_bus() {
+        extern crate std;
+
+        let address = Address(0x7000_0000_0000_0228);
+        let mut expected = Transaction::command(commands::READ_SCRATCHPAD, Some(&address));
+        expected.push(Transaction::ReadByte(0x91));
+        let shared = SharedBus::new_critical_section(Mock::new(&expected));
+
+        // a handle used from another thread, as it would be from an interrupt handler
+        let value = std::thread::scope(|scope| {
+            let mut handle = shared.handle();
+            scope
+                .spawn(move || {
+                    handle.send_command(commands::READ_SCRATCHPAD, Some(&address), &mut NoDelay)?;
+                    handle.read_byte(&mut NoDelay)
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(value, Ok(0x91));
+        shared.into_inner().done();
+    }
+}
@@ -0,0 +1,1888 @@
+//! A simulated bus with virtual devices, so the search algorithm and command sequences can be tested
+//! without hardware. Enabled with the `simulator` feature (which requires std).
+//!
+//! The simulator provides a pin and a delay that share the state of the bus. Time only moves forward
+//! when the delay is used, which makes every run deterministic.
+
+extern crate std;
+
+use crate::memory::{self, MemoryLayout, WriteMethod};
+use crate::{commands, crc, Address};
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use std::vec::Vec;
+
+/// The bus must be held low at least this long to be a reset
+const RESET_MIN_MICROS: u64 = 480;
+
+/// Held low for less than this, the master is writing a 1 (or starting a read slot)
+const WRITE_1_MAX_MICROS: u64 = 15;
+
+/// How long a device holds the bus low to send a 0
+const DEVICE_HOLD_MICROS: u64 = 30;
+
+// The presence pulse starts 15-60 µs after the reset ends, and lasts 60-240 µs
+const PRESENCE_START_MICROS: u64 = 15;
+const PRESENCE_END_MICROS: u64 = 135;
+
+// The same timings, for devices at overdrive speed
+const OVERDRIVE_RESET_MIN_MICROS: u64 = 48;
+const OVERDRIVE_WRITE_1_MAX_MICROS: u64 = 2;
+const OVERDRIVE_DEVICE_HOLD_MICROS: u64 = 3;
+const OVERDRIVE_PRESENCE_START_MICROS: u64 = 2;
+const OVERDRIVE_PRESENCE_END_MICROS: u64 = 10;
+
+// The function commands of a DS2409 coupler
+const ALL_LINES_OFF: u8 = 0x66;
+const DISCHARGE_LINES: u8 = 0x99;
+const DIRECT_ON_MAIN: u8 = 0xA5;
+const SMART_ON_MAIN: u8 = 0xCC;
+const SMART_ON_AUXILIARY: u8 = 0x33;
+
+/// A branch of a coupler created with `VirtualDevice::coupler`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CouplerBranch {
+    Main,
+    Auxiliary,
+}
+
+impl CouplerBranch {
+    fn smart_on_command(self) -> u8 {
+        match self {
+            CouplerBranch::Main => SMART_ON_MAIN,
+            CouplerBranch::Auxiliary => SMART_ON_AUXILIARY,
+        }
+    }
+}
+
+/// The state of a DS2409 coupler
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Coupler {
+    connected: Option<CouplerBranch>,
+    // whether any devices are on the branch being switched on by SMART ON
+    branch_present: bool,
+    // something the bus has to do for the coupler, after the current slot
+    request: Option<CouplerRequest>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CouplerRequest {
+    /// Check for devices on the branch, for the presence byte of SMART ON
+    Presence(CouplerBranch),
+    /// Switch the branch on, with a reset pulse
+    Connect(CouplerBranch),
+}
+
+/// A device on the simulated bus. The device responds to all ROM commands, and to the
+/// READ SCRATCHPAD, WRITE SCRATCHPAD, and READ POWER SUPPLY function commands. Devices created with
+/// `with_memory` also respond to the EEPROM commands used by the `memory` helpers, and devices
+/// created with `coupler` act as a DS2409 coupler, which other devices can be connected behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualDevice {
+    pub address: Address,
+
+    /// The device responds to alarm searches when this is set
+    pub alarming: bool,
+
+    /// Sent in response to READ SCRATCHPAD, and written by WRITE SCRATCHPAD (starting from the first byte)
+    pub scratchpad: Vec<u8>,
+
+    /// The device switches to overdrive speed after OVERDRIVE SKIP ROM or OVERDRIVE MATCH ROM when
+    /// this is set. It returns to standard speed after a standard speed reset
+    pub overdrive_capable: bool,
+
+    /// The device only communicates at overdrive speed (like the DS28E05), and ignores standard
+    /// speed resets
+    pub overdrive_only: bool,
+
+    /// EEPROM memory, read with READ MEMORY and written the way the device's `MemoryLayout`
+    /// describes. Empty for devices without memory
+    pub memory: Vec<u8>,
+
+    /// The device disconnects from the bus after this many more time slots (not counting resets),
+    /// like a device unplugged part way through a command or search
+    pub disconnect_after_slots: Option<u32>,
+
+    /// The branch of a coupler the device is connected to, or `None` if it's on the main bus. The
+    /// device can only be reached while the coupler (and every coupler before it) has the branch
+    /// switched on
+    pub connection: Option<(Address, CouplerBranch)>,
+
+    coupler: Option<Coupler>,
+    overdrive: bool,
+    memory_layout: Option<MemoryLayout>,
+    // the scratchpad, or the segment being written for devices without one
+    memory_scratchpad: Vec<u8>,
+    target_address: u16,
+    // the E/S byte: the offset of the last byte written to the memory scratchpad, and its flags
+    ending_status: u8,
+    state: DeviceState,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DeviceState {
+    /// Waiting for a reset
+    Idle,
+    RomCommand {
+        byte: u8,
+        bits: u8,
+    },
+    /// Each address bit has 3 steps: send the bit, send its complement, then receive the chosen bit
+    Search {
+        bit_index: u8,
+        step: u8,
+    },
+    MatchRom {
+        bit_index: u8,
+        matches: bool,
+    },
+    ReadRom {
+        bit_index: u8,
+    },
+    FunctionCommand {
+        byte: u8,
+        bits: u8,
+    },
+    ReadScratchpad {
+        bit_index: usize,
+    },
+    WriteScratchpad {
+        byte: u8,
+        bits: u8,
+        index: usize,
+    },
+    ReadPowerSupply,
+    /// Receiving the 2 byte target address of a memory command
+    MemoryAddress {
+        command: u8,
+        address: u16,
+        bits: u8,
+    },
+    ReadMemory {
+        bit_index: usize,
+    },
+    /// Sending the rest of the page from `address`, followed by its crc16
+    ExtendedReadMemory {
+        address: usize,
+        bit_index: usize,
+        first: bool,
+    },
+    WriteSegment {
+        byte: u8,
+        bits: u8,
+        offset: usize,
+    },
+    /// Sending the segment back inverted
+    SegmentEcho {
+        bit_index: usize,
+    },
+    /// Receiving the byte that starts programming the segment
+    SegmentRelease {
+        bits: u8,
+    },
+    WriteMemoryScratchpad {
+        byte: u8,
+        bits: u8,
+        offset: usize,
+    },
+    /// Sending the inverted crc16 after the end of the memory scratchpad was written
+    MemoryScratchpadCrc {
+        crc: u16,
+        bit_index: u8,
+    },
+    ReadMemoryScratchpad {
+        bit_index: usize,
+    },
+    CopyScratchpad {
+        authorization: u32,
+        bits: u8,
+    },
+    /// Sending alternating 1s and 0s after a successful copy
+    CopyDone {
+        bit_index: u8,
+    },
+    /// Receiving the reset stimulus byte of SMART ON
+    CouplerStimulus {
+        branch: CouplerBranch,
+        bits: u8,
+    },
+    /// Sending the presence byte of SMART ON, where the first bit is 0 if there are devices on
+    /// the branch
+    CouplerPresence {
+        branch: CouplerBranch,
+        bit_index: u8,
+    },
+    /// Sending a coupler command back to confirm it, which takes effect once it's sent
+    CouplerConfirm {
+        command: u8,
+        bit_index: u8,
+    },
+}
+
+/// Set in the E/S byte once the memory scratchpad has been copied
+const AUTHORIZATION_ACCEPTED: u8 = 0x80;
+
+/// Set in the E/S byte until a whole byte has been written to the memory scratchpad
+const PARTIAL_FLAG: u8 = 0x20;
+
+impl VirtualDevice {
+    pub fn new(address: Address) -> VirtualDevice {
+        VirtualDevice {
+            address,
+            alarming: false,
+            scratchpad: Vec::new(),
+            overdrive_capable: false,
+            overdrive_only: false,
+            memory: Vec::new(),
+            disconnect_after_slots: None,
+            connection: None,
+            coupler: None,
+            overdrive: false,
+            memory_layout: None,
+            memory_scratchpad: Vec::new(),
+            target_address: 0,
+            ending_status: 0,
+            state: DeviceState::Idle,
+        }
+    }
+
+    /// A device with erased (0xFF) EEPROM memory, laid out and written as `layout` describes
+    pub fn with_memory(address: Address, layout: &MemoryLayout) -> VirtualDevice {
+        assert!(layout.write_size.is_power_of_two());
+        VirtualDevice {
+            memory: std::vec![0xFF; layout.size as usize],
+            memory_layout: Some(*layout),
+            memory_scratchpad: std::vec![0xFF; layout.write_size as usize],
+            ..VirtualDevice::new(address)
+        }
+    }
+
+    /// A DS2409 coupler, with both branches switched off. It responds to ALL LINES OFF,
+    /// DISCHARGE LINES, DIRECT ON MAIN, and SMART ON MAIN / AUXILIARY
+    pub fn coupler(address: Address) -> VirtualDevice {
+        VirtualDevice {
+            coupler: Some(Coupler {
+                connected: None,
+                branch_present: false,
+                request: None,
+            }),
+            ..VirtualDevice::new(address)
+        }
+    }
+
+    /// The branch a coupler has switched on
+    pub fn connected_branch(&self) -> Option<CouplerBranch> {
+        self.coupler.and_then(|coupler| coupler.connected)
+    }
+
+    /// A coupler command has been confirmed
+    fn coupler_command(&mut self, command: u8) {
+        let coupler = match &mut self.coupler {
+            Some(coupler) => coupler,
+            None => return,
+        };
+        match command {
+            ALL_LINES_OFF | DISCHARGE_LINES => coupler.connected = None,
+            DIRECT_ON_MAIN => coupler.connected = Some(CouplerBranch::Main),
+            SMART_ON_MAIN => coupler.request = Some(CouplerRequest::Connect(CouplerBranch::Main)),
+            _ => {
+                coupler.request = Some(CouplerRequest::Connect(CouplerBranch::Auxiliary));
+            }
+        }
+    }
+
+    fn memory_byte(&self, address: usize) -> u8 {
+        self.memory.get(address).copied().unwrap_or(0xFF)
+    }
+
+    /// The address just past the end of the page `address` is in
+    fn page_end(&self, address: usize) -> usize {
+        let page_size = self
+            .memory_layout
+            .map_or(1, |layout| layout.page_size as usize);
+        (address / page_size + 1) * page_size
+    }
+
+    /// The bytes sent in response to EXTENDED READ MEMORY, from `address` to the end of its page
+    /// and then the inverted crc16
+    fn extended_read_byte(&self, address: usize, index: usize, first: bool) -> u8 {
+        let page_end = self.page_end(address);
+        if address + index < page_end {
+            return self.memory_byte(address + index);
+        }
+        let mut digest = crc::Crc16Digest::new();
+        if first {
+            let [ta1, ta2] = (address as u16).to_le_bytes();
+            digest.update(&[memory::commands::EXTENDED_READ_MEMORY, ta1, ta2]);
+        }
+        for offset in address..page_end {
+            digest.update(&[self.memory_byte(offset)]);
+        }
+        (!digest.finalize()).to_le_bytes()[address + index - page_end]
+    }
+
+    /// The offset within the memory scratchpad of the target address
+    fn scratchpad_offset(&self) -> usize {
+        self.target_address as usize & (self.memory_scratchpad.len() - 1)
+    }
+
+    /// The bytes sent in response to READ SCRATCHPAD: the target address, the E/S byte, then the
+    /// data from the target address to the ending offset
+    fn memory_scratchpad_byte(&self, index: usize) -> u8 {
+        let [ta1, ta2] = self.target_address.to_le_bytes();
+        let end = (self.ending_status & 0x1F) as usize;
+        match index {
+            0 => ta1,
+            1 => ta2,
+            2 => self.ending_status,
+            _ => {
+                let offset = self.scratchpad_offset() + index - 3;
+                if offset <= end {
+                    self.memory_scratchpad[offset]
+                } else {
+                    0xFF
+                }
+            }
+        }
+    }
+
+    fn copy_scratchpad(&mut self, authorization: [u8; 3]) -> DeviceState {
+        let [ta1, ta2] = self.target_address.to_le_bytes();
+        if authorization != [ta1, ta2, self.ending_status] || self.ending_status & PARTIAL_FLAG != 0
+        {
+            return DeviceState::Idle;
+        }
+        let start = self.scratchpad_offset();
+        let end = (self.ending_status & 0x1F) as usize;
+        let row = self.target_address as usize - start;
+        for offset in start..=end {
+            if let Some(byte) = self.memory.get_mut(row + offset) {
+                *byte = self.memory_scratchpad[offset];
+            }
+        }
+        self.ending_status |= AUTHORIZATION_ACCEPTED;
+        DeviceState::CopyDone { bit_index: 0 }
+    }
+
+    fn address_bit(&self, bit_index: u8) -> bool {
+        self.address.0 & (1_u64 << bit_index) != 0
+    }
+
+    fn reset(&mut self) {
+        self.state = DeviceState::RomCommand { byte: 0, bits: 0 };
+    }
+
+    fn at_overdrive(&self) -> bool {
+        self.overdrive || self.overdrive_only
+    }
+
+    fn write_1_max_micros(&self) -> u64 {
+        if self.at_overdrive() {
+            OVERDRIVE_WRITE_1_MAX_MICROS
+        } else {
+            WRITE_1_MAX_MICROS
+        }
+    }
+
+    fn hold_micros(&self) -> u64 {
+        if self.at_overdrive() {
+            OVERDRIVE_DEVICE_HOLD_MICROS
+        } else {
+            DEVICE_HOLD_MICROS
+        }
+    }
+
+    /// The bit this device sends during the next time slot, if it is sending
+    fn transmit_bit(&self) -> Option<bool> {
+        match self.state {
+            DeviceState::Search { bit_index, step: 0 } => Some(self.address_bit(bit_index)),
+            DeviceState::Search { bit_index, step: 1 } => Some(!self.address_bit(bit_index)),
+            DeviceState::ReadRom { bit_index } => Some(self.address_bit(bit_index)),
+            DeviceState::ReadScratchpad { bit_index } => self
+                .scratchpad
+                .get(bit_index / 8)
+                .map(|byte| byte & (1 << (bit_index % 8)) != 0),
+            DeviceState::ReadPowerSupply => Some(true),
+            DeviceState::ReadMemory { bit_index } => {
+                Some(self.memory_byte(bit_index / 8) & (1 << (bit_index % 8)) != 0)
+            }
+            DeviceState::ExtendedReadMemory {
+                address,
+                bit_index,
+                first,
+            } => Some(
+                self.extended_read_byte(address, bit_index / 8, first) & (1 << (bit_index % 8))
+                    != 0,
+            ),
+            DeviceState::SegmentEcho { bit_index } => {
+                Some(self.memory_scratchpad[bit_index / 8] & (1 << (bit_index % 8)) == 0)
+            }
+            DeviceState::MemoryScratchpadCrc { crc, bit_index } => {
+                Some(crc & (1 << bit_index) != 0)
+            }
+            DeviceState::ReadMemoryScratchpad { bit_index } => {
+                Some(self.memory_scratchpad_byte(bit_index / 8) & (1 << (bit_index % 8)) != 0)
+            }
+            // 0xAA, least significant bit first
+            DeviceState::CopyDone { bit_index } => Some(bit_index % 2 == 1),
+            DeviceState::CouplerPresence { bit_index, .. } => {
+                Some(bit_index != 0 || !self.coupler.is_some_and(|c| c.branch_present))
+            }
+            DeviceState::CouplerConfirm { command, bit_index } => {
+                Some(command & (1 << bit_index) != 0)
+            }
+            _ => None,
+        }
+    }
+
+    /// Advances the device by one time slot, where `value` is the value the bus had during the slot
+    fn slot(&mut self, value: bool) {
+        self.state = match self.state {
+            DeviceState::Idle => DeviceState::Idle,
+            DeviceState::RomCommand { byte, bits } => match shift_in(byte, bits, value) {
+                Ok(command) => self.rom_command(command),
+                Err((byte, bits)) => DeviceState::RomCommand { byte, bits },
+            },
+            DeviceState::Search { bit_index, step } => {
+                if step < 2 {
+                    DeviceState::Search {
+                        bit_index,
+                        step: step + 1,
+                    }
+                } else if value != self.address_bit(bit_index) {
+                    // The master chose the other branch
+                    DeviceState::Idle
+                } else if bit_index == 63 {
+                    DeviceState::FunctionCommand { byte: 0, bits: 0 }
+                } else {
+                    DeviceState::Search {
+                        bit_index: bit_index + 1,
+                        step: 0,
+                    }
+                }
+            }
+            DeviceState::MatchRom { bit_index, matches } => {
+                let matches = matches && value == self.address_bit(bit_index);
+                if bit_index < 63 {
+                    DeviceState::MatchRom {
+                        bit_index: bit_index + 1,
+                        matches,
+                    }
+                } else if matches {
+                    DeviceState::FunctionCommand { byte: 0, bits: 0 }
+                } else {
+                    DeviceState::Idle
+                }
+            }
+            DeviceState::ReadRom { bit_index } => {
+                if bit_index < 63 {
+                    DeviceState::ReadRom {
+                        bit_index: bit_index + 1,
+                    }
+                } else {
+                    DeviceState::FunctionCommand { byte: 0, bits: 0 }
+                }
+            }
+            DeviceState::FunctionCommand { byte, bits } => match shift_in(byte, bits, value) {
+                Ok(command) => self.function_command(command),
+                Err((byte, bits)) => DeviceState::FunctionCommand { byte, bits },
+            },
+            DeviceState::ReadScratchpad { bit_index } => DeviceState::ReadScratchpad {
+                bit_index: bit_index + 1,
+            },
+            DeviceState::WriteScratchpad { byte, bits, index } => {
+                match shift_in(byte, bits, value) {
+                    Ok(byte) => {
+                        if let Some(scratchpad_byte) = self.scratchpad.get_mut(index) {
+                            *scratchpad_byte = byte;
+                        }
+                        DeviceState::WriteScratchpad {
+                            byte: 0,
+                            bits: 0,
+                            index: index + 1,
+                        }
+                    }
+                    Err((byte, bits)) => DeviceState::WriteScratchpad { byte, bits, index },
+                }
+            }
+            DeviceState::ReadPowerSupply => DeviceState::ReadPowerSupply,
+            DeviceState::MemoryAddress {
+                command,
+                address,
+                bits,
+            } => {
+                let address = (address >> 1) | ((value as u16) << 15);
+                if bits < 15 {
+                    DeviceState::MemoryAddress {
+                        command,
+                        address,
+                        bits: bits + 1,
+                    }
+                } else {
+                    self.memory_command(command, address)
+                }
+            }
+            DeviceState::ReadMemory { bit_index } => DeviceState::ReadMemory {
+                bit_index: bit_index + 1,
+            },
+            DeviceState::ExtendedReadMemory {
+                address,
+                bit_index,
+                first,
+            } => {
+                let page_end = self.page_end(address);
+                if bit_index + 1 < (page_end - address + 2) * 8 {
+                    DeviceState::ExtendedReadMemory {
+                        address,
+                        bit_index: bit_index + 1,
+                        first,
+                    }
+                } else {
+                    DeviceState::ExtendedReadMemory {
+                        address: page_end,
+                        bit_index: 0,
+                        first: false,
+                    }
+                }
+            }
+            DeviceState::WriteSegment { byte, bits, offset } => match shift_in(byte, bits, value) {
+                Ok(byte) => {
+                    self.memory_scratchpad[offset] = byte;
+                    if offset + 1 < self.memory_scratchpad.len() {
+                        DeviceState::WriteSegment {
+                            byte: 0,
+                            bits: 0,
+                            offset: offset + 1,
+                        }
+                    } else {
+                        DeviceState::SegmentEcho { bit_index: 0 }
+                    }
+                }
+                Err((byte, bits)) => DeviceState::WriteSegment { byte, bits, offset },
+            },
+            DeviceState::SegmentEcho { bit_index } => {
+                if bit_index + 1 < self.memory_scratchpad.len() * 8 {
+                    DeviceState::SegmentEcho {
+                        bit_index: bit_index + 1,
+                    }
+                } else {
+                    DeviceState::SegmentRelease { bits: 0 }
+                }
+            }
+            DeviceState::SegmentRelease { bits } => {
+                if bits < 7 {
+                    DeviceState::SegmentRelease { bits: bits + 1 }
+                } else {
+                    let start = self.target_address as usize;
+                    for (offset, byte) in self.memory_scratchpad.iter().enumerate() {
+                        if let Some(memory_byte) = self.memory.get_mut(start + offset) {
+                            *memory_byte = *byte;
+                        }
+                    }
+                    DeviceState::CopyDone { bit_index: 0 }
+                }
+            }
+            DeviceState::WriteMemoryScratchpad { byte, bits, offset } => {
+                match shift_in(byte, bits, value) {
+                    Ok(byte) => {
+                        self.memory_scratchpad[offset] = byte;
+                        self.ending_status = offset as u8;
+                        if offset + 1 < self.memory_scratchpad.len() {
+                            DeviceState::WriteMemoryScratchpad {
+                                byte: 0,
+                                bits: 0,
+                                offset: offset + 1,
+                            }
+                        } else {
+                            let [ta1, ta2] = self.target_address.to_le_bytes();
+                            let mut digest = crc::Crc16Digest::new();
+                            digest.update(&[memory::commands::WRITE_SCRATCHPAD, ta1, ta2]);
+                            digest.update(&self.memory_scratchpad[self.scratchpad_offset()..]);
+                            DeviceState::MemoryScratchpadCrc {
+                                crc: !digest.finalize(),
+                                bit_index: 0,
+                            }
+                        }
+                    }
+                    Err((byte, bits)) => DeviceState::WriteMemoryScratchpad { byte, bits, offset },
+                }
+            }
+            DeviceState::MemoryScratchpadCrc { crc, bit_index } => {
+                if bit_index < 15 {
+                    DeviceState::MemoryScratchpadCrc {
+                        crc,
+                        bit_index: bit_index + 1,
+                    }
+                } else {
+                    DeviceState::Idle
+                }
+            }
+            DeviceState::ReadMemoryScratchpad { bit_index } => DeviceState::ReadMemoryScratchpad {
+                bit_index: bit_index + 1,
+            },
+            DeviceState::CopyScratchpad {
+                authorization,
+                bits,
+            } => {
+                let authorization = authorization | ((value as u32) << bits);
+                if bits < 23 {
+                    DeviceState::CopyScratchpad {
+                        authorization,
+                        bits: bits + 1,
+                    }
+                } else {
+                    let [a, b, c, _] = authorization.to_le_bytes();
+                    self.copy_scratchpad([a, b, c])
+                }
+            }
+            DeviceState::CopyDone { bit_index } => DeviceState::CopyDone {
+                bit_index: bit_index.wrapping_add(1),
+            },
+            DeviceState::CouplerStimulus { branch, bits } => {
+                if bits < 7 {
+                    DeviceState::CouplerStimulus {
+                        branch,
+                        bits: bits + 1,
+                    }
+                } else {
+                    if let Some(coupler) = &mut self.coupler {
+                        coupler.request = Some(CouplerRequest::Presence(branch));
+                    }
+                    DeviceState::CouplerPresence {
+                        branch,
+                        bit_index: 0,
+                    }
+                }
+            }
+            DeviceState::CouplerPresence { branch, bit_index } => {
+                if bit_index < 7 {
+                    DeviceState::CouplerPresence {
+                        branch,
+                        bit_index: bit_index + 1,
+                    }
+                } else {
+                    DeviceState::CouplerConfirm {
+                        command: branch.smart_on_command(),
+                        bit_index: 0,
+                    }
+                }
+            }
+            DeviceState::CouplerConfirm { command, bit_index } => {
+                if bit_index < 7 {
+                    DeviceState::CouplerConfirm {
+                        command,
+                        bit_index: bit_index + 1,
+                    }
+                } else {
+                    self.coupler_command(command);
+                    DeviceState::Idle
+                }
+            }
+        }
+    }
+
+    fn memory_command(&mut self, command: u8, address: u16) -> DeviceState {
+        match command {
+            memory::commands::READ_MEMORY => DeviceState::ReadMemory {
+                bit_index: address as usize * 8,
+            },
+            memory::commands::EXTENDED_READ_MEMORY => DeviceState::ExtendedReadMemory {
+                address: address as usize,
+                bit_index: 0,
+                first: true,
+            },
+            memory::commands::WRITE_MEMORY => {
+                // segments are always written whole
+                self.target_address = address & !(self.memory_scratchpad.len() as u16 - 1);
+                DeviceState::WriteSegment {
+                    byte: 0,
+                    bits: 0,
+                    offset: 0,
+                }
+            }
+            _ => {
+                self.target_address = address;
+                self.ending_status = PARTIAL_FLAG | self.scratchpad_offset() as u8;
+                DeviceState::WriteMemoryScratchpad {
+                    byte: 0,
+                    bits: 0,
+                    offset: self.scratchpad_offset(),
+                }
+            }
+        }
+    }
+
+    fn rom_command(&mut self, command: u8) -> DeviceState {
+        match command {
+            commands::OVERDRIVE_SKIP_ROM if self.overdrive_capable => {
+                self.overdrive = true;
+                DeviceState::FunctionCommand { byte: 0, bits: 0 }
+            }
+            commands::OVERDRIVE_MATCH_ROM if self.overdrive_capable => {
+                self.overdrive = true;
+                DeviceState::MatchRom {
+                    bit_index: 0,
+                    matches: true,
+                }
+            }
+            commands::SEARCH_NORMAL => DeviceState::Search {
+                bit_index: 0,
+                step: 0,
+            },
+            commands::SEARCH_ALARM if self.alarming => DeviceState::Search {
+                bit_index: 0,
+                step: 0,
+            },
+            commands::MATCH_ROM => DeviceState::MatchRom {
+                bit_index: 0,
+                matches: true,
+            },
+            commands::SKIP_ROM => DeviceState::FunctionCommand { byte: 0, bits: 0 },
+            commands::READ_ROM => DeviceState::ReadRom { bit_index: 0 },
+            _ => DeviceState::Idle,
+        }
+    }
+
+    fn function_command(&self, command: u8) -> DeviceState {
+        if self.coupler.is_some() {
+            return match command {
+                SMART_ON_MAIN => DeviceState::CouplerStimulus {
+                    branch: CouplerBranch::Main,
+                    bits: 0,
+                },
+                SMART_ON_AUXILIARY => DeviceState::CouplerStimulus {
+                    branch: CouplerBranch::Auxiliary,
+                    bits: 0,
+                },
+                ALL_LINES_OFF | DISCHARGE_LINES | DIRECT_ON_MAIN => DeviceState::CouplerConfirm {
+                    command,
+                    bit_index: 0,
+                },
+                _ => DeviceState::Idle,
+            };
+        }
+        match command {
+            commands::READ_SCRATCHPAD => DeviceState::ReadScratchpad { bit_index: 0 },
+            commands::WRITE_SCRATCHPAD => DeviceState::WriteScratchpad {
+                byte: 0,
+                bits: 0,
+                index: 0,
+            },
+            commands::READ_POWER_SUPPLY => DeviceState::ReadPowerSupply,
+            _ => self.memory_function_command(command),
+        }
+    }
+
+    fn memory_function_command(&self, command: u8) -> DeviceState {
+        let layout = match self.memory_layout {
+            Some(layout) => layout,
+            None => return DeviceState::Idle,
+        };
+        let address = DeviceState::MemoryAddress {
+            command,
+            address: 0,
+            bits: 0,
+        };
+        match (layout.write_method, command) {
+            (_, memory::commands::READ_MEMORY) => address,
+            (_, memory::commands::EXTENDED_READ_MEMORY) if layout.page_crc => address,
+            (WriteMethod::Scratchpad, memory::commands::WRITE_SCRATCHPAD) => address,
+            (WriteMethod::Scratchpad, memory::commands::READ_SCRATCHPAD) => {
+                DeviceState::ReadMemoryScratchpad { bit_index: 0 }
+            }
+            (WriteMethod::Scratchpad, memory::commands::COPY_SCRATCHPAD) => {
+                DeviceState::CopyScratchpad {
+                    authorization: 0,
+                    bits: 0,
+                }
+            }
+            (WriteMethod::Segments, memory::commands::WRITE_MEMORY) => address,
+            _ => DeviceState::Idle,
+        }
+    }
+}
+
+/// Shifts a bit into a byte (least significant bit first). Returns the byte once all 8 bits are received
+fn shift_in(byte: u8, bits: u8, value: bool) -> Result<u8, (u8, u8)> {
+    let byte = (byte >> 1) | ((value as u8) << 7);
+    if bits == 7 {
+        Ok(byte)
+    } else {
+        Err((byte, bits + 1))
+    }
+}
+
+/// Flips the bit of a time slot with a probability, using a splitmix64 generator so runs are
+/// repeatable
+#[derive(Debug)]
+struct BitFlips {
+    probability: f64,
+    state: u64,
+}
+
+impl BitFlips {
+    fn next(&mut self) -> bool {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1_u64 << 53) as f64) < self.probability
+    }
+}
+
+#[derive(Debug)]
+struct BusState {
+    time: u64,
+    master_low_since: Option<u64>,
+    // devices sending a 0 hold the bus low until this time
+    device_low_until: u64,
+    presence: Option<(u64, u64)>,
+    devices: Vec<VirtualDevice>,
+    stuck_low: bool,
+    bit_flips: Option<BitFlips>,
+    // the current slot is flipped, and the master reads the opposite level until this time
+    flipped_until: Option<u64>,
+    rise_micros: u64,
+    // the bus is still rising until this time, after the master released it
+    rising_until: u64,
+}
+
+impl BusState {
+    /// Whether the device can be reached from the master, through the couplers it's behind
+    fn is_reachable(&self, device: &VirtualDevice) -> bool {
+        let mut connection = device.connection;
+        // there can't be more couplers than devices, unless they're connected in a loop
+        for _ in 0..self.devices.len() {
+            let (address, branch) = match connection {
+                Some(connection) => connection,
+                None => return true,
+            };
+            let coupler = self.devices.iter().find(|coupler| {
+                coupler.address == address && coupler.connected_branch() == Some(branch)
+            });
+            match coupler {
+                Some(coupler) => connection = coupler.connection,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Whether the device is somewhere behind the branch of the coupler with the given address
+    fn is_behind(&self, device: &VirtualDevice, address: Address, branch: CouplerBranch) -> bool {
+        let mut connection = device.connection;
+        for _ in 0..self.devices.len() {
+            match connection {
+                Some(connection) if connection == (address, branch) => return true,
+                Some((coupler, _)) => {
+                    connection = self
+                        .devices
+                        .iter()
+                        .find(|device| device.address == coupler)
+                        .and_then(|device| device.connection);
+                }
+                None => return false,
+            }
+        }
+        false
+    }
+
+    fn reachable(&self) -> Vec<bool> {
+        self.devices
+            .iter()
+            .map(|device| self.is_reachable(device))
+            .collect()
+    }
+
+    /// Does what the couplers asked for during the last slot
+    fn coupler_requests(&mut self) {
+        for index in 0..self.devices.len() {
+            let request = match &mut self.devices[index].coupler {
+                Some(coupler) => coupler.request.take(),
+                None => None,
+            };
+            let address = self.devices[index].address;
+            match request {
+                Some(CouplerRequest::Presence(branch)) => {
+                    let present = self
+                        .devices
+                        .iter()
+                        .any(|device| device.connection == Some((address, branch)));
+                    if let Some(coupler) = &mut self.devices[index].coupler {
+                        coupler.branch_present = present;
+                    }
+                }
+                Some(CouplerRequest::Connect(branch)) => {
+                    if let Some(coupler) = &mut self.devices[index].coupler {
+                        coupler.connected = Some(branch);
+                    }
+                    // the coupler sends a reset pulse on the branch
+                    let reset: Vec<bool> = self
+                        .devices
+                        .iter()
+                        .map(|device| {
+                            self.is_reachable(device) && self.is_behind(device, address, branch)
+                        })
+                        .collect();
+                    for (device, reset) in self.devices.iter_mut().zip(reset) {
+                        if reset {
+                            device.overdrive = false;
+                            device.reset();
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn set_master_low(&mut self) {
+        if self.master_low_since.is_some() {
+            return;
+        }
+        self.master_low_since = Some(self.time);
+        let reachable = self.reachable();
+        // Every time slot starts with the master pulling the bus low, which is when devices start sending
+        let hold_micros = self
+            .devices
+            .iter()
+            .zip(&reachable)
+            .filter(|(device, reachable)| **reachable && device.transmit_bit() == Some(false))
+            .map(|(device, _)| device.hold_micros())
+            .max();
+        if let Some(hold_micros) = hold_micros {
+            self.device_low_until = self.time + hold_micros;
+        }
+        let flipped = self.bit_flips.as_mut().is_some_and(BitFlips::next);
+        self.flipped_until = if flipped {
+            let hold_micros = self
+                .devices
+                .iter()
+                .zip(&reachable)
+                .filter(|(_, reachable)| **reachable)
+                .map(|(device, _)| device.hold_micros())
+                .max()
+                .unwrap_or(DEVICE_HOLD_MICROS);
+            Some(self.time + hold_micros)
+        } else {
+            None
+        };
+    }
+
+    fn release_master(&mut self) {
+        let low_since = match self.master_low_since.take() {
+            Some(low_since) => low_since,
+            None => return,
+        };
+        let low_micros = self.time - low_since;
+        self.rising_until = self.time + self.rise_micros;
+        let reachable = self.reachable();
+        if low_micros >= RESET_MIN_MICROS {
+            let mut present = false;
+            for (device, reachable) in self.devices.iter_mut().zip(&reachable) {
+                if !*reachable {
+                    continue;
+                }
+                if device.overdrive_only {
+                    device.state = DeviceState::Idle;
+                } else {
+                    device.overdrive = false;
+                    device.reset();
+                    present = true;
+                }
+            }
+            if present {
+                self.presence = Some((
+                    self.time + PRESENCE_START_MICROS,
+                    self.time + PRESENCE_END_MICROS,
+                ));
+            }
+            return;
+        }
+
+        let mut overdrive_reset = false;
+        let device_low = self.device_low_until > low_since;
+        let flipped = self.flipped_until.is_some();
+        for (device, reachable) in self.devices.iter_mut().zip(&reachable) {
+            if !*reachable {
+                continue;
+            }
+            if device.at_overdrive() && low_micros >= OVERDRIVE_RESET_MIN_MICROS {
+                device.reset();
+                overdrive_reset = true;
+            } else {
+                let master_bit = low_micros < device.write_1_max_micros();
+                device.slot((master_bit && !device_low) != flipped);
+                if let Some(slots) = &mut device.disconnect_after_slots {
+                    *slots = slots.saturating_sub(1);
+                }
+            }
+        }
+        self.devices
+            .retain(|device| device.disconnect_after_slots != Some(0));
+        self.coupler_requests();
+        if overdrive_reset {
+            self.presence = Some((
+                self.time + OVERDRIVE_PRESENCE_START_MICROS,
+                self.time + OVERDRIVE_PRESENCE_END_MICROS,
+            ));
+        }
+    }
+
+    fn is_high(&self) -> bool {
+        let presence = match self.presence {
+            Some((start, end)) => self.time >= start && self.time < end,
+            None => false,
+        };
+        let high = self.master_low_since.is_none()
+            && self.time >= self.device_low_until
+            && self.time >= self.rising_until
+            && !presence;
+        let flipped = self.master_low_since.is_none()
+            && self.flipped_until.is_some_and(|until| self.time < until);
+        !self.stuck_low && high != flipped
+    }
+}
+
+/// A simulated bus. Use `pin` and `delay` to get the pin and delay to use with `OneWire`.
+#[derive(Debug)]
+pub struct Simulator {
+    state: RefCell<BusState>,
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator {
+            state: RefCell::new(BusState {
+                time: 0,
+                master_low_since: None,
+                device_low_until: 0,
+                presence: None,
+                devices: Vec::new(),
+                stuck_low: false,
+                bit_flips: None,
+                flipped_until: None,
+                rise_micros: 0,
+                rising_until: 0,
+            }),
+        }
+    }
+
+    pub fn add_device(&self, device: VirtualDevice) {
+        self.state.borrow_mut().devices.push(device);
+    }
+
+    /// Removes the device with the given address, returning it if it was on the bus
+    pub fn remove_device(&self, address: &Address) -> Option<VirtualDevice> {
+        let mut state = self.state.borrow_mut();
+        let index = state
+            .devices
+            .iter()
+            .position(|device| device.address == *address)?;
+        Some(state.devices.remove(index))
+    }
+
+    /// Runs `f` with the device with the given address, if it is on the bus
+    pub fn with_device<R>(
+        &self,
+        address: &Address,
+        f: impl FnOnce(&mut VirtualDevice) -> R,
+    ) -> Option<R> {
+        let mut state = self.state.borrow_mut();
+        state
+            .devices
+            .iter_mut()
+            .find(|device| device.address == *address)
+            .map(f)
+    }
+
+    /// Holds the bus low (like a short to ground, or a device stuck sending a 0) until this is
+    /// called again with `false`
+    pub fn set_stuck_low(&self, stuck: bool) {
+        self.state.borrow_mut().stuck_low = stuck;
+    }
+
+    /// Keeps the bus low for `micros` after the master releases it, like a bus with a lot of
+    /// capacitance that the pull-up takes a while to charge
+    pub fn set_rise_micros(&self, micros: u64) {
+        self.state.borrow_mut().rise_micros = micros;
+    }
+
+    /// Flips the bit of each time slot with the given probability, both the bit the devices receive
+    /// and the level the master samples. The flips are pseudo-random from `seed`, so runs are still
+    /// deterministic. A probability of 0 disables them
+    pub fn set_bit_flips(&self, probability: f64, seed: u64) {
+        self.state.borrow_mut().bit_flips = if probability > 0.0 {
+            Some(BitFlips {
+                probability,
+                state: seed,
+            })
+        } else {
+            None
+        };
+    }
+
+    /// The total time that has passed on the bus, according to the delay
+    pub fn time_micros(&self) -> u64 {
+        self.state.borrow().time
+    }
+
+    pub fn pin(&self) -> SimulatedPin<'_> {
+        SimulatedPin { simulator: self }
+    }
+
+    pub fn delay(&self) -> SimulatedDelay<'_> {
+        SimulatedDelay { simulator: self }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::new()
+    }
+}
+
+/// The pin connected to a simulated bus
+pub struct SimulatedPin<'a> {
+    simulator: &'a Simulator,
+}
+
+impl<'a> OutputPin for SimulatedPin<'a> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().set_master_low();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().release_master();
+        Ok(())
+    }
+}
+
+impl<'a> InputPin for SimulatedPin<'a> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.simulator.state.borrow().is_high())
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!self.simulator.state.borrow().is_high())
+    }
+}
+
+/// A delay that advances the time of a simulated bus
+pub struct SimulatedDelay<'a> {
+    simulator: &'a Simulator,
+}
+
+impl<'a> DelayUs<u32> for SimulatedDelay<'a> {
+    fn delay_us(&mut self, us: u32) {
+        self.simulator.state.borrow_mut().time += us as u64;
+    }
+}
+
+impl<'a> DelayUs<u16> for SimulatedDelay<'a> {
+    fn delay_us(&mut self, us: u16) {
+        DelayUs::<u32>::delay_us(self, us as u32);
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> embedded_hal_1::digital::ErrorType for SimulatedPin<'a> {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "async")]
+impl<'a> embedded_hal_1::digital::OutputPin for SimulatedPin<'a> {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        OutputPin::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        OutputPin::set_high(self)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> embedded_hal_1::digital::InputPin for SimulatedPin<'a> {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        InputPin::is_high(self)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        InputPin::is_low(self)
+    }
+}
+
+/// Time only moves forward when a delay is used, so waiting for the bus only completes while a
+/// delay (such as a timeout) is running at the same time
+#[cfg(feature = "async")]
+impl<'a> embedded_hal_async::digital::Wait for SimulatedPin<'a> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(false).await?;
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(true).await?;
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        let level = self.simulator.state.borrow().is_high();
+        self.wait_for_level(!level).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> SimulatedPin<'a> {
+    async fn wait_for_level(&mut self, high: bool) -> Result<(), Infallible> {
+        core::future::poll_fn(|cx| {
+            if self.simulator.state.borrow().is_high() == high {
+                core::task::Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                core::task::Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Advances the time by 1 µs each time it's polled, so other futures can see the bus change
+#[cfg(feature = "async")]
+impl<'a> embedded_hal_async::delay::DelayNs for SimulatedDelay<'a> {
+    async fn delay_ns(&mut self, ns: u32) {
+        let end = self.simulator.time_micros() + (ns as u64).div_ceil(1000);
+        core::future::poll_fn(|cx| {
+            let mut state = self.simulator.state.borrow_mut();
+            if state.time >= end {
+                core::task::Poll::Ready(())
+            } else {
+                state.time += 1;
+                cx.waker().wake_by_ref();
+                core::task::Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Runs a future to completion, for testing the async bus with the simulator
+#[cfg(all(test, feature = "async"))]
+pub(crate) fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    use core::task::{RawWaker, RawWakerVTable, Waker};
+
+    // The simulated delays wake themselves, so polling in a loop is enough
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(core::ptr::null(), &VTABLE);
+    let waker = unsafe { Waker::from_raw(RAW) };
+    let mut future = core::pin::pin!(future);
+    let mut context = core::task::Context::from_waker(&waker);
+    loop {
+        if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::commands::Addressing;
+    use crate::search::SearchOrder;
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::timing::Timings;
+    use crate::{
+        commands, crc, Address, OneWire, OneWireBus, OneWireError, Operation, OperationContext,
+        ResetResult, SearchState, Speed,
+    };
+    use core::time::Duration;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_reset() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(false));
+
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        assert_eq!(
+            one_wire.reset_detailed(&mut delay),
+            Ok(ResetResult::DevicesPresent)
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let simulator = Simulator::new();
+        let mut addresses = vec![
+            Address::for_test(0x28, 0x0B1FCD10),
+            Address::for_test(0x28, 0x08AC8516),
+            Address::for_test(0x10, 0x0B20687E),
+            Address::for_test(0x28, 0x0B2015FF),
+            Address::for_test(0x28, 0x0B2015FE),
+        ];
+        for address in &addresses {
+            simulator.add_device(VirtualDevice::new(*address));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let found: Vec<_> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+
+        // addresses are found in order of their bits, starting from the least significant bit
+        addresses.sort_by_key(|address| address.0.reverse_bits());
+        assert_eq!(found, addresses);
+    }
+
+    #[test]
+    fn test_overdrive_search() {
+        let simulator = Simulator::new();
+        let overdrive = [Address::for_test(0x43, 1), Address::for_test(0x43, 2)];
+        for address in &overdrive {
+            let mut device = VirtualDevice::new(*address);
+            device.overdrive_capable = true;
+            simulator.add_device(device);
+        }
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 3)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut found = Vec::new();
+        let mut state = None;
+        while let Some((address, next_state)) = one_wire
+            .device_search_overdrive(state.as_ref(), false, &mut delay)
+            .unwrap()
+        {
+            found.push(address);
+            state = Some(next_state);
+        }
+        found.sort();
+        assert_eq!(found, overdrive);
+
+        // the standard speed reset returns all devices to standard speed
+        let found: Vec<_> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(found.len(), 3);
+    }
+
+    #[test]
+    fn test_overdrive_addressing() {
+        let simulator = Simulator::new();
+        let addresses = [Address::for_test(0x43, 1), Address::for_test(0x43, 2)];
+        for (i, address) in addresses.iter().enumerate() {
+            let mut device = VirtualDevice::new(*address);
+            device.overdrive_capable = true;
+            device.scratchpad = vec![i as u8, 0x55];
+            device.scratchpad.push(crc::crc8(&device.scratchpad));
+            simulator.add_device(device);
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut scratchpad = [0; 3];
+        one_wire
+            .send_command_with(
+                commands::READ_SCRATCHPAD,
+                Addressing::OverdriveMatch(&addresses[1]),
+                &mut delay,
+            )
+            .unwrap();
+        assert_eq!(one_wire.speed(), Speed::Overdrive);
+        one_wire.read_bytes(&mut scratchpad, &mut delay).unwrap();
+        assert_eq!(scratchpad[..2], [1, 0x55]);
+        crc::check_crc8::<()>(&scratchpad).unwrap();
+
+        // the standard speed reset of the next command returns both devices to standard speed
+        one_wire
+            .read_scratchpad(Some(&addresses[0]), &mut scratchpad, &mut delay)
+            .unwrap();
+        assert_eq!(scratchpad[..2], [0, 0x55]);
+        assert_eq!(one_wire.speed(), Speed::Standard);
+        assert_eq!(one_wire.timings(), Timings::STANDARD);
+
+        simulator.remove_device(&addresses[0]);
+        one_wire
+            .send_command_with(
+                commands::READ_SCRATCHPAD,
+                Addressing::OverdriveSkip,
+                &mut delay,
+            )
+            .unwrap();
+        one_wire.read_bytes(&mut scratchpad, &mut delay).unwrap();
+        assert_eq!(scratchpad[..2], [1, 0x55]);
+    }
+
+    #[test]
+    fn test_resume_search() {
+        let simulator = Simulator::new();
+        for serial_number in 1..=3 {
+            simulator.add_device(VirtualDevice::new(Address::for_test(0x28, serial_number)));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let (first, state) = one_wire
+            .device_search(None, false, &mut delay)
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.address(), first);
+        let state = SearchState::from_bytes(&state.to_bytes()).unwrap();
+        let (second, state) = one_wire
+            .device_search(Some(&state), false, &mut delay)
+            .unwrap()
+            .unwrap();
+        let (third, state) = one_wire
+            .device_search(Some(&state), false, &mut delay)
+            .unwrap()
+            .unwrap();
+        assert!(state.is_finished());
+
+        let mut expected = vec![
+            Address::for_test(0x28, 1),
+            Address::for_test(0x28, 2),
+            Address::for_test(0x28, 3),
+        ];
+        expected.sort_by_key(|address| address.0.reverse_bits());
+        assert_eq!(vec![first, second, third], expected);
+
+        let mut invalid = state.to_bytes();
+        invalid[8] = 0x01;
+        invalid[16] = 5;
+        assert_eq!(SearchState::from_bytes(&invalid), None);
+    }
+
+    #[test]
+    fn test_devices_from() {
+        let simulator = Simulator::new();
+        for serial_number in 1..=3 {
+            simulator.add_device(VirtualDevice::new(Address::for_test(0x28, serial_number)));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut search = one_wire.devices(false, &mut delay);
+        let mut found = vec![search.next().unwrap().unwrap()];
+        let state = search.into_state();
+
+        // other traffic on the bus doesn't affect the search
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+
+        let mut search = one_wire.devices_from(state, false, &mut delay);
+        found.extend(search.by_ref().map(|address| address.unwrap()));
+        let state = search.into_state();
+        assert_eq!(found.len(), 3);
+        assert_eq!(one_wire.devices_from(state, false, &mut delay).count(), 0);
+    }
+
+    #[test]
+    fn test_failed_operation() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = vec![1, 2, 3];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // the address has a bad crc
+        let error = one_wire
+            .device_search(None, false, &mut delay)
+            .during(one_wire.last_failed_operation().unwrap())
+            .unwrap_err();
+        assert_eq!(error.operation, Operation::Search);
+        assert!(matches!(error.error, OneWireError::CrcMismatch { .. }));
+
+        let mut scratchpad = [0; 3];
+        assert!(one_wire
+            .read_scratchpad(None, &mut scratchpad, &mut delay)
+            .is_err());
+        assert_eq!(one_wire.last_failed_operation(), Some(Operation::Read));
+
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        assert_eq!(one_wire.last_failed_operation(), None);
+        simulator.set_stuck_low(true);
+        assert_eq!(
+            one_wire.send_command(commands::READ_SCRATCHPAD, Some(&Address(0x28)), &mut delay),
+            Err(OneWireError::BusNotHigh)
+        );
+        assert_eq!(one_wire.last_failed_operation(), Some(Operation::Reset));
+    }
+
+    #[test]
+    fn test_descending_search() {
+        let simulator = Simulator::new();
+        for serial_number in 0..8 {
+            simulator.add_device(VirtualDevice::new(Address::for_test(0x28, serial_number)));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let mut all: Vec<Address> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+
+        // continued part way through with the same order
+        let mut search = one_wire
+            .devices(false, &mut delay)
+            .order(SearchOrder::Descending);
+        let mut found = vec![search.next().unwrap().unwrap()];
+        let state = search.into_state();
+        found.extend(
+            one_wire
+                .devices_from(state, false, &mut delay)
+                .order(SearchOrder::Descending)
+                .map(|address| address.unwrap()),
+        );
+        all.reverse();
+        assert_eq!(found, all);
+    }
+
+    #[test]
+    fn test_search_size_hint() {
+        let simulator = Simulator::new();
+        for serial_number in 0..4 {
+            simulator.add_device(VirtualDevice::new(Address::for_test(0x28, serial_number)));
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut search = one_wire.devices(false, &mut delay);
+        assert_eq!(search.size_hint(), (0, None));
+        search.next().unwrap().unwrap();
+        assert_eq!(search.size_hint(), (1, None));
+        search.next().unwrap().unwrap();
+        search.next().unwrap().unwrap();
+        assert_eq!(search.size_hint(), (1, None));
+        search.next().unwrap().unwrap();
+        // the last device was found, so the search is known to be over before trying again
+        assert_eq!(search.size_hint(), (0, Some(0)));
+        assert_eq!(search.size_hint(), (0, Some(0)));
+        assert!(search.next().is_none());
+
+        // there are still untaken branches when the search fails, but only the error is returned
+        let mut search = one_wire.devices(false, &mut delay);
+        search.next().unwrap().unwrap();
+        assert_eq!(search.size_hint(), (1, None));
+        simulator.set_stuck_low(true);
+        assert_eq!(search.by_ref().count(), 1);
+        assert_eq!(search.size_hint(), (0, Some(0)));
+        simulator.set_stuck_low(false);
+
+        // the next device found might not be in the family
+        let mut search = one_wire.devices_of_family(0x28, false, &mut delay);
+        search.next().unwrap().unwrap();
+        assert_eq!(search.size_hint(), (0, None));
+    }
+
+    #[test]
+    fn test_majority_reads() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 2)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_majority_reads(true);
+        let found: Vec<_> = one_wire
+            .devices(false, &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(
+            found,
+            vec![Address::for_test(0x28, 2), Address::for_test(0x28, 1)]
+        );
+    }
+
+    #[test]
+    fn test_touch_byte() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address::for_test(0x28, 1));
+        device.scratchpad = vec![0b1010_0110];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire
+            .send_command(commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        // only the bits sent as 1 are read, and the byte gets the same recovery time as any other
+        one_wire.set_timings(Timings {
+            byte_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+        let start = simulator.time_micros();
+        assert_eq!(
+            one_wire.touch_byte(0b0000_1111, &mut delay),
+            Ok(0b0000_0110)
+        );
+        let slot = Timings::STANDARD.read_slot().as_micros() as u64;
+        assert_eq!(simulator.time_micros() - start, 8 * slot + 100);
+        assert_eq!(one_wire.touch_bit(true, &mut delay), Ok(true));
+        #[cfg(feature = "stats")]
+        assert_eq!(one_wire.stats().bytes_written, 3);
+    }
+
+    #[test]
+    fn test_poll_until_high() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        assert_eq!(one_wire.poll_until_high(1_000, &mut delay), Ok(70));
+
+        // the slots are counted at the bus's timings, so the time limit holds at overdrive
+        one_wire.set_speed(Speed::Overdrive);
+        assert_eq!(one_wire.poll_until_high(1_000, &mut delay), Ok(9));
+        simulator.set_stuck_low(true);
+        let start = simulator.time_micros();
+        assert_eq!(
+            one_wire.poll_until_high(1_000, &mut delay),
+            Err(OneWireError::Timeout(1_000))
+        );
+        let elapsed = simulator.time_micros() - start;
+        assert!(elapsed > 990 && elapsed <= 1_000, "{}", elapsed);
+    }
+
+    #[test]
+    fn test_devices_filtered() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        simulator.add_device(VirtualDevice {
+            alarming: true,
+            ..VirtualDevice::new(Address::for_test(0x28, 2))
+        });
+        simulator.add_device(VirtualDevice {
+            alarming: true,
+            ..VirtualDevice::new(Address::for_test(0x3A, 1))
+        });
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let found: Vec<_> = one_wire
+            .devices_filtered(true, Some(0x28), &mut delay)
+            .map(|address| address.unwrap())
+            .collect();
+        assert_eq!(found, vec![Address::for_test(0x28, 2)]);
+        let found = one_wire
+            .devices_filtered(false, Some(0x3A), &mut delay)
+            .count();
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn test_alarm_search() {
+        let simulator = Simulator::new();
+        let alarming = Address::for_test(0x28, 2);
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        simulator.add_device(VirtualDevice {
+            alarming: true,
+            ..VirtualDevice::new(alarming)
+        });
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let found: Vec<_> = one_wire.devices(true, &mut delay).collect();
+        assert_eq!(found, vec![Ok(alarming)]);
+    }
+
+    #[test]
+    fn test_scratchpad() {
+        let simulator = Simulator::new();
+        let first = Address::for_test(0x28, 1);
+        let second = Address::for_test(0x28, 2);
+        let scratchpad = vec![0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0x00];
+        let crc = crc::crc8(&scratchpad[..8]);
+        for address in &[first, second] {
+            simulator.add_device(VirtualDevice {
+                scratchpad: scratchpad.clone(),
+                ..VirtualDevice::new(*address)
+            });
+        }
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        one_wire
+            .write_scratchpad(Some(&second), &[1, 2, 3, 4, 5, 6, 7, 8], &mut delay)
+            .unwrap();
+        assert_eq!(
+            simulator.with_device(&first, |device| device.scratchpad[0]),
+            Some(0x91)
+        );
+        assert_eq!(
+            simulator.with_device(&second, |device| device.scratchpad[..8].to_vec()),
+            Some(vec![1, 2, 3, 4, 5, 6, 7, 8])
+        );
+
+        simulator.with_device(&first, |device| device.scratchpad[8] = crc);
+        let mut output = [0; 9];
+        one_wire
+            .read_scratchpad(Some(&first), &mut output, &mut delay)
+            .unwrap();
+        assert_eq!(output[..8], scratchpad[..8]);
+
+        // the second device's scratchpad now has a bad crc
+        assert!(one_wire
+            .read_scratchpad(Some(&second), &mut output, &mut delay)
+            .is_err());
+    }
+
+    #[test]
+    fn test_stuck_low() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address::for_test(0x28, 1)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        simulator.set_stuck_low(true);
+        assert_eq!(one_wire.reset(&mut delay), Err(OneWireError::BusNotHigh));
+        assert_eq!(
+            one_wire.devices(false, &mut delay).next(),
+            Some(Err(OneWireError::BusNotHigh))
+        );
+
+        simulator.set_stuck_low(false);
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+    }
+
+    #[test]
+    fn test_bit_flips() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address::for_test(0x28, 1));
+        device.scratchpad = std::vec![1, 2, 3, crc::crc8(&[1, 2, 3])];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        // about one in 200 slots is flipped, so most reads still succeed
+        simulator.set_bit_flips(0.005, 1);
+        let mut ok = 0;
+        let mut crc_mismatches = 0;
+        for _ in 0..100 {
+            let mut output = [0; 4];
+            match one_wire.read_scratchpad(None, &mut output, &mut delay) {
+                Ok(()) => {
+                    assert_eq!(output, [1, 2, 3, crc::crc8(&[1, 2, 3])]);
+                    ok += 1;
+                }
+                Err(OneWireError::CrcMismatch { .. }) => crc_mismatches += 1,
+                Err(err) => panic!("unexpected error: {:?}", err),
+            }
+        }
+        assert!(ok > 50, "{} reads succeeded", ok);
+        assert!(crc_mismatches > 0);
+
+        simulator.set_bit_flips(0.0, 0);
+        let mut output = [0; 4];
+        assert_eq!(
+            one_wire.read_scratchpad(None, &mut output, &mut delay),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_disconnect_mid_search() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address::for_test(0x28, 1));
+        // the 8 slots of SEARCH ROM, then 10 bits of the search (3 slots each)
+        device.disconnect_after_slots = Some(8 + 30);
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        assert_eq!(
+            one_wire.device_search(None, false, &mut delay),
+            Err(OneWireError::UnexpectedResponse)
+        );
+        assert_eq!(one_wire.reset(&mut delay), Ok(false));
+    }
+
+    /// Searches random sets of devices, and checks exactly those devices are found, in order. Set
+    /// `PROPTEST_CASES` to change the number of sets (64 by default). A failing case prints its
+    /// addresses, so it can be copied into a test
+    #[test]
+    fn test_random_searches() {
+        let cases = std::env::var("PROPTEST_CASES")
+            .ok()
+            .and_then(|cases| cases.parse().ok())
+            .unwrap_or(64_u32);
+        let mut seed = 0x9E37_79B9_7F4A_7C15_u64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for case in 0..cases {
+            let count = 1 + random() % 20;
+            // a few families, so devices share long prefixes
+            let mut devices: Vec<(Address, bool)> = (0..count)
+                .map(|_| {
+                    let family = [0x10, 0x28, 0x3A, random() as u8][random() as usize % 4];
+                    let address = Address::for_test(family, random() >> 16);
+                    (address, random() % 3 == 0)
+                })
+                .collect();
+            devices.sort_by_key(|(address, _)| address.0.reverse_bits());
+            devices.dedup_by_key(|(address, _)| *address);
+
+            let simulator = Simulator::new();
+            for (address, alarming) in &devices {
+                let mut device = VirtualDevice::new(*address);
+                device.alarming = *alarming;
+                simulator.add_device(device);
+            }
+            let mut delay = simulator.delay();
+            let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+            let mut expected: Vec<Address> = devices.iter().map(|(address, _)| *address).collect();
+            let found: Vec<Address> = one_wire
+                .devices(false, &mut delay)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(found, expected, "case {}: {:X?}", case, devices);
+
+            expected.reverse();
+            let found: Vec<Address> = one_wire
+                .devices(false, &mut delay)
+                .order(SearchOrder::Descending)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(found, expected, "case {}: {:X?}", case, devices);
+
+            let alarming: Vec<Address> = devices
+                .iter()
+                .filter(|(_, alarming)| *alarming)
+                .map(|(address, _)| *address)
+                .collect();
+            // with no alarming devices, nothing answers the alarm search after the reset, which is
+            // reported as an unexpected response
+            if alarming.is_empty() {
+                continue;
+            }
+            let found: Vec<Address> = one_wire
+                .devices(true, &mut delay)
+                .map(|address| address.unwrap())
+                .collect();
+            assert_eq!(found, alarming, "case {}: {:X?}", case, devices);
+        }
+    }
+}
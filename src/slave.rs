@@ -0,0 +1,406 @@
+use crate::delay::Delay;
+use crate::{commands, Address, OneWireError, OneWireResult};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// How often the bus is sampled while measuring how long it is held low
+const POLL_MICROS: u16 = 5;
+
+/// Masters hold the bus low for at least 480 µs during a reset. Anything held low this long is
+/// treated as a reset (some margin is given, since polling is not very precise)
+const RESET_MIN_MICROS: u16 = 400;
+
+/// Emulates a 1-Wire device on a pin. The pin must be configured the same way as for `OneWire`
+/// (open-drain, with a pull-up resistor on the bus).
+///
+/// This responds to resets with a presence pulse, takes part in device searches, and answers
+/// MATCH ROM / SKIP ROM / READ ROM. Function commands sent to this device are returned from `listen`,
+/// and can be answered with the read/write methods.
+///
+/// All timing is done by polling the pin, so this needs to be called often enough (with interrupts
+/// disabled, or on a fast enough MCU) to see the start of each time slot. Time slots can't be missed,
+/// so this should generally only be used for testing, or for simple MCU to MCU links. Waiting for
+/// the master never ends by itself, unless a timeout is set with `set_timeout`.
+pub struct OneWireSlave<T> {
+    pin: T,
+    address: Address,
+    alarming: bool,
+
+    // set when a reset was seen while waiting for a time slot, and the presence pulse was already sent
+    reset_received: bool,
+
+    timeout_micros: Option<u32>,
+}
+
+impl<T, E> OneWireSlave<T>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+{
+    pub fn new(pin: T, address: Address) -> OneWireResult<OneWireSlave<T>, E> {
+        let mut slave = OneWireSlave {
+            pin,
+            address,
+            alarming: false,
+            reset_received: false,
+            timeout_micros: None,
+        };
+        // Pin should be high during idle.
+        slave.release_bus()?;
+        Ok(slave)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.pin
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Sets whether this device takes part in alarm searches
+    pub fn set_alarming(&mut self, alarming: bool) {
+        self.alarming = alarming;
+    }
+
+    /// Sets how long to wait for the master (for the next time slot or reset, or for the bus to be
+    /// released) before giving up with `OneWireError::Timeout`. The default of `None` waits forever
+    pub fn set_timeout(&mut self, micros: Option<u32>) {
+        self.timeout_micros = micros;
+    }
+
+    fn release_bus(&mut self) -> OneWireResult<(), E> {
+        Ok(self.pin.set_high()?)
+    }
+
+    fn set_bus_low(&mut self) -> OneWireResult<(), E> {
+        Ok(self.pin.set_low()?)
+    }
+
+    fn is_bus_low(&self) -> OneWireResult<bool, E> {
+        Ok(self.pin.is_low()?)
+    }
+
+    /// Returns `OneWireError::Timeout` once `waited_micros` is past the timeout
+    fn check_timeout(&self, waited_micros: u32) -> OneWireResult<(), E> {
+        match self.timeout_micros {
+            Some(timeout) if waited_micros >= timeout => Err(OneWireError::Timeout(timeout)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for the master to pull the bus low, which starts every time slot
+    fn wait_for_low(&self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        let mut waited_micros: u32 = 0;
+        while !self.is_bus_low()? {
+            self.check_timeout(waited_micros)?;
+            delay.delay_us(POLL_MICROS);
+            waited_micros = waited_micros.saturating_add(POLL_MICROS as u32);
+        }
+        Ok(())
+    }
+
+    /// Waits for the master to release the bus. Returns true if it was held low long enough to be
+    /// a reset, in which case the presence pulse has already been sent
+    fn wait_for_release(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        let mut low_micros: u32 = 0;
+        while self.is_bus_low()? {
+            self.check_timeout(low_micros)?;
+            delay.delay_us(POLL_MICROS);
+            low_micros = low_micros.saturating_add(POLL_MICROS as u32);
+        }
+        if low_micros >= RESET_MIN_MICROS as u32 {
+            self.send_presence(delay)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn send_presence(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        delay.delay_us(30); // devices wait 15-60 µs before the presence pulse
+        self.set_bus_low()?;
+        delay.delay_us(120); // presence pulse is 60-240 µs
+        self.release_bus()
+    }
+
+    /// Waits for the end of a time slot. A reset in the middle of a transaction is answered with
+    /// a presence pulse, and then ends the current transaction with an error
+    fn end_slot(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        if self.wait_for_release(delay)? {
+            self.reset_received = true;
+            return Err(OneWireError::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    /// Waits for a reset pulse (unless one was already received), responds with a presence pulse,
+    /// then handles the ROM command.
+    ///
+    /// Returns the function command if this device was selected, or `None` if the master selected a
+    /// different device, or only searched. Any data needed by the function command should be
+    /// read / written before calling this again.
+    pub fn listen(&mut self, delay: &mut impl Delay) -> OneWireResult<Option<u8>, E> {
+        while !self.reset_received {
+            self.wait_for_low(delay)?;
+            self.reset_received = self.wait_for_release(delay)?;
+        }
+        self.reset_received = false;
+
+        match self.read_byte(delay)? {
+            commands::SEARCH_NORMAL => {
+                self.search(delay)?;
+                Ok(None)
+            }
+            commands::SEARCH_ALARM => {
+                if self.alarming {
+                    self.search(delay)?;
+                }
+                Ok(None)
+            }
+            commands::MATCH_ROM => {
+                let mut address = [0; 8];
+                self.read_bytes(&mut address, delay)?;
+                if Address::from_bytes(address) == self.address {
+                    Ok(Some(self.read_byte(delay)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            commands::SKIP_ROM => Ok(Some(self.read_byte(delay)?)),
+            commands::READ_ROM => {
+                self.write_bytes(&self.address.to_bytes(), delay)?;
+                Ok(Some(self.read_byte(delay)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Takes part in a device search, until the master chooses a branch that this device isn't on
+    fn search(&mut self, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        for bit_index in 0..64 {
+            let bit = self.address.0 & (1_u64 << bit_index) != 0;
+            self.write_bit(bit, delay)?;
+            self.write_bit(!bit, delay)?;
+            if self.read_bit(delay)? != bit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a bit written by the master
+    pub fn read_bit(&mut self, delay: &mut impl Delay) -> OneWireResult<bool, E> {
+        self.wait_for_low(delay)?;
+        // The master releases the bus within 15 µs to write a 1, or holds it low for at least 60 µs for a 0
+        delay.delay_us(30);
+        let bit_value = !self.is_bus_low()?;
+        self.end_slot(delay)?;
+        Ok(bit_value)
+    }
+
+    pub fn read_byte(&mut self, delay: &mut impl Delay) -> OneWireResult<u8, E> {
+        let mut output: u8 = 0;
+        for _ in 0..8 {
+            output >>= 1;
+            if self.read_bit(delay)? {
+                output |= 0x80;
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn read_bytes(
+        &mut self,
+        output: &mut [u8],
+        delay: &mut impl Delay,
+    ) -> OneWireResult<(), E> {
+        for byte in output.iter_mut() {
+            *byte = self.read_byte(delay)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a bit during the next read slot from the master
+    pub fn write_bit(&mut self, value: bool, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        self.wait_for_low(delay)?;
+        if !value {
+            // hold the bus low past the point where the master samples it (15 µs)
+            self.set_bus_low()?;
+            delay.delay_us(30);
+            self.release_bus()?;
+        }
+        self.end_slot(delay)
+    }
+
+    pub fn write_byte(&mut self, mut value: u8, delay: &mut impl Delay) -> OneWireResult<(), E> {
+        for _ in 0..8 {
+            self.write_bit(value & 0x01 == 0x01, delay)?;
+            value >>= 1;
+        }
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8], delay: &mut impl Delay) -> OneWireResult<(), E> {
+        for byte in bytes {
+            self.write_byte(*byte, delay)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::{Address, OneWire, OneWireError, OneWireSlave};
+    use core::convert::Infallible;
+    use embedded_hal::blocking::delay::DelayUs;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::vec::Vec;
+
+    /// One end of the wire: its own clock, and every time it started or stopped pulling the bus low
+    #[derive(Default)]
+    struct End {
+        time: u64,
+        changes: Vec<(u64, bool)>,
+    }
+
+    impl End {
+        fn is_low_at(&self, time: u64) -> bool {
+            self.changes
+                .iter()
+                .rev()
+                .find(|(changed, _)| *changed <= time)
+                .is_some_and(|(_, low)| *low)
+        }
+    }
+
+    /// A wire between two threads (the master and the slave), each with its own simulated time. A
+    /// read waits until the other end has caught up, so it sees what the other end was doing at
+    /// the same time
+    #[derive(Default)]
+    struct Wire {
+        ends: Mutex<[End; 2]>,
+        advanced: Condvar,
+    }
+
+    #[derive(Clone)]
+    struct WirePin {
+        wire: Arc<Wire>,
+        end: usize,
+    }
+
+    impl WirePin {
+        fn set(&self, low: bool) {
+            let mut ends = self.wire.ends.lock().unwrap();
+            let time = ends[self.end].time;
+            ends[self.end].changes.push((time, low));
+        }
+
+        /// Lets the other end run past everything this end does from now on
+        fn finish(&self) {
+            self.set(false);
+            self.wire.ends.lock().unwrap()[self.end].time = u64::MAX;
+            self.wire.advanced.notify_all();
+        }
+    }
+
+    impl OutputPin for WirePin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.set(true);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.set(false);
+            Ok(())
+        }
+    }
+
+    impl InputPin for WirePin {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            self.is_low().map(|low| !low)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            let mut ends = self.wire.ends.lock().unwrap();
+            let time = ends[self.end].time;
+            while ends[1 - self.end].time < time {
+                ends = self.wire.advanced.wait(ends).unwrap();
+            }
+            Ok(ends.iter().any(|end| end.is_low_at(time)))
+        }
+    }
+
+    impl DelayUs<u32> for WirePin {
+        fn delay_us(&mut self, us: u32) {
+            self.wire.ends.lock().unwrap()[self.end].time += us as u64;
+            self.wire.advanced.notify_all();
+        }
+    }
+
+    #[test]
+    fn test_slave() {
+        let address = Address::for_test(0x28, 1);
+        let wire = Arc::new(Wire::default());
+        let slave_pin = WirePin {
+            wire: wire.clone(),
+            end: 1,
+        };
+        let slave = std::thread::spawn(move || {
+            let mut delay = slave_pin.clone();
+            let mut slave = OneWireSlave::new(slave_pin.clone(), address).unwrap();
+            slave.set_timeout(Some(10_000));
+            let mut commands = Vec::new();
+            let result = loop {
+                match slave.listen(&mut delay) {
+                    Ok(Some(command)) => {
+                        commands.push(command);
+                        if command == 0xBE {
+                            slave.write_bytes(&[0x12, 0x34], &mut delay).unwrap();
+                        }
+                    }
+                    // a reset without a ROM command, which the slave sees in the middle of a byte
+                    Ok(None) | Err(OneWireError::UnexpectedResponse) => {}
+                    Err(err) => break err,
+                }
+            };
+            slave_pin.finish();
+            (commands, result)
+        });
+
+        let pin = WirePin { wire, end: 0 };
+        let mut delay = pin.clone();
+        let mut one_wire = OneWire::new(pin.clone()).unwrap();
+        assert_eq!(one_wire.reset(&mut delay), Ok(true));
+        let (found, state) = one_wire
+            .device_search(None, false, &mut delay)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found, address);
+        assert!(state.is_finished());
+
+        let mut data = [0; 2];
+        one_wire
+            .send_command(0xBE, Some(&address), &mut delay)
+            .unwrap();
+        one_wire.read_bytes(&mut data, &mut delay).unwrap();
+        assert_eq!(data, [0x12, 0x34]);
+        // another device is addressed, so the slave doesn't answer
+        one_wire
+            .send_command(0xBE, Some(&Address::for_test(0x28, 2)), &mut delay)
+            .unwrap();
+        one_wire.read_bytes(&mut data, &mut delay).unwrap();
+        assert_eq!(data, [0xFF, 0xFF]);
+        one_wire.send_command(0x44, None, &mut delay).unwrap();
+        pin.finish();
+
+        let (commands, err) = slave.join().unwrap();
+        assert_eq!(commands, [0xBE, 0x44]);
+        assert_eq!(err, OneWireError::Timeout(10_000));
+    }
+}
@@ -0,0 +1,69 @@
+//! Operation counters, enabled with the `stats` feature.
+
+/// Increments a counter in the stats of a bus. Does nothing if the `stats` feature is disabled
+macro_rules! count {
+    ($one_wire:expr, $counter:ident) => {
+        #[cfg(feature = "stats")]
+        {
+            $one_wire.stats.$counter = $one_wire.stats.$counter.wrapping_add(1);
+        }
+    };
+}
+
+/// Counts of operations on a bus since it was created (or since `OneWire::reset_stats`).
+/// All counters wrap around on overflow.
+#[cfg(feature = "stats")]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub resets: u32,
+    pub bytes_read: u32,
+    pub bytes_written: u32,
+
+    /// CRC failures detected by `OneWire` itself (device searches, `read_scratchpad`, and the memory
+    /// helpers). CRCs checked by device drivers are not included
+    pub crc_failures: u32,
+
+    /// The number of device searches started from the beginning (without a search state)
+    pub search_restarts: u32,
+
+    /// The number of overdrive transactions that failed and were retried at standard speed
+    pub overdrive_fallbacks: u32,
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire, Stats};
+
+    #[test]
+    fn test_stats() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut output = [0; 9];
+        assert!(one_wire
+            .read_scratchpad(None, &mut output, &mut delay)
+            .is_err());
+        assert_eq!(
+            one_wire.stats(),
+            &Stats {
+                resets: 1,
+                bytes_read: 9,
+                bytes_written: 2,
+                crc_failures: 1,
+                search_restarts: 0,
+                overdrive_fallbacks: 0,
+            }
+        );
+
+        // the address has an invalid crc
+        assert!(one_wire.devices(false, &mut delay).next().unwrap().is_err());
+        assert_eq!(one_wire.stats().search_restarts, 1);
+        assert_eq!(one_wire.stats().crc_failures, 2);
+
+        one_wire.reset_stats();
+        assert_eq!(one_wire.stats(), &Stats::default());
+    }
+}
@@ -0,0 +1,340 @@
+//! The timings of the bus, and precise delays from a free-running counter (such as the Cortex-M
+//! DWT cycle counter or a hardware timer).
+//!
+//! Many HAL `DelayUs` implementations have an overhead of several microseconds per call (or round
+//! up to their tick rate), which is enough to miss the 15 µs window for sampling a read slot.
+//! Busy-waiting on a counter only adds the time taken to read it.
+
+use core::time::Duration;
+use embedded_hal::blocking::delay::DelayUs;
+
+/// The timings of the reset pulse and time slots, used with `OneWire::set_timings`. Each time is
+/// measured from the end of the previous step, and is rounded down to whole µs (up to 65535 µs).
+///
+/// The defaults are the Maxim recommended timings for standard speed. Long buses with a lot of
+/// capacitance may need a longer time before sampling, and more time to recover between slots (see
+/// `slot_recovery`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timings {
+    /// How long the bus is held low for a reset
+    pub reset_low: Duration,
+
+    /// The time from releasing the bus to sampling the presence pulse
+    pub presence_sample: Duration,
+
+    /// The time from sampling the presence pulse to the end of the reset
+    pub reset_recovery: Duration,
+
+    /// How long the bus is held low to start a write-one or read slot
+    pub write_one_low: Duration,
+
+    /// The rest of a write-one slot, after the bus is released
+    pub write_one_recovery: Duration,
+
+    /// How long the bus is held low for a write-zero slot
+    pub write_zero_low: Duration,
+
+    /// The rest of a write-zero slot, after the bus is released
+    pub write_zero_recovery: Duration,
+
+    /// The time from releasing the bus to sampling it in a read slot
+    pub read_sample: Duration,
+
+    /// The rest of a read slot, after sampling the bus
+    pub read_recovery: Duration,
+
+    /// Extra time the bus is left idle after every slot, on top of the recovery time of the slot.
+    /// Parasite powered devices recharge while the bus is idle, so a large group of them may need
+    /// more time than the recommended timings allow
+    pub slot_recovery: Duration,
+
+    /// Extra time the bus is left idle after every byte read or written
+    pub byte_recovery: Duration,
+
+    /// How long an active pull-up (see `pins::ActivePullUp`) is switched on each time the bus is
+    /// released. This is taken out of the wait that follows, so it doesn't change the length of
+    /// the slot. 0 never switches it off, so this must be set when there is one (as
+    /// `OneWire::new_active_pull_up` does). The same time is used at overdrive speed
+    pub active_pull_up: Duration,
+}
+
+impl Timings {
+    /// The Maxim recommended timings (from application note 126)
+    pub const STANDARD: Timings = Timings {
+        reset_low: Duration::from_micros(480),
+        presence_sample: Duration::from_micros(70),
+        reset_recovery: Duration::from_micros(410),
+        write_one_low: Duration::from_micros(6),
+        write_one_recovery: Duration::from_micros(64),
+        write_zero_low: Duration::from_micros(60),
+        write_zero_recovery: Duration::from_micros(10),
+        read_sample: Duration::from_micros(9),
+        read_recovery: Duration::from_micros(55),
+        slot_recovery: Duration::ZERO,
+        byte_recovery: Duration::ZERO,
+        active_pull_up: Duration::ZERO,
+    };
+
+    /// The recommended timings at overdrive speed (from application note 126). These are truncated
+    /// to whole µs when used, and need a delay that is accurate to about 1 µs
+    pub const OVERDRIVE: Timings = Timings {
+        reset_low: Duration::from_micros(70),
+        presence_sample: Duration::from_nanos(8_500),
+        reset_recovery: Duration::from_micros(40),
+        write_one_low: Duration::from_micros(1),
+        write_one_recovery: Duration::from_nanos(7_500),
+        write_zero_low: Duration::from_nanos(7_500),
+        write_zero_recovery: Duration::from_nanos(2_500),
+        read_sample: Duration::from_micros(1),
+        read_recovery: Duration::from_micros(7),
+        slot_recovery: Duration::ZERO,
+        byte_recovery: Duration::ZERO,
+        active_pull_up: Duration::ZERO,
+    };
+}
+
+impl Timings {
+    /// The length of a read slot (from the start of the slot to the end of the recovery time)
+    pub fn read_slot(&self) -> Duration {
+        self.write_one_low + self.read_sample + self.read_recovery
+    }
+
+    /// Moves the sample point of read slots to `sample` after the start of the slot, keeping the
+    /// length of the slot the same. Buses with a lot of capacitance need a later sample point, and
+    /// short buses can use an earlier one. The sample point is limited to the length of the slot
+    pub fn with_read_sample_point(mut self, sample: Duration) -> Timings {
+        let slot = self.read_slot();
+        let sample = sample.max(self.write_one_low).min(slot);
+        self.read_sample = sample - self.write_one_low;
+        self.read_recovery = slot - sample;
+        self
+    }
+}
+
+impl Default for Timings {
+    fn default() -> Timings {
+        Timings::STANDARD
+    }
+}
+
+/// `Timings` converted to µs once, instead of for every slot
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SlotTimes {
+    pub reset_low: u16,
+    pub presence_sample: u16,
+    pub reset_recovery: u16,
+    pub write_one_low: u16,
+    pub write_one_recovery: u16,
+    pub write_zero_low: u16,
+    pub write_zero_recovery: u16,
+    pub read_sample: u16,
+    pub read_recovery: u16,
+    pub slot_recovery: u16,
+    pub byte_recovery: u16,
+    pub active_pull_up: u16,
+}
+
+fn to_micros(duration: Duration) -> u16 {
+    duration.as_micros().min(u16::MAX as u128) as u16
+}
+
+fn from_micros(micros: u16) -> Duration {
+    Duration::from_micros(micros as u64)
+}
+
+impl From<&Timings> for SlotTimes {
+    fn from(timings: &Timings) -> SlotTimes {
+        SlotTimes {
+            reset_low: to_micros(timings.reset_low),
+            presence_sample: to_micros(timings.presence_sample),
+            reset_recovery: to_micros(timings.reset_recovery),
+            write_one_low: to_micros(timings.write_one_low),
+            write_one_recovery: to_micros(timings.write_one_recovery),
+            write_zero_low: to_micros(timings.write_zero_low),
+            write_zero_recovery: to_micros(timings.write_zero_recovery),
+            read_sample: to_micros(timings.read_sample),
+            read_recovery: to_micros(timings.read_recovery),
+            slot_recovery: to_micros(timings.slot_recovery),
+            byte_recovery: to_micros(timings.byte_recovery),
+            active_pull_up: to_micros(timings.active_pull_up),
+        }
+    }
+}
+
+impl From<&SlotTimes> for Timings {
+    fn from(times: &SlotTimes) -> Timings {
+        Timings {
+            reset_low: from_micros(times.reset_low),
+            presence_sample: from_micros(times.presence_sample),
+            reset_recovery: from_micros(times.reset_recovery),
+            write_one_low: from_micros(times.write_one_low),
+            write_one_recovery: from_micros(times.write_one_recovery),
+            write_zero_low: from_micros(times.write_zero_low),
+            write_zero_recovery: from_micros(times.write_zero_recovery),
+            read_sample: from_micros(times.read_sample),
+            read_recovery: from_micros(times.read_recovery),
+            slot_recovery: from_micros(times.slot_recovery),
+            byte_recovery: from_micros(times.byte_recovery),
+            active_pull_up: from_micros(times.active_pull_up),
+        }
+    }
+}
+
+/// A free-running counter, used for timing with `PreciseDelay`
+pub trait PreciseTimer {
+    /// Returns the current count. This is expected to wrap around at `u32::MAX`
+    fn now(&mut self) -> u32;
+
+    /// The number of counts per microsecond (for a cycle counter, the core clock in MHz)
+    fn ticks_per_micro(&self) -> u32;
+}
+
+/// Implements `DelayUs` by busy-waiting on a `PreciseTimer`, so it can be used anywhere a delay is
+/// accepted
+pub struct PreciseDelay<C> {
+    timer: C,
+}
+
+impl<C: PreciseTimer> PreciseDelay<C> {
+    pub fn new(timer: C) -> PreciseDelay<C> {
+        PreciseDelay { timer }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.timer
+    }
+}
+
+impl<C: PreciseTimer> DelayUs<u32> for PreciseDelay<C> {
+    fn delay_us(&mut self, us: u32) {
+        let start = self.timer.now();
+        let ticks = us.saturating_mul(self.timer.ticks_per_micro());
+        while self.timer.now().wrapping_sub(start) < ticks {}
+    }
+}
+
+impl<C: PreciseTimer> DelayUs<u16> for PreciseDelay<C> {
+    fn delay_us(&mut self, us: u16) {
+        DelayUs::<u32>::delay_us(self, us as u32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::timing::{PreciseDelay, PreciseTimer, SlotTimes, Timings};
+    use crate::{Address, OneWire};
+    use core::time::Duration;
+    use embedded_hal::blocking::delay::DelayUs;
+
+    /// Counts up by one every time it's read
+    struct Counter(u32);
+
+    impl PreciseTimer for Counter {
+        fn now(&mut self) -> u32 {
+            self.0 = self.0.wrapping_add(1);
+            self.0
+        }
+
+        fn ticks_per_micro(&self) -> u32 {
+            4
+        }
+    }
+
+    #[test]
+    fn test_precise_delay() {
+        let mut delay = PreciseDelay::new(Counter(0));
+        delay.delay_us(10u32);
+        assert_eq!(delay.into_inner().0, 41);
+
+        // the counter wraps around during the delay
+        let mut delay = PreciseDelay::new(Counter(u32::MAX - 5));
+        delay.delay_us(6u32);
+        assert_eq!(delay.into_inner().0, 19);
+    }
+
+    #[test]
+    fn test_timings() {
+        let relaxed = Timings {
+            reset_low: Duration::from_micros(500),
+            read_sample: Duration::from_micros(11),
+            read_recovery: Duration::from_micros(60),
+            ..Timings::STANDARD
+        };
+        let times = SlotTimes::from(&relaxed);
+        assert_eq!(times.reset_low, 500);
+        assert_eq!(Timings::from(&times), relaxed);
+        assert_eq!(
+            SlotTimes::from(&Timings {
+                reset_low: Duration::from_secs(1),
+                ..relaxed
+            })
+            .reset_low,
+            u16::MAX
+        );
+
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0xA5];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(relaxed);
+        assert_eq!(one_wire.timings(), relaxed);
+        one_wire
+            .send_command(crate::commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0xA5));
+    }
+
+    #[test]
+    fn test_read_sample_point() {
+        let timings = Timings::STANDARD.with_read_sample_point(Duration::from_micros(25));
+        assert_eq!(timings.read_sample, Duration::from_micros(19));
+        assert_eq!(timings.read_recovery, Duration::from_micros(45));
+        assert_eq!(timings.read_slot(), Timings::STANDARD.read_slot());
+
+        let timings = Timings::STANDARD.with_read_sample_point(Duration::from_micros(2));
+        assert_eq!(timings.read_sample, Duration::ZERO);
+        let timings = Timings::STANDARD.with_read_sample_point(Duration::from_millis(1));
+        assert_eq!(timings.read_recovery, Duration::ZERO);
+
+        // a sample point later than the simulated device holds the bus reads a 0 as a 1
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0x00];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(Timings::STANDARD.with_read_sample_point(Duration::from_micros(40)));
+        one_wire
+            .send_command(crate::commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0xFF));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![0xA5, 0x5A];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_timings(Timings {
+            slot_recovery: Duration::from_micros(20),
+            byte_recovery: Duration::from_micros(100),
+            ..Timings::STANDARD
+        });
+        one_wire
+            .send_command(crate::commands::READ_SCRATCHPAD, None, &mut delay)
+            .unwrap();
+        let start = simulator.time_micros();
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0xA5));
+        let slot = Timings::STANDARD.read_slot().as_micros() as u64;
+        assert_eq!(simulator.time_micros() - start, 8 * (slot + 20) + 100);
+        assert_eq!(one_wire.read_byte(&mut delay), Ok(0x5A));
+    }
+}
@@ -0,0 +1,126 @@
+//! Protocol level tracing, enabled with the `trace` feature.
+//!
+//! A tracer is a plain function that is called for every event on the bus, so it can be used without
+//! an allocator. It is only called at the end of a time slot, but it still adds to the time between
+//! slots, so it should be fast (copying the event into a buffer, for example).
+
+#[cfg(feature = "trace")]
+use crate::ResetResult;
+
+/// Sends an event to the tracer of a bus. Does nothing if the `trace` feature is disabled
+macro_rules! trace {
+    ($one_wire:expr, $event:expr) => {
+        #[cfg(feature = "trace")]
+        $one_wire.trace($event);
+    };
+}
+
+/// Logs a message with the `log` crate. Does nothing if the `log` feature is disabled.
+///
+/// Unlike the tracer, this is only used between operations, not for each slot
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::$level!($($arg)*);
+    };
+}
+
+#[cfg(feature = "trace")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusEvent {
+    Reset(ResetResult),
+    BitWritten(bool),
+    BitRead(bool),
+
+    /// A whole byte was written. This is sent after the events for the individual bits
+    ByteWritten(u8),
+
+    /// A whole byte was read. This is sent after the events for the individual bits
+    ByteRead(u8),
+
+    /// A pin error, or the bus wasn't in the expected state
+    Error,
+}
+
+#[cfg(all(test, feature = "trace"))]
+mod test {
+    extern crate std;
+
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, BusEvent, OneWire, ResetResult};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static EVENTS: Mutex<Vec<BusEvent>> = Mutex::new(Vec::new());
+
+    fn record(event: BusEvent) {
+        EVENTS.lock().unwrap().push(event);
+    }
+
+    #[test]
+    fn test_trace() {
+        let simulator = Simulator::new();
+        simulator.add_device(VirtualDevice::new(Address(0x28)));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        one_wire.set_tracer(Some(record));
+
+        one_wire.reset(&mut delay).unwrap();
+        one_wire.write_byte(0x02, &mut delay).unwrap();
+        one_wire.set_tracer(None);
+        one_wire.read_bit(&mut delay).unwrap();
+
+        let mut expected = std::vec![BusEvent::Reset(ResetResult::DevicesPresent)];
+        expected.push(BusEvent::BitWritten(false));
+        expected.push(BusEvent::BitWritten(true));
+        expected.extend((0..6).map(|_| BusEvent::BitWritten(false)));
+        expected.push(BusEvent::ByteWritten(0x02));
+        assert_eq!(*EVENTS.lock().unwrap(), expected);
+    }
+}
+
+#[cfg(all(test, feature = "log"))]
+mod log_test {
+    extern crate std;
+
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::{Address, OneWire};
+    use log::{Log, Metadata, Record};
+    use std::string::{String, ToString};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Logger;
+
+    impl Log for Logger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            MESSAGES.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log() {
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let simulator = Simulator::new();
+        let address = Address::for_test(0x3A, 0x1234);
+        simulator.add_device(VirtualDevice::new(address));
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let found: Vec<_> = one_wire.devices(false, &mut delay).collect();
+        assert_eq!(found, [Ok(address)]);
+
+        // other tests may be logging at the same time
+        let messages = MESSAGES.lock().unwrap();
+        assert!(messages.contains(&std::format!("found device {}", address)));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::commands::RomCommand;
+use crate::delay::Delay;
+use crate::pullup::{NoStrongPullup, StrongPullupPin};
+use crate::{Address, ErrorKind, OneWire, OneWireError, OneWireResult};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// The speed an `overdrive_transaction` was done at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Speed {
+    Standard,
+    Overdrive,
+}
+
+/// Access to the bus during `OneWire::transaction`. The device(s) have already been reset and
+/// addressed, so this only allows reading and writing data.
+pub struct Transaction<'a, T, D, P = NoStrongPullup> {
+    onewire: &'a mut OneWire<T, P>,
+    delay: &'a mut D,
+}
+
+impl<'a, T, E, D, P> Transaction<'a, T, D, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+    D: Delay,
+{
+    pub fn read_bit(&mut self) -> OneWireResult<bool, E> {
+        self.onewire.read_bit(self.delay)
+    }
+
+    pub fn read_byte(&mut self) -> OneWireResult<u8, E> {
+        self.onewire.read_byte(self.delay)
+    }
+
+    pub fn read_bytes(&mut self, output: &mut [u8]) -> OneWireResult<(), E> {
+        self.onewire.read_bytes(output, self.delay)
+    }
+
+    pub fn write_bit(&mut self, value: bool) -> OneWireResult<(), E> {
+        self.onewire.write_bit(value, self.delay)
+    }
+
+    pub fn write_byte(&mut self, value: u8) -> OneWireResult<(), E> {
+        self.onewire.write_byte(value, self.delay)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> OneWireResult<(), E> {
+        self.onewire.write_bytes(bytes, self.delay)
+    }
+
+    /// The delay, for commands that need to wait (for a conversion, EEPROM write, etc)
+    pub fn delay(&mut self) -> &mut D {
+        self.delay
+    }
+}
+
+impl<T, E, P> OneWire<T, P>
+where
+    T: InputPin<Error = E>,
+    T: OutputPin<Error = E>,
+    P: StrongPullupPin<E>,
+{
+    /// Sends a reset, followed with either a SKIP_ROM or MATCH_ROM (with an address), then runs `f`
+    /// to send a function command and read / write its data.
+    ///
+    /// Fails with `OneWireError::NoDevicePresent` if no devices respond to the reset, without
+    /// running `f`. The bus is released when `f` returns, even if it returned an error. If the
+    /// transaction fails with an `ErrorKind::Data` or `ErrorKind::Protocol` error, it's run again
+    /// from the reset, up to `set_retries` times.
+    pub fn transaction<D, R>(
+        &mut self,
+        address: Option<&Address>,
+        delay: &mut D,
+        mut f: impl FnMut(&mut Transaction<T, D, P>) -> OneWireResult<R, E>,
+    ) -> OneWireResult<R, E>
+    where
+        D: Delay,
+    {
+        let mut retries = self.retries;
+        loop {
+            match self.transaction_attempt(address, delay, &mut f) {
+                Err(err)
+                    if retries > 0
+                        && matches!(err.kind(), ErrorKind::Data | ErrorKind::Protocol) =>
+                {
+                    retries -= 1;
+                    log_event!(debug, "transaction failed ({:?}), retrying", err.kind());
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn transaction_attempt<D, R>(
+        &mut self,
+        address: Option<&Address>,
+        delay: &mut D,
+        f: &mut impl FnMut(&mut Transaction<T, D, P>) -> OneWireResult<R, E>,
+    ) -> OneWireResult<R, E>
+    where
+        D: Delay,
+    {
+        self.set_speed(Speed::Standard);
+        if !self.reset(delay)? {
+            return Err(OneWireError::NoDevicePresent);
+        }
+        if let Some(address) = address {
+            self.match_address(address, delay)?;
+        } else {
+            self.skip_address(delay)?;
+        }
+        let result = f(&mut Transaction {
+            onewire: self,
+            delay,
+        });
+        let released = self.release_bus();
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    /// The same as `transaction`, but at overdrive speed. This sends a standard speed reset,
+    /// followed with either an OVERDRIVE SKIP ROM or OVERDRIVE MATCH ROM (with the address sent at
+    /// overdrive speed), then runs `f` with `Timings::OVERDRIVE`. The bus timings are restored
+    /// afterwards. `f` should check the data it reads (with a crc), since a device that missed an
+    /// overdrive slot usually just stops responding.
+    ///
+    /// If `f` fails and `set_overdrive_fallback` is enabled, the whole transaction is retried once
+    /// at standard speed, and `Speed::Standard` is returned along with the result. Pin errors are
+    /// never retried.
+    pub fn overdrive_transaction<D, R>(
+        &mut self,
+        address: Option<&Address>,
+        delay: &mut D,
+        mut f: impl FnMut(&mut Transaction<T, D, P>) -> OneWireResult<R, E>,
+    ) -> OneWireResult<(R, Speed), E>
+    where
+        D: Delay,
+    {
+        let speed = self.speed();
+        let result = self.overdrive_attempt(address, delay, &mut f);
+        self.set_speed(speed);
+        let released = self.release_bus();
+        match result {
+            Ok(value) => {
+                released?;
+                Ok((value, Speed::Overdrive))
+            }
+            Err(OneWireError::PinError(err)) => Err(OneWireError::PinError(err)),
+            Err(err) if !self.overdrive_fallback => Err(err),
+            Err(_err) => {
+                count!(self, overdrive_fallbacks);
+                log_event!(
+                    warn,
+                    "overdrive transaction failed ({:?}), retrying at standard speed",
+                    _err.kind()
+                );
+                let value = self.transaction(address, delay, f)?;
+                Ok((value, Speed::Standard))
+            }
+        }
+    }
+
+    fn overdrive_attempt<D, R>(
+        &mut self,
+        address: Option<&Address>,
+        delay: &mut D,
+        f: &mut impl FnMut(&mut Transaction<T, D, P>) -> OneWireResult<R, E>,
+    ) -> OneWireResult<R, E>
+    where
+        D: Delay,
+    {
+        self.set_speed(Speed::Standard);
+        if !self.reset(delay)? {
+            return Err(OneWireError::NoDevicePresent);
+        }
+        match address {
+            Some(address) => {
+                self.write_rom_command(RomCommand::OverdriveMatch, delay)?;
+                self.set_speed(Speed::Overdrive);
+                self.write_bytes(&address.to_bytes(), delay)?;
+            }
+            None => {
+                self.write_rom_command(RomCommand::OverdriveSkip, delay)?;
+                self.set_speed(Speed::Overdrive);
+            }
+        }
+        f(&mut Transaction {
+            onewire: self,
+            delay,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use crate::simulator::{Simulator, VirtualDevice};
+    use crate::transaction::{Speed, Transaction};
+    use crate::{commands, crc, Address, OneWire, OneWireError};
+
+    #[test]
+    fn test_transaction() {
+        let simulator = Simulator::new();
+        let mut device = VirtualDevice::new(Address(0x28));
+        device.scratchpad = std::vec![1, 2, 3];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let scratchpad = one_wire
+            .transaction(None, &mut delay, |tx| {
+                tx.write_byte(commands::READ_SCRATCHPAD)?;
+                let mut scratchpad = [0; 3];
+                tx.read_bytes(&mut scratchpad)?;
+                Ok(scratchpad)
+            })
+            .unwrap();
+        assert_eq!(scratchpad, [1, 2, 3]);
+
+        let result: Result<(), _> =
+            one_wire.transaction(None, &mut delay, |_| Err(OneWireError::UnexpectedResponse));
+        assert_eq!(result, Err(OneWireError::UnexpectedResponse));
+        assert_eq!(one_wire.is_bus_high(), Ok(true));
+
+        // data errors are retried, and timeouts aren't
+        one_wire.set_retries(2);
+        let mut attempts = 0;
+        let result = one_wire.transaction(None, &mut delay, |_| {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(OneWireError::VerificationFailed),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result, Ok(3));
+        let mut attempts = 0;
+        let result: Result<(), _> = one_wire.transaction(None, &mut delay, |_| {
+            attempts += 1;
+            Err(OneWireError::Timeout(100))
+        });
+        assert_eq!(result, Err(OneWireError::Timeout(100)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_no_device_present() {
+        let simulator = Simulator::new();
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+        let result: Result<(), _> =
+            one_wire.transaction(None, &mut delay, |_| panic!("run without a device present"));
+        assert_eq!(result, Err(OneWireError::NoDevicePresent));
+        let result: Result<((), _), _> =
+            one_wire.overdrive_transaction(Some(&Address(0x28)), &mut delay, |_| {
+                panic!("run without a device present")
+            });
+        assert_eq!(result, Err(OneWireError::NoDevicePresent));
+    }
+
+    #[test]
+    fn test_overdrive_transaction() {
+        let simulator = Simulator::new();
+        let overdrive = Address::for_test(0x43, 1);
+        let mut device = VirtualDevice::new(overdrive);
+        device.overdrive_capable = true;
+        device.scratchpad = std::vec![1, 2, 3, crc::crc8(&[1, 2, 3])];
+        simulator.add_device(device);
+        let standard = Address::for_test(0x28, 2);
+        let mut device = VirtualDevice::new(standard);
+        device.scratchpad = std::vec![4, 5, 6, crc::crc8(&[4, 5, 6])];
+        simulator.add_device(device);
+        let mut delay = simulator.delay();
+        let mut one_wire = OneWire::new(simulator.pin()).unwrap();
+
+        let mut read_scratchpad = |tx: &mut Transaction<_, _>| {
+            tx.write_byte(commands::READ_SCRATCHPAD)?;
+            let mut scratchpad = [0; 4];
+            tx.read_bytes(&mut scratchpad)?;
+            crc::check_crc8(&scratchpad)?;
+            Ok(scratchpad[0])
+        };
+        assert_eq!(
+            one_wire.overdrive_transaction(Some(&overdrive), &mut delay, &mut read_scratchpad),
+            Ok((1, Speed::Overdrive))
+        );
+        assert_eq!(one_wire.timings(), crate::timing::Timings::STANDARD);
+
+        // a device that doesn't respond at overdrive speed only works with the fallback
+        assert!(one_wire
+            .overdrive_transaction(Some(&standard), &mut delay, &mut read_scratchpad)
+            .is_err());
+        one_wire.set_overdrive_fallback(true);
+        assert_eq!(
+            one_wire.overdrive_transaction(Some(&standard), &mut delay, &mut read_scratchpad),
+            Ok((4, Speed::Standard))
+        );
+        #[cfg(feature = "stats")]
+        assert_eq!(one_wire.stats().overdrive_fallbacks, 1);
+
+        assert_eq!(
+            one_wire.overdrive_transaction(Some(&overdrive), &mut delay, &mut read_scratchpad),
+            Ok((1, Speed::Overdrive))
+        );
+
+        // the reset and ROM command are sent at standard speed, even if the bus is at overdrive
+        one_wire.set_overdrive_fallback(false);
+        one_wire.reset(&mut delay).unwrap();
+        one_wire.set_speed(Speed::Overdrive);
+        assert_eq!(
+            one_wire.overdrive_transaction(Some(&overdrive), &mut delay, &mut read_scratchpad),
+            Ok((1, Speed::Overdrive))
+        );
+        assert_eq!(one_wire.speed(), Speed::Overdrive);
+    }
+}